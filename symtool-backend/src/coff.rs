@@ -0,0 +1,336 @@
+//! Manipulate COFF objects, such as the `.obj` files produced by MSVC and clang-cl.
+//!
+//! COFF symbols have no visibility.  Instead, a symbol is exported from the DLL an object is
+//! linked into by an `/EXPORT:` directive in the object's `.drectve` section, so hiding a symbol
+//! removes its export directives.
+
+use crate::error::{Error, Result};
+use crate::object::{ForeignObject, ForeignSymbol, Object, ObjectFormat};
+use crate::patch::{Location, Patch, Rooted};
+use goblin::container::{Container, Ctx, Endian};
+use goblin::pe::header::{CoffHeader, SIZEOF_COFF_HEADER};
+use goblin::pe::section_table::{SectionTable, SIZEOF_SECTION_TABLE};
+use goblin::pe::symbol::{
+    Symbol, IMAGE_SYM_CLASS_EXTERNAL, IMAGE_SYM_CLASS_STATIC, IMAGE_SYM_CLASS_WEAK_EXTERNAL,
+};
+use scroll::ctx::StrCtx;
+use scroll::{Pread, LE};
+
+/// The size of a symbol table record, including auxiliary records.
+const SYMBOL_SIZE: usize = 18;
+
+/// The offset of the storage class in a symbol table record.
+const STORAGE_CLASS_OFFSET: usize = 16;
+
+const IMAGE_FILE_MACHINE_I386: u16 = 0x14c;
+
+/// The machine types of objects which are recognized.
+///
+/// The machine type is the only identification of a COFF object, so other values are assumed to
+/// be other kinds of files.
+const MACHINES: &[u16] = &[
+    IMAGE_FILE_MACHINE_I386,
+    0x8664, // AMD64
+    0x1c4,  // ARMNT
+    0xaa64, // ARM64
+    0xa641, // ARM64EC
+    0xa64e, // ARM64X
+];
+
+fn context() -> Ctx {
+    Ctx::new(Container::Little, Endian::Little)
+}
+
+/// The COFF object format.
+///
+/// PE images and the "big object" variant of COFF are not recognized.
+pub struct CoffFormat;
+
+impl ObjectFormat for CoffFormat {
+    fn name(&self) -> &str {
+        "COFF"
+    }
+
+    fn parse<'a>(&self, bytes: &'a [u8]) -> Result<Option<Object<'a>>> {
+        if bytes.len() < SIZEOF_COFF_HEADER {
+            return Ok(None);
+        }
+        let header: CoffHeader = bytes.pread_with(0, LE)?;
+        if !MACHINES.contains(&header.machine) || header.size_of_optional_header != 0 {
+            return Ok(None);
+        }
+        Ok(Some(Object::Other(Box::new(CoffObject::parse(
+            bytes, &header,
+        )?))))
+    }
+}
+
+/// An `/EXPORT:` directive in the `.drectve` section.
+struct Export<'a> {
+    /// The whole directive, such as `/EXPORT:foo,DATA`
+    directive: Rooted<&'a str>,
+
+    /// The name of the exported symbol, which is part of `directive`
+    symbol: &'a str,
+}
+
+impl<'a> Export<'a> {
+    /// Parse an export directive, such as `/EXPORT:foo`, `-export:"foo",DATA`, or
+    /// `/EXPORT:bar=foo`, which exports the symbol `foo` as `bar`.
+    fn parse(directive: Rooted<&'a str>) -> Option<Self> {
+        let option = directive.get(1..8)?;
+        if !directive.starts_with(['/', '-']) || !option.eq_ignore_ascii_case("export:") {
+            return None;
+        }
+        let spec = directive[8..].split(',').next()?;
+        let symbol = spec.rsplit('=').next()?;
+        let symbol = symbol.trim_matches('"');
+        Some(Self { directive, symbol })
+    }
+
+    /// Whether the directive exports a symbol.
+    ///
+    /// The names of 32-bit x86 symbols are decorated with a leading underscore, which export
+    /// directives may omit.
+    fn exports(&self, machine: u16, name: &str) -> bool {
+        self.symbol == name
+            || (machine == IMAGE_FILE_MACHINE_I386 && name.strip_prefix('_') == Some(self.symbol))
+    }
+
+    /// The directive exporting the symbol `name` after renaming it to `new_name`, padded with
+    /// spaces.
+    fn renamed(&self, machine: u16, name: &str, new_name: &str) -> String {
+        let start = self.symbol.as_ptr() as usize - self.directive.as_ptr() as usize;
+        let end = start + self.symbol.len();
+        // Keep omitting the leading underscore of 32-bit x86 symbols
+        let new_name = if machine == IMAGE_FILE_MACHINE_I386 && self.symbol != name {
+            new_name.strip_prefix('_').unwrap_or(new_name)
+        } else {
+            new_name
+        };
+        let mut directive = format!(
+            "{}{}{}",
+            &self.directive[..start],
+            new_name,
+            &self.directive[end..]
+        );
+        while directive.len() < self.directive.len() {
+            directive.push(' ');
+        }
+        directive
+    }
+}
+
+/// Split the contents of a `.drectve` section into directives, which are separated by spaces
+/// and may contain quoted arguments.
+fn directives(bytes: &[u8], offset: usize, size: usize) -> Result<Vec<Rooted<&str>>> {
+    let contents = offset
+        .checked_add(size)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| Error::Malformed(".drectve out of bounds".to_string()))?;
+    let contents = match std::str::from_utf8(contents) {
+        Ok(contents) => contents,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut directives = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    for (index, c) in contents.char_indices().chain(Some((contents.len(), ' '))) {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' | '\t' | '\r' | '\n' | '\0' | '\u{feff}' if !quoted => {
+                if let Some(start) = start.take() {
+                    let location = Location {
+                        offset: offset + start,
+                        size: index - start,
+                        ctx: context(),
+                    };
+                    directives.push(Rooted::new(location, &contents[start..index]));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(index);
+    }
+    Ok(directives)
+}
+
+/// A COFF object.
+pub struct CoffObject<'a> {
+    bytes: &'a [u8],
+    machine: u16,
+    symoff: usize,
+    nsyms: usize,
+
+    /// The offset of the string table, which follows the symbol table
+    strtab: usize,
+    exports: Vec<Export<'a>>,
+}
+
+impl<'a> CoffObject<'a> {
+    fn parse(bytes: &'a [u8], header: &CoffHeader) -> Result<Self> {
+        let symoff = header.pointer_to_symbol_table as usize;
+        let nsyms = header.number_of_symbol_table as usize;
+        let strtab = symoff + nsyms * SYMBOL_SIZE;
+        if symoff != 0 && strtab > bytes.len() {
+            return Err(Error::Malformed(
+                "Symbol table extends past the end of the file".to_string(),
+            ));
+        }
+        let mut exports = Vec::new();
+        for index in 0..header.number_of_sections as usize {
+            let mut offset = SIZEOF_COFF_HEADER + index * SIZEOF_SECTION_TABLE;
+            let section = SectionTable::parse(bytes, &mut offset, strtab)?;
+            if section.name()? == ".drectve" {
+                for directive in directives(
+                    bytes,
+                    section.pointer_to_raw_data as usize,
+                    section.size_of_raw_data as usize,
+                )? {
+                    exports.extend(Export::parse(directive));
+                }
+            }
+        }
+        Ok(Self {
+            bytes,
+            machine: header.machine,
+            symoff,
+            nsyms,
+            strtab,
+            exports,
+        })
+    }
+
+    fn symbol(&self, index: usize) -> Result<Symbol> {
+        Ok(self
+            .bytes
+            .pread_with(self.symoff + index * SYMBOL_SIZE, LE)?)
+    }
+
+    fn is_exported(&self, name: &str) -> bool {
+        self.exports
+            .iter()
+            .any(|export| export.exports(self.machine, name))
+    }
+}
+
+impl<'a> ForeignObject<'a> for CoffObject<'a> {
+    fn symbols(&self) -> Result<Vec<ForeignSymbol<'a>>> {
+        let mut symbols = Vec::new();
+        let mut index = 0;
+        while index < self.nsyms {
+            let offset = self.symoff + index * SYMBOL_SIZE;
+            let symbol = self.symbol(index)?;
+            let symbol_index = index;
+            index += 1 + symbol.number_of_aux_symbols as usize;
+            // Section and file names are stored like symbols, but aren't renamed
+            if symbol.is_section_definition() || symbol.is_file() {
+                continue;
+            }
+            let name = match symbol.name_offset() {
+                Some(name_offset) => {
+                    let offset = self.strtab + name_offset as usize;
+                    let name: &str = self.bytes.pread(offset)?;
+                    Rooted::new(
+                        Location {
+                            offset,
+                            size: name.len(),
+                            ctx: context(),
+                        },
+                        name,
+                    )
+                }
+                None => {
+                    let name: &str = self
+                        .bytes
+                        .pread_with(offset, StrCtx::DelimiterUntil(0, 8))?;
+                    Rooted::new(
+                        Location {
+                            offset,
+                            size: name.len(),
+                            ctx: context(),
+                        },
+                        name,
+                    )
+                }
+            };
+            let global = matches!(
+                symbol.storage_class,
+                IMAGE_SYM_CLASS_EXTERNAL | IMAGE_SYM_CLASS_WEAK_EXTERNAL
+            );
+            let hidden = global && symbol.section_number > 0 && !self.is_exported(&name);
+            symbols.push(ForeignSymbol {
+                name,
+                index: symbol_index,
+                global,
+                hidden,
+            });
+        }
+        Ok(symbols)
+    }
+
+    /// Remove the export directives of a symbol to hide it.
+    ///
+    /// Exports can't be added in place, so symbols which aren't exported can't be made visible.
+    /// Several directives exporting the same symbol are removed with one patch, which also
+    /// covers the directives between them.
+    fn set_hidden(&self, symbol: &ForeignSymbol<'a>, hidden: bool) -> Result<Option<Patch>> {
+        let exports: Vec<_> = self
+            .exports
+            .iter()
+            .filter(|export| export.exports(self.machine, &symbol.name))
+            .collect();
+        let (first, last) = match (hidden, exports.first(), exports.last()) {
+            (true, Some(first), Some(last)) => (first, last),
+            _ => return Ok(None),
+        };
+        let start = first.directive.as_ptr() as usize;
+        let end = last.directive.as_ptr() as usize + last.directive.len();
+        let offset = start - self.bytes.as_ptr() as usize;
+        let mut contents = self.bytes[offset..offset + end - start].to_vec();
+        for export in exports {
+            let start = export.directive.as_ptr() as usize - start;
+            contents[start..start + export.directive.len()].fill(b' ');
+        }
+        let location = Location {
+            offset,
+            size: contents.len(),
+            ctx: context(),
+        };
+        Ok(Some(Patch::from_bytes(&location, &contents)?))
+    }
+
+    /// Change the storage class of a defined symbol between `IMAGE_SYM_CLASS_EXTERNAL` and
+    /// `IMAGE_SYM_CLASS_STATIC`.
+    fn set_global(&self, symbol: &ForeignSymbol<'a>, global: bool) -> Result<Option<Patch>> {
+        let record = self.symbol(symbol.index)?;
+        let storage_class = match (global, record.storage_class) {
+            _ if record.section_number <= 0 => return Ok(None),
+            (true, IMAGE_SYM_CLASS_STATIC) => IMAGE_SYM_CLASS_EXTERNAL,
+            (false, IMAGE_SYM_CLASS_EXTERNAL) => IMAGE_SYM_CLASS_STATIC,
+            _ => return Ok(None),
+        };
+        let location = Location {
+            offset: self.symoff + symbol.index * SYMBOL_SIZE + STORAGE_CLASS_OFFSET,
+            size: 1,
+            ctx: context(),
+        };
+        Ok(Some(Patch::from_bytes(&location, &[storage_class])?))
+    }
+
+    /// Rename the symbol in its export directives.
+    fn rename_references(&self, symbol: &ForeignSymbol<'a>, new_name: &str) -> Result<Vec<Patch>> {
+        self.exports
+            .iter()
+            .filter(|export| export.exports(self.machine, &symbol.name))
+            .map(|export| {
+                export.directive.patch_with_bytes(
+                    export
+                        .renamed(self.machine, &symbol.name, new_name)
+                        .as_bytes(),
+                )
+            })
+            .collect()
+    }
+}
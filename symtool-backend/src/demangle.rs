@@ -0,0 +1,25 @@
+//! Demangle C++ and Rust symbol names.
+//!
+//! C++ names follow the Itanium C++ ABI used by GCC and Clang, and Rust names use either the
+//! legacy mangling built on it or the v0 mangling.
+
+use cpp_demangle::Symbol;
+
+/// Demangle a symbol name, producing names in the style of `c++filt`.
+///
+/// Mach-O symbol names with an extra leading underscore are accepted, and the hashes of Rust
+/// symbols are left out.  Returns `None` if the name is not mangled or can't be demangled.
+pub fn demangle(name: &str) -> Option<String> {
+    let name = match name.strip_prefix('_') {
+        Some(stripped) if stripped.starts_with("_Z") || stripped.starts_with("_R") => stripped,
+        _ => name,
+    };
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return Some(format!("{:#}", demangled));
+    }
+    // Other names are parsed as C++ types by cpp_demangle, so "i" would become "int"
+    if !name.starts_with("_Z") {
+        return None;
+    }
+    Symbol::new(name).ok()?.demangle().ok()
+}
@@ -0,0 +1,95 @@
+//! Identify files that are not supported objects.
+
+use scroll::{Pread, BE, LE};
+
+/// Describe what an unsupported file appears to contain, such as "a gzip-compressed tarball".
+///
+/// Returns `None` if the contents are not recognized.
+pub fn describe(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.is_empty() {
+        return Some("an empty file");
+    }
+    let description = if bytes.starts_with(&[0x1f, 0x8b]) {
+        if gzip_filename(bytes).is_some_and(|name| name.ends_with(b".tar")) {
+            "a gzip-compressed tarball"
+        } else {
+            "a gzip-compressed file"
+        }
+    } else if bytes.starts_with(b"BZh") {
+        "a bzip2-compressed file"
+    } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+        "an xz-compressed file"
+    } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        "a zstd-compressed file"
+    } else if bytes.starts_with(b"PK\x03\x04") {
+        "a zip archive"
+    } else if bytes.get(257..262) == Some(b"ustar") {
+        "a tarball"
+    } else if bytes.starts_with(b"MZ") {
+        pe_description(bytes)
+    } else if bytes.starts_with(b"BC\xc0\xde") || bytes.starts_with(&[0xde, 0xc0, 0x17, 0x0b]) {
+        "LLVM bitcode"
+    } else if bytes.starts_with(b"\0asm") {
+        "a WebAssembly module"
+    } else if bytes.starts_with(&[0xca, 0xfe, 0xba, 0xbe]) {
+        // Java class files share the magic number, but store a version where fat binaries store
+        // the number of architectures.
+        match bytes.pread_with::<u32>(4, BE) {
+            Ok(count) if count < 20 => "a fat Mach-O binary",
+            _ => "a Java class file",
+        }
+    } else if bytes.starts_with(&[0, 0, 0xff, 0xff]) {
+        // IMPORT_OBJECT_HEADER and ANON_OBJECT_HEADER_BIGOBJ, told apart by their version
+        match bytes.pread_with::<u16>(4, LE) {
+            Ok(0) => "a COFF import library member",
+            _ => "a COFF object with extended section numbering (/bigobj)",
+        }
+    } else if bytes.starts_with(b"<bigaf>\n") {
+        "an AIX big archive"
+    } else if bytes.starts_with(&[0x01, 0xdf]) || bytes.starts_with(&[0x01, 0xf7]) {
+        // XCOFF objects are supported, so this is an executable or shared object
+        "an XCOFF executable or shared object"
+    } else if bytes.starts_with(b"#!") {
+        "a script"
+    } else if std::str::from_utf8(bytes).is_ok_and(|text| {
+        text.chars()
+            .all(|c| !c.is_control() || c.is_ascii_whitespace())
+    }) {
+        "a text file"
+    } else {
+        return None;
+    };
+    Some(description)
+}
+
+/// The original file name stored in a gzip header, if present.
+fn gzip_filename(bytes: &[u8]) -> Option<&[u8]> {
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    let flags = *bytes.get(3)?;
+    if flags & FNAME == 0 {
+        return None;
+    }
+    let mut offset = 10;
+    if flags & FEXTRA != 0 {
+        offset += 2 + bytes.pread_with::<u16>(offset, LE).ok()? as usize;
+    }
+    let name = bytes.get(offset..)?;
+    Some(&name[..name.iter().position(|b| *b == 0)?])
+}
+
+fn pe_description(bytes: &[u8]) -> &'static str {
+    let pe = match bytes.pread_with::<u32>(0x3c, LE) {
+        Ok(offset) => offset as usize,
+        Err(_) => return "a DOS executable",
+    };
+    if bytes.get(pe..pe + 4) != Some(b"PE\0\0") {
+        return "a DOS executable";
+    }
+    // The optional header follows the 4 byte signature and 20 byte COFF file header.
+    match bytes.pread_with::<u16>(pe + 24, LE) {
+        Ok(0x10b) => "a PE32 image",
+        Ok(0x20b) => "a PE32+ image",
+        _ => "a PE image",
+    }
+}
@@ -0,0 +1,392 @@
+//! Maintain DWARF accelerator tables.
+//!
+//! Accelerator tables (`.debug_names`, and Apple's `__apple_names`) index debug info entries by
+//! the hash of their name.  When names in the debug string table change, the hashes must be
+//! recomputed or debuggers will fail to find the renamed entries.
+
+use crate::error::{Error, Result};
+use crate::patch::{Location, Patch};
+use goblin::container::Ctx;
+use goblin::elf::section_header::{SHT_REL, SHT_RELA};
+use goblin::elf::Elf;
+use goblin::mach::MachO;
+use scroll::Pread;
+
+const APPLE_HASH_MAGIC: u32 = 0x4841_5348;
+const APPLE_HASH_FUNCTION_DJB: u16 = 0;
+const APPLE_EMPTY_BUCKET: u32 = u32::MAX;
+
+/// The kind of an accelerator table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccelKind {
+    /// A DWARF 5 `.debug_names` name index
+    DebugNames,
+
+    /// An Apple `.apple_names` hash table
+    Apple,
+}
+
+/// An accelerator table in an object.
+pub struct AccelTable {
+    /// The name of the section containing the table
+    pub section: String,
+
+    /// The kind of table
+    pub kind: AccelKind,
+
+    offset: usize,
+    size: usize,
+    relocated: bool,
+    compressed: bool,
+    ctx: Ctx,
+}
+
+fn djb_hash(name: &[u8]) -> u32 {
+    name.iter().fold(5381u32, |h, c| {
+        h.wrapping_mul(33).wrapping_add(u32::from(*c))
+    })
+}
+
+fn case_folding_djb_hash(name: &[u8]) -> u32 {
+    name.iter().fold(5381u32, |h, c| {
+        h.wrapping_mul(33)
+            .wrapping_add(u32::from(c.to_ascii_lowercase()))
+    })
+}
+
+/// Read the NUL-terminated string at `offset` in a string table.
+fn string_at(strings: &[u8], offset: u64) -> Result<&[u8]> {
+    let tail = strings
+        .get(offset as usize..)
+        .ok_or_else(|| Error::Malformed(format!("string offset {:#x} out of bounds", offset)))?;
+    Ok(&tail[..tail.iter().position(|b| *b == 0).unwrap_or(tail.len())])
+}
+
+/// Accumulates patches writing offsets and hashes into a table.
+struct Writer {
+    ctx: Ctx,
+    patches: Vec<Patch>,
+}
+
+impl Writer {
+    fn new(ctx: Ctx) -> Self {
+        Self {
+            ctx,
+            patches: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, offset: usize, value: u64, size: usize) -> Result<()> {
+        let location = Location {
+            offset,
+            size,
+            ctx: self.ctx,
+        };
+        self.patches.push(if size == 8 {
+            Patch::from_primitive(&location, value)?
+        } else {
+            Patch::from_primitive(&location, value as u32)?
+        });
+        Ok(())
+    }
+}
+
+impl AccelTable {
+    fn new(
+        section: &str,
+        offset: usize,
+        size: usize,
+        relocated: bool,
+        compressed: bool,
+        ctx: Ctx,
+    ) -> Self {
+        let kind = if section.ends_with("debug_names") {
+            AccelKind::DebugNames
+        } else {
+            AccelKind::Apple
+        };
+        Self {
+            section: section.to_string(),
+            kind,
+            offset,
+            size,
+            relocated,
+            compressed,
+            ctx,
+        }
+    }
+
+    /// Find the accelerator tables indexing names in an ELF binary.
+    pub fn from_elf(elf: &Elf) -> Vec<Self> {
+        let ctx = crate::elf::context_from_elf(elf);
+        let mut tables = Vec::new();
+        for (index, header) in elf.section_headers.iter().enumerate() {
+            if let Some(Ok(name @ ".debug_names")) | Some(Ok(name @ ".apple_names")) =
+                elf.shdr_strtab.get(header.sh_name)
+            {
+                let relocated = elf.section_headers.iter().any(|reloc| {
+                    (reloc.sh_type == SHT_REL || reloc.sh_type == SHT_RELA)
+                        && reloc.sh_info as usize == index
+                });
+                tables.push(Self::new(
+                    name,
+                    header.sh_offset as usize,
+                    header.sh_size as usize,
+                    relocated,
+                    crate::elf::is_compressed(header),
+                    ctx,
+                ));
+            }
+        }
+        tables
+    }
+
+    /// Find the accelerator tables indexing names in a Mach-O binary.
+    pub fn from_mach(mach: &MachO) -> Result<Vec<Self>> {
+        let ctx = crate::mach::context_from_macho(mach);
+        let mut tables = Vec::new();
+        for segment in &mach.segments {
+            for (section, _) in segment.sections()? {
+                if section.segname()? != "__DWARF" {
+                    continue;
+                }
+                let name = section.name()?;
+                if name == "__debug_names" || name == "__apple_names" {
+                    tables.push(Self::new(
+                        name,
+                        section.offset as usize,
+                        section.size as usize,
+                        false,
+                        false,
+                        ctx,
+                    ));
+                }
+            }
+        }
+        Ok(tables)
+    }
+
+    /// Construct patches recomputing the hashes in the table from the (already renamed) debug
+    /// string table `strings`.
+    ///
+    /// Entries are reordered within the table, so its size does not change.  Returns
+    /// `Error::Unsupported` if the table can't be updated in place, in which case it may be
+    /// invalidated instead.
+    pub fn rehash(&self, bytes: &[u8], strings: &[u8]) -> Result<Vec<Patch>> {
+        if self.compressed {
+            return Err(self.compressed_error());
+        }
+        if self.relocated {
+            return Err(Error::Unsupported(format!(
+                "{} contains relocated string offsets",
+                self.section
+            )));
+        }
+        let end = self
+            .offset
+            .checked_add(self.size)
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(|| Error::Malformed(format!("{} out of bounds", self.section)))?;
+        let table = &bytes[self.offset..end];
+        match self.kind {
+            AccelKind::DebugNames => self.rehash_debug_names(table, strings),
+            AccelKind::Apple => self.rehash_apple(table, strings),
+        }
+    }
+
+    /// Construct a patch invalidating the table, so debuggers ignore it and index the debug info
+    /// directly.
+    ///
+    /// Returns `Error::Unsupported` for compressed tables, which can't be patched at all.
+    pub fn invalidate(&self, bytes: &[u8]) -> Result<Patch> {
+        if self.compressed {
+            return Err(self.compressed_error());
+        }
+        let (offset, data) = match self.kind {
+            // Clear the version of the first name index.
+            AccelKind::DebugNames => {
+                let unit_length: u32 = bytes.pread_with(self.offset, self.ctx.le)?;
+                let version = if unit_length == 0xffff_ffff { 12 } else { 4 };
+                (self.offset + version, &[0u8; 2][..])
+            }
+            // Clear the magic number.
+            AccelKind::Apple => (self.offset, &[0u8; 4][..]),
+        };
+        let location = Location {
+            offset,
+            size: data.len(),
+            ctx: self.ctx,
+        };
+        Patch::from_bytes(&location, data)
+    }
+
+    fn compressed_error(&self) -> Error {
+        Error::Unsupported(format!("{} is compressed", self.section))
+    }
+
+    fn rehash_debug_names(&self, table: &[u8], strings: &[u8]) -> Result<Vec<Patch>> {
+        let le = self.ctx.le;
+        let mut writer = Writer::new(self.ctx);
+        let mut unit = 0;
+        while unit < table.len() {
+            let mut offset = unit;
+            let mut unit_length = u64::from(table.gread_with::<u32>(&mut offset, le)?);
+            let offset_size = if unit_length == 0xffff_ffff {
+                unit_length = table.gread_with::<u64>(&mut offset, le)?;
+                8
+            } else {
+                4
+            };
+            let next_unit = offset + unit_length as usize;
+            let version: u16 = table.gread_with(&mut offset, le)?;
+            if version != 5 {
+                return Err(Error::Unsupported(format!(
+                    "{} version {}",
+                    self.section, version
+                )));
+            }
+            offset += 2;
+            let comp_unit_count: u32 = table.gread_with(&mut offset, le)?;
+            let local_type_unit_count: u32 = table.gread_with(&mut offset, le)?;
+            let foreign_type_unit_count: u32 = table.gread_with(&mut offset, le)?;
+            let bucket_count: u32 = table.gread_with(&mut offset, le)?;
+            let name_count: u32 = table.gread_with(&mut offset, le)?;
+            let _abbrev_table_size: u32 = table.gread_with(&mut offset, le)?;
+            let augmentation_string_size: u32 = table.gread_with(&mut offset, le)?;
+            offset += augmentation_string_size as usize;
+            offset += (comp_unit_count + local_type_unit_count) as usize * offset_size;
+            offset += foreign_type_unit_count as usize * 8;
+
+            let buckets = offset;
+            let hashes = buckets + bucket_count as usize * 4;
+            let string_offsets = hashes + name_count as usize * 4;
+            let entry_offsets = string_offsets + name_count as usize * offset_size;
+            if entry_offsets + name_count as usize * offset_size > next_unit {
+                return Err(Error::Malformed(format!("{} too small", self.section)));
+            }
+
+            // Without buckets there is no hash table, and names are searched linearly.
+            if bucket_count != 0 {
+                let read_offset = |offset: usize| -> Result<u64> {
+                    Ok(if offset_size == 8 {
+                        table.pread_with::<u64>(offset, le)?
+                    } else {
+                        u64::from(table.pread_with::<u32>(offset, le)?)
+                    })
+                };
+                let mut names = Vec::new();
+                for i in 0..name_count as usize {
+                    let string_offset = read_offset(string_offsets + i * offset_size)?;
+                    let entry_offset = read_offset(entry_offsets + i * offset_size)?;
+                    let hash = case_folding_djb_hash(string_at(strings, string_offset)?);
+                    names.push((hash, string_offset, entry_offset));
+                }
+                names.sort_by_key(|(hash, _, _)| (hash % bucket_count, *hash));
+
+                let base = self.offset;
+                for bucket in 0..bucket_count {
+                    let index = names
+                        .iter()
+                        .position(|(hash, _, _)| hash % bucket_count == bucket)
+                        .map(|i| i + 1)
+                        .unwrap_or(0);
+                    writer.write(base + buckets + bucket as usize * 4, index as u64, 4)?;
+                }
+                for (i, (hash, string_offset, entry_offset)) in names.into_iter().enumerate() {
+                    writer.write(base + hashes + i * 4, u64::from(hash), 4)?;
+                    writer.write(
+                        base + string_offsets + i * offset_size,
+                        string_offset,
+                        offset_size,
+                    )?;
+                    writer.write(
+                        base + entry_offsets + i * offset_size,
+                        entry_offset,
+                        offset_size,
+                    )?;
+                }
+            }
+            unit = next_unit;
+        }
+        Ok(writer.patches)
+    }
+
+    fn rehash_apple(&self, table: &[u8], strings: &[u8]) -> Result<Vec<Patch>> {
+        let le = self.ctx.le;
+        let mut offset = 0;
+        let magic: u32 = table.gread_with(&mut offset, le)?;
+        let _version: u16 = table.gread_with(&mut offset, le)?;
+        let hash_function: u16 = table.gread_with(&mut offset, le)?;
+        let bucket_count: u32 = table.gread_with(&mut offset, le)?;
+        let hashes_count: u32 = table.gread_with(&mut offset, le)?;
+        let header_data_length: u32 = table.gread_with(&mut offset, le)?;
+        if magic != APPLE_HASH_MAGIC || hash_function != APPLE_HASH_FUNCTION_DJB {
+            return Err(Error::Unsupported(format!(
+                "{} has an unknown format",
+                self.section
+            )));
+        }
+        let _die_offset_base: u32 = table.gread_with(&mut offset, le)?;
+        let atom_count: u32 = table.gread_with(&mut offset, le)?;
+        let mut atoms_size = 0;
+        for _ in 0..atom_count {
+            let _atom_type: u16 = table.gread_with(&mut offset, le)?;
+            let form: u16 = table.gread_with(&mut offset, le)?;
+            atoms_size += match form {
+                0x0b | 0x0c | 0x11 => 1, // DW_FORM_data1, DW_FORM_flag, DW_FORM_ref1
+                0x05 | 0x12 => 2,        // DW_FORM_data2, DW_FORM_ref2
+                0x06 | 0x13 => 4,        // DW_FORM_data4, DW_FORM_ref4
+                0x07 | 0x14 => 8,        // DW_FORM_data8, DW_FORM_ref8
+                _ => {
+                    return Err(Error::Unsupported(format!(
+                        "{} contains variable size form {:#x}",
+                        self.section, form
+                    )))
+                }
+            };
+        }
+
+        let buckets = 20 + header_data_length as usize;
+        let hashes = buckets + bucket_count as usize * 4;
+        let offsets = hashes + hashes_count as usize * 4;
+        if bucket_count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut entries = Vec::new();
+        for i in 0..hashes_count as usize {
+            let mut data: usize = table.pread_with::<u32>(offsets + i * 4, le)? as usize;
+            let string_offset: u32 = table.gread_with(&mut data, le)?;
+            let count: u32 = table.gread_with(&mut data, le)?;
+            data += count as usize * atoms_size;
+            // Each hash must name exactly one string for the data to stay with its hash.
+            let terminator: u32 = table.pread_with(data, le)?;
+            if terminator != 0 {
+                return Err(Error::Unsupported(format!(
+                    "{} contains hash collisions",
+                    self.section
+                )));
+            }
+            let hash = djb_hash(string_at(strings, u64::from(string_offset))?);
+            let data_offset: u32 = table.pread_with(offsets + i * 4, le)?;
+            entries.push((hash, data_offset));
+        }
+        entries.sort_by_key(|(hash, _)| (hash % bucket_count, *hash));
+
+        let mut writer = Writer::new(self.ctx);
+        let base = self.offset;
+        for bucket in 0..bucket_count {
+            let index = entries
+                .iter()
+                .position(|(hash, _)| hash % bucket_count == bucket)
+                .map(|i| i as u32)
+                .unwrap_or(APPLE_EMPTY_BUCKET);
+            writer.write(base + buckets + bucket as usize * 4, u64::from(index), 4)?;
+        }
+        for (i, (hash, data_offset)) in entries.into_iter().enumerate() {
+            writer.write(base + hashes + i * 4, u64::from(hash), 4)?;
+            writer.write(base + offsets + i * 4, u64::from(data_offset), 4)?;
+        }
+        Ok(writer.patches)
+    }
+}
@@ -1,15 +1,31 @@
 //! Manipulate ELF binaries.
 
 use crate::error::{Error, Result};
-use crate::patch::{Location, Rooted};
+use crate::patch::{Location, Patch, Rooted};
+use crate::strtab::{string_at, CompactStrtab, RebuiltStrtab, StrtabIter};
 use goblin::container::{Container, Ctx, Endian};
-use goblin::elf::section_header::{SHT_DYNSYM, SHT_SYMTAB};
-use goblin::elf::sym::Sym;
+use goblin::elf::compression_header::CompressionHeader;
+use goblin::elf::dynamic::{
+    Dyn, DT_GNU_HASH, DT_HASH, DT_NULL, DT_SONAME, DT_STRSZ, DT_STRTAB, DT_SYMTAB, DT_VERDEF,
+    DT_VERDEFNUM, DT_VERNEED, DT_VERNEEDNUM, DT_VERSYM,
+};
+use goblin::elf::header::{EM_ARM, EM_MIPS, ET_REL};
+use goblin::elf::note::NT_GNU_BUILD_ID;
+use goblin::elf::program_header::{ProgramHeader, PF_R, PF_W, PF_X, PT_DYNAMIC, PT_LOAD, PT_PHDR};
+use goblin::elf::section_header::{
+    SHF_ALLOC, SHF_COMPRESSED, SHF_EXECINSTR, SHF_WRITE, SHN_LORESERVE, SHN_UNDEF, SHN_XINDEX,
+    SHT_DYNAMIC, SHT_DYNSYM, SHT_GNU_HASH, SHT_GNU_VERDEF, SHT_GNU_VERNEED, SHT_GNU_VERSYM,
+    SHT_GROUP, SHT_HASH, SHT_NOBITS, SHT_NULL, SHT_REL, SHT_RELA, SHT_SYMTAB, SHT_SYMTAB_SHNDX,
+};
+use goblin::elf::sym::{Sym, STB_LOCAL, STT_FILE, STT_FUNC, STT_SECTION};
 use goblin::elf::{Elf, SectionHeader};
-use scroll::ctx::TryFromCtx;
-use scroll::Pread;
+use goblin::strtab::Strtab;
+use scroll::ctx::{SizeWith, TryFromCtx, TryIntoCtx};
+use scroll::{Pread, Pwrite};
+use std::cell::RefCell;
+use std::collections::HashSet;
 
-fn context_from_elf(elf: &Elf) -> Ctx {
+pub(crate) fn context_from_elf(elf: &Elf) -> Ctx {
     let container = if elf.is_64 {
         Container::Big
     } else {
@@ -23,6 +39,28 @@ fn context_from_elf(elf: &Elf) -> Ctx {
     Ctx::new(container, endian)
 }
 
+/// Parse an ELF binary.
+///
+/// Unlike `Elf::parse`, this reads the section names of binaries with `SHN_LORESERVE` or more
+/// sections, which store the index of the section name table in the `sh_link` field of the
+/// first section header instead of `e_shstrndx`.
+pub fn parse(bytes: &[u8]) -> Result<Elf<'_>> {
+    let mut elf = Elf::parse(bytes)?;
+    if elf.header.e_shstrndx == SHN_XINDEX as u16 {
+        let header = elf
+            .section_headers
+            .first()
+            .and_then(|first| elf.section_headers.get(first.sh_link as usize))
+            .ok_or_else(|| {
+                Error::Malformed("extended section name table index out of range".to_string())
+            })?;
+        header.check_size(bytes.len())?;
+        elf.shdr_strtab =
+            Strtab::parse(bytes, header.sh_offset as usize, header.sh_size as usize, 0)?;
+    }
+    Ok(elf)
+}
+
 /// An iterator over an ELF symbol table.
 pub struct SymtabIter<'a> {
     bytes: &'a [u8],
@@ -32,6 +70,7 @@ pub struct SymtabIter<'a> {
     step: usize,
     count: usize,
     index: usize,
+    extended_indexes: ExtendedIndexes<'a>,
 }
 
 impl<'a> SymtabIter<'a> {
@@ -55,21 +94,35 @@ impl<'a> SymtabIter<'a> {
         if (header.sh_link as usize) > headers.len() {
             return Err(Error::Malformed("sh_link too large".to_string()));
         }
+        let extended_indexes = headers
+            .iter()
+            .find(|shndx| {
+                shndx.sh_type == SHT_SYMTAB_SHNDX
+                    && headers.get(shndx.sh_link as usize) == Some(header)
+            })
+            .map_or_else(ExtendedIndexes::default, |shndx| ExtendedIndexes {
+                bytes,
+                ctx,
+                offset: shndx.sh_offset as usize,
+                count: (shndx.sh_size / 4) as usize,
+            });
         Ok(Self {
             bytes,
             ctx,
             symoff: header.sh_offset as usize,
             stroff: headers[header.sh_link as usize].sh_offset as usize,
             step: header.sh_entsize as usize,
-            count: if header.sh_entsize == 0 {
-                0
-            } else {
-                header.sh_size / header.sh_entsize
-            } as usize,
+            count: header.sh_size.checked_div(header.sh_entsize).unwrap_or(0) as usize,
             index: 0,
+            extended_indexes,
         })
     }
 
+    /// The extended section indexes of the symbols in this table.
+    pub fn extended_indexes(&self) -> ExtendedIndexes<'a> {
+        self.extended_indexes.clone()
+    }
+
     /// Construct a `SymtabIter` from an ELF binary's static symbol table.
     ///
     /// The static symbol table is in the `SHT_SYMTAB` section.
@@ -129,7 +182,7 @@ impl<'a> std::iter::Iterator for SymtabIter<'a> {
                     Rooted::new(location, sym)
                 };
                 let name = if sym.st_name != 0 {
-                    let offset = self.stroff + sym.st_name as usize;
+                    let offset = self.stroff + sym.st_name;
                     let name: &str = self.bytes.pread(offset)?;
                     let location = Location {
                         offset,
@@ -145,3 +198,2180 @@ impl<'a> std::iter::Iterator for SymtabIter<'a> {
         }
     }
 }
+
+/// The extended section indexes of a symbol table, stored in its `SHT_SYMTAB_SHNDX` section.
+///
+/// Symbols defined in sections with indexes of `SHN_LORESERVE` or greater, which are common in
+/// objects built with `-ffunction-sections`, have `st_shndx` set to `SHN_XINDEX` and their
+/// section index stored in this table instead.  Symbols are identified by their index in the
+/// symbol table.
+#[derive(Clone, Default)]
+pub struct ExtendedIndexes<'a> {
+    bytes: &'a [u8],
+    ctx: Ctx,
+    offset: usize,
+    count: usize,
+}
+
+impl<'a> ExtendedIndexes<'a> {
+    /// The extended section index entry of a symbol.
+    ///
+    /// Returns `None` if the symbol table has no `SHT_SYMTAB_SHNDX` section.
+    pub fn get(&self, symbol: usize) -> Result<Option<Rooted<u32>>> {
+        if self.count == 0 {
+            return Ok(None);
+        }
+        if symbol >= self.count {
+            return Err(Error::Malformed(
+                "symbol index out of range of SHT_SYMTAB_SHNDX".to_string(),
+            ));
+        }
+        let offset = self.offset + symbol * 4;
+        let value: u32 = self.bytes.pread_with(offset, self.ctx.le)?;
+        let location = Location {
+            offset,
+            size: 4,
+            ctx: self.ctx,
+        };
+        Ok(Some(Rooted::new(location, value)))
+    }
+
+    /// The index of the section a symbol is defined in, or a reserved index such as `SHN_UNDEF`.
+    pub fn section_index(&self, symbol: usize, sym: &Sym) -> Result<usize> {
+        if sym.st_shndx != SHN_XINDEX as usize {
+            return Ok(sym.st_shndx);
+        }
+        match self.get(symbol)? {
+            Some(index) => Ok(index.value as usize),
+            None => Err(Error::Malformed(
+                "SHN_XINDEX symbol without SHT_SYMTAB_SHNDX section".to_string(),
+            )),
+        }
+    }
+
+    /// Set the section index of a symbol.
+    ///
+    /// Returns the symbol with `st_shndx` updated, and a patch to the extended section index
+    /// entry if it changed.  Fails if the index requires an `SHT_SYMTAB_SHNDX` section but the
+    /// symbol table doesn't have one, since one can't be added in place.
+    pub fn set_section_index(
+        &self,
+        symbol: usize,
+        sym: Sym,
+        index: usize,
+    ) -> Result<(Sym, Option<Patch>)> {
+        let extended = index >= SHN_LORESERVE as usize;
+        let (st_shndx, entry) = if extended {
+            (SHN_XINDEX as usize, index as u32)
+        } else {
+            (index, 0)
+        };
+        let patch = match self.get(symbol)? {
+            Some(current) if current.value != entry => Some(current.patch_with_primitive(entry)?),
+            Some(_) => None,
+            None if extended => {
+                return Err(Error::Unsupported(format!(
+                    "section index {} requires an SHT_SYMTAB_SHNDX section",
+                    index
+                )))
+            }
+            None => None,
+        };
+        Ok((Sym { st_shndx, ..sym }, patch))
+    }
+}
+
+/// `STT_ARM_TFUNC`, the symbol type older ARM toolchains gave Thumb functions.
+const STT_ARM_TFUNC: u8 = 13;
+
+/// Whether a symbol refers to Thumb code, or `None` if the object is not 32-bit ARM.
+///
+/// Thumb functions have bit 0 of their value set, and older toolchains also gave them the type
+/// `STT_ARM_TFUNC`.  Edits must preserve both, or branches to the symbol switch to the wrong
+/// instruction set.
+pub fn is_thumb(elf: &Elf, sym: &Sym) -> Option<bool> {
+    if elf.header.e_machine != EM_ARM {
+        return None;
+    }
+    Some(match sym.st_type() {
+        STT_ARM_TFUNC => true,
+        STT_FUNC => sym.st_value & 1 != 0,
+        _ => false,
+    })
+}
+
+/// Split a symbol name such as `foo@@VERS_1` or `foo@VERS_1` into the name and its version
+/// suffix, including the `@` characters, which is empty for unversioned names.
+///
+/// GNU tools write versioned names in the `.symtab` of linked binaries and of relocatable objects
+/// using `.symver`, while `.dynsym` names are unversioned.
+pub fn split_version(name: &str) -> (&str, &str) {
+    match name.find('@') {
+        Some(index) if index > 0 => name.split_at(index),
+        _ => (name, ""),
+    }
+}
+
+/// Find a section header by name.
+pub fn section_by_name<'a>(elf: &'a Elf, name: &str) -> Option<&'a SectionHeader> {
+    elf.section_headers
+        .iter()
+        .find(|header| matches!(elf.shdr_strtab.get(header.sh_name), Some(Ok(n)) if n == name))
+}
+
+/// Find a section header by name, returning its index and the header rooted for patching.
+pub fn rooted_section_by_name(elf: &Elf, name: &str) -> Option<(usize, Rooted<SectionHeader>)> {
+    let (index, header) = elf.section_headers.iter().enumerate().find(
+        |(_, header)| matches!(elf.shdr_strtab.get(header.sh_name), Some(Ok(n)) if n == name),
+    )?;
+    Some((
+        index,
+        Rooted::new(
+            Location {
+                offset: elf.header.e_shoff as usize + index * elf.header.e_shentsize as usize,
+                size: elf.header.e_shentsize as usize,
+                ctx: context_from_elf(elf),
+            },
+            header.clone(),
+        ),
+    ))
+}
+
+/// A section of an ELF binary.
+pub struct Section<'a> {
+    /// The section name, if it can be read
+    pub name: Option<String>,
+
+    /// The section header
+    pub header: Rooted<SectionHeader>,
+
+    /// The contents of the section, which are empty for `SHT_NOBITS` sections
+    pub contents: Rooted<&'a [u8]>,
+}
+
+/// Read the sections of an ELF binary.
+pub fn sections<'a>(bytes: &'a [u8], elf: &Elf) -> Result<Vec<Section<'a>>> {
+    let ctx = context_from_elf(elf);
+    let mut sections = Vec::new();
+    for (index, header) in elf.section_headers.iter().enumerate() {
+        let (offset, size) = if header.sh_type == SHT_NOBITS {
+            (header.sh_offset as usize, 0)
+        } else {
+            (header.sh_offset as usize, header.sh_size as usize)
+        };
+        let contents = offset
+            .checked_add(size)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or_else(|| Error::Malformed(format!("section {} out of bounds", index)))?;
+        sections.push(Section {
+            name: elf
+                .shdr_strtab
+                .get(header.sh_name)
+                .and_then(|name| name.ok())
+                .map(str::to_string),
+            header: Rooted::new(
+                Location {
+                    offset: elf.header.e_shoff as usize + index * elf.header.e_shentsize as usize,
+                    size: elf.header.e_shentsize as usize,
+                    ctx,
+                },
+                header.clone(),
+            ),
+            contents: Rooted::new(Location { offset, size, ctx }, contents),
+        });
+    }
+    Ok(sections)
+}
+
+/// Check whether a section is compressed (`SHF_COMPRESSED`), like the debug sections of objects
+/// built with `-gz`.
+pub fn is_compressed(header: &SectionHeader) -> bool {
+    header.sh_flags & u64::from(SHF_COMPRESSED) != 0
+}
+
+/// Read the compression header at the start of a compressed section, which records the size and
+/// alignment of the uncompressed contents.
+///
+/// Returns `None` if the section isn't compressed.
+pub fn compression_header(
+    bytes: &[u8],
+    elf: &Elf,
+    header: &SectionHeader,
+) -> Result<Option<CompressionHeader>> {
+    if !is_compressed(header) {
+        return Ok(None);
+    }
+    Ok(Some(bytes.pread_with(
+        header.sh_offset as usize,
+        context_from_elf(elf),
+    )?))
+}
+
+/// Rebuild the string table of `.symtab` at the end of the file, so symbols can be renamed to
+/// longer names.
+///
+/// Returns `None` if there is no `.symtab`.  String tables loaded at run time (`SHF_ALLOC`) can't
+/// be moved, and return `Error::Unsupported`.
+pub fn rebuild_strtab(bytes: &[u8], elf: &Elf) -> Result<Option<RebuiltStrtab>> {
+    let symtab = match elf
+        .section_headers
+        .iter()
+        .find(|header| header.sh_type == SHT_SYMTAB)
+    {
+        Some(symtab) => symtab,
+        None => return Ok(None),
+    };
+    let index = symtab.sh_link as usize;
+    let header = elf
+        .section_headers
+        .get(index)
+        .ok_or_else(|| Error::Malformed("sh_link too large".to_string()))?;
+    if header.sh_flags & u64::from(SHF_ALLOC) != 0 {
+        return Err(Error::Unsupported(
+            "The string table of .symtab is loaded at run time, so it can't be rebuilt".to_string(),
+        ));
+    }
+    let ctx = context_from_elf(elf);
+    let location = Location {
+        offset: elf.header.e_shoff as usize + index * elf.header.e_shentsize as usize,
+        size: elf.header.e_shentsize as usize,
+        ctx,
+    };
+    RebuiltStrtab::elf(bytes, Rooted::new(location, header.clone()), ctx).map(Some)
+}
+
+/// Construct patches compacting the string table of `.symtab` in place.
+///
+/// The table is rebuilt with only the names symbols still refer to, such as after stripping
+/// them, storing duplicate names once and merging names into the tails of longer ones, like
+/// `CompactStrtab`.  If the table is also the section name table, section names are kept too.
+/// The freed bytes at the end of the table are cleared, and `sh_size` is reduced to match.
+///
+/// Returns the original and compacted sizes with the patches, which are empty if there is no
+/// `.symtab` or the table can't be made smaller.  String tables loaded at run time
+/// (`SHF_ALLOC`) return `Error::Unsupported`.
+pub fn compact_strtab(bytes: &[u8], elf: &Elf) -> Result<(usize, usize, Vec<Patch>)> {
+    let symtab = match elf
+        .section_headers
+        .iter()
+        .find(|header| header.sh_type == SHT_SYMTAB)
+    {
+        Some(symtab) => symtab,
+        None => return Ok((0, 0, Vec::new())),
+    };
+    let link = symtab.sh_link as usize;
+    let header = elf
+        .section_headers
+        .get(link)
+        .ok_or_else(|| Error::Malformed("sh_link too large".to_string()))?;
+    if header.sh_flags & u64::from(SHF_ALLOC) != 0 {
+        return Err(Error::Unsupported(
+            "The string table of .symtab is loaded at run time, so it can't be compacted"
+                .to_string(),
+        ));
+    }
+    let (offset, size) = (header.sh_offset as usize, header.sh_size as usize);
+    let table = offset
+        .checked_add(size)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| Error::Malformed("string table out of bounds".to_string()))?;
+    let section_names = elf.header.e_shstrndx as usize == link;
+
+    let mut symbols = Vec::new();
+    if let Some(iter) = SymtabIter::symtab_from_elf(bytes, elf)? {
+        for item in iter {
+            let (_, sym) = item?;
+            symbols.push((string_at(table, sym.st_name)?, sym));
+        }
+    }
+    let mut names = symbols.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    if section_names {
+        for header in &elf.section_headers {
+            names.push(string_at(table, header.sh_name)?);
+        }
+    }
+    let compacted = CompactStrtab::new(string_at(table, 0)?, names);
+    if compacted.len() >= size {
+        return Ok((size, size, Vec::new()));
+    }
+
+    let ctx = context_from_elf(elf);
+    let mut patches = vec![compacted.patch(offset, size, ctx)?];
+    for (name, sym) in symbols {
+        let st_name = compacted.offset(name);
+        if st_name != sym.st_name {
+            patches.push(sym.patch_with(Sym {
+                st_name,
+                ..sym.value
+            })?);
+        }
+    }
+    for (index, header) in elf.section_headers.iter().enumerate() {
+        let mut new_header = header.clone();
+        if section_names {
+            new_header.sh_name = compacted.offset(string_at(table, header.sh_name)?);
+        }
+        if index == link {
+            new_header.sh_size = compacted.len() as u64;
+        }
+        if new_header.sh_name != header.sh_name || new_header.sh_size != header.sh_size {
+            let location = Location {
+                offset: elf.header.e_shoff as usize + index * elf.header.e_shentsize as usize,
+                size: elf.header.e_shentsize as usize,
+                ctx,
+            };
+            patches.push(Rooted::new(location, header.clone()).patch_with(new_header)?);
+        }
+    }
+    Ok((size, compacted.len(), patches))
+}
+
+/// The index of the section name table, which binaries with `SHN_LORESERVE` or more sections
+/// store in the `sh_link` field of the first section header.
+fn shstrndx(elf: &Elf) -> usize {
+    match elf.header.e_shstrndx {
+        index if index == SHN_XINDEX as u16 => elf
+            .section_headers
+            .first()
+            .map_or(0, |first| first.sh_link as usize),
+        index => index as usize,
+    }
+}
+
+/// The alignment of the section header table written by `rebuild_tables`.
+const SECTION_HEADERS_ALIGN: usize = 8;
+
+/// Construct patches rebuilding the symbol string table, the symbol table and the section header
+/// table of an ELF binary, and laying out the sections not loaded at run time again after the
+/// loaded ones.
+///
+/// The string table of `.symtab` is rebuilt with only the names symbols refer to, like
+/// `compact_strtab`, and the symbol names are pointed into it.  Sections are written in order,
+/// each aligned to `sh_addralign`, followed by the section header table, and the binary ends
+/// there, so string and symbol tables appended by `rebuild_strtab` or `add_symbols` are moved into
+/// place and the tables they replaced are dropped.  In relocatable objects every section is laid
+/// out again.  In linked binaries, the sections loaded at run time and any section before the end
+/// of the loaded segments keep their offsets, and the string table of `.symtab` is only rebuilt
+/// if it isn't loaded at run time.
+pub fn rebuild_tables(bytes: &[u8], elf: &Elf) -> Result<Vec<Patch>> {
+    let ctx = context_from_elf(elf);
+    let sections = sections(bytes, elf)?;
+    let mut headers: Vec<SectionHeader> = sections
+        .iter()
+        .map(|section| section.header.value.clone())
+        .collect();
+    let mut contents: Vec<Vec<u8>> = sections
+        .iter()
+        .map(|section| section.contents.to_vec())
+        .collect();
+
+    let symtab = sections
+        .iter()
+        .position(|section| section.header.sh_type == SHT_SYMTAB);
+    if let Some(symtab) = symtab {
+        let link = headers[symtab].sh_link as usize;
+        let strtab = sections
+            .get(link)
+            .ok_or_else(|| Error::Malformed("sh_link too large".to_string()))?;
+        if strtab.header.sh_flags & u64::from(SHF_ALLOC) == 0 {
+            let table = *strtab.contents;
+            let section_names = shstrndx(elf) == link;
+            let mut symbols = Vec::new();
+            if let Some(iter) = SymtabIter::symtab_from_elf(bytes, elf)? {
+                for item in iter {
+                    let (_, sym) = item?;
+                    symbols.push((string_at(table, sym.st_name)?, sym.value));
+                }
+            }
+            let mut names = symbols.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            if section_names {
+                for header in &headers {
+                    names.push(string_at(table, header.sh_name)?);
+                }
+            }
+            let compacted = CompactStrtab::new(string_at(table, 0)?, names);
+            let step = headers[symtab].sh_entsize as usize;
+            let size = Sym::size(ctx.container);
+            if step < size {
+                return Err(Error::Malformed("sh_entsize too small".to_string()));
+            }
+            for (index, (name, sym)) in symbols.into_iter().enumerate() {
+                let sym = Sym {
+                    st_name: compacted.offset(name),
+                    ..sym
+                };
+                sym.try_into_ctx(
+                    &mut contents[symtab][index * step..index * step + size],
+                    ctx,
+                )?;
+            }
+            if section_names {
+                for header in headers.iter_mut() {
+                    header.sh_name = compacted.offset(string_at(table, header.sh_name)?);
+                }
+            }
+            contents[link] = compacted.bytes().to_vec();
+            headers[link].sh_size = compacted.len() as u64;
+        }
+    }
+
+    // Everything before the end of the headers and the loaded segments stays in place
+    let linked = elf.header.e_type != ET_REL;
+    let mut fixed_end = (elf.header.e_ehsize as usize).max(
+        elf.header.e_phoff as usize + elf.header.e_phnum as usize * elf.header.e_phentsize as usize,
+    );
+    for header in &elf.program_headers {
+        fixed_end = fixed_end.max((header.p_offset + header.p_filesz) as usize);
+    }
+    let loaded = |header: &SectionHeader| linked && header.sh_flags & u64::from(SHF_ALLOC) != 0;
+    for section in &sections {
+        if loaded(&section.header) && section.header.sh_type != SHT_NOBITS {
+            fixed_end = fixed_end.max(section.header.sh_offset as usize + section.contents.len());
+        }
+    }
+
+    let mut patches = Vec::new();
+    let mut tail = Vec::new();
+    for (index, section) in sections.iter().enumerate().skip(1) {
+        let header = &mut headers[index];
+        let (offset, size) = (section.header.sh_offset as usize, section.contents.len());
+        if header.sh_type == SHT_NULL {
+            continue;
+        }
+        if loaded(header) || (linked && offset + size <= fixed_end && header.sh_type != SHT_NOBITS)
+        {
+            // Rebuilt tables are never larger than the originals, so they are written in place
+            if contents[index].len() > size {
+                return Err(Error::Unsupported(format!(
+                    "Section {} can't grow in place",
+                    index
+                )));
+            }
+            if contents[index][..] != **section.contents {
+                let mut data = contents[index].clone();
+                data.resize(size, 0);
+                patches.push(section.contents.patch_with_bytes(&data)?);
+            }
+            continue;
+        }
+        let align = (header.sh_addralign as usize).max(1);
+        let position = (fixed_end + tail.len()).div_ceil(align) * align;
+        tail.resize(position - fixed_end, 0);
+        header.sh_offset = position as u64;
+        if header.sh_type != SHT_NOBITS {
+            tail.extend_from_slice(&contents[index]);
+        }
+    }
+
+    let shentsize = elf.header.e_shentsize as usize;
+    let shoff = (fixed_end + tail.len() + SECTION_HEADERS_ALIGN - 1) & !(SECTION_HEADERS_ALIGN - 1);
+    tail.resize(shoff - fixed_end, 0);
+    for header in headers {
+        let mut entry = vec![0; shentsize];
+        header.try_into_ctx(&mut entry[..], ctx)?;
+        tail.extend_from_slice(&entry);
+    }
+    patches.push(Patch::ending_object(fixed_end, tail));
+
+    // e_shoff follows e_entry and e_phoff, which are words
+    let word = if elf.is_64 { 8 } else { 4 };
+    let location = Location {
+        offset: 0x18 + 2 * word,
+        size: word,
+        ctx,
+    };
+    patches.push(match elf.is_64 {
+        true => Patch::from_primitive(&location, shoff as u64)?,
+        false => Patch::from_primitive(&location, shoff as u32)?,
+    });
+    Ok(patches)
+}
+
+/// The dynamic section entries holding the address of a section.
+const SECTION_ADDRESS_TAGS: &[u64] = &[
+    DT_HASH,
+    DT_GNU_HASH,
+    DT_STRTAB,
+    DT_SYMTAB,
+    DT_VERSYM,
+    DT_VERDEF,
+    DT_VERNEED,
+];
+
+/// Construct patches moving sections loaded at run time to a new loadable segment at the end of
+/// a linked ELF binary, so they can grow.
+///
+/// `moved` gives the new contents of each moved section by index.  The program header table
+/// needs an entry for the segment, so it is moved to the start of the segment, followed by the
+/// sections, each aligned to `sh_addralign`.  The section headers and the entries of the dynamic
+/// section (such as `DT_STRTAB` and `DT_STRSZ`) are pointed at the new locations, and so is
+/// `PT_PHDR`, and `PT_DYNAMIC` if the dynamic section is moved.  The old contents are left in
+/// place, unreferenced.  Relocatable objects, which have no segments, return
+/// `Error::Unsupported`.
+pub fn load_sections(bytes: &[u8], elf: &Elf, moved: &[(usize, Vec<u8>)]) -> Result<Vec<Patch>> {
+    let sections = sections(bytes, elf)?;
+    let mut loaded = Vec::new();
+    for (index, contents) in moved {
+        let section = sections
+            .get(*index)
+            .ok_or_else(|| Error::Malformed(format!("section {} out of range", index)))?;
+        loaded.push(Loaded {
+            index: Some(*index),
+            header: section.header.value.clone(),
+            contents: contents.clone(),
+            tag: None,
+        });
+    }
+    load(bytes, elf, &sections, loaded, &[])
+}
+
+/// A section placed in the segment added by `load`.
+struct Loaded {
+    /// The index of the moved section, or `None` for a new one
+    index: Option<usize>,
+
+    /// The header of the section, whose location and size are set by `load`
+    header: SectionHeader,
+    contents: Vec<u8>,
+
+    /// The tag of the dynamic section entry pointed at a new section
+    tag: Option<u64>,
+}
+
+/// Place `loaded` in a new loadable segment, like `load_sections`.
+///
+/// New sections are named by `names`, in order, and appended to the section header table, which
+/// is copied to the end of the file with the section name table.
+fn load(
+    bytes: &[u8],
+    elf: &Elf,
+    sections: &[Section<'_>],
+    mut loaded: Vec<Loaded>,
+    names: &[&str],
+) -> Result<Vec<Patch>> {
+    if elf.header.e_type == ET_REL {
+        return Err(Error::Unsupported(
+            "Relocatable objects have no segments to load sections with".to_string(),
+        ));
+    }
+    let ctx = context_from_elf(elf);
+    let loads = elf
+        .program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD);
+    let page = loads
+        .clone()
+        .map(|header| header.p_align)
+        .max()
+        .unwrap_or(0)
+        .max(1) as usize;
+    let end = loads
+        .map(|header| header.p_vaddr + header.p_memsz)
+        .max()
+        .ok_or_else(|| Error::Unsupported("The binary has no loadable segments".to_string()))?;
+    let offset = bytes.len().div_ceil(page) * page;
+    let vaddr = (end as usize).div_ceil(page) * page;
+
+    // The new location of each section, after the program header table
+    let phentsize = elf.header.e_phentsize as usize;
+    let phnum = elf.program_headers.len() + 1;
+    let mut size = phnum * phentsize;
+    let mut flags = PF_R;
+    for section in loaded.iter_mut() {
+        let header = &mut section.header;
+        if header.sh_flags & u64::from(SHF_ALLOC) == 0 {
+            return Err(Error::Unsupported(format!(
+                "Section {} isn't loaded at run time",
+                section
+                    .index
+                    .map_or("added".to_string(), |index| index.to_string())
+            )));
+        }
+        if header.sh_flags & u64::from(SHF_WRITE) != 0 {
+            flags |= PF_W;
+        }
+        if header.sh_flags & u64::from(SHF_EXECINSTR) != 0 {
+            flags |= PF_X;
+        }
+        let align = (header.sh_addralign as usize).max(1);
+        let position = size.div_ceil(align) * align;
+        size = position + section.contents.len();
+        header.sh_offset = (offset + position) as u64;
+        header.sh_addr = (vaddr + position) as u64;
+        header.sh_size = section.contents.len() as u64;
+    }
+
+    // Entries of the dynamic section pointing to moved sections are updated, wherever it is
+    let mut patches = Vec::new();
+    let new_address = |loaded: &[Loaded], address: u64| {
+        loaded
+            .iter()
+            .find(|section| {
+                section
+                    .index
+                    .is_some_and(|index| sections[index].header.sh_addr == address)
+            })
+            .map(|section| section.header.clone())
+    };
+    let dynamic_address = elf
+        .program_headers
+        .iter()
+        .find(|header| header.p_type == PT_DYNAMIC)
+        .and_then(|header| new_address(&loaded, header.p_vaddr));
+    if let Some(dynamic) = sections
+        .iter()
+        .position(|section| section.header.sh_type == SHT_DYNAMIC)
+    {
+        let position = loaded
+            .iter()
+            .position(|section| section.index == Some(dynamic));
+        let mut entries = match position {
+            Some(position) => loaded[position].contents.clone(),
+            None => sections[dynamic].contents.to_vec(),
+        };
+        let step = Dyn::size_with(&ctx);
+        let strtab = entries
+            .chunks_exact(step)
+            .map(|entry| entry.pread_with::<Dyn>(0, ctx))
+            .find(|entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |entry| entry.d_tag == DT_STRTAB)
+            })
+            .transpose()?
+            .and_then(|entry| new_address(&loaded, entry.d_val));
+        for entry in entries.chunks_exact_mut(step) {
+            let mut dyn_: Dyn = entry.pread_with(0, ctx)?;
+            let added = loaded
+                .iter()
+                .find(|section| section.tag == Some(dyn_.d_tag));
+            match (dyn_.d_tag, added) {
+                (DT_NULL, _) => break,
+                (_, Some(section)) => dyn_.d_val = section.header.sh_addr,
+                (DT_STRSZ, None) => match &strtab {
+                    Some(header) => dyn_.d_val = header.sh_size,
+                    None => continue,
+                },
+                (tag, None) if SECTION_ADDRESS_TAGS.contains(&tag) => {
+                    match new_address(&loaded, dyn_.d_val) {
+                        Some(header) => dyn_.d_val = header.sh_addr,
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            }
+            entry.pwrite_with(dyn_, 0, ctx)?;
+        }
+        match position {
+            Some(position) => loaded[position].contents = entries,
+            None if entries[..] != **sections[dynamic].contents => {
+                patches.push(sections[dynamic].contents.patch_with_bytes(&entries)?)
+            }
+            None => {}
+        }
+    }
+
+    let mut program_headers = Vec::with_capacity(phnum);
+    for header in &elf.program_headers {
+        let mut header = header.clone();
+        if header.p_type == PT_PHDR {
+            header.p_offset = offset as u64;
+            header.p_vaddr = vaddr as u64;
+            header.p_paddr = vaddr as u64;
+            header.p_filesz = (phnum * phentsize) as u64;
+            header.p_memsz = header.p_filesz;
+        } else if header.p_type == PT_DYNAMIC {
+            if let Some(new_header) = &dynamic_address {
+                header.p_offset = new_header.sh_offset;
+                header.p_vaddr = new_header.sh_addr;
+                header.p_paddr = new_header.sh_addr;
+                header.p_filesz = new_header.sh_size;
+                header.p_memsz = new_header.sh_size;
+            }
+        }
+        program_headers.push(header);
+    }
+    // Loadable segments must be sorted by address, so the new one follows the others
+    let last_load = program_headers
+        .iter()
+        .rposition(|header| header.p_type == PT_LOAD)
+        .unwrap_or(0);
+    program_headers.insert(
+        last_load + 1,
+        ProgramHeader {
+            p_type: PT_LOAD,
+            p_flags: flags,
+            p_offset: offset as u64,
+            p_vaddr: vaddr as u64,
+            p_paddr: vaddr as u64,
+            p_filesz: size as u64,
+            p_memsz: size as u64,
+            p_align: page as u64,
+        },
+    );
+
+    let mut data = vec![0; size];
+    for (index, header) in program_headers.into_iter().enumerate() {
+        header.try_into_ctx(&mut data[index * phentsize..], ctx)?;
+    }
+    for section in &loaded {
+        let position = section.header.sh_offset as usize - offset;
+        data[position..position + section.contents.len()].copy_from_slice(&section.contents);
+    }
+
+    if names.is_empty() {
+        for section in &loaded {
+            if let Some(index) = section.index {
+                patches.push(sections[index].header.patch_with(section.header.clone())?);
+            }
+        }
+    } else {
+        // The section header table is copied after the segment, with the new sections
+        let mut headers = sections
+            .iter()
+            .map(|section| section.header.value.clone())
+            .collect::<Vec<_>>();
+        let mut added = Vec::new();
+        let mut names = names.iter();
+        for section in &loaded {
+            match section.index {
+                Some(index) => headers[index] = section.header.clone(),
+                None => {
+                    let name = names.next().ok_or_else(|| {
+                        Error::Malformed("every added section must be named".to_string())
+                    })?;
+                    added.push((*name, section.header.clone()));
+                }
+            }
+        }
+        let (table, table_patches) =
+            append_section_headers(elf, sections, headers, &added, offset + data.len())?;
+        data.extend_from_slice(&table);
+        patches.extend(table_patches);
+    }
+    let location = Location {
+        offset,
+        size: data.len(),
+        ctx,
+    };
+    patches.push(Patch::from_bytes(&location, &data)?);
+
+    // e_phoff follows e_entry, and e_phnum follows e_flags, e_ehsize and e_phentsize
+    let word = if elf.is_64 { 8 } else { 4 };
+    let location = Location {
+        offset: 0x18 + word,
+        size: word,
+        ctx,
+    };
+    patches.push(match elf.is_64 {
+        true => Patch::from_primitive(&location, offset as u64)?,
+        false => Patch::from_primitive(&location, offset as u32)?,
+    });
+    let location = Location {
+        offset: 0x18 + 3 * word + 8,
+        size: 2,
+        ctx,
+    };
+    patches.push(Patch::from_primitive(&location, phnum as u16)?);
+    Ok(patches)
+}
+
+/// Construct patches adding sections to an ELF binary, which `added` gives by name and header.
+///
+/// The section name table and the section header table are copied to the end of the file with
+/// the new sections appended, which get the indexes following the existing sections, and the old
+/// tables are left in place, unreferenced.  Binaries with extended section numbering return
+/// `Error::Unsupported`.
+pub fn add_sections(
+    bytes: &[u8],
+    elf: &Elf,
+    added: &[(&str, SectionHeader)],
+) -> Result<Vec<Patch>> {
+    let sections = sections(bytes, elf)?;
+    let headers = sections
+        .iter()
+        .map(|section| section.header.value.clone())
+        .collect();
+    let offset = bytes.len();
+    let (data, mut patches) = append_section_headers(elf, &sections, headers, added, offset)?;
+    let location = Location {
+        offset,
+        size: data.len(),
+        ctx: context_from_elf(elf),
+    };
+    patches.push(Patch::from_bytes(&location, &data)?);
+    Ok(patches)
+}
+
+/// Build a copy of the section name table with the names of `added` appended, followed by a copy
+/// of the section header table, `headers`, with their headers appended, to be written at `offset`.
+///
+/// Returns the copies with the patches pointing the ELF header at them.
+fn append_section_headers(
+    elf: &Elf,
+    sections: &[Section<'_>],
+    mut headers: Vec<SectionHeader>,
+    added: &[(&str, SectionHeader)],
+    offset: usize,
+) -> Result<(Vec<u8>, Vec<Patch>)> {
+    let ctx = context_from_elf(elf);
+    let shstrndx = shstrndx(elf);
+    if elf.header.e_shnum == 0
+        || shstrndx == 0
+        || headers.len() + added.len() >= SHN_LORESERVE as usize
+    {
+        return Err(Error::Unsupported(
+            "Sections can only be added to binaries with a section name table and fewer than SHN_LORESERVE sections".to_string(),
+        ));
+    }
+    if headers[shstrndx].sh_flags & u64::from(SHF_ALLOC) != 0 {
+        return Err(Error::Unsupported(
+            "The section name table is loaded at run time, so it can't grow".to_string(),
+        ));
+    }
+    let mut data = sections[shstrndx].contents.to_vec();
+    for (name, header) in added {
+        headers.push(SectionHeader {
+            sh_name: data.len(),
+            ..header.clone()
+        });
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+    }
+    headers[shstrndx].sh_offset = offset as u64;
+    headers[shstrndx].sh_size = data.len() as u64;
+    let shoff = (offset + data.len()).div_ceil(SECTION_HEADERS_ALIGN) * SECTION_HEADERS_ALIGN;
+    data.resize(shoff - offset, 0);
+    let shentsize = elf.header.e_shentsize as usize;
+    for header in &headers {
+        let mut entry = vec![0; shentsize];
+        header.clone().try_into_ctx(&mut entry[..], ctx)?;
+        data.extend_from_slice(&entry);
+    }
+
+    // e_shoff follows e_entry and e_phoff, and e_shnum follows e_phnum and e_shentsize
+    let word = if elf.is_64 { 8 } else { 4 };
+    let location = Location {
+        offset: 0x18 + 2 * word,
+        size: word,
+        ctx,
+    };
+    let mut patches = vec![match elf.is_64 {
+        true => Patch::from_primitive(&location, shoff as u64)?,
+        false => Patch::from_primitive(&location, shoff as u32)?,
+    }];
+    let location = Location {
+        offset: 0x18 + 3 * word + 12,
+        size: 2,
+        ctx,
+    };
+    patches.push(Patch::from_primitive(&location, headers.len() as u16)?);
+    Ok((data, patches))
+}
+
+/// The hash of a symbol name in a `.gnu.hash` section.
+fn gnu_hash(name: &[u8]) -> u32 {
+    name.iter().fold(5381u32, |hash, &c| {
+        hash.wrapping_mul(33).wrapping_add(u32::from(c))
+    })
+}
+
+/// The hash of a symbol name in a SysV `.hash` section.
+fn sysv_hash(name: &[u8]) -> u32 {
+    name.iter().fold(0u32, |hash, &c| {
+        let hash = (hash << 4).wrapping_add(u32::from(c));
+        let high = hash & 0xf000_0000;
+        (hash ^ (high >> 24)) & !high
+    })
+}
+
+/// Rebuild a `.gnu.hash` section for the symbols named `names`, keeping its number of buckets,
+/// symbol offset and Bloom filter parameters.
+///
+/// The hashed symbols must be sorted by bucket, so returns the old index of each symbol in its
+/// new order with the new contents of the section.
+pub(crate) fn rebuild_gnu_hash(
+    contents: &[u8],
+    names: &[&[u8]],
+    ctx: Ctx,
+) -> Result<(Vec<usize>, Vec<u8>)> {
+    let le = ctx.le;
+    let buckets: u32 = contents.pread_with(0, le)?;
+    let symoffset: u32 = contents.pread_with(4, le)?;
+    let bloom_size: u32 = contents.pread_with(8, le)?;
+    let bloom_shift: u32 = contents.pread_with(12, le)?;
+    let (buckets, symoffset, bloom_size) =
+        (buckets as usize, symoffset as usize, bloom_size as usize);
+    if buckets == 0 || bloom_size == 0 || symoffset > names.len() {
+        return Err(Error::Malformed("invalid .gnu.hash header".to_string()));
+    }
+    let hashes = names.iter().map(|name| gnu_hash(name)).collect::<Vec<_>>();
+    let mut order = (0..names.len()).collect::<Vec<_>>();
+    order[symoffset..].sort_by_key(|&index| hashes[index] as usize % buckets);
+
+    let word = if ctx.container.is_big() { 64 } else { 32 };
+    let mut bloom = vec![0u64; bloom_size];
+    let mut bucket_starts = vec![0u32; buckets];
+    let mut chains = Vec::with_capacity(names.len() - symoffset);
+    for (new_index, &index) in order.iter().enumerate().skip(symoffset) {
+        let hash = hashes[index];
+        bloom[(hash / word) as usize % bloom_size] |=
+            1 << (hash % word) | 1 << ((hash >> bloom_shift) % word);
+        let bucket = hash as usize % buckets;
+        if bucket_starts[bucket] == 0 {
+            bucket_starts[bucket] = new_index as u32;
+        }
+        let last = order
+            .get(new_index + 1)
+            .is_none_or(|&next| hashes[next] as usize % buckets != bucket);
+        chains.push(hash & !1 | u32::from(last));
+    }
+
+    let mut data = vec![0u8; 16];
+    data.pwrite_with(buckets as u32, 0, le)?;
+    data.pwrite_with(symoffset as u32, 4, le)?;
+    data.pwrite_with(bloom_size as u32, 8, le)?;
+    data.pwrite_with(bloom_shift, 12, le)?;
+    for word in bloom {
+        let offset = data.len();
+        if ctx.container.is_big() {
+            data.resize(offset + 8, 0);
+            data.pwrite_with(word, offset, le)?;
+        } else {
+            data.resize(offset + 4, 0);
+            data.pwrite_with(word as u32, offset, le)?;
+        }
+    }
+    for value in bucket_starts.into_iter().chain(chains) {
+        let offset = data.len();
+        data.resize(offset + 4, 0);
+        data.pwrite_with(value, offset, le)?;
+    }
+    Ok((order, data))
+}
+
+/// Rebuild a SysV `.hash` section for the symbols named `names`, keeping its number of buckets.
+pub(crate) fn rebuild_sysv_hash(contents: &[u8], names: &[&[u8]], ctx: Ctx) -> Result<Vec<u8>> {
+    let le = ctx.le;
+    let buckets: u32 = contents.pread_with(0, le)?;
+    let chains: u32 = contents.pread_with(4, le)?;
+    if buckets == 0 || chains as usize != names.len() {
+        return Err(Error::Malformed("invalid .hash header".to_string()));
+    }
+    let mut bucket_starts = vec![0u32; buckets as usize];
+    let mut chains = vec![0u32; names.len()];
+    for (index, name) in names.iter().enumerate().skip(1) {
+        let bucket = sysv_hash(name) as usize % bucket_starts.len();
+        chains[index] = bucket_starts[bucket];
+        bucket_starts[bucket] = index as u32;
+    }
+    let mut data = vec![0u8; 8 + 4 * (bucket_starts.len() + chains.len())];
+    data.pwrite_with(buckets, 0, le)?;
+    data.pwrite_with(names.len() as u32, 4, le)?;
+    for (index, value) in bucket_starts.into_iter().chain(chains).enumerate() {
+        data.pwrite_with(value, 8 + 4 * index, le)?;
+    }
+    Ok(data)
+}
+
+/// Construct patches renaming symbols of the dynamic symbol table (`.dynsym`) of a linked ELF
+/// binary, which `renames` gives by index.
+///
+/// The new names are appended to the dynamic string table, which is moved to a new segment
+/// with `load_sections`, so the names may be longer.  The hash tables are rebuilt for the new
+/// names.  Symbols found through `.gnu.hash` must be sorted by their hash, so they are reordered,
+/// and the symbol versions (`.gnu.version`) and relocations referring to them are renumbered to
+/// match.  Hash tables with 64-bit entries return `Error::Unsupported`.
+pub fn rename_dynamic_symbols(
+    bytes: &[u8],
+    elf: &Elf,
+    renames: &[(usize, &str)],
+) -> Result<Vec<Patch>> {
+    let ctx = context_from_elf(elf);
+    let sections = sections(bytes, elf)?;
+    let dynsym = sections
+        .iter()
+        .position(|section| section.header.sh_type == SHT_DYNSYM)
+        .ok_or_else(|| Error::Unsupported("The binary has no .dynsym".to_string()))?;
+    let header = &sections[dynsym].header;
+    let entries = &sections[dynsym].contents;
+    let step = header.sh_entsize as usize;
+    if step < Sym::size(ctx.container) {
+        return Err(Error::Malformed("sh_entsize too small".to_string()));
+    }
+    let link = header.sh_link as usize;
+    let table = *sections
+        .get(link)
+        .ok_or_else(|| Error::Malformed("sh_link too large".to_string()))?
+        .contents;
+
+    // Old strings keep their offsets, since version sections and DT_NEEDED refer to them too
+    let mut strtab = table.to_vec();
+    let mut symbols = Vec::new();
+    for entry in entries.chunks_exact(step) {
+        let (sym, _) = Sym::try_from_ctx(entry, ctx)?;
+        symbols.push(sym);
+    }
+    for (index, name) in renames {
+        let sym = symbols
+            .get_mut(*index)
+            .ok_or_else(|| Error::Malformed(format!("symbol {} out of range of .dynsym", index)))?;
+        sym.st_name = strtab.len();
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+    }
+    let names = symbols
+        .iter()
+        .map(|sym| {
+            let name = strtab
+                .get(sym.st_name..)
+                .ok_or_else(|| Error::Malformed("st_name out of range".to_string()))?;
+            Ok(&name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())])
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut patches = Vec::new();
+    let mut order = (0..symbols.len()).collect::<Vec<_>>();
+    let hashes = sections.iter().enumerate().filter(|(_, section)| {
+        section.header.sh_link as usize == dynsym
+            && [SHT_HASH, SHT_GNU_HASH].contains(&section.header.sh_type)
+    });
+    for (_, section) in hashes.clone() {
+        if section.header.sh_type == SHT_GNU_HASH {
+            let (new_order, data) = rebuild_gnu_hash(&section.contents, &names, ctx)?;
+            order = new_order;
+            patches.push(section.contents.patch_with_bytes(&data)?);
+        }
+    }
+    let names = order.iter().map(|&index| names[index]).collect::<Vec<_>>();
+    for (_, section) in hashes {
+        if section.header.sh_type == SHT_HASH {
+            if section.header.sh_entsize == 8 {
+                return Err(Error::Unsupported(
+                    "Hash tables with 64-bit entries are not supported".to_string(),
+                ));
+            }
+            let data = rebuild_sysv_hash(&section.contents, &names, ctx)?;
+            patches.push(section.contents.patch_with_bytes(&data)?);
+        }
+    }
+
+    let mut table = vec![0; entries.len()];
+    for (new_index, &index) in order.iter().enumerate() {
+        let entry = &mut table[new_index * step..(new_index + 1) * step];
+        entry.copy_from_slice(&entries[index * step..(index + 1) * step]);
+        symbols[index].try_into_ctx(entry, ctx)?;
+    }
+    patches.push(entries.patch_with_bytes(&table)?);
+    if order.iter().copied().ne(0..order.len()) {
+        let mut indexes = vec![0; order.len()];
+        for (new_index, &index) in order.iter().enumerate() {
+            indexes[index] = new_index;
+        }
+        let renumber = |index: usize| {
+            indexes.get(index).copied().ok_or_else(|| {
+                Error::Malformed(format!(
+                    "Reference to symbol {} past the end of .dynsym",
+                    index
+                ))
+            })
+        };
+        for section in &sections {
+            if section.header.sh_link as usize != dynsym {
+                continue;
+            }
+            match section.header.sh_type {
+                SHT_GNU_VERSYM => {
+                    let mut versions = vec![0; section.contents.len()];
+                    for (new_index, &index) in order.iter().enumerate() {
+                        let version =
+                            section
+                                .contents
+                                .get(index * 2..index * 2 + 2)
+                                .ok_or_else(|| {
+                                    Error::Malformed(
+                                        "symbol index out of range of .gnu.version".into(),
+                                    )
+                                })?;
+                        versions[new_index * 2..new_index * 2 + 2].copy_from_slice(version);
+                    }
+                    patches.push(section.contents.patch_with_bytes(&versions)?);
+                }
+                SHT_REL | SHT_RELA => {
+                    renumber_relocations(elf, section, &renumber, &mut patches)?;
+                }
+                _ => {}
+            }
+        }
+    }
+    patches.extend(load_sections(bytes, elf, &[(link, strtab)])?);
+    Ok(patches)
+}
+
+/// Construct patches removing the static symbol table (`.symtab`) of a linked ELF binary, with
+/// its string table and extended section index table, like `strip --strip-all`.
+///
+/// Sections can't be removed in place, so their headers are replaced with `SHT_NULL` headers and
+/// their contents are cleared.  The dynamic symbol table is kept.  The string table is kept if it
+/// is also the section name table or loaded at run time.  Relocatable objects, and binaries with
+/// other sections referring to the symbol table such as relocations kept with `--emit-relocs`,
+/// need their symbol table, and return `Error::Unsupported`.
+///
+/// If `keep` returns `true` for the index of any symbol, the symbol table is kept with only those
+/// symbols instead, like `strip_symbols`.  Returns the number of symbols removed with the patches,
+/// or no patches if there is no `.symtab`.
+pub fn strip_all(
+    bytes: &[u8],
+    elf: &Elf,
+    keep: &dyn Fn(usize) -> bool,
+) -> Result<(usize, Vec<Patch>)> {
+    let sections = sections(bytes, elf)?;
+    let symtab = match sections
+        .iter()
+        .position(|section| section.header.sh_type == SHT_SYMTAB)
+    {
+        Some(symtab) => symtab,
+        None => return Ok((0, Vec::new())),
+    };
+    if elf.header.e_type == ET_REL {
+        return Err(Error::Unsupported(
+            "The symbol table of relocatable objects is needed to link them".to_string(),
+        ));
+    }
+    let header = &sections[symtab].header;
+    let count = match header.sh_entsize {
+        0 => 0,
+        entsize => (header.sh_size / entsize) as usize,
+    };
+    if (1..count).any(keep) {
+        return strip_symbols(bytes, elf, &|index, _| !keep(index));
+    }
+    let shstrndx = if u32::from(elf.header.e_shstrndx) == SHN_XINDEX {
+        sections[0].header.sh_link as usize
+    } else {
+        elf.header.e_shstrndx as usize
+    };
+    let mut removed = vec![symtab];
+    let strtab = sections[symtab].header.sh_link as usize;
+    let keep_strtab = strtab == shstrndx
+        || sections
+            .get(strtab)
+            .is_none_or(|section| section.header.sh_flags & u64::from(SHF_ALLOC) != 0)
+        || sections
+            .iter()
+            .enumerate()
+            .any(|(index, section)| index != symtab && section.header.sh_link as usize == strtab);
+    if !keep_strtab {
+        removed.push(strtab);
+    }
+    removed.extend(
+        sections
+            .iter()
+            .enumerate()
+            .filter(|(_, section)| {
+                section.header.sh_type == SHT_SYMTAB_SHNDX
+                    && section.header.sh_link as usize == symtab
+            })
+            .map(|(index, _)| index),
+    );
+    if let Some((_, section)) = sections.iter().enumerate().find(|(index, section)| {
+        !removed.contains(index) && section.header.sh_link as usize == symtab
+    }) {
+        return Err(Error::Unsupported(format!(
+            "{} refers to the symbol table",
+            section.name.as_deref().unwrap_or("A section")
+        )));
+    }
+
+    let mut patches = Vec::new();
+    for index in removed {
+        patches.extend(remove_section(&sections[index])?);
+    }
+    Ok((count.saturating_sub(1), patches))
+}
+
+/// Construct patches removing a section in place, by replacing its header with an `SHT_NULL`
+/// header and clearing its contents.
+fn remove_section(section: &Section) -> Result<Vec<Patch>> {
+    Ok(vec![
+        section
+            .contents
+            .patch_with_bytes(&vec![0; section.contents.len()])?,
+        section.header.patch_with(SectionHeader::default())?,
+    ])
+}
+
+/// The dynamic section entries describing symbol versions.
+const VERSION_TAGS: [u64; 5] = [
+    DT_VERSYM,
+    DT_VERDEF,
+    DT_VERDEFNUM,
+    DT_VERNEED,
+    DT_VERNEEDNUM,
+];
+
+/// Construct patches removing the symbol versioning of an ELF binary.
+///
+/// The `.gnu.version`, `.gnu.version_d` and `.gnu.version_r` sections are removed in place like in
+/// `strip_all`, clearing the version indices, and the `DT_VERSYM`, `DT_VERDEF`, `DT_VERDEFNUM`,
+/// `DT_VERNEED` and `DT_VERNEEDNUM` entries are removed from the dynamic section, moving the
+/// following entries up and padding it with `DT_NULL`.  Returns the names of the removed sections
+/// with the patches, which are empty if the binary has no symbol versions.
+pub fn strip_versions(bytes: &[u8], elf: &Elf) -> Result<(Vec<String>, Vec<Patch>)> {
+    let sections = sections(bytes, elf)?;
+    let mut names = Vec::new();
+    let mut patches = Vec::new();
+    for (index, section) in sections.iter().enumerate() {
+        if matches!(
+            section.header.sh_type,
+            SHT_GNU_VERSYM | SHT_GNU_VERDEF | SHT_GNU_VERNEED
+        ) {
+            names.push(section.name.clone().unwrap_or_else(|| index.to_string()));
+            patches.extend(remove_section(section)?);
+        }
+    }
+    if patches.is_empty() {
+        return Ok((names, patches));
+    }
+
+    let ctx = context_from_elf(elf);
+    let word = if elf.is_64 { 8 } else { 4 };
+    let read = |offset: usize| -> Result<u64> {
+        Ok(match elf.is_64 {
+            true => bytes.pread_with::<u64>(offset, ctx.le)?,
+            false => u64::from(bytes.pread_with::<u32>(offset, ctx.le)?),
+        })
+    };
+    for section in sections
+        .iter()
+        .filter(|section| section.header.sh_type == SHT_DYNAMIC)
+    {
+        let offset = section.header.sh_offset as usize;
+        let count = section.contents.len() / (2 * word);
+        let mut contents = vec![0; section.contents.len()];
+        let mut kept = 0;
+        for entry in 0..count {
+            let (tag, value) = (
+                read(offset + entry * 2 * word)?,
+                read(offset + entry * 2 * word + word)?,
+            );
+            if tag == DT_NULL {
+                break;
+            }
+            if VERSION_TAGS.contains(&tag) {
+                continue;
+            }
+            for (field, value) in [tag, value].iter().enumerate() {
+                let at = kept * 2 * word + field * word;
+                match elf.is_64 {
+                    true => contents.pwrite_with(*value, at, ctx.le)?,
+                    false => contents.pwrite_with(*value as u32, at, ctx.le)?,
+                };
+            }
+            kept += 1;
+        }
+        patches.push(section.contents.patch_with_bytes(&contents)?);
+    }
+    Ok((names, patches))
+}
+
+/// Whether a section holds debugging information, like the DWARF `.debug_*` sections.
+pub fn is_debug_section(name: &str) -> bool {
+    [".debug", ".zdebug", ".stab", ".line", ".gdb_index"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Construct patches removing the debugging information of an ELF binary, like
+/// `strip --strip-debug`.
+///
+/// Debug sections and their relocation sections are removed in place like in `strip_all`, and
+/// the symbols defined in them, such as their section symbols, are removed from `.symtab`.
+/// Returns the names of the removed sections with the patches.
+pub fn strip_debug(bytes: &[u8], elf: &Elf) -> Result<(Vec<String>, Vec<Patch>)> {
+    let sections = sections(bytes, elf)?;
+    let debug: Vec<usize> = sections
+        .iter()
+        .enumerate()
+        .filter(|(_, section)| section.name.as_deref().is_some_and(is_debug_section))
+        .map(|(index, _)| index)
+        .collect();
+    if debug.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let mut removed = debug.clone();
+    removed.extend(
+        sections
+            .iter()
+            .enumerate()
+            .filter(|(_, section)| {
+                matches!(section.header.sh_type, SHT_REL | SHT_RELA)
+                    && debug.contains(&(section.header.sh_info as usize))
+            })
+            .map(|(index, _)| index),
+    );
+
+    let mut patches = Vec::new();
+    if let Some(iter) = SymtabIter::symtab_from_elf(bytes, elf)? {
+        let indexes = iter.extended_indexes();
+        let mut defined_in_debug = Vec::new();
+        for (index, item) in iter.enumerate() {
+            let (_, sym) = item?;
+            let section = indexes.section_index(index, &sym)?;
+            defined_in_debug.push(section < SHN_LORESERVE as usize && debug.contains(&section));
+        }
+        let (_, symbol_patches) = compact_symtab(
+            bytes,
+            elf,
+            &|index, _| defined_in_debug.get(index) == Some(&true),
+            &removed,
+        )?;
+        patches.extend(symbol_patches);
+    }
+    let mut names = Vec::new();
+    for index in removed {
+        let section = &sections[index];
+        names.push(section.name.clone().unwrap_or_else(|| index.to_string()));
+        patches.extend(remove_section(section)?);
+    }
+    Ok((names, patches))
+}
+
+/// Find the symbols of `.symtab` referred to by relocations and section groups, which can't be
+/// removed.
+pub fn referenced_symbols(bytes: &[u8], elf: &Elf) -> Result<HashSet<usize>> {
+    let sections = sections(bytes, elf)?;
+    let symtab = match sections
+        .iter()
+        .position(|section| section.header.sh_type == SHT_SYMTAB)
+    {
+        Some(symtab) => symtab,
+        None => return Ok(HashSet::new()),
+    };
+    let referenced = RefCell::new(HashSet::new());
+    let record = |index: usize| {
+        referenced.borrow_mut().insert(index);
+        Ok(index)
+    };
+    // Relocations are visited by renumbering them to the same indices, which patches nothing
+    let mut patches = Vec::new();
+    for section in &sections {
+        if section.header.sh_link as usize != symtab {
+            continue;
+        }
+        match section.header.sh_type {
+            SHT_GROUP => {
+                record(section.header.sh_info as usize)?;
+            }
+            SHT_REL | SHT_RELA => renumber_relocations(elf, section, &record, &mut patches)?,
+            _ => {}
+        }
+    }
+    Ok(referenced.into_inner())
+}
+
+/// Construct patches removing the symbols of `.symtab` which aren't needed to link a binary,
+/// like `strip --strip-unneeded`.
+///
+/// Local and undefined symbols are removed unless relocations or section groups refer to them.
+/// Section symbols, and symbols for which `keep` returns `true`, are kept.  Returns the number of
+/// symbols removed with the patches.
+pub fn strip_unneeded(
+    bytes: &[u8],
+    elf: &Elf,
+    keep: &dyn Fn(usize) -> bool,
+) -> Result<(usize, Vec<Patch>)> {
+    let referenced = referenced_symbols(bytes, elf)?;
+    strip_symbols(bytes, elf, &|index, sym| {
+        let unneeded = sym.st_bind() == STB_LOCAL || sym.st_shndx == SHN_UNDEF as usize;
+        unneeded && sym.st_type() != STT_SECTION && !referenced.contains(&index) && !keep(index)
+    })
+}
+
+/// Construct patches removing the local symbols of `.symtab`, keeping the global ones, like
+/// `strip --discard-all`.
+///
+/// Local symbols are removed unless relocations or section groups refer to them.  Section
+/// symbols, and symbols for which `keep` returns `true`, are kept.  Returns the number of symbols
+/// removed with the patches.
+pub fn discard_locals(
+    bytes: &[u8],
+    elf: &Elf,
+    keep: &dyn Fn(usize) -> bool,
+) -> Result<(usize, Vec<Patch>)> {
+    let referenced = referenced_symbols(bytes, elf)?;
+    strip_symbols(bytes, elf, &|index, sym| {
+        sym.st_bind() == STB_LOCAL
+            && sym.st_type() != STT_SECTION
+            && !referenced.contains(&index)
+            && !keep(index)
+    })
+}
+
+/// Construct patches removing the entries for which `strip` returns `true` from the static
+/// symbol table of an ELF binary.
+///
+/// `strip` is called with the index of each symbol; the null symbol at index 0 is always kept.
+/// The remaining symbols are moved to the start of the table, local symbols first as `sh_info`
+/// requires, and the relocations and section groups referring to them are renumbered; removing
+/// a symbol they refer to is an error.  The names of removed symbols are left in the string
+/// table.  Returns the number of symbols removed with the patches, which are empty if no symbol
+/// is removed or moved.
+pub fn strip_symbols(
+    bytes: &[u8],
+    elf: &Elf,
+    strip: &dyn Fn(usize, &Sym) -> bool,
+) -> Result<(usize, Vec<Patch>)> {
+    compact_symtab(bytes, elf, strip, &[])
+}
+
+/// Construct patches removing the file symbols (`STT_FILE`), which name the source files the
+/// binary was built from, from the static symbol table of an ELF binary.
+///
+/// Returns the number of symbols removed with the patches, like `strip_symbols`.
+pub fn strip_file_symbols(bytes: &[u8], elf: &Elf) -> Result<(usize, Vec<Patch>)> {
+    strip_symbols(bytes, elf, &|_, sym| sym.st_type() == STT_FILE)
+}
+
+/// Construct patches moving the local symbols of `.symtab` before the global ones, as `sh_info`
+/// requires, after symbols have been made local or global in place.
+///
+/// Relocations and section groups are renumbered like in `strip_symbols`.  Returns no patches if
+/// the symbols are already in order.
+pub fn sort_locals(bytes: &[u8], elf: &Elf) -> Result<Vec<Patch>> {
+    Ok(compact_symtab(bytes, elf, &|_, _| false, &[])?.1)
+}
+
+/// The alignment of symbol tables moved to the end of the file by `add_symbols`.
+const MOVED_SYMTAB_ALIGN: usize = 8;
+
+/// Construct patches appending symbols to `.symtab`, with their names given separately from their
+/// `st_name` fields, which are ignored.
+///
+/// The string table is rebuilt with the new names like `rebuild_strtab`, and the symbol table is
+/// copied after it at the end of the file with the new symbols appended.  The old tables are left
+/// in place, unreferenced.  Local symbols appended after global ones must be moved with
+/// `sort_locals`.  Symbol tables with extended section indexes (`SHT_SYMTAB_SHNDX`) return
+/// `Error::Unsupported`.
+pub fn add_symbols(bytes: &[u8], elf: &Elf, symbols: &[(&str, Sym)]) -> Result<Vec<Patch>> {
+    let ctx = context_from_elf(elf);
+    let sections = sections(bytes, elf)?;
+    let symtab = sections
+        .iter()
+        .position(|section| section.header.sh_type == SHT_SYMTAB)
+        .ok_or_else(|| Error::Unsupported("The binary has no .symtab".to_string()))?;
+    if sections.iter().any(|section| {
+        section.header.sh_type == SHT_SYMTAB_SHNDX && section.header.sh_link as usize == symtab
+    }) {
+        return Err(Error::Unsupported(
+            "Adding symbols to a symbol table with extended section indexes is not supported"
+                .to_string(),
+        ));
+    }
+    let section = &sections[symtab];
+    let step = section.header.sh_entsize as usize;
+    let size = Sym::size(ctx.container);
+    if step < size {
+        return Err(Error::Malformed("sh_entsize too small".to_string()));
+    }
+
+    let mut strtab = rebuild_strtab(bytes, elf)?
+        .ok_or_else(|| Error::Unsupported("The binary has no .symtab".to_string()))?;
+    let mut table = section.contents.to_vec();
+    for (name, sym) in symbols {
+        let sym = Sym {
+            st_name: strtab.add(name),
+            ..*sym
+        };
+        let mut entry = vec![0; step];
+        sym.try_into_ctx(&mut entry[..size], ctx)?;
+        table.extend_from_slice(&entry);
+    }
+    let mut patches = strtab.patches()?;
+    let end = patches
+        .iter()
+        .map(|patch| patch.offset() + patch.data().len())
+        .chain(std::iter::once(bytes.len()))
+        .max()
+        .unwrap_or(bytes.len());
+    let offset = (end + MOVED_SYMTAB_ALIGN - 1) & !(MOVED_SYMTAB_ALIGN - 1);
+    let location = Location {
+        offset,
+        size: table.len(),
+        ctx,
+    };
+    patches.push(Patch::from_bytes(&location, &table)?);
+    patches.push(section.header.patch_with(SectionHeader {
+        sh_offset: offset as u64,
+        sh_size: table.len() as u64,
+        sh_addralign: section.header.sh_addralign.max(MOVED_SYMTAB_ALIGN as u64),
+        ..section.header.value.clone()
+    })?);
+    Ok(patches)
+}
+
+/// Like `strip_symbols`, ignoring the relocation sections in `removed`, which are being removed.
+fn compact_symtab(
+    bytes: &[u8],
+    elf: &Elf,
+    strip: &dyn Fn(usize, &Sym) -> bool,
+    removed: &[usize],
+) -> Result<(usize, Vec<Patch>)> {
+    let ctx = context_from_elf(elf);
+    let sections = sections(bytes, elf)?;
+    let symtab = match sections
+        .iter()
+        .position(|section| section.header.sh_type == SHT_SYMTAB)
+    {
+        Some(symtab) => symtab,
+        None => return Ok((0, Vec::new())),
+    };
+    let header = &sections[symtab].header;
+    let entries = &sections[symtab].contents;
+    let step = header.sh_entsize as usize;
+    if step < Sym::size(ctx.container) {
+        return Err(Error::Malformed("sh_entsize too small".to_string()));
+    }
+
+    // The old indices of the remaining symbols in their new order, with local symbols first
+    let count = entries.len() / step;
+    let mut locals = Vec::with_capacity(count);
+    let mut globals = Vec::new();
+    for (index, entry) in entries.chunks_exact(step).enumerate() {
+        let (sym, _) = Sym::try_from_ctx(entry, ctx)?;
+        if index == 0 || sym.st_bind() == STB_LOCAL {
+            if index == 0 || !strip(index, &sym) {
+                locals.push(index);
+            }
+        } else if !strip(index, &sym) {
+            globals.push(index);
+        }
+    }
+    let first_global = locals.len();
+    let mut order = locals;
+    order.extend(globals);
+    if order.iter().copied().eq(0..count) && header.sh_info as usize == first_global {
+        return Ok((0, Vec::new()));
+    }
+    let stripped = count - order.len();
+
+    // The new index of each symbol, or `None` for removed ones
+    let mut indexes = vec![None; count];
+    let mut table = Vec::with_capacity(entries.len());
+    for (new_index, &index) in order.iter().enumerate() {
+        indexes[index] = Some(new_index);
+        table.extend_from_slice(&entries[index * step..(index + 1) * step]);
+    }
+    let renumber = |index: usize| match indexes.get(index) {
+        Some(Some(new_index)) => Ok(*new_index),
+        Some(None) => Err(Error::Malformed(format!(
+            "Symbol {} is removed, but is referred to by the binary",
+            index
+        ))),
+        None => Err(Error::Malformed(format!(
+            "Reference to symbol {} past the end of the symbol table",
+            index
+        ))),
+    };
+
+    let mut patches = Vec::new();
+    table.resize(entries.len(), 0);
+    patches.push(entries.patch_with_bytes(&table)?);
+    patches.push(header.patch_with(SectionHeader {
+        sh_size: (order.len() * step) as u64,
+        sh_info: first_global as u32,
+        ..header.value.clone()
+    })?);
+
+    for (index, section) in sections.iter().enumerate() {
+        if section.header.sh_link as usize != symtab || removed.contains(&index) {
+            continue;
+        }
+        match section.header.sh_type {
+            SHT_SYMTAB_SHNDX => {
+                let mut shndx = Vec::with_capacity(section.contents.len());
+                for &index in &order {
+                    let entry = section
+                        .contents
+                        .get(index * 4..(index + 1) * 4)
+                        .ok_or_else(|| {
+                            Error::Malformed(
+                                "symbol index out of range of SHT_SYMTAB_SHNDX".to_string(),
+                            )
+                        })?;
+                    shndx.extend_from_slice(entry);
+                }
+                shndx.resize(section.contents.len(), 0);
+                patches.push(section.contents.patch_with_bytes(&shndx)?);
+                patches.push(section.header.patch_with(SectionHeader {
+                    sh_size: (order.len() * 4) as u64,
+                    ..section.header.value.clone()
+                })?);
+            }
+            SHT_GROUP => {
+                let signature = renumber(section.header.sh_info as usize)?;
+                patches.push(section.header.patch_with(SectionHeader {
+                    sh_info: signature as u32,
+                    ..section.header.value.clone()
+                })?);
+            }
+            SHT_REL | SHT_RELA => {
+                renumber_relocations(elf, section, &renumber, &mut patches)?;
+            }
+            _ => {}
+        }
+    }
+    Ok((stripped, patches))
+}
+
+/// Renumber the symbols referred to by the `r_info` fields of a relocation section.
+fn renumber_relocations(
+    elf: &Elf,
+    section: &Section,
+    renumber: &dyn Fn(usize) -> Result<usize>,
+    patches: &mut Vec<Patch>,
+) -> Result<()> {
+    if elf.is_64 && elf.little_endian && elf.header.e_machine == EM_MIPS {
+        return Err(Error::Unsupported(
+            "Renumbering MIPS64 relocations is not supported".to_string(),
+        ));
+    }
+    let ctx = context_from_elf(elf);
+    let step = section.header.sh_entsize as usize;
+    let info_size = if elf.is_64 { 8 } else { 4 };
+    if step < 2 * info_size {
+        return Err(Error::Malformed("sh_entsize too small".to_string()));
+    }
+    for offset in (0..section.contents.len() / step).map(|index| index * step + info_size) {
+        let (info, symbol) = if elf.is_64 {
+            let info: u64 = section.contents.pread_with(offset, ctx.le)?;
+            (info, (info >> 32) as usize)
+        } else {
+            let info: u32 = section.contents.pread_with(offset, ctx.le)?;
+            (u64::from(info), (info >> 8) as usize)
+        };
+        let new_symbol = renumber(symbol)?;
+        if new_symbol == symbol {
+            continue;
+        }
+        let location = Location {
+            offset: section.header.sh_offset as usize + offset,
+            size: info_size,
+            ctx,
+        };
+        patches.push(if elf.is_64 {
+            Patch::from_primitive(&location, (new_symbol as u64) << 32 | info & 0xffff_ffff)?
+        } else {
+            Patch::from_primitive(&location, (new_symbol as u32) << 8 | info as u32 & 0xff)?
+        });
+    }
+    Ok(())
+}
+
+/// Construct a `StrtabIter` over the strings in a named section, such as `.debug_str`.
+///
+/// Compressed sections can't be patched in place, so they return `Error::Unsupported`.
+pub fn section_strings<'a>(
+    bytes: &'a [u8],
+    elf: &Elf,
+    name: &str,
+) -> Result<Option<StrtabIter<'a>>> {
+    section_by_name(elf, name)
+        .map(|header| {
+            if is_compressed(header) {
+                return Err(Error::Unsupported(format!("{} is compressed", name)));
+            }
+            StrtabIter::new(
+                bytes,
+                header.sh_offset as usize,
+                header.sh_size as usize,
+                context_from_elf(elf),
+            )
+        })
+        .transpose()
+}
+
+/// The `.comment` section, which identifies the toolchain that produced an object.
+pub struct Comment<'a> {
+    /// The section header
+    pub header: Rooted<SectionHeader>,
+
+    /// The contents of the section, a sequence of NUL-terminated strings
+    pub contents: Rooted<&'a [u8]>,
+}
+
+impl<'a> Comment<'a> {
+    /// Read the `.comment` section of an ELF binary, if present.
+    pub fn from_elf(bytes: &'a [u8], elf: &Elf) -> Result<Option<Self>> {
+        let ctx = context_from_elf(elf);
+        let header = match rooted_section_by_name(elf, ".comment") {
+            Some((_, header)) => header,
+            None => return Ok(None),
+        };
+        let offset = header.sh_offset as usize;
+        let size = header.sh_size as usize;
+        let contents = offset
+            .checked_add(size)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or_else(|| Error::Malformed(".comment out of bounds".to_string()))?;
+        Ok(Some(Self {
+            header,
+            contents: Rooted::new(Location { offset, size, ctx }, contents),
+        }))
+    }
+
+    /// Construct patches replacing the contents of the section.
+    ///
+    /// The section is shrunk to the new contents and the remaining bytes are cleared, so replacing
+    /// with empty contents strips the section.
+    pub fn replace(&self, contents: &[u8]) -> Result<Vec<Patch>> {
+        if contents.len() > self.contents.len() {
+            return Err(Error::PatchTooBig);
+        }
+        let mut bytes = contents.to_vec();
+        bytes.resize(self.contents.len(), 0);
+        Ok(vec![
+            self.contents.patch_with_bytes(&bytes)?,
+            self.header.patch_with(SectionHeader {
+                sh_size: contents.len() as u64,
+                ..self.header.value.clone()
+            })?,
+        ])
+    }
+}
+
+/// The contents of a `.gnu_debuglink` section, which names a separate debug file.
+pub struct Debuglink<'a> {
+    /// The file name of the separate debug file
+    pub filename: Rooted<&'a str>,
+
+    /// The CRC-32 of the separate debug file
+    pub crc: Rooted<u32>,
+}
+
+impl<'a> Debuglink<'a> {
+    /// Read the `.gnu_debuglink` section of an ELF binary, if present.
+    pub fn from_elf(bytes: &'a [u8], elf: &Elf) -> Result<Option<Self>> {
+        let ctx = context_from_elf(elf);
+        let header = match section_by_name(elf, ".gnu_debuglink") {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let offset = header.sh_offset as usize;
+        let filename: &str = bytes.pread(offset)?;
+        let crc_offset = (offset + filename.len() + 1 + 3) & !3;
+        if crc_offset + 4 > offset + header.sh_size as usize {
+            return Err(Error::Malformed(".gnu_debuglink too small".to_string()));
+        }
+        let crc: u32 = bytes.pread_with(crc_offset, ctx.le)?;
+        Ok(Some(Self {
+            filename: Rooted::new(
+                Location {
+                    offset,
+                    size: filename.len(),
+                    ctx,
+                },
+                filename,
+            ),
+            crc: Rooted::new(
+                Location {
+                    offset: crc_offset,
+                    size: 4,
+                    ctx,
+                },
+                crc,
+            ),
+        }))
+    }
+}
+
+/// Returns the GNU build ID of an ELF binary, if present.
+pub fn build_id<'a>(bytes: &'a [u8], elf: &Elf<'a>) -> Option<&'a [u8]> {
+    elf.iter_note_headers(bytes)
+        .into_iter()
+        .chain(elf.iter_note_sections(bytes, None))
+        .flatten()
+        .filter_map(|note| note.ok())
+        .find(|note| note.n_type == NT_GNU_BUILD_ID && note.name == "GNU")
+        .map(|note| note.desc)
+}
+
+/// Marks the version definition naming the object itself.
+const VER_FLG_BASE: u16 = 0x1;
+
+/// Marks a symbol version that isn't the default for its name.
+const VERSYM_HIDDEN: u16 = 0x8000;
+
+/// The version of a dynamic symbol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SymbolVersion<'a> {
+    /// The version name, such as `GLIBC_2.2.5`
+    pub name: &'a str,
+
+    /// Whether the version is hidden, in which case it is only used when requested explicitly
+    /// (`name@VERSION` rather than `name@@VERSION`)
+    pub hidden: bool,
+}
+
+/// The versions of the dynamic symbols of an ELF binary.
+///
+/// Versions are read from the `.gnu.version` section, which holds an index for each dynamic
+/// symbol, and the version definitions and requirements in `.gnu.version_d` and
+/// `.gnu.version_r`.
+#[derive(Default)]
+pub struct SymbolVersions<'a> {
+    bytes: &'a [u8],
+    ctx: Ctx,
+    offset: usize,
+    count: usize,
+    names: std::collections::HashMap<u16, &'a str>,
+
+    /// The indices of the versions defined by the binary, by name
+    definitions: std::collections::HashMap<&'a str, u16>,
+}
+
+impl<'a> SymbolVersions<'a> {
+    /// Read the symbol versions of an ELF binary, which are empty if it has none.
+    pub fn from_elf(bytes: &'a [u8], elf: &Elf) -> Result<Self> {
+        let ctx = context_from_elf(elf);
+        let mut versions = Self {
+            bytes,
+            ctx,
+            ..Self::default()
+        };
+        for header in &elf.section_headers {
+            let strtab = || {
+                elf.section_headers
+                    .get(header.sh_link as usize)
+                    .map(|strtab| strtab.sh_offset as usize)
+                    .ok_or_else(|| Error::Malformed("sh_link too large".to_string()))
+            };
+            let offset = header.sh_offset as usize;
+            match header.sh_type {
+                SHT_GNU_VERSYM => {
+                    versions.offset = offset;
+                    versions.count = (header.sh_size / 2) as usize;
+                }
+                SHT_GNU_VERDEF => versions.read_definitions(offset, header.sh_info, strtab()?)?,
+                SHT_GNU_VERNEED => versions.read_requirements(offset, header.sh_info, strtab()?)?,
+                _ => {}
+            }
+        }
+        Ok(versions)
+    }
+
+    fn read_definitions(&mut self, mut offset: usize, count: u32, strtab: usize) -> Result<()> {
+        for _ in 0..count {
+            let flags: u16 = self.bytes.pread_with(offset + 2, self.ctx.le)?;
+            let index: u16 = self.bytes.pread_with(offset + 4, self.ctx.le)?;
+            let aux: u32 = self.bytes.pread_with(offset + 12, self.ctx.le)?;
+            let next: u32 = self.bytes.pread_with(offset + 16, self.ctx.le)?;
+            // The first auxiliary entry names the version, the rest name its parents
+            if flags & VER_FLG_BASE == 0 {
+                let name: u32 = self.bytes.pread_with(offset + aux as usize, self.ctx.le)?;
+                let name = self.bytes.pread(strtab + name as usize)?;
+                self.names.insert(index, name);
+                self.definitions.insert(name, index);
+            }
+            if next == 0 {
+                break;
+            }
+            offset += next as usize;
+        }
+        Ok(())
+    }
+
+    fn read_requirements(&mut self, mut offset: usize, count: u32, strtab: usize) -> Result<()> {
+        for _ in 0..count {
+            let aux_count: u16 = self.bytes.pread_with(offset + 2, self.ctx.le)?;
+            let aux: u32 = self.bytes.pread_with(offset + 8, self.ctx.le)?;
+            let next: u32 = self.bytes.pread_with(offset + 12, self.ctx.le)?;
+            let mut aux_offset = offset + aux as usize;
+            for _ in 0..aux_count {
+                let index: u16 = self.bytes.pread_with(aux_offset + 6, self.ctx.le)?;
+                let name: u32 = self.bytes.pread_with(aux_offset + 8, self.ctx.le)?;
+                let aux_next: u32 = self.bytes.pread_with(aux_offset + 12, self.ctx.le)?;
+                self.names
+                    .insert(index, self.bytes.pread(strtab + name as usize)?);
+                if aux_next == 0 {
+                    break;
+                }
+                aux_offset += aux_next as usize;
+            }
+            if next == 0 {
+                break;
+            }
+            offset += next as usize;
+        }
+        Ok(())
+    }
+
+    /// Returns the index of a version defined by the binary, or `None` if it isn't defined.
+    pub fn definition(&self, name: &str) -> Option<u16> {
+        self.definitions.get(name).copied()
+    }
+
+    /// Returns the names of the versions defined by the binary, sorted by name.
+    pub fn definitions(&self) -> Vec<&'a str> {
+        let mut names: Vec<_> = self.definitions.keys().copied().collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Construct a patch setting the version of a dynamic symbol, by its index in `.dynsym`, to
+    /// the version with index `version`.
+    ///
+    /// Hidden versions are only used when requested explicitly, like `name@VERSION`.
+    pub fn set(&self, symbol: usize, version: u16, hidden: bool) -> Result<Patch> {
+        if symbol >= self.count {
+            return Err(Error::Malformed(format!(
+                "dynamic symbol {} has no .gnu.version entry",
+                symbol
+            )));
+        }
+        let location = Location {
+            offset: self.offset + symbol * 2,
+            size: 2,
+            ctx: self.ctx,
+        };
+        let flags = if hidden { VERSYM_HIDDEN } else { 0 };
+        Rooted::new(location, ()).patch_with_primitive(version | flags)
+    }
+
+    /// Returns the version of a dynamic symbol, by its index in `.dynsym`.
+    ///
+    /// Returns `None` for unversioned symbols.
+    pub fn get(&self, symbol: usize) -> Result<Option<SymbolVersion<'a>>> {
+        if symbol >= self.count {
+            return Ok(None);
+        }
+        let index: u16 = self
+            .bytes
+            .pread_with(self.offset + symbol * 2, self.ctx.le)?;
+        Ok(self
+            .names
+            .get(&(index & !VERSYM_HIDDEN))
+            .map(|name| SymbolVersion {
+                name,
+                hidden: index & VERSYM_HIDDEN != 0,
+            }))
+    }
+}
+
+/// The size of a version definition (`Elf_Verdef`) followed by its name (`Elf_Verdaux`).
+const VERDEF_SIZE: usize = 28;
+
+/// Construct patches adding version definitions to a linked ELF binary, so its dynamic symbols
+/// can be given the versions with `SymbolVersions::set`.
+///
+/// The definitions are appended to `.gnu.version_d`, which is created with a base definition
+/// named after `DT_SONAME` if the binary defines no versions, and `.gnu.version` is created with
+/// every symbol unversioned if it is missing.  The version names are appended to `.dynstr`, and
+/// `.dynamic` gains the entries locating the new sections, so these sections are moved to a new
+/// segment with `load_sections`, and new section headers are appended to a copy of the section
+/// header table.  Versions already defined are skipped, and no patches are returned if there are
+/// none to add.
+pub fn define_versions(bytes: &[u8], elf: &Elf, names: &[&str]) -> Result<Vec<Patch>> {
+    let ctx = context_from_elf(elf);
+    let versions = SymbolVersions::from_elf(bytes, elf)?;
+    let mut names = names
+        .iter()
+        .filter(|name| versions.definition(name).is_none())
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sections = sections(bytes, elf)?;
+    let find = |sh_type| {
+        sections
+            .iter()
+            .position(|section| section.header.sh_type == sh_type)
+    };
+    let dynsym = find(SHT_DYNSYM)
+        .ok_or_else(|| Error::Unsupported("The binary has no .dynsym".to_string()))?;
+    let dynamic = find(SHT_DYNAMIC)
+        .ok_or_else(|| Error::Unsupported("The binary has no .dynamic".to_string()))?;
+    let dynstr = sections[dynsym].header.sh_link as usize;
+    let mut strtab = sections
+        .get(dynstr)
+        .ok_or_else(|| Error::Malformed("sh_link too large".to_string()))?
+        .contents
+        .to_vec();
+
+    let step = Dyn::size_with(&ctx);
+    let mut entries = sections[dynamic]
+        .contents
+        .chunks_exact(step)
+        .map(|entry| entry.pread_with::<Dyn>(0, ctx))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    entries.truncate(
+        entries
+            .iter()
+            .position(|entry| entry.d_tag == DT_NULL)
+            .unwrap_or(entries.len()),
+    );
+    // Version indexes are shared with the versions required from other objects
+    let mut index = versions.names.keys().copied().max().unwrap_or(0).max(1);
+    let verdef = find(SHT_GNU_VERDEF);
+    let (mut contents, mut header, mut count, mut last) = match verdef {
+        Some(verdef) => {
+            let section = &sections[verdef];
+            let mut last = 0;
+            for _ in 1..section.header.sh_info {
+                let next: u32 = section.contents.pread_with(last + 16, ctx.le)?;
+                if next == 0 {
+                    break;
+                }
+                last += next as usize;
+            }
+            (
+                section.contents.to_vec(),
+                section.header.value.clone(),
+                section.header.sh_info,
+                last,
+            )
+        }
+        None => {
+            // The base definition names the binary itself
+            let soname = entries
+                .iter()
+                .find(|entry| entry.d_tag == DT_SONAME)
+                .map_or(0, |entry| entry.d_val as usize);
+            let mut contents = vec![0; VERDEF_SIZE];
+            write_verdef(&mut contents, ctx, VER_FLG_BASE, 1, soname as u32, &strtab)?;
+            let header = SectionHeader {
+                sh_type: SHT_GNU_VERDEF,
+                sh_flags: u64::from(SHF_ALLOC),
+                sh_link: dynstr as u32,
+                sh_addralign: if elf.is_64 { 8 } else { 4 },
+                ..SectionHeader::new()
+            };
+            entries.push(Dyn {
+                d_tag: DT_VERDEF,
+                d_val: 0,
+            });
+            entries.push(Dyn {
+                d_tag: DT_VERDEFNUM,
+                d_val: 0,
+            });
+            (contents, header, 1, 0)
+        }
+    };
+    for name in names {
+        let offset = contents.len();
+        let name_offset = strtab.len();
+        strtab.extend_from_slice(name.as_bytes());
+        strtab.push(0);
+        index += 1;
+        contents.resize(offset + VERDEF_SIZE, 0);
+        contents.pwrite_with((offset - last) as u32, last + 16, ctx.le)?;
+        write_verdef(
+            &mut contents[offset..],
+            ctx,
+            0,
+            index,
+            name_offset as u32,
+            &strtab,
+        )?;
+        last = offset;
+        count += 1;
+    }
+    header.sh_info = count;
+    for entry in entries.iter_mut() {
+        if entry.d_tag == DT_VERDEFNUM {
+            entry.d_val = u64::from(count);
+        }
+    }
+
+    let mut loaded = vec![Loaded {
+        index: verdef,
+        header,
+        contents,
+        tag: Some(DT_VERDEF),
+    }];
+    let mut added = Vec::new();
+    if verdef.is_none() {
+        added.push(".gnu.version_d");
+    }
+    if find(SHT_GNU_VERSYM).is_none() {
+        // Local symbols have version index 0, and the others are global with index 1
+        let mut versym = Vec::new();
+        let sym_step = sections[dynsym].header.sh_entsize as usize;
+        if sym_step < Sym::size(ctx.container) {
+            return Err(Error::Malformed("sh_entsize too small".to_string()));
+        }
+        for (symbol, entry) in sections[dynsym].contents.chunks_exact(sym_step).enumerate() {
+            let (sym, _) = Sym::try_from_ctx(entry, ctx)?;
+            let version: u16 = if symbol == 0 || sym.st_bind() == STB_LOCAL {
+                0
+            } else {
+                1
+            };
+            versym.extend_from_slice(&[0; 2]);
+            versym.pwrite_with(version, symbol * 2, ctx.le)?;
+        }
+        loaded.push(Loaded {
+            index: None,
+            header: SectionHeader {
+                sh_type: SHT_GNU_VERSYM,
+                sh_flags: u64::from(SHF_ALLOC),
+                sh_link: dynsym as u32,
+                sh_addralign: 2,
+                sh_entsize: 2,
+                ..SectionHeader::new()
+            },
+            contents: versym,
+            tag: Some(DT_VERSYM),
+        });
+        added.push(".gnu.version");
+        entries.push(Dyn {
+            d_tag: DT_VERSYM,
+            d_val: 0,
+        });
+    }
+
+    // The dynamic section ends with a null entry
+    let mut dynamic_contents = vec![0; (entries.len() + 1) * step];
+    for (position, entry) in entries.into_iter().enumerate() {
+        dynamic_contents.pwrite_with(entry, position * step, ctx)?;
+    }
+    loaded.push(Loaded {
+        index: Some(dynamic),
+        header: sections[dynamic].header.value.clone(),
+        contents: dynamic_contents,
+        tag: None,
+    });
+    loaded.push(Loaded {
+        index: Some(dynstr),
+        header: sections[dynstr].header.value.clone(),
+        contents: strtab,
+        tag: None,
+    });
+    load(bytes, elf, &sections, loaded, &added)
+}
+
+/// Write a version definition with a single name to `contents`, with no next definition.
+fn write_verdef(
+    contents: &mut [u8],
+    ctx: Ctx,
+    flags: u16,
+    index: u16,
+    name: u32,
+    strtab: &[u8],
+) -> Result<()> {
+    let hash = sysv_hash(string_at(strtab, name as usize)?);
+    // vd_version, vd_flags, vd_ndx, vd_cnt, vd_hash, vd_aux and vd_next, then vda_name and
+    // vda_next
+    contents.pwrite_with(1u16, 0, ctx.le)?;
+    contents.pwrite_with(flags, 2, ctx.le)?;
+    contents.pwrite_with(index, 4, ctx.le)?;
+    contents.pwrite_with(1u16, 6, ctx.le)?;
+    contents.pwrite_with(hash, 8, ctx.le)?;
+    contents.pwrite_with(20u32, 12, ctx.le)?;
+    contents.pwrite_with(0u32, 16, ctx.le)?;
+    contents.pwrite_with(name, 20, ctx.le)?;
+    contents.pwrite_with(0u32, 24, ctx.le)?;
+    Ok(())
+}
@@ -27,7 +27,10 @@ pub enum Error {
     /// The loaded object could not be recognized
     UnknownObject,
 
-    /// Returned when loading a macOS fat binary
+    /// The loaded file was recognized, but is not a supported object
+    UnsupportedObject(&'static str),
+
+    /// Returned when loading a macOS fat binary with 64-bit offsets
     FatBinaryUnsupported,
 
     /// The ELF section header did not match a symbol table
@@ -35,6 +38,18 @@ pub enum Error {
 
     /// A patch was too big to insert into the binary
     PatchTooBig,
+
+    /// An error in an archive member
+    Member(String, Box<Error>),
+
+    /// An error in an architecture slice of a fat binary
+    Slice(String, Box<Error>),
+
+    /// The object uses a feature that can't be updated in place
+    Unsupported(String),
+
+    /// The transformation was cancelled with a `CancellationToken`
+    Cancelled,
 }
 
 impl std::fmt::Display for Error {
@@ -44,13 +59,28 @@ impl std::fmt::Display for Error {
             Self::Goblin(e) => write!(f, "{}", e),
             Self::Scroll(e) => write!(f, "{}", e),
             Self::Malformed(s) => write!(f, "{}", s),
-            Self::ReplaceString { original, replacement } => {write!(
+            Self::ReplaceString {
+                original,
+                replacement,
+            } => {
+                write!(
                 f,
-                "Replacement string (\"{}\") must be the same size or smaller than the original (\"{}\")", replacement, original)},
+                "Replacement string (\"{}\") must be the same size or smaller than the original (\"{}\")", replacement, original)
+            }
             Self::UnknownObject => write!(f, "Unknown object type"),
-            Self::FatBinaryUnsupported => write!(f, "Fat MachO binaries are not yet supported"),
+            Self::UnsupportedObject(s) => {
+                write!(f, "Unsupported object type: this looks like {}", s)
+            }
+            Self::FatBinaryUnsupported => write!(
+                f,
+                "Fat Mach-O binaries with 64-bit offsets are not supported"
+            ),
             Self::WrongSectionHeader(s) => write!(f, "{}", s),
             Self::PatchTooBig => write!(f, "Patched data too big for original location"),
+            Self::Member(member, e) => write!(f, "{}: {}", member, e),
+            Self::Slice(arch, e) => write!(f, "{} slice: {}", arch, e),
+            Self::Unsupported(s) => write!(f, "{}", s),
+            Self::Cancelled => write!(f, "Cancelled"),
         }
     }
 }
@@ -61,6 +91,8 @@ impl std::error::Error for Error {
             Self::Io(e) => Some(e),
             Self::Goblin(e) => Some(e),
             Self::Scroll(e) => Some(e),
+            Self::Member(_, e) => Some(e.as_ref()),
+            Self::Slice(_, e) => Some(e.as_ref()),
             _ => None,
         }
     }
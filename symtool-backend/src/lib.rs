@@ -1,10 +1,21 @@
-//! This crate provides utilities for patching symbol tables in ELF and Mach-O binaries.
-//! 
+//! This crate provides utilities for patching symbol tables in ELF, Mach-O, COFF,
+//! WebAssembly and XCOFF binaries.
+//!
 //! This is the implementation behind the [symtool](https://github.com/calebzulawski/symtool)
 //! utility.
 
+pub mod coff;
+pub mod demangle;
+pub mod detect;
+pub mod dwarf;
 pub mod elf;
 pub mod error;
 pub mod mach;
 pub mod object;
 pub mod patch;
+pub mod ranlib;
+pub mod strtab;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod wasm;
+pub mod xcoff;
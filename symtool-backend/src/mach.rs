@@ -1,15 +1,39 @@
 //! Manipulate Mach-O binaries.
 
-use crate::error::Result;
-use crate::patch::{Location, Rooted};
+use crate::error::{Error, Result};
+use crate::patch::{Location, Patch, Rooted};
+use crate::strtab::{string_at, CompactStrtab, Linkedit, RebuiltStrtab, StrtabIter};
 use goblin::container::{Container, Ctx, Endian};
-use goblin::mach::load_command::{CommandVariant, SymtabCommand};
-use goblin::mach::symbols::Nlist;
+use goblin::mach::bind_opcodes::{
+    BIND_IMMEDIATE_MASK, BIND_OPCODE_ADD_ADDR_ULEB, BIND_OPCODE_DONE, BIND_OPCODE_DO_BIND,
+    BIND_OPCODE_DO_BIND_ADD_ADDR_IMM_SCALED, BIND_OPCODE_DO_BIND_ADD_ADDR_ULEB,
+    BIND_OPCODE_DO_BIND_ULEB_TIMES_SKIPPING_ULEB, BIND_OPCODE_MASK, BIND_OPCODE_SET_ADDEND_SLEB,
+    BIND_OPCODE_SET_DYLIB_ORDINAL_IMM, BIND_OPCODE_SET_DYLIB_ORDINAL_ULEB,
+    BIND_OPCODE_SET_DYLIB_SPECIAL_IMM, BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB,
+    BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM, BIND_OPCODE_SET_TYPE_IMM,
+};
+use goblin::mach::constants::cputype::{
+    get_arch_name_from_types, CpuSubType, CpuType, CPU_SUBTYPE_MASK, CPU_TYPE_ARM,
+};
+use goblin::mach::constants::{SECTION_TYPE, S_GB_ZEROFILL, S_THREAD_LOCAL_ZEROFILL, S_ZEROFILL};
+use goblin::mach::exports::{Export, ExportTrie};
+use goblin::mach::header::{MH_BUNDLE, MH_DYLIB, MH_OBJECT};
+use goblin::mach::load_command::{
+    CommandVariant, DyldInfoCommand, DysymtabCommand, LinkeditDataCommand, SymtabCommand,
+    SIZEOF_SEGMENT_COMMAND_32, SIZEOF_SEGMENT_COMMAND_64,
+};
+use goblin::mach::segment;
+use goblin::mach::symbols::{
+    Nlist, N_EXT, N_OSO, N_PBUD, N_PEXT, N_SO, N_SOL, N_STAB, N_TYPE, N_UNDF,
+};
 use goblin::mach::MachO;
 use scroll::ctx::{SizeWith, TryFromCtx};
-use scroll::Pread;
+use scroll::{Pread, Sleb128, Uleb128};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
 
-fn context_from_macho(macho: &MachO) -> Ctx {
+pub(crate) fn context_from_macho(macho: &MachO) -> Ctx {
     let container = if macho.is_64 {
         Container::Big
     } else {
@@ -23,6 +47,17 @@ fn context_from_macho(macho: &MachO) -> Ctx {
     Ctx::new(container, endian)
 }
 
+/// The name of an architecture given by CPU type and subtype, such as "arm64".
+///
+/// Unknown architectures are named by their CPU type and subtype.
+pub fn arch_name(cputype: CpuType, cpusubtype: CpuSubType) -> String {
+    let cpusubtype = cpusubtype & !CPU_SUBTYPE_MASK;
+    match get_arch_name_from_types(cputype, cpusubtype) {
+        Some(name) => name.to_string(),
+        None => format!("cputype {:#x} subtype {:#x}", cputype, cpusubtype),
+    }
+}
+
 /// An iterator over a Mach-O symbol table.
 pub struct SymtabIter<'a> {
     bytes: &'a [u8],
@@ -62,6 +97,878 @@ impl<'a> SymtabIter<'a> {
     }
 }
 
+/// Rebuild the string table of a Mach-O binary at the end of the file, so symbols can be renamed
+/// to longer names.
+///
+/// Returns `None` if there is no `LC_SYMTAB`.  The string table of linked images must stay in
+/// `__LINKEDIT`, so it is grown to cover the end of the file; see `linkedit` for the images
+/// supported.
+pub fn rebuild_strtab(bytes: &[u8], mach: &MachO) -> Result<Option<RebuiltStrtab>> {
+    let linkedit = match mach.header.filetype {
+        MH_OBJECT => None,
+        _ => Some(linkedit(bytes, mach)?),
+    };
+    let ctx = context_from_macho(mach);
+    for command in &mach.load_commands {
+        if let CommandVariant::Symtab(symtab) = command.command {
+            let location = Location {
+                offset: command.offset,
+                size: SymtabCommand::size_with(&ctx.le),
+                ctx,
+            };
+            let symtab = Rooted::new(location, symtab);
+            return RebuiltStrtab::mach(bytes, symtab, linkedit, ctx).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Construct patches compacting the symbol string table of a Mach-O binary in place.
+///
+/// The table is rebuilt with only the names symbols still refer to, such as after stripping
+/// them, storing duplicate names once and merging names into the tails of longer ones, like
+/// `CompactStrtab`.  The freed bytes at the end of the table are cleared, and `strsize` is
+/// reduced to match, keeping it a multiple of the pointer size.
+///
+/// Returns the original and compacted sizes with the patches, which are empty if there is no
+/// symbol table or the table can't be made smaller.
+pub fn compact_strtab(bytes: &[u8], mach: &MachO) -> Result<(usize, usize, Vec<Patch>)> {
+    let ctx = context_from_macho(mach);
+    let (command_offset, symtab) = match mach.load_commands.iter().find_map(|command| match command
+        .command
+    {
+        CommandVariant::Symtab(symtab) => Some((command.offset, symtab)),
+        _ => None,
+    }) {
+        Some(symtab) => symtab,
+        None => return Ok((0, 0, Vec::new())),
+    };
+    let (offset, size) = (symtab.stroff as usize, symtab.strsize as usize);
+    let table = offset
+        .checked_add(size)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| Error::Malformed("string table out of bounds".to_string()))?;
+
+    let mut symbols = Vec::new();
+    if let Some(iter) = SymtabIter::from_mach(bytes, mach) {
+        for item in iter {
+            let (_, nlist) = item?;
+            // An index of zero means the entry has no name
+            if nlist.n_strx != 0 {
+                symbols.push((string_at(table, nlist.n_strx)?, nlist));
+            }
+        }
+    }
+    let compacted = CompactStrtab::new(string_at(table, 0)?, symbols.iter().map(|(name, _)| *name));
+    let align = if mach.is_64 { 8 } else { 4 };
+    let new_size = (compacted.len() + align - 1) & !(align - 1);
+    if new_size >= size {
+        return Ok((size, size, Vec::new()));
+    }
+
+    let mut patches = vec![compacted.patch(offset, size, ctx)?];
+    for (name, nlist) in symbols {
+        let n_strx = compacted.offset(name);
+        if n_strx != nlist.n_strx {
+            patches.push(nlist.patch_with(Nlist {
+                n_strx,
+                ..nlist.value.clone()
+            })?);
+        }
+    }
+    let location = Location {
+        offset: command_offset,
+        size: SymtabCommand::size_with(&ctx.le),
+        ctx,
+    };
+    patches.push(Patch::from_primitive(
+        &location,
+        SymtabCommand {
+            strsize: new_size as u32,
+            ..symtab
+        },
+    )?);
+    Ok((size, new_size, patches))
+}
+
+/// Find the `__LINKEDIT` segment of a linked image, so it can be grown to cover data appended to
+/// the file.
+///
+/// `__LINKEDIT` must end the file and have the highest address of all segments.  The code
+/// signature must stay at the end of `__LINKEDIT` and would be invalidated anyway, so signed
+/// images return `Error::Unsupported`.
+fn linkedit(bytes: &[u8], mach: &MachO) -> Result<Linkedit> {
+    let ctx = context_from_macho(mach);
+    let mut linkedit = None;
+    let mut last_vmaddr = 0;
+    for command in &mach.load_commands {
+        let location = |size| Location {
+            offset: command.offset,
+            size,
+            ctx,
+        };
+        let (is_linkedit, vmaddr, end, segment) = match command.command {
+            CommandVariant::Segment32(segment) => (
+                segment.name()? == "__LINKEDIT",
+                u64::from(segment.vmaddr),
+                u64::from(segment.fileoff) + u64::from(segment.filesize),
+                Linkedit::Segment32(Rooted::new(location(SIZEOF_SEGMENT_COMMAND_32), segment)),
+            ),
+            CommandVariant::Segment64(segment) => (
+                segment.name()? == "__LINKEDIT",
+                segment.vmaddr,
+                segment.fileoff + segment.filesize,
+                Linkedit::Segment64(Rooted::new(location(SIZEOF_SEGMENT_COMMAND_64), segment)),
+            ),
+            CommandVariant::CodeSignature(_) => {
+                return Err(Error::Unsupported(
+                    "The string table of a signed image can't be rebuilt, since the code signature must end __LINKEDIT".to_string(),
+                ))
+            }
+            _ => continue,
+        };
+        if is_linkedit {
+            linkedit = Some((vmaddr, end, segment));
+        }
+        last_vmaddr = last_vmaddr.max(vmaddr);
+    }
+    let (vmaddr, end, segment) =
+        linkedit.ok_or_else(|| Error::Malformed("Linked image without __LINKEDIT".to_string()))?;
+    if end != bytes.len() as u64 || vmaddr != last_vmaddr {
+        return Err(Error::Unsupported(
+            "The string table can only be rebuilt if __LINKEDIT is the last segment of the image"
+                .to_string(),
+        ));
+    }
+    Ok(segment)
+}
+
+/// Set in `n_desc` of symbols defined in Thumb code.
+pub const N_ARM_THUMB_DEF: u16 = 0x0008;
+
+/// Whether a symbol is defined in Thumb code, or `None` if the object is not 32-bit ARM.
+///
+/// Edits must preserve `N_ARM_THUMB_DEF`, or branches to the symbol switch to the wrong
+/// instruction set.
+pub fn is_thumb(mach: &MachO, nlist: &Nlist) -> Option<bool> {
+    if mach.header.cputype != CPU_TYPE_ARM {
+        return None;
+    }
+    Some(nlist.n_desc & N_ARM_THUMB_DEF != 0)
+}
+
+/// A directive to the static linker encoded in the name of a symbol exported by a dylib, such
+/// as `$ld$hide$os10.4$_foo`.
+///
+/// Apple's libraries use these to present the dylib as it was in an earlier OS release to
+/// clients targeting that release.  They must keep their exact names and stay exported, or the
+/// linker silently ignores them.
+#[derive(Debug, PartialEq)]
+pub enum LdDirective<'a> {
+    /// Hide `symbol` from clients targeting `os`
+    Hide { os: &'a str, symbol: &'a str },
+
+    /// Add `symbol` for clients targeting `os`
+    Add { os: &'a str, symbol: &'a str },
+
+    /// Make `symbol` a weak import for clients targeting `os`
+    Weak { os: &'a str, symbol: &'a str },
+
+    /// Link clients targeting `os` against the install name `path`
+    InstallName { os: &'a str, path: &'a str },
+
+    /// A directive which isn't interpreted, such as `$ld$previous$`, without the `$ld$` prefix
+    Other(&'a str),
+}
+
+impl<'a> LdDirective<'a> {
+    /// The prefix of symbol names which are linker directives.
+    pub const PREFIX: &'static str = "$ld$";
+
+    /// Parse a linker directive from a symbol name, or return `None` if it isn't one.
+    pub fn parse(name: &'a str) -> Option<Self> {
+        let directive = name.strip_prefix(Self::PREFIX)?;
+        let mut parts = directive.splitn(3, '$');
+        let (action, os, argument) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(action), Some(condition), Some(argument)) if !argument.is_empty() => {
+                match condition.strip_prefix("os") {
+                    Some(os) => (action, os, argument),
+                    None => return Some(Self::Other(directive)),
+                }
+            }
+            _ => return Some(Self::Other(directive)),
+        };
+        Some(match action {
+            "hide" => Self::Hide {
+                os,
+                symbol: argument,
+            },
+            "add" => Self::Add {
+                os,
+                symbol: argument,
+            },
+            "weak" => Self::Weak {
+                os,
+                symbol: argument,
+            },
+            "install_name" => Self::InstallName { os, path: argument },
+            _ => Self::Other(directive),
+        })
+    }
+
+    /// The symbol the directive applies to, if any.
+    pub fn symbol(&self) -> Option<&'a str> {
+        match *self {
+            Self::Hide { symbol, .. } | Self::Add { symbol, .. } | Self::Weak { symbol, .. } => {
+                Some(symbol)
+            }
+            Self::InstallName { .. } | Self::Other(_) => None,
+        }
+    }
+
+    /// The same directive applied to another symbol.
+    pub fn with_symbol<'b>(&self, symbol: &'b str) -> LdDirective<'b>
+    where
+        'a: 'b,
+    {
+        match *self {
+            Self::Hide { os, .. } => LdDirective::Hide { os, symbol },
+            Self::Add { os, .. } => LdDirective::Add { os, symbol },
+            Self::Weak { os, .. } => LdDirective::Weak { os, symbol },
+            Self::InstallName { os, path } => LdDirective::InstallName { os, path },
+            Self::Other(directive) => LdDirective::Other(directive),
+        }
+    }
+}
+
+impl<'a> std::fmt::Display for LdDirective<'a> {
+    /// Formats the directive as a symbol name.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(Self::PREFIX)?;
+        match self {
+            Self::Hide { os, symbol } => write!(f, "hide$os{}${}", os, symbol),
+            Self::Add { os, symbol } => write!(f, "add$os{}${}", os, symbol),
+            Self::Weak { os, symbol } => write!(f, "weak$os{}${}", os, symbol),
+            Self::InstallName { os, path } => write!(f, "install_name$os{}${}", os, path),
+            Self::Other(directive) => f.write_str(directive),
+        }
+    }
+}
+
+const LC_BUILD_VERSION: u32 = 0x32;
+
+/// A tool recorded in an `LC_BUILD_VERSION` load command, identifying the toolchain that produced
+/// a binary.
+pub struct BuildTool {
+    /// The tool type, such as `TOOL_CLANG` or `TOOL_LD`
+    pub tool: u32,
+
+    /// The version of the tool
+    pub version: Rooted<u32>,
+}
+
+/// Read the tools recorded in the `LC_BUILD_VERSION` load commands of a Mach-O binary.
+pub fn build_tools(bytes: &[u8], mach: &MachO) -> Result<Vec<BuildTool>> {
+    let ctx = context_from_macho(mach);
+    let mut tools = Vec::new();
+    for command in &mach.load_commands {
+        let cmd: u32 = bytes.pread_with(command.offset, ctx.le)?;
+        if cmd != LC_BUILD_VERSION {
+            continue;
+        }
+        // cmd, cmdsize, platform, minos, sdk
+        let ntools: u32 = bytes.pread_with(command.offset + 20, ctx.le)?;
+        for index in 0..ntools as usize {
+            let offset = command.offset + 24 + index * 8;
+            let tool: u32 = bytes.pread_with(offset, ctx.le)?;
+            let version: u32 = bytes.pread_with(offset + 4, ctx.le)?;
+            let location = Location {
+                offset: offset + 4,
+                size: 4,
+                ctx,
+            };
+            tools.push(BuildTool {
+                tool,
+                version: Rooted::new(location, version),
+            });
+        }
+    }
+    Ok(tools)
+}
+
+/// Construct a `StrtabIter` over the strings in a named section, such as `__DWARF,__debug_str`.
+pub fn section_strings<'a>(
+    bytes: &'a [u8],
+    mach: &MachO,
+    segname: &str,
+    sectname: &str,
+) -> Result<Option<StrtabIter<'a>>> {
+    for segment in &mach.segments {
+        for (section, _) in segment.sections()? {
+            if section.segname()? == segname && section.name()? == sectname {
+                return StrtabIter::new(
+                    bytes,
+                    section.offset as usize,
+                    section.size as usize,
+                    context_from_macho(mach),
+                )
+                .map(Some);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// A section of a Mach-O binary.
+pub struct Section<'a> {
+    /// The name of the segment containing the section, such as `__TEXT`
+    pub segname: String,
+
+    /// The name of the section, such as `__text`
+    pub sectname: String,
+
+    /// The contents of the section, which are empty for zero-fill sections
+    pub contents: Rooted<&'a [u8]>,
+}
+
+/// Read the sections of a Mach-O binary.
+pub fn sections<'a>(bytes: &'a [u8], mach: &MachO) -> Result<Vec<Section<'a>>> {
+    let ctx = context_from_macho(mach);
+    let mut sections = Vec::new();
+    for segment in &mach.segments {
+        for (section, _) in segment.sections()? {
+            let zerofill = matches!(
+                section.flags & SECTION_TYPE,
+                S_ZEROFILL | S_GB_ZEROFILL | S_THREAD_LOCAL_ZEROFILL
+            );
+            let (offset, size) = if zerofill {
+                (section.offset as usize, 0)
+            } else {
+                (section.offset as usize, section.size as usize)
+            };
+            let contents = offset
+                .checked_add(size)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or_else(|| {
+                    Error::Malformed(format!(
+                        "section {} out of bounds",
+                        section.name().unwrap_or("?")
+                    ))
+                })?;
+            sections.push(Section {
+                segname: section.segname()?.to_string(),
+                sectname: section.name()?.to_string(),
+                contents: Rooted::new(Location { offset, size, ctx }, contents),
+            });
+        }
+    }
+    Ok(sections)
+}
+
+/// Set in entries of the indirect symbol table which don't refer to a symbol.
+const INDIRECT_SYMBOL_LOCAL: u32 = 0x8000_0000;
+const INDIRECT_SYMBOL_ABS: u32 = 0x4000_0000;
+
+/// Set in `r_address` of scattered relocations, which 32-bit binaries use and which don't refer
+/// to a symbol.
+const R_SCATTERED: u32 = 0x8000_0000;
+
+/// Renumber the symbols referred to by external relocations.
+///
+/// `r_symbolnum` and `r_extern` are bitfields, which are allocated from the least significant
+/// bit in little-endian binaries and from the most significant bit in big-endian ones.
+fn renumber_relocations(
+    bytes: &[u8],
+    offset: usize,
+    count: usize,
+    mach: &MachO,
+    renumber: &dyn Fn(u32) -> Result<u32>,
+    patches: &mut Vec<Patch>,
+) -> Result<()> {
+    let ctx = context_from_macho(mach);
+    for index in 0..count {
+        let offset = offset + index * 8;
+        let address: u32 = bytes.pread_with(offset, ctx.le)?;
+        if !mach.is_64 && address & R_SCATTERED != 0 {
+            continue;
+        }
+        let info: u32 = bytes.pread_with(offset + 4, ctx.le)?;
+        let (symbolnum, external) = if mach.little_endian {
+            (info & 0x00ff_ffff, info & 0x0800_0000 != 0)
+        } else {
+            (info >> 8, info & 0x10 != 0)
+        };
+        if !external {
+            continue;
+        }
+        let new_symbolnum = renumber(symbolnum)?;
+        if new_symbolnum == symbolnum {
+            continue;
+        }
+        let new_info = if mach.little_endian {
+            info & !0x00ff_ffff | new_symbolnum
+        } else {
+            info & 0xff | new_symbolnum << 8
+        };
+        let location = Location {
+            offset: offset + 4,
+            size: 4,
+            ctx,
+        };
+        patches.push(Patch::from_primitive(&location, new_info)?);
+    }
+    Ok(())
+}
+
+/// Find the `LC_DYSYMTAB` load command of a Mach-O binary, with its offset.
+fn dysymtab_command(mach: &MachO) -> Option<(usize, DysymtabCommand)> {
+    mach.load_commands
+        .iter()
+        .find_map(|command| match command.command {
+            CommandVariant::Dysymtab(dysymtab) => Some((command.offset, dysymtab)),
+            _ => None,
+        })
+}
+
+/// Find the symbols referred to by the indirect symbol table and the external relocations of a
+/// Mach-O binary, which can't be removed from the symbol table.
+pub fn referenced_symbols(bytes: &[u8], mach: &MachO) -> Result<HashSet<usize>> {
+    let ctx = context_from_macho(mach);
+    let referenced = RefCell::new(HashSet::new());
+    let record = |index: u32| {
+        referenced.borrow_mut().insert(index as usize);
+        Ok(index)
+    };
+    // Relocations are visited by renumbering them to the same indices, which patches nothing
+    let mut patches = Vec::new();
+    if let Some((_, dysymtab)) = dysymtab_command(mach) {
+        for index in 0..dysymtab.nindirectsyms as usize {
+            let offset = dysymtab.indirectsymoff as usize + index * 4;
+            let symbol: u32 = bytes.pread_with(offset, ctx.le)?;
+            if symbol & (INDIRECT_SYMBOL_LOCAL | INDIRECT_SYMBOL_ABS) == 0 {
+                record(symbol)?;
+            }
+        }
+        renumber_relocations(
+            bytes,
+            dysymtab.extreloff as usize,
+            dysymtab.nextrel as usize,
+            mach,
+            &record,
+            &mut patches,
+        )?;
+    }
+    for segment in &mach.segments {
+        for (section, _) in segment.sections()? {
+            renumber_relocations(
+                bytes,
+                section.reloff as usize,
+                section.nreloc as usize,
+                mach,
+                &record,
+                &mut patches,
+            )?;
+        }
+    }
+    Ok(referenced.into_inner())
+}
+
+/// Construct patches removing the debugging (stab) entries from the symbol table of a Mach-O
+/// binary.
+///
+/// Returns the number of entries removed with the patches, which are empty if there are no
+/// stabs.
+pub fn strip_stabs(bytes: &[u8], mach: &MachO) -> Result<(usize, Vec<Patch>)> {
+    strip_symbols(bytes, mach, &|_, nlist| nlist.n_type & N_STAB != 0)
+}
+
+/// Construct patches removing the debugging (stab) entries naming source and object files
+/// (`N_SO`, `N_SOL` and `N_OSO`) from the symbol table of a Mach-O binary.
+///
+/// Without them, the debug map no longer refers to the objects the binary was linked from, so
+/// `dsymutil` can't find their debug info.  Returns the number of entries removed with the
+/// patches, like `strip_stabs`.
+pub fn strip_file_symbols(bytes: &[u8], mach: &MachO) -> Result<(usize, Vec<Patch>)> {
+    strip_symbols(bytes, mach, &|_, nlist| {
+        matches!(nlist.n_type, N_SO | N_SOL | N_OSO)
+    })
+}
+
+/// Construct patches removing the DWARF sections (the `__DWARF` segment) of a Mach-O binary.
+///
+/// Sections can't be removed from a segment in place, so their contents are cleared and their
+/// size and relocation count are set to zero.  The debugging (stab) entries of the symbol table
+/// are removed by `strip_stabs`.  Returns the names of the cleared sections with the patches.
+pub fn strip_dwarf(bytes: &[u8], mach: &MachO) -> Result<(Vec<String>, Vec<Patch>)> {
+    let ctx = context_from_macho(mach);
+    let mut names = Vec::new();
+    let mut patches = Vec::new();
+    for command in &mach.load_commands {
+        let (nsects, mut offset) = match command.command {
+            CommandVariant::Segment32(segment) => {
+                (segment.nsects, command.offset + SIZEOF_SEGMENT_COMMAND_32)
+            }
+            CommandVariant::Segment64(segment) => {
+                (segment.nsects, command.offset + SIZEOF_SEGMENT_COMMAND_64)
+            }
+            _ => continue,
+        };
+        for _ in 0..nsects {
+            let section: segment::Section = bytes.pread_with(offset, ctx)?;
+            let location = Location {
+                offset,
+                size: segment::Section::size_with(&ctx),
+                ctx,
+            };
+            offset += location.size;
+            if section.segname()? != "__DWARF" || section.size == 0 {
+                continue;
+            }
+            names.push(section.name()?.to_string());
+            let start = section.offset as usize;
+            let size = section.size as usize;
+            if start.checked_add(size).is_none_or(|end| end > bytes.len()) {
+                return Err(Error::Malformed(format!(
+                    "section {} out of bounds",
+                    section.name()?
+                )));
+            }
+            let contents = Location {
+                offset: start,
+                size,
+                ctx,
+            };
+            patches.push(Patch::from_bytes(&contents, &vec![0; size])?);
+            let header = Rooted::new(location, section);
+            patches.push(header.patch_with(segment::Section {
+                size: 0,
+                reloff: 0,
+                nreloc: 0,
+                ..header.value
+            })?);
+        }
+    }
+    Ok((names, patches))
+}
+
+/// Construct patches removing the symbol table entries of a linked Mach-O binary that dyld
+/// doesn't need, like `strip`.
+///
+/// Undefined symbols, the symbols referred to by the indirect symbol table or relocations, and
+/// the exported symbols of dylibs and bundles are kept, as are the symbols for which `keep`
+/// returns `true`.  Relocatable objects need their symbol table to be linked, and return
+/// `Error::Unsupported`.  Returns the number of entries removed with the patches.
+pub fn strip_all(
+    bytes: &[u8],
+    mach: &MachO,
+    keep: &dyn Fn(usize) -> bool,
+) -> Result<(usize, Vec<Patch>)> {
+    if mach.header.filetype == MH_OBJECT {
+        return Err(Error::Unsupported(
+            "The symbol table of relocatable objects is needed to link them".to_string(),
+        ));
+    }
+    let referenced = referenced_symbols(bytes, mach)?;
+    let exports = matches!(mach.header.filetype, MH_DYLIB | MH_BUNDLE);
+    strip_symbols(bytes, mach, &|index, nlist| {
+        if nlist.n_type & N_STAB != 0 {
+            return true;
+        }
+        let undefined = nlist.n_type & N_TYPE == N_UNDF;
+        let exported = exports && nlist.n_type & N_EXT != 0 && nlist.n_type & N_PEXT == 0;
+        !(undefined || exported || referenced.contains(&index) || keep(index))
+    })
+}
+
+/// Construct patches removing the symbol table entries of a Mach-O binary which aren't needed to
+/// link it, like `strip --strip-unneeded`.
+///
+/// Debugging (stab) entries, local symbols and undefined symbols are removed unless the indirect
+/// symbol table or relocations refer to them.  Other symbols for which `keep` returns `true` are
+/// kept.  Returns the number of entries removed with the patches.
+pub fn strip_unneeded(
+    bytes: &[u8],
+    mach: &MachO,
+    keep: &dyn Fn(usize) -> bool,
+) -> Result<(usize, Vec<Patch>)> {
+    let referenced = referenced_symbols(bytes, mach)?;
+    strip_symbols(bytes, mach, &|index, nlist| {
+        if nlist.n_type & N_STAB != 0 {
+            return true;
+        }
+        // Common symbols are undefined with a size
+        let undefined = nlist.n_type & N_TYPE == N_UNDF && nlist.n_value == 0;
+        let unneeded = nlist.n_type & N_EXT == 0 || undefined;
+        unneeded && !referenced.contains(&index) && !keep(index)
+    })
+}
+
+/// Construct patches removing the local symbols of a Mach-O binary, keeping the external ones,
+/// like `strip -x`.
+///
+/// Debugging (stab) entries and symbols without `N_EXT` are removed unless the indirect symbol
+/// table or relocations refer to them.  Symbols for which `keep` returns `true` are kept.  Returns
+/// the number of entries removed with the patches.
+pub fn discard_locals(
+    bytes: &[u8],
+    mach: &MachO,
+    keep: &dyn Fn(usize) -> bool,
+) -> Result<(usize, Vec<Patch>)> {
+    let referenced = referenced_symbols(bytes, mach)?;
+    strip_symbols(bytes, mach, &|index, nlist| {
+        if nlist.n_type & N_STAB != 0 {
+            return true;
+        }
+        nlist.n_type & N_EXT == 0 && !referenced.contains(&index) && !keep(index)
+    })
+}
+
+/// Construct patches removing the entries for which `strip` returns `true` from the symbol table
+/// of a Mach-O binary.
+///
+/// `strip` is called with the index of each entry.  The remaining entries are moved to the start
+/// of the table, and the symbol ranges of `LC_DYSYMTAB`, the indirect symbol table and external
+/// relocations are renumbered to match; removing an entry they refer to is an error.  The names
+/// of removed entries are left in the string table.  Returns the number of entries removed with
+/// the patches, which are empty if nothing is removed.
+pub fn strip_symbols(
+    bytes: &[u8],
+    mach: &MachO,
+    strip: &dyn Fn(usize, &Nlist) -> bool,
+) -> Result<(usize, Vec<Patch>)> {
+    rewrite_symbols(bytes, mach, strip, false)
+}
+
+/// Construct patches sorting the symbol table of a Mach-O binary into the local, external defined
+/// and undefined groups `LC_DYSYMTAB` describes.
+///
+/// Changing the binding of symbols can leave them outside their group, which the linker and
+/// dyld reject.  Entries are moved stably, so they keep their order within each group, and the
+/// ranges of `LC_DYSYMTAB`, the indirect symbol table and external relocations are updated to
+/// match.  The patches are empty if the table is already sorted, or if the binary has no
+/// `LC_DYSYMTAB`.
+pub fn sort_symbols(bytes: &[u8], mach: &MachO) -> Result<Vec<Patch>> {
+    if dysymtab_command(mach).is_none() {
+        return Ok(Vec::new());
+    }
+    let (_, patches) = rewrite_symbols(bytes, mach, &|_, _| false, true)?;
+    Ok(patches)
+}
+
+/// The `LC_DYSYMTAB` group of a symbol table entry: local (including stabs), external defined or
+/// undefined (including common symbols).
+fn symbol_group(nlist: &Nlist) -> usize {
+    if nlist.n_type & N_STAB != 0 || nlist.n_type & N_EXT == 0 {
+        0
+    } else if matches!(nlist.n_type & N_TYPE, N_UNDF | N_PBUD) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Rewrite the symbol table of a Mach-O binary, removing the entries for which `strip` returns
+/// `true` and, if `sort` is set, sorting the remaining ones into their `LC_DYSYMTAB` groups.
+fn rewrite_symbols(
+    bytes: &[u8],
+    mach: &MachO,
+    strip: &dyn Fn(usize, &Nlist) -> bool,
+    sort: bool,
+) -> Result<(usize, Vec<Patch>)> {
+    let ctx = context_from_macho(mach);
+    let mut symtab = None;
+    let mut dysymtab = None;
+    for command in &mach.load_commands {
+        match command.command {
+            CommandVariant::Symtab(symtab_command) => {
+                symtab = Some((command.offset, symtab_command))
+            }
+            CommandVariant::Dysymtab(dysymtab_command) => {
+                dysymtab = Some((command.offset, dysymtab_command))
+            }
+            _ => {}
+        }
+    }
+    let (symtab_offset, symtab) = match symtab {
+        Some(symtab) => symtab,
+        None => return Ok((0, Vec::new())),
+    };
+
+    // The new index of each entry, or `None` for removed ones
+    let size = Nlist::size_with(&ctx);
+    let symoff = symtab.symoff as usize;
+    let entries = symoff
+        .checked_add(symtab.nsyms as usize * size)
+        .and_then(|end| bytes.get(symoff..end))
+        .ok_or_else(|| {
+            Error::Malformed("Symbol table extends past the end of the file".to_string())
+        })?;
+    let mut kept = Vec::with_capacity(symtab.nsyms as usize);
+    let mut groups = [0u32; 3];
+    for (index, entry) in entries.chunks(size).enumerate() {
+        let nlist: Nlist = entry.pread_with(0, ctx)?;
+        if !strip(index, &nlist) {
+            let group = symbol_group(&nlist);
+            groups[group] += 1;
+            kept.push((group, index, entry));
+        }
+    }
+    if sort {
+        // A stable sort, which keeps the order of the entries within each group
+        kept.sort_by_key(|(group, _, _)| *group);
+    }
+    let mut indexes = vec![None; symtab.nsyms as usize];
+    let mut table = Vec::with_capacity(entries.len());
+    for (new_index, (_, index, entry)) in kept.iter().enumerate() {
+        indexes[*index] = Some(new_index as u32);
+        table.extend_from_slice(entry);
+    }
+    let stripped = indexes.iter().filter(|index| index.is_none()).count();
+    let moved = kept
+        .iter()
+        .enumerate()
+        .any(|(new_index, (_, index, _))| new_index != *index);
+    // The new `LC_DYSYMTAB` ranges of the groups, if the table is sorted
+    let sorted_ranges = [
+        (0, groups[0]),
+        (groups[0], groups[1]),
+        (groups[0] + groups[1], groups[2]),
+    ];
+    let regrouped = sort
+        && dysymtab.is_some_and(|(_, dysymtab)| {
+            sorted_ranges
+                != [
+                    (dysymtab.ilocalsym, dysymtab.nlocalsym),
+                    (dysymtab.iextdefsym, dysymtab.nextdefsym),
+                    (dysymtab.iundefsym, dysymtab.nundefsym),
+                ]
+        });
+    if stripped == 0 && !moved && !regrouped {
+        return Ok((0, Vec::new()));
+    }
+    let renumber = |index: u32| match indexes.get(index as usize) {
+        Some(Some(new_index)) => Ok(*new_index),
+        Some(None) => Err(Error::Malformed(format!(
+            "Symbol {} is removed, but is referred to by the binary",
+            index
+        ))),
+        None => Err(Error::Malformed(format!(
+            "Reference to symbol {} past the end of the symbol table",
+            index
+        ))),
+    };
+    // The new index of the first entry at or after `index`, for the bounds of a range
+    let renumber_bound = |index: u32| -> u32 {
+        indexes
+            .iter()
+            .take(index as usize)
+            .filter(|index| index.is_some())
+            .count() as u32
+    };
+
+    let mut patches = Vec::new();
+    table.resize(entries.len(), 0);
+    let location = Location {
+        offset: symoff,
+        size: entries.len(),
+        ctx,
+    };
+    patches.push(Patch::from_bytes(&location, &table)?);
+    let location = Location {
+        offset: symtab_offset,
+        size: SymtabCommand::size_with(&ctx.le),
+        ctx,
+    };
+    patches.push(Patch::from_primitive(
+        &location,
+        SymtabCommand {
+            nsyms: symtab.nsyms - stripped as u32,
+            ..symtab
+        },
+    )?);
+
+    if let Some((dysymtab_offset, dysymtab)) = dysymtab {
+        if dysymtab.ntoc != 0 || dysymtab.nmodtab != 0 || dysymtab.nextrefsyms != 0 {
+            return Err(Error::Unsupported(
+                "Rewriting the symbol table of binaries with a table of contents, module table \
+                 or referenced symbol table is not supported"
+                    .to_string(),
+            ));
+        }
+        let range = |first: u32, count: u32| {
+            let new_first = renumber_bound(first);
+            (
+                new_first,
+                renumber_bound(first.saturating_add(count)) - new_first,
+            )
+        };
+        let [(ilocalsym, nlocalsym), (iextdefsym, nextdefsym), (iundefsym, nundefsym)] = if sort {
+            sorted_ranges
+        } else {
+            [
+                range(dysymtab.ilocalsym, dysymtab.nlocalsym),
+                range(dysymtab.iextdefsym, dysymtab.nextdefsym),
+                range(dysymtab.iundefsym, dysymtab.nundefsym),
+            ]
+        };
+        let location = Location {
+            offset: dysymtab_offset,
+            size: DysymtabCommand::size_with(&ctx.le),
+            ctx,
+        };
+        patches.push(Patch::from_primitive(
+            &location,
+            DysymtabCommand {
+                ilocalsym,
+                nlocalsym,
+                iextdefsym,
+                nextdefsym,
+                iundefsym,
+                nundefsym,
+                ..dysymtab
+            },
+        )?);
+
+        for index in 0..dysymtab.nindirectsyms as usize {
+            let offset = dysymtab.indirectsymoff as usize + index * 4;
+            let symbol: u32 = bytes.pread_with(offset, ctx.le)?;
+            if symbol & (INDIRECT_SYMBOL_LOCAL | INDIRECT_SYMBOL_ABS) != 0 {
+                continue;
+            }
+            let new_symbol = renumber(symbol)?;
+            if new_symbol != symbol {
+                let location = Location {
+                    offset,
+                    size: 4,
+                    ctx,
+                };
+                patches.push(Patch::from_primitive(&location, new_symbol)?);
+            }
+        }
+        renumber_relocations(
+            bytes,
+            dysymtab.extreloff as usize,
+            dysymtab.nextrel as usize,
+            mach,
+            &renumber,
+            &mut patches,
+        )?;
+    }
+    for segment in &mach.segments {
+        for (section, _) in segment.sections()? {
+            renumber_relocations(
+                bytes,
+                section.reloff as usize,
+                section.nreloc as usize,
+                mach,
+                &renumber,
+                &mut patches,
+            )?;
+        }
+    }
+    Ok((stripped, patches))
+}
+
 impl<'a> std::iter::Iterator for SymtabIter<'a> {
     type Item = Result<(Option<Rooted<&'a str>>, Rooted<Nlist>)>;
 
@@ -83,7 +990,7 @@ impl<'a> std::iter::Iterator for SymtabIter<'a> {
                     Rooted::new(location, nlist)
                 };
                 let name = if nlist.n_strx != 0 {
-                    let offset = self.stroff + nlist.n_strx as usize;
+                    let offset = self.stroff + nlist.n_strx;
                     let name: &str = self.bytes.pread(offset)?;
                     let location = Location {
                         offset,
@@ -99,3 +1006,339 @@ impl<'a> std::iter::Iterator for SymtabIter<'a> {
         }
     }
 }
+
+/// The load command locating the export trie of binaries using chained fixups.
+const LC_DYLD_EXPORTS_TRIE: u32 = 0x8000_0033;
+
+/// Read the symbols exported by a Mach-O binary from its export trie.
+///
+/// The trie is located by `LC_DYLD_INFO`, or by `LC_DYLD_EXPORTS_TRIE` in binaries using
+/// chained fixups.
+pub fn exports<'a>(bytes: &'a [u8], mach: &MachO<'a>) -> Result<Vec<Export<'a>>> {
+    let ctx = context_from_macho(mach);
+    for command in &mach.load_commands {
+        let info = match command.command {
+            CommandVariant::DyldInfo(info) | CommandVariant::DyldInfoOnly(info) => info,
+            _ if command.command.cmd() == LC_DYLD_EXPORTS_TRIE => {
+                let data: LinkeditDataCommand = bytes.pread_with(command.offset, ctx.le)?;
+                DyldInfoCommand {
+                    export_off: data.dataoff,
+                    export_size: data.datasize,
+                    ..DyldInfoCommand::default()
+                }
+            }
+            _ => continue,
+        };
+        return Ok(ExportTrie::new(bytes, &info).exports(&mach.libs)?);
+    }
+    Ok(Vec::new())
+}
+
+/// The load command locating the chained fixups of binaries using them instead of bind opcodes.
+const LC_DYLD_CHAINED_FIXUPS: u32 = 0x8000_0034;
+
+/// The formats of the imports table of chained fixups.
+const DYLD_CHAINED_IMPORT: u32 = 1;
+const DYLD_CHAINED_IMPORT_ADDEND: u32 = 2;
+const DYLD_CHAINED_IMPORT_ADDEND64: u32 = 3;
+
+/// Used by arm64e binaries in place of other bind opcodes.
+const BIND_OPCODE_THREADED: u8 = 0xd0;
+const BIND_SUBOPCODE_THREADED_SET_BIND_ORDINAL_TABLE_SIZE_ULEB: u8 = 0x00;
+
+/// How a library ordinal is encoded.
+#[derive(Debug)]
+enum OrdinalEncoding {
+    /// `BIND_OPCODE_SET_DYLIB_ORDINAL_IMM` or `BIND_OPCODE_SET_DYLIB_SPECIAL_IMM`
+    Immediate(Location),
+
+    /// The operand of `BIND_OPCODE_SET_DYLIB_ORDINAL_ULEB`
+    Uleb(Location),
+
+    /// A `DYLD_CHAINED_IMPORT` or `DYLD_CHAINED_IMPORT_ADDEND` entry
+    ChainedImport(Rooted<u32>),
+
+    /// A `DYLD_CHAINED_IMPORT_ADDEND64` entry
+    ChainedImport64(Rooted<u64>),
+}
+
+/// The library ordinal an import is bound from, as recorded in the dynamic linking information
+/// of a Mach-O binary.
+///
+/// Ordinals index the dylib load commands from 1, or are special values such as
+/// `BIND_SPECIAL_DYLIB_FLAT_LOOKUP` (-2).
+#[derive(Debug)]
+pub struct ImportOrdinal<'a> {
+    /// The name of the imported symbol
+    pub symbol: &'a str,
+
+    /// The library ordinal
+    pub ordinal: i32,
+
+    /// Whether other imports are bound with the same opcode, so the ordinal can't be changed for
+    /// this import alone
+    pub shared: bool,
+    encoding: Rc<OrdinalEncoding>,
+}
+
+impl<'a> ImportOrdinal<'a> {
+    /// Construct a patch binding the import from the library with ordinal `ordinal`.
+    pub fn patch(&self, ordinal: u16) -> Result<Patch> {
+        let too_big = || {
+            Error::Unsupported(format!(
+                "Library ordinal {} doesn't fit in the bind information of '{}'",
+                ordinal, self.symbol
+            ))
+        };
+        match &*self.encoding {
+            OrdinalEncoding::Immediate(location) => {
+                if ordinal > u16::from(BIND_IMMEDIATE_MASK) {
+                    return Err(too_big());
+                }
+                Patch::from_bytes(
+                    location,
+                    &[BIND_OPCODE_SET_DYLIB_ORDINAL_IMM | ordinal as u8],
+                )
+            }
+            OrdinalEncoding::Uleb(location) => {
+                // Pad with continuation bytes to keep the opcode stream in place
+                let mut operand = vec![0x80; location.size];
+                let mut value = ordinal;
+                for byte in operand.iter_mut() {
+                    *byte |= (value & 0x7f) as u8;
+                    value >>= 7;
+                }
+                *operand.last_mut().unwrap() &= 0x7f;
+                if value != 0 {
+                    return Err(too_big());
+                }
+                Patch::from_bytes(location, &operand)
+            }
+            OrdinalEncoding::ChainedImport(import) => {
+                // lib_ordinal is the low 8 bits, with values from 0xf0 reserved for special
+                // ordinals
+                if ordinal >= 0xf0 {
+                    return Err(too_big());
+                }
+                import.patch_with_primitive(import.value & !0xff | u32::from(ordinal))
+            }
+            OrdinalEncoding::ChainedImport64(import) => {
+                if ordinal >= 0xfff0 {
+                    return Err(too_big());
+                }
+                import.patch_with_primitive(import.value & !0xffff | u64::from(ordinal))
+            }
+        }
+    }
+}
+
+/// Read the library ordinals of imports from bind opcodes.
+///
+/// Weak binds are looked up by name in every library, and have no ordinals.
+fn bind_ordinals<'a>(
+    bytes: &'a [u8],
+    offset: u32,
+    size: u32,
+    ctx: Ctx,
+    ordinals: &mut Vec<ImportOrdinal<'a>>,
+) -> Result<()> {
+    let end = offset as usize + size as usize;
+    let opcodes = bytes.get(..end).ok_or_else(|| {
+        Error::Malformed("Bind information extends past the end of the file".to_string())
+    })?;
+    let mut offset = offset as usize;
+    let mut current: Option<(Rc<OrdinalEncoding>, i32)> = None;
+    let mut group = Vec::new();
+    let mut finish_group = |group: &mut Vec<ImportOrdinal<'a>>| {
+        let shared = group
+            .iter()
+            .any(|import: &ImportOrdinal| import.symbol != group[0].symbol);
+        for mut import in group.drain(..) {
+            import.shared = shared;
+            ordinals.push(import);
+        }
+    };
+    while offset < end {
+        let byte = opcodes[offset];
+        let (opcode, immediate) = (byte & BIND_OPCODE_MASK, byte & BIND_IMMEDIATE_MASK);
+        offset += 1;
+        match opcode {
+            BIND_OPCODE_SET_DYLIB_ORDINAL_IMM | BIND_OPCODE_SET_DYLIB_SPECIAL_IMM => {
+                finish_group(&mut group);
+                let ordinal = if opcode == BIND_OPCODE_SET_DYLIB_ORDINAL_IMM || immediate == 0 {
+                    i32::from(immediate)
+                } else {
+                    i32::from((immediate | BIND_OPCODE_MASK) as i8)
+                };
+                let location = Location {
+                    offset: offset - 1,
+                    size: 1,
+                    ctx,
+                };
+                current = Some((Rc::new(OrdinalEncoding::Immediate(location)), ordinal));
+            }
+            BIND_OPCODE_SET_DYLIB_ORDINAL_ULEB => {
+                finish_group(&mut group);
+                let start = offset;
+                let ordinal = Uleb128::read(opcodes, &mut offset)?;
+                let location = Location {
+                    offset: start,
+                    size: offset - start,
+                    ctx,
+                };
+                current = Some((Rc::new(OrdinalEncoding::Uleb(location)), ordinal as i32));
+            }
+            BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM => {
+                let symbol: &str = opcodes.pread(offset)?;
+                offset += symbol.len() + 1;
+                if let Some((encoding, ordinal)) = &current {
+                    group.push(ImportOrdinal {
+                        symbol,
+                        ordinal: *ordinal,
+                        shared: false,
+                        encoding: encoding.clone(),
+                    });
+                }
+            }
+            BIND_OPCODE_SET_ADDEND_SLEB => {
+                Sleb128::read(opcodes, &mut offset)?;
+            }
+            BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB
+            | BIND_OPCODE_ADD_ADDR_ULEB
+            | BIND_OPCODE_DO_BIND_ADD_ADDR_ULEB => {
+                Uleb128::read(opcodes, &mut offset)?;
+            }
+            BIND_OPCODE_DO_BIND_ULEB_TIMES_SKIPPING_ULEB => {
+                Uleb128::read(opcodes, &mut offset)?;
+                Uleb128::read(opcodes, &mut offset)?;
+            }
+            BIND_OPCODE_THREADED => {
+                if immediate == BIND_SUBOPCODE_THREADED_SET_BIND_ORDINAL_TABLE_SIZE_ULEB {
+                    Uleb128::read(opcodes, &mut offset)?;
+                }
+            }
+            BIND_OPCODE_DONE
+            | BIND_OPCODE_SET_TYPE_IMM
+            | BIND_OPCODE_DO_BIND
+            | BIND_OPCODE_DO_BIND_ADD_ADDR_IMM_SCALED => {}
+            _ => {
+                return Err(Error::Malformed(format!(
+                    "Unknown bind opcode {:#x}",
+                    opcode
+                )))
+            }
+        }
+    }
+    finish_group(&mut group);
+    Ok(())
+}
+
+/// Interpret the values of an unsigned ordinal field from `first_special` as negative special
+/// ordinals.
+fn special_ordinal(ordinal: i32, first_special: i32, range: i32) -> i32 {
+    if ordinal >= first_special {
+        ordinal - range
+    } else {
+        ordinal
+    }
+}
+
+/// Read the library ordinals of imports from the imports table of chained fixups.
+fn chained_import_ordinals<'a>(
+    bytes: &'a [u8],
+    fixups: usize,
+    ctx: Ctx,
+    ordinals: &mut Vec<ImportOrdinal<'a>>,
+) -> Result<()> {
+    // fixups_version, starts_offset, imports_offset, symbols_offset, imports_count,
+    // imports_format, symbols_format
+    let imports_offset: u32 = bytes.pread_with(fixups + 8, ctx.le)?;
+    let symbols_offset: u32 = bytes.pread_with(fixups + 12, ctx.le)?;
+    let imports_count: u32 = bytes.pread_with(fixups + 16, ctx.le)?;
+    let imports_format: u32 = bytes.pread_with(fixups + 20, ctx.le)?;
+    let symbols_format: u32 = bytes.pread_with(fixups + 24, ctx.le)?;
+    if symbols_format != 0 {
+        return Err(Error::Unsupported(
+            "Compressed chained fixup symbols are not supported".to_string(),
+        ));
+    }
+    let imports = fixups + imports_offset as usize;
+    let symbols = fixups + symbols_offset as usize;
+    for index in 0..imports_count as usize {
+        let (ordinal, name_offset, encoding) = match imports_format {
+            DYLD_CHAINED_IMPORT | DYLD_CHAINED_IMPORT_ADDEND => {
+                let size = if imports_format == DYLD_CHAINED_IMPORT {
+                    4
+                } else {
+                    8
+                };
+                let offset = imports + index * size;
+                let import: u32 = bytes.pread_with(offset, ctx.le)?;
+                let location = Location {
+                    offset,
+                    size: 4,
+                    ctx,
+                };
+                (
+                    special_ordinal(i32::from(import as u8), 0xf0, 0x100),
+                    (import >> 9) as usize,
+                    OrdinalEncoding::ChainedImport(Rooted::new(location, import)),
+                )
+            }
+            DYLD_CHAINED_IMPORT_ADDEND64 => {
+                let offset = imports + index * 16;
+                let import: u64 = bytes.pread_with(offset, ctx.le)?;
+                let location = Location {
+                    offset,
+                    size: 8,
+                    ctx,
+                };
+                (
+                    special_ordinal(i32::from(import as u16), 0xfff0, 0x10000),
+                    (import >> 32) as usize,
+                    OrdinalEncoding::ChainedImport64(Rooted::new(location, import)),
+                )
+            }
+            format => {
+                return Err(Error::Malformed(format!(
+                    "Unknown chained fixup imports format {}",
+                    format
+                )))
+            }
+        };
+        ordinals.push(ImportOrdinal {
+            symbol: bytes.pread(symbols + name_offset)?,
+            ordinal,
+            shared: false,
+            encoding: Rc::new(encoding),
+        });
+    }
+    Ok(())
+}
+
+/// Read the library ordinals of the imports of a Mach-O binary from its bind opcodes or chained
+/// fixups.
+pub fn import_ordinals<'a>(bytes: &'a [u8], mach: &MachO) -> Result<Vec<ImportOrdinal<'a>>> {
+    let ctx = context_from_macho(mach);
+    let mut ordinals = Vec::new();
+    for command in &mach.load_commands {
+        match command.command {
+            CommandVariant::DyldInfo(info) | CommandVariant::DyldInfoOnly(info) => {
+                bind_ordinals(bytes, info.bind_off, info.bind_size, ctx, &mut ordinals)?;
+                bind_ordinals(
+                    bytes,
+                    info.lazy_bind_off,
+                    info.lazy_bind_size,
+                    ctx,
+                    &mut ordinals,
+                )?;
+            }
+            _ if command.command.cmd() == LC_DYLD_CHAINED_FIXUPS => {
+                let data: LinkeditDataCommand = bytes.pread_with(command.offset, ctx.le)?;
+                chained_import_ordinals(bytes, data.dataoff as usize, ctx, &mut ordinals)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(ordinals)
+}
@@ -1,10 +1,14 @@
 //! Apply transformations to an object.
 
 use crate::error::{Error, Result, TransformError, TransformResult};
-use crate::patch::Patch;
+use crate::patch::{Patch, Rooted};
 use goblin::elf::Elf;
-use goblin::mach::MachO;
-use std::io::{Read, Seek, SeekFrom, Write};
+use goblin::mach::fat::{FatHeader, FAT_MAGIC, SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER};
+use goblin::mach::{MachO, MultiArch};
+use scroll::{Pread, Pwrite, BE};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 fn get_variant_and_identifiers<R: Read + Seek>(
     reader: &mut R,
@@ -19,7 +23,46 @@ fn get_variant_and_identifiers<R: Read + Seek>(
     Ok((variant, identifiers))
 }
 
-enum ArchiveBuilder<'a> {
+/// Check for the archive magic number, allowing inputs too short to contain it.
+fn is_archive<R: Read + Seek>(reader: &mut R) -> Result<bool> {
+    let mut magic = Vec::new();
+    reader.seek(SeekFrom::Start(0))?;
+    reader
+        .take(goblin::archive::SIZEOF_MAGIC as u64)
+        .read_to_end(&mut magic)?;
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(magic == goblin::archive::MAGIC)
+}
+
+/// The magic number of fat binaries with 64-bit offsets, which goblin doesn't define.
+const FAT_MAGIC_64: u32 = 0xcafe_babf;
+
+/// Check for the fat Mach-O magic number.
+///
+/// Java class files share the magic number, but store a version where fat binaries store the
+/// number of architectures.
+fn is_fat<R: Read + Seek>(reader: &mut R) -> Result<bool> {
+    let mut header = Vec::new();
+    reader.seek(SeekFrom::Start(0))?;
+    reader
+        .take(SIZEOF_FAT_HEADER as u64)
+        .read_to_end(&mut header)?;
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(match header.pread_with::<FatHeader>(0, BE) {
+        Ok(header) => {
+            (header.magic == FAT_MAGIC || header.magic == FAT_MAGIC_64) && header.nfat_arch < 20
+        }
+        Err(_) => false,
+    })
+}
+
+/// Round `offset` up to a multiple of `2^align`.
+fn align_up(offset: usize, align: u32) -> Option<usize> {
+    let mask = 1usize.checked_shl(align)? - 1;
+    Some(offset.checked_add(mask)? & !mask)
+}
+
+pub(crate) enum ArchiveBuilder<'a> {
     Bsd(ar::Builder<&'a mut dyn Write>),
     Gnu(ar::GnuBuilder<&'a mut dyn Write>),
 }
@@ -50,79 +93,554 @@ impl<'a> ArchiveBuilder<'a> {
 pub enum Object<'a> {
     Elf(Box<Elf<'a>>),
     MachO(Box<MachO<'a>>),
+
+    /// An object parsed by a format registered with `Formats::register`
+    Other(Box<dyn ForeignObject<'a> + 'a>),
+}
+
+/// A symbol in an object of a registered format.
+pub struct ForeignSymbol<'a> {
+    /// The name of the symbol
+    pub name: Rooted<&'a str>,
+
+    /// The index of the symbol in the object's symbol table
+    pub index: usize,
+
+    /// Whether the symbol is visible outside the object
+    pub global: bool,
+
+    /// Whether the symbol has hidden visibility
+    pub hidden: bool,
+}
+
+/// An object parsed by a registered format.
+///
+/// Symbols are exposed generically so that renames and visibility rules apply to any format.
+pub trait ForeignObject<'a> {
+    /// The symbols in the object's symbol table.
+    fn symbols(&self) -> Result<Vec<ForeignSymbol<'a>>>;
+
+    /// Construct a patch changing the visibility of a symbol.
+    ///
+    /// Returns `None` if the visibility of the symbol can't be changed.
+    fn set_hidden(&self, symbol: &ForeignSymbol<'a>, hidden: bool) -> Result<Option<Patch>>;
+
+    /// Construct a patch changing whether a symbol is visible outside the object.
+    ///
+    /// Returns `None` if the binding of the symbol can't be changed, which is the default.
+    fn set_global(&self, _symbol: &ForeignSymbol<'a>, _global: bool) -> Result<Option<Patch>> {
+        Ok(None)
+    }
+
+    /// Construct a patch renaming a symbol.
+    ///
+    /// The default pads the new name with NUL bytes, which suits NUL-terminated string tables.
+    fn rename(&self, symbol: &ForeignSymbol<'a>, new_name: &str) -> Result<Patch> {
+        let mut bytes = new_name.as_bytes().to_vec();
+        bytes.resize(symbol.name.len(), 0);
+        symbol.name.patch_with_bytes(&bytes)
+    }
+
+    /// Construct patches updating references to a symbol by name elsewhere in the object, after
+    /// the symbol is renamed to `new_name`.
+    ///
+    /// The default is to construct no patches.
+    fn rename_references(
+        &self,
+        _symbol: &ForeignSymbol<'a>,
+        _new_name: &str,
+    ) -> Result<Vec<Patch>> {
+        Ok(Vec::new())
+    }
+}
+
+/// An object format recognized by `transform_object`.
+pub trait ObjectFormat {
+    /// The name of the format, such as "ELF".
+    fn name(&self) -> &str;
+
+    /// Parse an object, returning `None` if `bytes` does not contain an object of this format.
+    fn parse<'a>(&self, bytes: &'a [u8]) -> Result<Option<Object<'a>>>;
+}
+
+/// The ELF object format.
+pub struct ElfFormat;
+
+impl ObjectFormat for ElfFormat {
+    fn name(&self) -> &str {
+        "ELF"
+    }
+
+    fn parse<'a>(&self, bytes: &'a [u8]) -> Result<Option<Object<'a>>> {
+        if !bytes.starts_with(goblin::elf::header::ELFMAG) {
+            return Ok(None);
+        }
+        Ok(Some(Object::Elf(Box::new(crate::elf::parse(bytes)?))))
+    }
+}
+
+/// The Mach-O object format.
+///
+/// Fat binaries are not recognized.
+pub struct MachOFormat;
+
+impl ObjectFormat for MachOFormat {
+    fn name(&self) -> &str {
+        "Mach-O"
+    }
+
+    fn parse<'a>(&self, bytes: &'a [u8]) -> Result<Option<Object<'a>>> {
+        use goblin::mach::header::{MH_CIGAM, MH_CIGAM_64, MH_MAGIC, MH_MAGIC_64};
+        match goblin::mach::peek(bytes, 0) {
+            Ok(MH_MAGIC) | Ok(MH_CIGAM) | Ok(MH_MAGIC_64) | Ok(MH_CIGAM_64) => {
+                Ok(Some(Object::MachO(Box::new(MachO::parse(bytes, 0)?))))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// A token for cancelling a transformation from another thread or a signal handler.
+///
+/// Clones of a token share its state, so cancelling any clone cancels them all.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Construct a token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel transformations checking this token.
+    ///
+    /// This only stores to an atomic, so it is safe to call from a signal handler.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Return `Error::Cancelled` if the token has been cancelled.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A callback for archive members that are skipped because they can't be parsed.
+type SkippedMember = dyn Fn(&str, &Error);
+
+/// A registry of object formats.
+///
+/// Formats are tried in the order they are registered.  The default registry contains the
+/// built-in ELF, Mach-O, COFF, WebAssembly and XCOFF formats, and out-of-tree formats can be
+/// added with `register`.
+pub struct Formats {
+    formats: Vec<Box<dyn ObjectFormat>>,
+    skipped_member: Option<Box<SkippedMember>>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl Default for Formats {
+    fn default() -> Self {
+        let mut formats = Self::new();
+        formats
+            .register(ElfFormat)
+            .register(MachOFormat)
+            .register(crate::coff::CoffFormat)
+            .register(crate::wasm::WasmFormat)
+            .register(crate::xcoff::XcoffFormat);
+        formats
+    }
+}
+
+impl Formats {
+    /// Construct an empty registry.
+    pub fn new() -> Self {
+        Self {
+            formats: Vec::new(),
+            skipped_member: None,
+            cancellation: None,
+        }
+    }
+
+    /// Register a format.
+    pub fn register<F: ObjectFormat + 'static>(&mut self, format: F) -> &mut Self {
+        self.formats.push(Box::new(format));
+        self
+    }
+
+    /// Copy archive members that can't be parsed through unmodified, instead of failing.
+    ///
+    /// `skipped` is called with the identifier of each skipped member and the parse error.
+    pub fn skip_unrecognized_members<F: Fn(&str, &Error) + 'static>(
+        &mut self,
+        skipped: F,
+    ) -> &mut Self {
+        self.skipped_member = Some(Box::new(skipped));
+        self
+    }
+
+    /// Stop transforming with `Error::Cancelled` once `token` is cancelled.
+    ///
+    /// The token is checked before each object, including each archive member, so large
+    /// archives stop promptly.
+    pub fn cancel_with(&mut self, token: CancellationToken) -> &mut Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        self.cancellation
+            .as_ref()
+            .map_or(Ok(()), CancellationToken::check)
+    }
+
+    /// The registered formats.
+    pub fn iter(&self) -> impl Iterator<Item = &dyn ObjectFormat> {
+        self.formats.iter().map(|format| format.as_ref())
+    }
+
+    /// Parse an object with the first format that recognizes it.
+    pub fn parse<'a>(&self, bytes: &'a [u8]) -> Result<Object<'a>> {
+        for format in &self.formats {
+            if let Some(object) = format.parse(bytes)? {
+                return Ok(object);
+            }
+        }
+        Err(match crate::detect::describe(bytes) {
+            Some(description) => Error::UnsupportedObject(description),
+            None => Error::UnknownObject,
+        })
+    }
+
+    /// Apply a transformation to a binary or an archive of binaries of the registered formats.
+    ///
+    /// The transformation is called with a `TransformContext` for each object, and patches
+    /// pushed to the context are applied to the object.  See `transform`.
+    pub fn transform<R, W, E, F>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        mut transformation: F,
+    ) -> TransformResult<(), E>
+    where
+        R: Read + Seek,
+        W: Write,
+        E: std::error::Error,
+        F: FnMut(&mut TransformContext) -> std::result::Result<(), E>,
+    {
+        self.apply(reader, writer, |member, bytes, object| {
+            let mut context = TransformContext {
+                member,
+                bytes,
+                object,
+                patches: Vec::new(),
+            };
+            transformation(&mut context)?;
+            Ok(context.patches)
+        })
+    }
+
+    /// Apply a transformation to a binary or an archive of binaries of the registered formats.
+    ///
+    /// See `transform_object`.
+    pub fn transform_object<R, W, E>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        transformation: &ObjectTransform<E>,
+    ) -> TransformResult<(), E>
+    where
+        R: Read + Seek,
+        W: Write,
+        E: std::error::Error,
+    {
+        self.apply(reader, writer, |_, bytes, object| {
+            transformation(bytes, object)
+        })
+    }
+
+    fn apply<R, W, E, F>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        transformation: F,
+    ) -> TransformResult<(), E>
+    where
+        R: Read + Seek,
+        W: Write,
+        E: std::error::Error,
+        F: for<'a> FnMut(
+            Option<&'a str>,
+            &'a [u8],
+            Object<'a>,
+        ) -> std::result::Result<Vec<Patch>, E>,
+    {
+        if is_archive(reader)? {
+            self.apply_archive(reader, writer, transformation)
+        } else if is_fat(reader)? {
+            self.apply_fat(reader, writer, transformation)
+        } else {
+            self.apply_single(reader, writer, transformation)
+        }
+    }
+
+    fn apply_archive<R, W, E, F>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        mut transformation: F,
+    ) -> TransformResult<(), E>
+    where
+        R: Read + Seek,
+        W: Write,
+        E: std::error::Error,
+        F: for<'a> FnMut(
+            Option<&'a str>,
+            &'a [u8],
+            Object<'a>,
+        ) -> std::result::Result<Vec<Patch>, E>,
+    {
+        let (variant, identifiers) = get_variant_and_identifiers(reader)?;
+        // The symbol table of BSD archives is rebuilt from the transformed members
+        let symdef = match variant {
+            ar::Variant::GNU => None,
+            _ => crate::ranlib::read_symdef(reader)?,
+        };
+        let mut members = Vec::new();
+        let mut input = ar::Archive::new(reader);
+        let mut output = if symdef.is_some() {
+            ArchiveBuilder::new(&mut members, variant, identifiers)
+        } else {
+            ArchiveBuilder::new(writer, variant, identifiers)
+        };
+        while let Some(mut entry) = input.next_entry().transpose()? {
+            self.check_cancelled()?;
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            let member = String::from_utf8_lossy(entry.header().identifier()).into_owned();
+            let patches = match self.parse(&data) {
+                Ok(object) => transformation(Some(&member), &data, object)
+                    .map_err(TransformError::Transform)?,
+                Err(e) => match &self.skipped_member {
+                    Some(skipped) => {
+                        skipped(&member, &e);
+                        Vec::new()
+                    }
+                    None => return Err(Error::Member(member, Box::new(e)).into()),
+                },
+            };
+            for patch in patches {
+                patch.apply(&mut data);
+            }
+            // Rebuilt string tables grow the member
+            let mut header = entry.header().clone();
+            header.set_size(data.len() as u64);
+            output.append(&header, data.as_slice())?;
+        }
+        drop(output);
+        if let Some(symdef) = symdef {
+            writer.write_all(&crate::ranlib::add_symdef(&members, &symdef)?)?;
+        }
+        Ok(())
+    }
+
+    /// Transform each slice of a fat binary, which may be an object or an archive.
+    ///
+    /// Slices keep their offsets unless a transformed archive grows into the next slice, in which
+    /// case the following slices are moved to the next offset satisfying their alignment.
+    fn apply_fat<R, W, E, F>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        mut transformation: F,
+    ) -> TransformResult<(), E>
+    where
+        R: Read + Seek,
+        W: Write,
+        E: std::error::Error,
+        F: for<'a> FnMut(
+            Option<&'a str>,
+            &'a [u8],
+            Object<'a>,
+        ) -> std::result::Result<Vec<Patch>, E>,
+    {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        if FatHeader::parse(&buf)?.magic == FAT_MAGIC_64 {
+            return Err(Error::FatBinaryUnsupported.into());
+        }
+        let mut arches = MultiArch::new(&buf)?.arches()?;
+        let mut slices = Vec::with_capacity(arches.len());
+        for arch in &arches {
+            let name = crate::mach::arch_name(arch.cputype(), arch.cpusubtype());
+            let (offset, size) = (arch.offset as usize, arch.size as usize);
+            let slice = offset
+                .checked_add(size)
+                .and_then(|end| buf.get(offset..end))
+                .ok_or_else(|| {
+                    Error::Malformed(format!(
+                        "The {} slice extends past the end of the file",
+                        name
+                    ))
+                })?;
+            let mut reader = Cursor::new(slice);
+            let mut output = Vec::new();
+            let result = if is_archive(&mut reader)? {
+                self.apply_archive(&mut reader, &mut output, &mut transformation)
+            } else {
+                self.apply_single(&mut reader, &mut output, &mut transformation)
+            };
+            match result {
+                Err(TransformError::SymTool(e)) => {
+                    return Err(Error::Slice(name, Box::new(e)).into())
+                }
+                result => result?,
+            }
+            slices.push(output);
+        }
+
+        let mut end = SIZEOF_FAT_HEADER + arches.len() * SIZEOF_FAT_ARCH;
+        for (arch, slice) in arches.iter_mut().zip(&slices) {
+            let offset = match arch.offset as usize {
+                offset if offset >= end => offset,
+                _ => align_up(end, arch.align).ok_or_else(|| {
+                    Error::Malformed(format!("Invalid alignment 2^{}", arch.align))
+                })?,
+            };
+            end = offset + slice.len();
+            arch.offset = offset as u32;
+            arch.size = slice.len() as u32;
+            if end > u32::MAX as usize {
+                return Err(Error::Unsupported(
+                    "The transformed fat binary is too big for 32-bit offsets".to_string(),
+                )
+                .into());
+            }
+        }
+        let mut output = vec![0; end];
+        output[..SIZEOF_FAT_HEADER].copy_from_slice(&buf[..SIZEOF_FAT_HEADER]);
+        for (index, (arch, slice)) in arches.iter().zip(&slices).enumerate() {
+            output.pwrite_with(*arch, SIZEOF_FAT_HEADER + index * SIZEOF_FAT_ARCH, BE)?;
+            let offset = arch.offset as usize;
+            output[offset..offset + slice.len()].copy_from_slice(slice);
+        }
+        writer.write_all(&output)?;
+        Ok(())
+    }
+
+    fn apply_single<R, W, E, F>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        mut transformation: F,
+    ) -> TransformResult<(), E>
+    where
+        R: Read + Seek,
+        W: Write,
+        E: std::error::Error,
+        F: for<'a> FnMut(
+            Option<&'a str>,
+            &'a [u8],
+            Object<'a>,
+        ) -> std::result::Result<Vec<Patch>, E>,
+    {
+        self.check_cancelled()?;
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        let object = self.parse(&buf)?;
+        let patches = transformation(None, &buf, object).map_err(TransformError::Transform)?;
+        for patch in patches {
+            patch.apply(&mut buf);
+        }
+        writer.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+/// The object being transformed, passed to the transformation given to `transform`.
+///
+/// Fields are public so a transformation can inspect `object` while pushing to `patches`.
+pub struct TransformContext<'a> {
+    /// The identifier of the archive member being transformed, or `None` if the object is not in
+    /// an archive
+    pub member: Option<&'a str>,
+
+    /// The contents of the object
+    pub bytes: &'a [u8],
+
+    /// The parsed object
+    pub object: Object<'a>,
+
+    /// Patches to apply to the object, in order
+    pub patches: Vec<Patch>,
 }
 
 /// The type of a transformation applied to an object.
 ///
 /// A transformation is expected to return a set of patches which are applied in order to the
-/// binary.
+/// binary.  `transform` accepts closures directly, and also passes the identifier of archive
+/// members.
 pub type ObjectTransform<Error> =
     dyn for<'a> Fn(&'a [u8], Object) -> std::result::Result<Vec<Patch>, Error>;
 
 /// Apply a transformation to a binary or an archive of binaries.
 ///
 /// Objects are parsed from `reader` and stored into `writer`.
-/// This function supports both BSD and GNU style archives.
-pub fn transform_object<'b, R, W, E>(
-    reader: &mut R,
-    writer: &mut W,
-    transformation: &'b ObjectTransform<E>,
-) -> TransformResult<(), E>
-where
-    R: Read + Seek,
-    W: Write,
-    E: std::error::Error,
-{
-    match goblin::peek(reader)? {
-        goblin::Hint::Archive => transform_archive(reader, writer, transformation),
-        _ => transform_single(reader, writer, transformation),
-    }
-}
-
-fn transform_archive<'b, R, W, E>(
+/// This function supports BSD and GNU style archives, fat Mach-O binaries, and the built-in ELF,
+/// Mach-O, COFF, WebAssembly and XCOFF formats.  Use `Formats::transform` to support additional
+/// formats.
+///
+/// ```no_run
+/// # use std::io::Cursor;
+/// # use symtool_backend::object::{transform, Object};
+/// # let input = Vec::new();
+/// let mut output = Vec::new();
+/// transform(&mut Cursor::new(&input), &mut output, |context| {
+///     if let Object::Elf(elf) = &context.object {
+///         println!("{:?}: {} symbols", context.member, elf.syms.len());
+///     }
+///     Ok::<_, std::io::Error>(())
+/// })
+/// .unwrap();
+/// ```
+pub fn transform<R, W, E, F>(
     reader: &mut R,
     writer: &mut W,
-    transformation: &'b ObjectTransform<E>,
+    transformation: F,
 ) -> TransformResult<(), E>
 where
     R: Read + Seek,
     W: Write,
     E: std::error::Error,
+    F: FnMut(&mut TransformContext) -> std::result::Result<(), E>,
 {
-    let (variant, identifiers) = get_variant_and_identifiers(reader)?;
-    let mut input = ar::Archive::new(reader);
-    let mut output = ArchiveBuilder::new(writer, variant, identifiers);
-    while let Some(mut entry) = input.next_entry().transpose()? {
-        let mut data = Vec::new();
-        transform_single(&mut entry, &mut data, transformation)?;
-        output.append(entry.header(), data.as_slice())?;
-    }
-    Ok(())
+    Formats::default().transform(reader, writer, transformation)
 }
 
-fn transform_single<'b, R, W, E>(
+/// Apply a transformation to a binary or an archive of binaries.
+///
+/// Objects are parsed from `reader` and stored into `writer`.
+/// This function supports BSD and GNU style archives, fat Mach-O binaries, and the built-in ELF,
+/// Mach-O, COFF, WebAssembly and XCOFF formats.  Use `Formats::transform_object` to support
+/// additional formats.
+pub fn transform_object<R, W, E>(
     reader: &mut R,
     writer: &mut W,
-    transformation: &'b ObjectTransform<E>,
+    transformation: &ObjectTransform<E>,
 ) -> TransformResult<(), E>
 where
     R: Read + Seek,
     W: Write,
     E: std::error::Error,
 {
-    let mut buf = Vec::new();
-    reader.read_to_end(&mut buf)?;
-    let object = match goblin::Object::parse(&buf)? {
-        goblin::Object::Elf(elf) => Ok(Object::Elf(Box::new(elf))),
-        goblin::Object::Mach(goblin::mach::Mach::Binary(macho)) => {
-            Ok(Object::MachO(Box::new(macho)))
-        }
-        _ => Err(Error::UnknownObject),
-    }?;
-    let patches = transformation(&buf, object).map_err(TransformError::Transform)?;
-    for patch in patches {
-        patch.apply(&mut buf);
-    }
-    writer.write_all(&buf)?;
-    Ok(())
+    Formats::default().transform_object(reader, writer, transformation)
 }
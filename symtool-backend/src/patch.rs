@@ -3,10 +3,11 @@
 use crate::error::{Error, Result};
 use goblin::container::Ctx;
 use scroll::ctx::{SizeWith, TryIntoCtx};
+use scroll::Endian;
 
 /// The location of a set of bytes in an object.
 #[derive(Debug)]
-pub(crate) struct Location {
+pub struct Location {
     /// The byte offset into the object
     pub offset: usize,
 
@@ -25,7 +26,8 @@ pub struct Rooted<T> {
 }
 
 impl<T> Rooted<T> {
-    pub(crate) fn new(location: Location, value: T) -> Self {
+    /// Root a value to a location.
+    pub fn new(location: Location, value: T) -> Self {
         Self { value, location }
     }
 
@@ -37,6 +39,15 @@ impl<T> Rooted<T> {
         Patch::from_ctx(&self.location, value)
     }
 
+    /// Construct a patch that replaces this rooted value with a primitive in the object's byte
+    /// order.
+    pub fn patch_with_primitive<U>(&self, value: U) -> Result<Patch>
+    where
+        U: TryIntoCtx<Endian, [u8], Error = scroll::Error> + SizeWith<Endian>,
+    {
+        Patch::from_primitive(&self.location, value)
+    }
+
     /// Construct a patch that replaces this rooted value with specific bytes.
     pub fn patch_with_bytes(&self, value: &[u8]) -> Result<Patch> {
         Patch::from_bytes(&self.location, value)
@@ -56,6 +67,9 @@ impl<T> std::ops::Deref for Rooted<T> {
 pub struct Patch {
     offset: usize,
     data: Vec<u8>,
+
+    /// Whether the object ends after the patch, dropping any following bytes
+    truncates: bool,
 }
 
 impl Patch {
@@ -72,21 +86,67 @@ impl Patch {
         Ok(Self {
             offset: location.offset,
             data: buf,
+            truncates: false,
         })
     }
 
-    fn from_bytes(location: &Location, data: &[u8]) -> Result<Self> {
+    pub(crate) fn from_primitive<T>(location: &Location, data: T) -> Result<Self>
+    where
+        T: TryIntoCtx<Endian, [u8], Error = scroll::Error> + SizeWith<Endian>,
+    {
+        let size = T::size_with(&location.ctx.le);
+        if size > location.size {
+            return Err(Error::PatchTooBig);
+        }
+        let mut buf = vec![0u8; size];
+        data.try_into_ctx(&mut buf, location.ctx.le)?;
+        Ok(Self {
+            offset: location.offset,
+            data: buf,
+            truncates: false,
+        })
+    }
+
+    pub(crate) fn from_bytes(location: &Location, data: &[u8]) -> Result<Self> {
         if data.len() > location.size {
             return Err(Error::PatchTooBig);
         }
         Ok(Self {
             offset: location.offset,
             data: data.to_vec(),
+            truncates: false,
         })
     }
 
+    /// Construct a patch writing `data` at `offset`, and ending the object after it.
+    pub(crate) fn ending_object(offset: usize, data: Vec<u8>) -> Self {
+        Self {
+            offset,
+            data,
+            truncates: true,
+        }
+    }
+
+    /// The byte offset of the patch into the object.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The bytes written by the patch.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
     /// Apply the patch to the bytes of an object.
-    pub fn apply(&self, data: &mut [u8]) {
-        data[self.offset..(self.offset + self.data.len())].clone_from_slice(&self.data);
+    ///
+    /// Patches past the end of the object, such as rebuilt string tables, extend it, and patches
+    /// rebuilding the end of the object, such as the tables rebuilt by `elf::rebuild_tables`,
+    /// truncate it.
+    pub fn apply(&self, data: &mut Vec<u8>) {
+        let end = self.offset + self.data.len();
+        if end > data.len() || self.truncates {
+            data.resize(end, 0);
+        }
+        data[self.offset..end].clone_from_slice(&self.data);
     }
 }
@@ -0,0 +1,251 @@
+//! Rebuild the symbol table of BSD archives.
+//!
+//! BSD archives, as written by the Darwin `ar` and `ranlib`, start with a `__.SYMDEF` member
+//! listing the external symbols defined by each member, which the linker uses to find members.
+//! The `ar` crate drops it when reading, so it is rebuilt from the transformed members.
+
+use crate::error::{Error, Result};
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::sym::{STB_GLOBAL, STB_WEAK};
+use goblin::mach::header::{MH_CIGAM, MH_CIGAM_64, MH_MAGIC, MH_MAGIC_64};
+use goblin::mach::symbols::{N_EXT, N_STAB, N_TYPE, N_UNDF};
+use goblin::mach::MachO;
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom};
+
+const SYMDEF: &[u8] = b"__.SYMDEF";
+
+/// The size of an archive member header.
+const HEADER_SIZE: usize = 60;
+
+/// The alignment of members, as written by the Darwin `ar`.
+const MEMBER_ALIGN: usize = 8;
+
+/// The symbol table of an archive before it was transformed.
+///
+/// Members that can't be parsed, such as the LLVM bitcode members of archives built with
+/// `-flto`, keep their entries from this table.
+pub struct Symdef {
+    /// The symbol names and the indices of the members that define them
+    entries: Vec<(Vec<u8>, usize)>,
+}
+
+/// Read the symbol table at the start of a BSD archive, if it has one.
+pub fn read_symdef<R: Read + Seek>(reader: &mut R) -> Result<Option<Symdef>> {
+    reader.seek(SeekFrom::Start(goblin::archive::SIZEOF_MAGIC as u64))?;
+    let mut table = None;
+    let mut offsets = Vec::new();
+    let mut offset = goblin::archive::SIZEOF_MAGIC;
+    loop {
+        let mut header = Vec::new();
+        (&mut *reader)
+            .take(HEADER_SIZE as u64)
+            .read_to_end(&mut header)?;
+        if header.len() < HEADER_SIZE {
+            break;
+        }
+        let size = field(&header, 48..58)?;
+        if offset == goblin::archive::SIZEOF_MAGIC {
+            let mut data = Vec::new();
+            (&mut *reader).take(size as u64).read_to_end(&mut data)?;
+            let (name, payload) = match header.strip_prefix(b"#1/") {
+                Some(_) => data.split_at(field(&header, 3..16)?.min(data.len())),
+                None => (&header[..16], &data[..]),
+            };
+            if !name.starts_with(SYMDEF) {
+                break;
+            }
+            let is_64 = name.starts_with(b"__.SYMDEF_64");
+            table = Some(parse_symdef(payload, is_64)?);
+            reader.seek(SeekFrom::Current((size % 2) as i64))?;
+        } else {
+            offsets.push(offset);
+            reader.seek(SeekFrom::Current((size + size % 2) as i64))?;
+        }
+        offset += HEADER_SIZE + size + size % 2;
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(table.map(|table| Symdef {
+        entries: table
+            .into_iter()
+            .filter_map(|(name, offset)| Some((name, offsets.binary_search(&offset).ok()?)))
+            .collect(),
+    }))
+}
+
+/// Parse the symbol names and member offsets of a `__.SYMDEF` member.
+fn parse_symdef(payload: &[u8], is_64: bool) -> Result<Vec<(Vec<u8>, usize)>> {
+    let malformed = || Error::Malformed("Invalid archive symbol table".to_string());
+    let word = if is_64 { 8 } else { 4 };
+    let read = |offset: usize, little: bool| -> Option<usize> {
+        let bytes = payload.get(offset..offset + word)?;
+        let mut value = 0u64;
+        for i in 0..word {
+            let byte = if little {
+                bytes[word - 1 - i]
+            } else {
+                bytes[i]
+            };
+            value = value << 8 | u64::from(byte);
+        }
+        Some(value as usize)
+    };
+    // The table is in the byte order of the members, so pick the one that fits
+    let size = read(0, true).ok_or_else(malformed)?;
+    let little = size + word <= payload.len();
+    let size = read(0, little).ok_or_else(malformed)?;
+    let strings = word + size;
+    let strings_size = read(strings, little).ok_or_else(malformed)?;
+    let strings = payload
+        .get(strings + word..)
+        .map(|strings| &strings[..strings_size.min(strings.len())])
+        .ok_or_else(malformed)?;
+    let mut entries = Vec::new();
+    for entry in (word..word + size).step_by(2 * word) {
+        let strx = read(entry, little).ok_or_else(malformed)?;
+        let offset = read(entry + word, little).ok_or_else(malformed)?;
+        let name = strings.get(strx..).ok_or_else(malformed)?;
+        let end = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        entries.push((name[..end].to_vec(), offset));
+    }
+    Ok(entries)
+}
+
+/// A member of a BSD archive.
+struct Member<'a> {
+    /// The offset of the member header
+    offset: usize,
+    data: &'a [u8],
+}
+
+fn field(header: &[u8], range: std::ops::Range<usize>) -> Result<usize> {
+    std::str::from_utf8(&header[range])
+        .ok()
+        .and_then(|field| field.trim_end().parse().ok())
+        .ok_or_else(|| Error::Malformed("Invalid archive member header".to_string()))
+}
+
+fn members(archive: &[u8]) -> Result<Vec<Member<'_>>> {
+    let mut members = Vec::new();
+    let mut offset = goblin::archive::SIZEOF_MAGIC;
+    while offset < archive.len() {
+        let header = archive
+            .get(offset..offset + HEADER_SIZE)
+            .ok_or_else(|| Error::Malformed("Truncated archive member header".to_string()))?;
+        let size = field(header, 48..58)?;
+        let name_size = match header.strip_prefix(b"#1/") {
+            Some(_) => field(header, 3..16)?,
+            None => 0,
+        };
+        let start = offset + HEADER_SIZE;
+        let data = start
+            .checked_add(size)
+            .filter(|_| name_size <= size)
+            .and_then(|end| archive.get(start + name_size..end))
+            .ok_or_else(|| Error::Malformed("Archive member extends past the end".to_string()))?;
+        members.push(Member { offset, data });
+        offset = start + size + size % 2;
+    }
+    Ok(members)
+}
+
+/// The external symbols defined by an object, and whether it is little-endian.
+fn defined_symbols(data: &[u8]) -> Result<Option<(Vec<String>, bool)>> {
+    if data.starts_with(goblin::elf::header::ELFMAG) {
+        let elf = crate::elf::parse(data)?;
+        let names = elf
+            .syms
+            .iter()
+            .filter(|sym| {
+                matches!(sym.st_bind(), STB_GLOBAL | STB_WEAK) && sym.st_shndx != SHN_UNDEF as usize
+            })
+            .filter_map(|sym| elf.strtab.get(sym.st_name)?.ok())
+            .map(str::to_string)
+            .collect();
+        return Ok(Some((names, elf.little_endian)));
+    }
+    let mach = match goblin::mach::peek(data, 0) {
+        Ok(MH_MAGIC) | Ok(MH_CIGAM) | Ok(MH_MAGIC_64) | Ok(MH_CIGAM_64) => MachO::parse(data, 0)?,
+        _ => return Ok(None),
+    };
+    let mut names = Vec::new();
+    for symbol in mach.symbols() {
+        let (name, nlist) = symbol?;
+        // Common symbols are undefined with a size
+        let defined = nlist.n_type & N_TYPE != N_UNDF || nlist.n_value != 0;
+        if nlist.n_type & N_STAB == 0 && nlist.n_type & N_EXT != 0 && defined {
+            names.push(name.to_string());
+        }
+    }
+    Ok(Some((names, mach.little_endian)))
+}
+
+/// Add a `__.SYMDEF` member to the start of a BSD archive, listing the symbols defined by its
+/// members.
+///
+/// The members are expected in the same order as in the archive `original` was read from.
+pub fn add_symdef(archive: &[u8], original: &Symdef) -> Result<Vec<u8>> {
+    let mut entries = Vec::new();
+    let mut strings = Vec::new();
+    let mut little_endian = true;
+    for (index, member) in members(archive)?.into_iter().enumerate() {
+        let names: Vec<Vec<u8>> = match defined_symbols(member.data)? {
+            Some((names, little)) => {
+                little_endian = little;
+                names.into_iter().map(String::into_bytes).collect()
+            }
+            None => original
+                .entries
+                .iter()
+                .filter(|(_, member)| *member == index)
+                .map(|(name, _)| name.clone())
+                .collect(),
+        };
+        for name in names {
+            entries.push((strings.len() as u32, member.offset));
+            strings.extend_from_slice(&name);
+            strings.push(0);
+        }
+    }
+    let name_size = (SYMDEF.len() + 3) & !3;
+    let mut size = name_size + 4 + entries.len() * 8 + 4 + strings.len();
+    // Keep the following members aligned
+    let end = goblin::archive::SIZEOF_MAGIC + HEADER_SIZE + size;
+    size += (MEMBER_ALIGN - end % MEMBER_ALIGN) % MEMBER_ALIGN;
+    let shift = HEADER_SIZE + size;
+
+    let u32_bytes = |value: u32| {
+        if little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        }
+    };
+    let mut output = archive[..goblin::archive::SIZEOF_MAGIC].to_vec();
+    let header = format!(
+        "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+        format!("#1/{}", name_size),
+        0,
+        0,
+        0,
+        0,
+        size
+    );
+    output.extend_from_slice(header.as_bytes());
+    let symdef_start = output.len();
+    output.extend_from_slice(SYMDEF);
+    output.resize(symdef_start + name_size, 0);
+    output.extend_from_slice(&u32_bytes(entries.len() as u32 * 8));
+    for (strx, offset) in entries {
+        let offset = u32::try_from(offset + shift).map_err(|_| {
+            Error::Unsupported("Archive is too big for a 32-bit symbol table".to_string())
+        })?;
+        output.extend_from_slice(&u32_bytes(strx));
+        output.extend_from_slice(&u32_bytes(offset));
+    }
+    output.extend_from_slice(&u32_bytes(strings.len() as u32));
+    output.extend_from_slice(&strings);
+    output.resize(symdef_start + size, 0);
+    output.extend_from_slice(&archive[goblin::archive::SIZEOF_MAGIC..]);
+    Ok(output)
+}
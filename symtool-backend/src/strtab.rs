@@ -0,0 +1,307 @@
+//! Iterate over and rebuild string tables.
+
+use crate::error::{Error, Result};
+use crate::patch::{Location, Patch, Rooted};
+use goblin::container::Ctx;
+use goblin::elf::SectionHeader;
+use goblin::mach::load_command::{SegmentCommand32, SegmentCommand64, SymtabCommand};
+use std::collections::HashMap;
+
+/// An iterator over the NUL-terminated strings in a string table.
+///
+/// Strings are produced as bytes, since string tables such as `.debug_str` are not required to
+/// contain valid UTF-8.
+///
+/// Only strings starting immediately after a NUL terminator (or at the start of the table) are
+/// produced, so strings referenced by tail merging are not visited separately.
+pub struct StrtabIter<'a> {
+    bytes: &'a [u8],
+    ctx: Ctx,
+    offset: usize,
+    end: usize,
+}
+
+impl<'a> StrtabIter<'a> {
+    /// Construct a `StrtabIter` over `size` bytes starting at `offset`.
+    pub fn new(bytes: &'a [u8], offset: usize, size: usize, ctx: Ctx) -> Result<Self> {
+        let end = offset
+            .checked_add(size)
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(|| Error::Malformed("string table out of bounds".to_string()))?;
+        Ok(Self {
+            bytes,
+            ctx,
+            offset,
+            end,
+        })
+    }
+
+    /// The byte range of the remaining strings in the table.
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.offset..self.end
+    }
+}
+
+impl<'a> std::iter::Iterator for StrtabIter<'a> {
+    type Item = Rooted<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.end {
+            return None;
+        }
+        let offset = self.offset;
+        let len = self.bytes[offset..self.end]
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.end - offset);
+        self.offset += len + 1;
+        let location = Location {
+            offset,
+            size: len,
+            ctx: self.ctx,
+        };
+        Some(Rooted::new(location, &self.bytes[offset..offset + len]))
+    }
+}
+
+/// The header recording the location of a rebuilt string table.
+enum TableHeader {
+    Elf(Rooted<SectionHeader>),
+
+    /// The symbol table command, and the `__LINKEDIT` segment of linked images
+    MachO(Rooted<SymtabCommand>, Option<Linkedit>),
+}
+
+/// The `__LINKEDIT` segment of a linked Mach-O image, which must cover the string table.
+pub(crate) enum Linkedit {
+    Segment32(Rooted<SegmentCommand32>),
+    Segment64(Rooted<SegmentCommand64>),
+}
+
+/// The page size `__LINKEDIT` is grown by, which is the largest Mach-O page size (arm64's).
+const LINKEDIT_PAGE_SIZE: u64 = 0x4000;
+
+impl Linkedit {
+    /// Construct a patch growing the segment to end at file offset `end`.
+    fn patch_to(&self, end: usize) -> Result<Patch> {
+        let vmsize = |filesize: u64, vmsize: u64| {
+            vmsize.max((filesize + LINKEDIT_PAGE_SIZE - 1) & !(LINKEDIT_PAGE_SIZE - 1))
+        };
+        match self {
+            Self::Segment32(segment) => {
+                let filesize = (end as u64)
+                    .checked_sub(u64::from(segment.fileoff))
+                    .filter(|filesize| *filesize <= u64::from(u32::MAX))
+                    .ok_or_else(|| Error::Malformed("__LINKEDIT out of bounds".to_string()))?;
+                segment.patch_with_primitive(SegmentCommand32 {
+                    filesize: filesize as u32,
+                    vmsize: vmsize(filesize, u64::from(segment.vmsize)) as u32,
+                    ..segment.value
+                })
+            }
+            Self::Segment64(segment) => {
+                let filesize = (end as u64)
+                    .checked_sub(segment.fileoff)
+                    .ok_or_else(|| Error::Malformed("__LINKEDIT out of bounds".to_string()))?;
+                segment.patch_with_primitive(SegmentCommand64 {
+                    filesize,
+                    vmsize: vmsize(filesize, segment.vmsize),
+                    ..segment.value
+                })
+            }
+        }
+    }
+}
+
+/// A symbol string table rebuilt at the end of the file, so symbols can be renamed to names
+/// longer than the originals.
+///
+/// The rebuilt table is a copy of the original with new names appended, so offsets into the
+/// original remain valid.  The original is left in place, unreferenced.  Construct one with
+/// `elf::rebuild_strtab` or `mach::rebuild_strtab`.
+///
+/// In linked Mach-O images, the string table must be in the `__LINKEDIT` segment, which is grown
+/// to cover the rebuilt table.
+pub struct RebuiltStrtab {
+    strings: Vec<u8>,
+    original_size: usize,
+    offset: usize,
+    header: TableHeader,
+    ctx: Ctx,
+}
+
+/// The alignment of rebuilt string tables.
+const REBUILT_ALIGN: usize = 8;
+
+impl RebuiltStrtab {
+    fn new(
+        bytes: &[u8],
+        offset: usize,
+        size: usize,
+        header: TableHeader,
+        ctx: Ctx,
+    ) -> Result<Self> {
+        let strings = offset
+            .checked_add(size)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or_else(|| Error::Malformed("string table out of bounds".to_string()))?;
+        Ok(Self {
+            strings: strings.to_vec(),
+            original_size: size,
+            offset: (bytes.len() + REBUILT_ALIGN - 1) & !(REBUILT_ALIGN - 1),
+            header,
+            ctx,
+        })
+    }
+
+    pub(crate) fn elf(bytes: &[u8], header: Rooted<SectionHeader>, ctx: Ctx) -> Result<Self> {
+        let (offset, size) = (header.sh_offset as usize, header.sh_size as usize);
+        Self::new(bytes, offset, size, TableHeader::Elf(header), ctx)
+    }
+
+    pub(crate) fn mach(
+        bytes: &[u8],
+        command: Rooted<SymtabCommand>,
+        linkedit: Option<Linkedit>,
+        ctx: Ctx,
+    ) -> Result<Self> {
+        let (offset, size) = (command.stroff as usize, command.strsize as usize);
+        Self::new(
+            bytes,
+            offset,
+            size,
+            TableHeader::MachO(command, linkedit),
+            ctx,
+        )
+    }
+
+    /// Add a name to the table, returning its offset.
+    pub fn add(&mut self, name: &str) -> usize {
+        let offset = self.strings.len();
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        offset
+    }
+
+    /// Construct patches appending the table to the file and pointing its header at it.
+    ///
+    /// Returns no patches if no names were added.
+    pub fn patches(mut self) -> Result<Vec<Patch>> {
+        if self.strings.len() == self.original_size {
+            return Ok(Vec::new());
+        }
+        let size = (self.strings.len() + REBUILT_ALIGN - 1) & !(REBUILT_ALIGN - 1);
+        self.strings.resize(size, 0);
+        let location = Location {
+            offset: self.offset,
+            size,
+            ctx: self.ctx,
+        };
+        let mut patches = vec![Patch::from_bytes(&location, &self.strings)?];
+        match &self.header {
+            TableHeader::Elf(header) => patches.push(header.patch_with(SectionHeader {
+                sh_offset: self.offset as u64,
+                sh_size: size as u64,
+                ..header.value.clone()
+            })?),
+            TableHeader::MachO(command, linkedit) => {
+                patches.push(command.patch_with_primitive(SymtabCommand {
+                    stroff: self.offset as u32,
+                    strsize: size as u32,
+                    ..command.value
+                })?);
+                if let Some(linkedit) = linkedit {
+                    patches.push(linkedit.patch_to(self.offset + size)?);
+                }
+            }
+        }
+        Ok(patches)
+    }
+}
+
+/// Read the NUL-terminated string at `offset` in a string table.
+pub(crate) fn string_at(table: &[u8], offset: usize) -> Result<&[u8]> {
+    let tail = table
+        .get(offset..)
+        .ok_or_else(|| Error::Malformed(format!("String table offset {} out of bounds", offset)))?;
+    let len = tail
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or_else(|| Error::Malformed(format!("String at offset {} isn't terminated", offset)))?;
+    Ok(&tail[..len])
+}
+
+/// A string table compacted to the names still referred to, to be written over the original.
+///
+/// Each name is stored once, and names which are a suffix of another, such as `bar` of `foobar`,
+/// refer to the tail of the longer one, like linkers merge string tables.  Construct one with
+/// `elf::compact_strtab` or `mach::compact_strtab`.
+pub(crate) struct CompactStrtab<'a> {
+    strings: Vec<u8>,
+    first: &'a [u8],
+    offsets: HashMap<&'a [u8], usize>,
+}
+
+impl<'a> CompactStrtab<'a> {
+    /// Merge `names` into a table starting with `first`, the string at offset 0 of the original.
+    pub(crate) fn new(first: &'a [u8], names: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        let mut names: Vec<&[u8]> = names.into_iter().collect();
+        // Sorting by the reversed names puts each name just before the names it is a suffix of
+        names.sort_by(|a, b| a.iter().rev().cmp(b.iter().rev()));
+        names.dedup();
+        let mut strings = first.to_vec();
+        strings.push(0);
+        let mut offsets = HashMap::new();
+        let mut previous: Option<(&[u8], usize)> = None;
+        for name in names.into_iter().rev() {
+            if first.ends_with(name) {
+                continue;
+            }
+            let offset = match previous {
+                Some((longer, offset)) if longer.ends_with(name) => {
+                    offset + longer.len() - name.len()
+                }
+                _ => {
+                    let offset = strings.len();
+                    strings.extend_from_slice(name);
+                    strings.push(0);
+                    previous = Some((name, offset));
+                    offset
+                }
+            };
+            offsets.insert(name, offset);
+        }
+        Self {
+            strings,
+            first,
+            offsets,
+        }
+    }
+
+    /// The offset of a name passed to `new` in the compacted table.
+    pub(crate) fn offset(&self, name: &[u8]) -> usize {
+        match self.offsets.get(name) {
+            Some(offset) => *offset,
+            None => self.first.len() - name.len(),
+        }
+    }
+
+    /// The size of the compacted table.
+    pub(crate) fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    /// The contents of the compacted table.
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.strings
+    }
+
+    /// Construct a patch writing the compacted table over the original, at `offset`, clearing
+    /// the rest of the `size` bytes of the original.
+    pub(crate) fn patch(&self, offset: usize, size: usize, ctx: Ctx) -> Result<Patch> {
+        let mut strings = self.strings.clone();
+        strings.resize(size, 0);
+        let location = Location { offset, size, ctx };
+        Patch::from_bytes(&location, &strings)
+    }
+}
@@ -0,0 +1,990 @@
+//! Build synthetic objects for testing.
+//!
+//! The objects produced here are minimal relocatable objects or ELF shared libraries containing
+//! only the requested symbols, which is enough to exercise symbol table manipulation without
+//! checking binaries into a repository.
+//!
+//! This module requires the `testing` feature.
+
+use goblin::container::{Container, Ctx, Endian};
+use goblin::elf::dynamic::{
+    Dyn, DT_GNU_HASH, DT_HASH, DT_NULL, DT_RELA, DT_RELAENT, DT_RELASZ, DT_STRSZ, DT_STRTAB,
+    DT_SYMENT, DT_SYMTAB,
+};
+use goblin::elf::program_header::{ProgramHeader, PF_R, PF_W, PF_X, PT_DYNAMIC, PT_LOAD, PT_PHDR};
+use goblin::elf::section_header::{
+    SHF_ALLOC, SHF_EXECINSTR, SHF_INFO_LINK, SHF_WRITE, SHN_LORESERVE, SHN_XINDEX, SHT_DYNAMIC,
+    SHT_DYNSYM, SHT_GNU_HASH, SHT_HASH, SHT_PROGBITS, SHT_RELA, SHT_STRTAB, SHT_SYMTAB,
+};
+use goblin::elf::sym::{STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_FUNC, STT_OBJECT, STV_HIDDEN};
+use goblin::elf::{Header as ElfHeader, SectionHeader, Sym};
+use goblin::mach::constants::cputype::{
+    CPU_TYPE_I386, CPU_TYPE_POWERPC, CPU_TYPE_POWERPC64, CPU_TYPE_X86_64,
+};
+use goblin::mach::header::{Header as MachHeader, MH_OBJECT};
+use goblin::mach::load_command::{
+    DysymtabCommand, Section32, Section64, SegmentCommand32, SegmentCommand64, SymtabCommand,
+    LC_DYSYMTAB, LC_SEGMENT, LC_SEGMENT_64, SIZEOF_DYSYMTAB_COMMAND, SIZEOF_SECTION_32,
+    SIZEOF_SECTION_64, SIZEOF_SEGMENT_COMMAND_32, SIZEOF_SEGMENT_COMMAND_64, SIZEOF_SYMTAB_COMMAND,
+};
+use goblin::mach::symbols::{Nlist, N_EXT, N_PEXT, N_SECT, N_UNDF};
+use scroll::ctx::{IntoCtx, SizeWith};
+use scroll::Pwrite;
+
+const ET_REL: u16 = 1;
+const ET_DYN: u16 = 3;
+const EM_386: u16 = 3;
+const EM_PPC: u16 = 20;
+const EM_PPC64: u16 = 21;
+const EM_X86_64: u16 = 62;
+const R_ADDR: u32 = 1;
+const R_GLOB_DAT_X86: u32 = 6;
+const R_GLOB_DAT_PPC: u32 = 20;
+const N_WEAK_REF: u16 = 0x40;
+const N_WEAK_DEF: u16 = 0x80;
+
+/// The size reserved in the text section for each defined function.
+const FUNCTION_SIZE: usize = 16;
+
+/// The size reserved in the data section for each defined object.
+const OBJECT_SIZE: usize = 8;
+
+/// The alignment of the segments of shared libraries.
+const PAGE_SIZE: usize = 0x1000;
+
+/// The number of buckets in the hash tables of shared libraries.
+const HASH_BUCKETS: u32 = 3;
+
+/// An object file format.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Elf,
+    MachO,
+}
+
+/// The binding of a symbol.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Local,
+    Global,
+    Weak,
+}
+
+/// The kind of entity a symbol refers to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Function,
+    Object,
+}
+
+/// A symbol to be placed in a synthetic object.
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub binding: Binding,
+    pub kind: Kind,
+    pub defined: bool,
+    pub hidden: bool,
+}
+
+impl Symbol {
+    /// Construct a global, defined function symbol with default visibility.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            binding: Binding::Global,
+            kind: Kind::Function,
+            defined: true,
+            hidden: false,
+        }
+    }
+
+    /// Set the binding of the symbol.
+    pub fn binding(mut self, binding: Binding) -> Self {
+        self.binding = binding;
+        self
+    }
+
+    /// Set the kind of the symbol.
+    pub fn kind(mut self, kind: Kind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Make the symbol an undefined reference.
+    pub fn undefined(mut self) -> Self {
+        self.defined = false;
+        self
+    }
+
+    /// Give the symbol hidden visibility.
+    pub fn hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+}
+
+/// Builds a relocatable object containing a set of symbols.
+#[derive(Clone, Debug)]
+pub struct ObjectBuilder {
+    ctx: Ctx,
+    symbols: Vec<Symbol>,
+    extra_sections: usize,
+    relocations: Vec<String>,
+}
+
+impl Default for ObjectBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A symbol with its location in the synthetic object.
+struct Placed<'a> {
+    symbol: &'a Symbol,
+    name: usize,
+    value: u64,
+}
+
+impl ObjectBuilder {
+    /// Construct a builder for a 64-bit little endian object.
+    pub fn new() -> Self {
+        Self {
+            ctx: Ctx::new(Container::Big, Endian::Little),
+            symbols: Vec::new(),
+            extra_sections: 0,
+            relocations: Vec::new(),
+        }
+    }
+
+    /// Set the container size (32 or 64-bit) of the object.
+    pub fn container(mut self, container: Container) -> Self {
+        self.ctx = Ctx::new(container, self.ctx.le);
+        self
+    }
+
+    /// Set the byte order of the object.
+    pub fn endian(mut self, endian: Endian) -> Self {
+        self.ctx = Ctx::new(self.ctx.container, endian);
+        self
+    }
+
+    /// Add a symbol to the object.
+    pub fn symbol(mut self, symbol: Symbol) -> Self {
+        self.symbols.push(symbol);
+        self
+    }
+
+    /// Add empty text sections to ELF objects, as `-ffunction-sections` does for each function.
+    ///
+    /// The sections are placed before the symbol table, so objects with `SHN_LORESERVE` or more
+    /// sections use extended section numbering for `e_shnum` and `e_shstrndx`.
+    pub fn extra_sections(mut self, count: usize) -> Self {
+        self.extra_sections = count;
+        self
+    }
+
+    /// Add a relocation referring to the named symbol to ELF objects.
+    ///
+    /// The relocations of relocatable objects are placed in `.rela.text` after the symbol and
+    /// string tables, and those of shared libraries in `.rela.dyn`.
+    pub fn relocation(mut self, name: &str) -> Self {
+        self.relocations.push(name.to_string());
+        self
+    }
+
+    /// Build an object of the specified format.
+    pub fn build(&self, format: Format) -> Vec<u8> {
+        match format {
+            Format::Elf => self.build_elf(),
+            Format::MachO => self.build_macho(),
+        }
+    }
+
+    /// Order symbols as required by the symbol table, assign values and build the string table.
+    ///
+    /// Symbols are grouped into locals, defined globals and undefined globals.  Functions are
+    /// placed in the text section and objects in the data section.
+    fn place(&self, strtab: &mut Vec<u8>) -> (Vec<Placed<'_>>, usize, usize) {
+        let mut ordered: Vec<&Symbol> = self.symbols.iter().collect();
+        ordered.sort_by_key(|s| match (s.binding, s.defined) {
+            (Binding::Local, _) => 0,
+            (_, true) => 1,
+            (_, false) => 2,
+        });
+        let (mut text, mut data) = (0, 0);
+        let placed = ordered
+            .into_iter()
+            .map(|symbol| {
+                let name = strtab.len();
+                strtab.extend_from_slice(symbol.name.as_bytes());
+                strtab.push(0);
+                let value = if !symbol.defined {
+                    0
+                } else if symbol.kind == Kind::Function {
+                    text += FUNCTION_SIZE;
+                    text - FUNCTION_SIZE
+                } else {
+                    data += OBJECT_SIZE;
+                    data - OBJECT_SIZE
+                };
+                Placed {
+                    symbol,
+                    name,
+                    value: value as u64,
+                }
+            })
+            .collect();
+        (placed, text, data)
+    }
+
+    /// The machine of ELF objects, which matches the container and byte order.
+    fn machine(&self) -> u16 {
+        match (self.ctx.container, self.ctx.le) {
+            (Container::Big, Endian::Little) => EM_X86_64,
+            (Container::Little, Endian::Little) => EM_386,
+            (Container::Big, Endian::Big) => EM_PPC64,
+            (Container::Little, Endian::Big) => EM_PPC,
+        }
+    }
+
+    /// Build the `Elf_Rela` entries of the relocations, of type `r_type`, referring to the
+    /// symbols by their index in `placed` plus one.
+    fn relocations(&self, placed: &[&Placed<'_>], r_type: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (index, name) in self.relocations.iter().enumerate() {
+            let symbol = 1 + placed
+                .iter()
+                .position(|p| p.symbol.name == *name)
+                .expect("relocation refers to an unknown symbol");
+            let offset = bytes.len();
+            if self.ctx.container.is_big() {
+                bytes.resize(offset + 24, 0);
+                let info = (symbol as u64) << 32 | u64::from(r_type);
+                bytes
+                    .pwrite_with(8 * index as u64, offset, self.ctx.le)
+                    .unwrap();
+                bytes.pwrite_with(info, offset + 8, self.ctx.le).unwrap();
+            } else {
+                bytes.resize(offset + 12, 0);
+                let info = (symbol as u32) << 8 | r_type;
+                bytes
+                    .pwrite_with(8 * index as u32, offset, self.ctx.le)
+                    .unwrap();
+                bytes.pwrite_with(info, offset + 4, self.ctx.le).unwrap();
+            }
+        }
+        bytes
+    }
+
+    /// Build an ELF relocatable object.
+    ///
+    /// The object contains the sections `.text`, `.data`, any extra sections, `.symtab`,
+    /// `.strtab`, `.shstrtab` and `.rela.text` if there are relocations.
+    pub fn build_elf(&self) -> Vec<u8> {
+        let mut strtab = vec![0u8];
+        let (placed, text_size, data_size) = self.place(&mut strtab);
+        let shstrtab = b"\0.text\0.data\0.symtab\0.strtab\0.shstrtab\0.rela.text\0";
+
+        let ehdr_size = ElfHeader::size(self.ctx);
+        let sym_size = Sym::size(self.ctx.container);
+        let shdr_size = SectionHeader::size(self.ctx);
+        let text_offset = align(ehdr_size, 16);
+        let data_offset = align(text_offset + text_size, 8);
+        let symtab_offset = align(data_offset + data_size, 8);
+        let symtab_size = (placed.len() + 1) * sym_size;
+        let strtab_offset = symtab_offset + symtab_size;
+        let shstrtab_offset = strtab_offset + strtab.len();
+        let rela = self.relocations(&placed.iter().collect::<Vec<_>>(), R_ADDR);
+        let rela_offset = align(shstrtab_offset + shstrtab.len(), 8);
+        let shdr_offset = align(rela_offset + rela.len(), 8);
+        let symtab_index = 3 + self.extra_sections;
+        let section_count = symtab_index + 3 + usize::from(!rela.is_empty());
+        let mut bytes = vec![0u8; shdr_offset + section_count * shdr_size];
+
+        let mut header = ElfHeader::new(self.ctx);
+        header.e_type = ET_REL;
+        header.e_machine = self.machine();
+        header.e_phoff = 0;
+        header.e_phnum = 0;
+        header.e_shoff = shdr_offset as u64;
+        // Counts and indexes from SHN_LORESERVE are stored in the first section header
+        let extended = section_count >= SHN_LORESERVE as usize;
+        header.e_shnum = if extended { 0 } else { section_count as u16 };
+        header.e_shstrndx = if extended {
+            SHN_XINDEX as u16
+        } else {
+            (symtab_index + 2) as u16
+        };
+        header.into_ctx(&mut bytes, self.ctx);
+
+        let first_global = 1 + placed
+            .iter()
+            .filter(|p| p.symbol.binding == Binding::Local)
+            .count();
+        for (i, p) in placed.iter().enumerate() {
+            let bind = match p.symbol.binding {
+                Binding::Local => STB_LOCAL,
+                Binding::Global => STB_GLOBAL,
+                Binding::Weak => STB_WEAK,
+            };
+            let (typ, shndx, size) = match p.symbol.kind {
+                Kind::Function => (STT_FUNC, 1, FUNCTION_SIZE),
+                Kind::Object => (STT_OBJECT, 2, OBJECT_SIZE),
+            };
+            let (shndx, size) = if p.symbol.defined {
+                (shndx, size)
+            } else {
+                (0, 0)
+            };
+            let sym = Sym {
+                st_name: p.name,
+                st_info: (bind << 4) | typ,
+                st_other: if p.symbol.hidden { STV_HIDDEN } else { 0 },
+                st_shndx: shndx,
+                st_value: p.value,
+                st_size: size as u64,
+            };
+            bytes
+                .pwrite_with(sym, symtab_offset + (i + 1) * sym_size, self.ctx)
+                .unwrap();
+        }
+        bytes[strtab_offset..strtab_offset + strtab.len()].copy_from_slice(&strtab);
+        bytes[shstrtab_offset..shstrtab_offset + shstrtab.len()].copy_from_slice(shstrtab);
+        bytes[rela_offset..rela_offset + rela.len()].copy_from_slice(&rela);
+
+        let mut sections = vec![SectionHeader {
+            sh_size: if extended { section_count as u64 } else { 0 },
+            sh_link: if extended { symtab_index as u32 + 2 } else { 0 },
+            ..SectionHeader::new()
+        }];
+        sections.extend(vec![
+            SectionHeader {
+                sh_name: 1,
+                sh_type: SHT_PROGBITS,
+                sh_flags: (SHF_ALLOC | SHF_EXECINSTR) as u64,
+                sh_offset: text_offset as u64,
+                sh_size: text_size as u64,
+                sh_addralign: 16,
+                ..SectionHeader::new()
+            },
+            SectionHeader {
+                sh_name: 7,
+                sh_type: SHT_PROGBITS,
+                sh_flags: (SHF_ALLOC | SHF_WRITE) as u64,
+                sh_offset: data_offset as u64,
+                sh_size: data_size as u64,
+                sh_addralign: 8,
+                ..SectionHeader::new()
+            },
+        ]);
+        sections.extend((0..self.extra_sections).map(|_| SectionHeader {
+            sh_name: 1,
+            sh_type: SHT_PROGBITS,
+            sh_flags: (SHF_ALLOC | SHF_EXECINSTR) as u64,
+            sh_offset: text_offset as u64,
+            sh_addralign: 1,
+            ..SectionHeader::new()
+        }));
+        sections.extend(vec![
+            SectionHeader {
+                sh_name: 13,
+                sh_type: SHT_SYMTAB,
+                sh_offset: symtab_offset as u64,
+                sh_size: symtab_size as u64,
+                sh_link: symtab_index as u32 + 1,
+                sh_info: first_global as u32,
+                sh_addralign: 8,
+                sh_entsize: sym_size as u64,
+                // `SectionHeader::new` sets SHF_ALLOC, which symbol and string tables lack
+                sh_flags: 0,
+                ..SectionHeader::new()
+            },
+            SectionHeader {
+                sh_name: 21,
+                sh_type: SHT_STRTAB,
+                sh_offset: strtab_offset as u64,
+                sh_size: strtab.len() as u64,
+                sh_addralign: 1,
+                sh_flags: 0,
+                ..SectionHeader::new()
+            },
+            SectionHeader {
+                sh_name: 29,
+                sh_type: SHT_STRTAB,
+                sh_offset: shstrtab_offset as u64,
+                sh_size: shstrtab.len() as u64,
+                sh_addralign: 1,
+                sh_flags: 0,
+                ..SectionHeader::new()
+            },
+        ]);
+        if !rela.is_empty() {
+            sections.push(SectionHeader {
+                sh_name: 39,
+                sh_type: SHT_RELA,
+                sh_offset: rela_offset as u64,
+                sh_size: rela.len() as u64,
+                sh_link: symtab_index as u32,
+                sh_info: 1,
+                sh_addralign: 8,
+                sh_entsize: (rela.len() / self.relocations.len()) as u64,
+                sh_flags: SHF_INFO_LINK as u64,
+                ..SectionHeader::new()
+            });
+        }
+        for (i, section) in sections.iter().enumerate() {
+            bytes
+                .pwrite_with(section.clone(), shdr_offset + i * shdr_size, self.ctx)
+                .unwrap();
+        }
+        bytes
+    }
+
+    /// Build an ELF shared library (`ET_DYN`).
+    ///
+    /// The library has the sections `.hash`, `.gnu.hash`, `.dynsym`, `.dynstr`, `.rela.dyn` and
+    /// `.text` in a read-only segment, and `.dynamic` and `.data` in a writable one, followed by
+    /// `.symtab`, `.strtab` and `.shstrtab`, which aren't loaded.  The global symbols are in
+    /// `.dynsym`, with the defined ones sorted by their `.gnu.hash` bucket, and sections are loaded
+    /// at their offsets.
+    pub fn build_shared_elf(&self) -> Vec<u8> {
+        let ctx = self.ctx;
+        let mut strtab = vec![0u8];
+        let (placed, text_size, data_size) = self.place(&mut strtab);
+        let data_size = data_size.max(8 * self.relocations.len());
+
+        // Undefined symbols aren't hashed, so they come first
+        let mut dynamic = placed
+            .iter()
+            .filter(|p| p.symbol.binding != Binding::Local && !p.symbol.defined)
+            .collect::<Vec<_>>();
+        let symoffset = 1 + dynamic.len();
+        dynamic.extend(
+            placed
+                .iter()
+                .filter(|p| p.symbol.binding != Binding::Local && p.symbol.defined),
+        );
+        let names = std::iter::once(&b""[..])
+            .chain(dynamic.iter().map(|p| p.symbol.name.as_bytes()))
+            .collect::<Vec<_>>();
+        let mut header = vec![0u8; 16];
+        let bloom_shift: u32 = if ctx.container.is_big() { 6 } else { 5 };
+        for (index, value) in [HASH_BUCKETS, symoffset as u32, 1, bloom_shift]
+            .iter()
+            .enumerate()
+        {
+            header.pwrite_with(*value, index * 4, ctx.le).unwrap();
+        }
+        let (order, gnu_hash) = crate::elf::rebuild_gnu_hash(&header, &names, ctx).unwrap();
+        let dynamic = order[1..]
+            .iter()
+            .map(|&index| dynamic[index - 1])
+            .collect::<Vec<_>>();
+        let names = order.iter().map(|&index| names[index]).collect::<Vec<_>>();
+        header.pwrite_with(names.len() as u32, 4, ctx.le).unwrap();
+        let hash = crate::elf::rebuild_sysv_hash(&header, &names, ctx).unwrap();
+        let mut dynstr = vec![0u8];
+        let dynstr_names = names
+            .iter()
+            .map(|name| {
+                let offset = dynstr.len();
+                dynstr.extend_from_slice(name);
+                dynstr.push(0);
+                offset
+            })
+            .collect::<Vec<_>>();
+        let r_type = match self.machine() {
+            EM_X86_64 | EM_386 => R_GLOB_DAT_X86,
+            _ => R_GLOB_DAT_PPC,
+        };
+        let rela = self.relocations(&dynamic, r_type);
+
+        let mut shstrtab = vec![0u8];
+        let sh_names = [
+            ".hash",
+            ".gnu.hash",
+            ".dynsym",
+            ".dynstr",
+            ".rela.dyn",
+            ".text",
+            ".dynamic",
+            ".data",
+            ".symtab",
+            ".strtab",
+            ".shstrtab",
+        ]
+        .iter()
+        .map(|name| {
+            let offset = shstrtab.len();
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+            offset
+        })
+        .collect::<Vec<_>>();
+
+        let sym_size = Sym::size(ctx.container);
+        let rela_size = if ctx.container.is_big() { 24 } else { 12 };
+        let dyn_size = Dyn::size(ctx.container);
+        let phentsize = ProgramHeader::size(ctx);
+        let phnum = 4;
+        let phdr_offset = ElfHeader::size(ctx);
+        let hash_offset = align(phdr_offset + phnum * phentsize, 8);
+        let gnu_hash_offset = align(hash_offset + hash.len(), 8);
+        let dynsym_offset = align(gnu_hash_offset + gnu_hash.len(), 8);
+        let dynstr_offset = dynsym_offset + names.len() * sym_size;
+        let rela_offset = align(dynstr_offset + dynstr.len(), 8);
+        let text_offset = align(rela_offset + rela.len(), 16);
+        let mut dyns = vec![
+            (DT_HASH, hash_offset),
+            (DT_GNU_HASH, gnu_hash_offset),
+            (DT_STRTAB, dynstr_offset),
+            (DT_SYMTAB, dynsym_offset),
+            (DT_STRSZ, dynstr.len()),
+            (DT_SYMENT, sym_size),
+        ];
+        if !rela.is_empty() {
+            dyns.extend(vec![
+                (DT_RELA, rela_offset),
+                (DT_RELASZ, rela.len()),
+                (DT_RELAENT, rela_size),
+            ]);
+        }
+        dyns.push((DT_NULL, 0));
+        let dynamic_offset = align(text_offset + text_size, PAGE_SIZE);
+        let data_offset = align(dynamic_offset + dyns.len() * dyn_size, 8);
+        let symtab_offset = align(data_offset + data_size, 8);
+        let strtab_offset = symtab_offset + (placed.len() + 1) * sym_size;
+        let shstrtab_offset = strtab_offset + strtab.len();
+        let shdr_offset = align(shstrtab_offset + shstrtab.len(), 8);
+        let shdr_size = SectionHeader::size(ctx);
+        let mut bytes = vec![0u8; shdr_offset + 12 * shdr_size];
+
+        let mut header = ElfHeader::new(ctx);
+        header.e_type = ET_DYN;
+        header.e_machine = self.machine();
+        header.e_phoff = phdr_offset as u64;
+        header.e_phnum = phnum as u16;
+        header.e_shoff = shdr_offset as u64;
+        header.e_shnum = 12;
+        header.e_shstrndx = 11;
+        header.into_ctx(&mut bytes, ctx);
+        let data_end = data_offset + data_size;
+        let segments = [
+            (PT_PHDR, PF_R, phdr_offset, phnum * phentsize, 8),
+            (PT_LOAD, PF_R | PF_X, 0, text_offset + text_size, PAGE_SIZE),
+            (
+                PT_LOAD,
+                PF_R | PF_W,
+                dynamic_offset,
+                data_end - dynamic_offset,
+                PAGE_SIZE,
+            ),
+            (
+                PT_DYNAMIC,
+                PF_R | PF_W,
+                dynamic_offset,
+                dyns.len() * dyn_size,
+                8,
+            ),
+        ];
+        for (index, &(p_type, p_flags, offset, size, p_align)) in segments.iter().enumerate() {
+            let segment = ProgramHeader {
+                p_type,
+                p_flags,
+                p_offset: offset as u64,
+                p_vaddr: offset as u64,
+                p_paddr: offset as u64,
+                p_filesz: size as u64,
+                p_memsz: size as u64,
+                p_align: p_align as u64,
+            };
+            bytes
+                .pwrite_with(segment, phdr_offset + index * phentsize, ctx)
+                .unwrap();
+        }
+
+        bytes[hash_offset..hash_offset + hash.len()].copy_from_slice(&hash);
+        bytes[gnu_hash_offset..gnu_hash_offset + gnu_hash.len()].copy_from_slice(&gnu_hash);
+        bytes[dynstr_offset..dynstr_offset + dynstr.len()].copy_from_slice(&dynstr);
+        bytes[rela_offset..rela_offset + rela.len()].copy_from_slice(&rela);
+        for (index, &(d_tag, value)) in dyns.iter().enumerate() {
+            let entry = Dyn {
+                d_tag,
+                d_val: value as u64,
+            };
+            bytes
+                .pwrite_with(entry, dynamic_offset + index * dyn_size, ctx)
+                .unwrap();
+        }
+        let sym = |p: &Placed<'_>, st_name: usize| {
+            let bind = match p.symbol.binding {
+                Binding::Local => STB_LOCAL,
+                Binding::Global => STB_GLOBAL,
+                Binding::Weak => STB_WEAK,
+            };
+            let (typ, shndx, address, size) = match p.symbol.kind {
+                Kind::Function => (STT_FUNC, 6, text_offset, FUNCTION_SIZE),
+                Kind::Object => (STT_OBJECT, 8, data_offset, OBJECT_SIZE),
+            };
+            let (shndx, value, size) = if p.symbol.defined {
+                (shndx, address as u64 + p.value, size)
+            } else {
+                (0, 0, 0)
+            };
+            Sym {
+                st_name,
+                st_info: (bind << 4) | typ,
+                st_other: if p.symbol.hidden { STV_HIDDEN } else { 0 },
+                st_shndx: shndx,
+                st_value: value,
+                st_size: size as u64,
+            }
+        };
+        for (index, p) in dynamic.iter().enumerate() {
+            bytes
+                .pwrite_with(
+                    sym(p, dynstr_names[index + 1]),
+                    dynsym_offset + (index + 1) * sym_size,
+                    ctx,
+                )
+                .unwrap();
+        }
+        for (index, p) in placed.iter().enumerate() {
+            bytes
+                .pwrite_with(sym(p, p.name), symtab_offset + (index + 1) * sym_size, ctx)
+                .unwrap();
+        }
+        bytes[strtab_offset..strtab_offset + strtab.len()].copy_from_slice(&strtab);
+        bytes[shstrtab_offset..shstrtab_offset + shstrtab.len()].copy_from_slice(&shstrtab);
+
+        let first_global = 1 + placed
+            .iter()
+            .filter(|p| p.symbol.binding == Binding::Local)
+            .count();
+        let loaded =
+            |name, sh_type, flags: u32, offset: usize, size: usize, align: usize| SectionHeader {
+                sh_name: sh_names[name],
+                sh_type,
+                sh_flags: u64::from(flags),
+                sh_addr: offset as u64,
+                sh_offset: offset as u64,
+                sh_size: size as u64,
+                sh_addralign: align as u64,
+                ..SectionHeader::new()
+            };
+        let unloaded = |name, sh_type, offset: usize, size: usize| SectionHeader {
+            sh_flags: 0,
+            sh_addr: 0,
+            ..loaded(name, sh_type, 0, offset, size, 1)
+        };
+        let sections = vec![
+            SectionHeader {
+                sh_flags: 0,
+                ..SectionHeader::new()
+            },
+            SectionHeader {
+                sh_link: 3,
+                sh_entsize: 4,
+                ..loaded(0, SHT_HASH, SHF_ALLOC, hash_offset, hash.len(), 8)
+            },
+            SectionHeader {
+                sh_link: 3,
+                ..loaded(
+                    1,
+                    SHT_GNU_HASH,
+                    SHF_ALLOC,
+                    gnu_hash_offset,
+                    gnu_hash.len(),
+                    8,
+                )
+            },
+            SectionHeader {
+                sh_link: 4,
+                sh_info: 1,
+                sh_entsize: sym_size as u64,
+                ..loaded(
+                    2,
+                    SHT_DYNSYM,
+                    SHF_ALLOC,
+                    dynsym_offset,
+                    names.len() * sym_size,
+                    8,
+                )
+            },
+            loaded(3, SHT_STRTAB, SHF_ALLOC, dynstr_offset, dynstr.len(), 1),
+            SectionHeader {
+                sh_link: 3,
+                sh_entsize: rela_size as u64,
+                ..loaded(4, SHT_RELA, SHF_ALLOC, rela_offset, rela.len(), 8)
+            },
+            loaded(
+                5,
+                SHT_PROGBITS,
+                SHF_ALLOC | SHF_EXECINSTR,
+                text_offset,
+                text_size,
+                16,
+            ),
+            SectionHeader {
+                sh_link: 4,
+                sh_entsize: dyn_size as u64,
+                ..loaded(
+                    6,
+                    SHT_DYNAMIC,
+                    SHF_ALLOC | SHF_WRITE,
+                    dynamic_offset,
+                    dyns.len() * dyn_size,
+                    8,
+                )
+            },
+            loaded(
+                7,
+                SHT_PROGBITS,
+                SHF_ALLOC | SHF_WRITE,
+                data_offset,
+                data_size,
+                8,
+            ),
+            SectionHeader {
+                sh_link: 10,
+                sh_info: first_global as u32,
+                sh_entsize: sym_size as u64,
+                sh_addralign: 8,
+                ..unloaded(8, SHT_SYMTAB, symtab_offset, (placed.len() + 1) * sym_size)
+            },
+            unloaded(9, SHT_STRTAB, strtab_offset, strtab.len()),
+            unloaded(10, SHT_STRTAB, shstrtab_offset, shstrtab.len()),
+        ];
+        for (i, section) in sections.into_iter().enumerate() {
+            bytes
+                .pwrite_with(section, shdr_offset + i * shdr_size, ctx)
+                .unwrap();
+        }
+        bytes
+    }
+
+    /// Build a Mach-O object file (`MH_OBJECT`).
+    ///
+    /// The object contains a single segment with the sections `__TEXT,__text` and
+    /// `__DATA,__data`, followed by `LC_SYMTAB` and `LC_DYSYMTAB` load commands.
+    pub fn build_macho(&self) -> Vec<u8> {
+        let mut strtab = vec![b' ', 0u8];
+        let (placed, text_size, data_size) = self.place(&mut strtab);
+        let is_64 = self.ctx.is_big();
+
+        let header_size = MachHeader::size_with(&self.ctx);
+        let (segment_size, section_size) = if is_64 {
+            (SIZEOF_SEGMENT_COMMAND_64, SIZEOF_SECTION_64)
+        } else {
+            (SIZEOF_SEGMENT_COMMAND_32, SIZEOF_SECTION_32)
+        };
+        let segment_cmdsize = segment_size + 2 * section_size;
+        let sizeofcmds = segment_cmdsize + SIZEOF_SYMTAB_COMMAND + SIZEOF_DYSYMTAB_COMMAND;
+        let text_offset = align(header_size + sizeofcmds, 16);
+        let data_offset = align(text_offset + text_size, 8);
+        let symoff = align(data_offset + data_size, 8);
+        let nlist_size = Nlist::size_with(&self.ctx);
+        let stroff = symoff + placed.len() * nlist_size;
+        let mut bytes = vec![0u8; align(stroff + strtab.len(), 8)];
+
+        let mut header = MachHeader::new(self.ctx);
+        header.cputype = match (is_64, self.ctx.le) {
+            (true, Endian::Little) => CPU_TYPE_X86_64,
+            (false, Endian::Little) => CPU_TYPE_I386,
+            (true, Endian::Big) => CPU_TYPE_POWERPC64,
+            (false, Endian::Big) => CPU_TYPE_POWERPC,
+        };
+        header.cpusubtype = 3;
+        header.filetype = MH_OBJECT;
+        header.ncmds = 3;
+        header.sizeofcmds = sizeofcmds as u32;
+        bytes.pwrite_with(header, 0, self.ctx).unwrap();
+
+        let mut offset = header_size;
+        let data_addr = (data_offset - text_offset) as u64;
+        let vmsize = (data_offset + data_size - text_offset) as u64;
+        if is_64 {
+            let segment = SegmentCommand64 {
+                cmd: LC_SEGMENT_64,
+                cmdsize: segment_cmdsize as u32,
+                segname: [0; 16],
+                vmaddr: 0,
+                vmsize,
+                fileoff: text_offset as u64,
+                filesize: vmsize,
+                maxprot: 7,
+                initprot: 7,
+                nsects: 2,
+                flags: 0,
+            };
+            offset += bytes.pwrite_with(segment, offset, self.ctx.le).unwrap();
+            let text = Section64 {
+                sectname: name16("__text"),
+                segname: name16("__TEXT"),
+                addr: 0,
+                size: text_size as u64,
+                offset: text_offset as u32,
+                align: 4,
+                reloff: 0,
+                nreloc: 0,
+                flags: 0x8000_0400,
+                reserved1: 0,
+                reserved2: 0,
+                reserved3: 0,
+            };
+            offset += bytes.pwrite_with(text, offset, self.ctx.le).unwrap();
+            let data = Section64 {
+                sectname: name16("__data"),
+                segname: name16("__DATA"),
+                addr: data_addr,
+                size: data_size as u64,
+                offset: data_offset as u32,
+                align: 3,
+                flags: 0,
+                ..text
+            };
+            offset += bytes.pwrite_with(data, offset, self.ctx.le).unwrap();
+        } else {
+            let segment = SegmentCommand32 {
+                cmd: LC_SEGMENT,
+                cmdsize: segment_cmdsize as u32,
+                segname: [0; 16],
+                vmaddr: 0,
+                vmsize: vmsize as u32,
+                fileoff: text_offset as u32,
+                filesize: vmsize as u32,
+                maxprot: 7,
+                initprot: 7,
+                nsects: 2,
+                flags: 0,
+            };
+            offset += bytes.pwrite_with(segment, offset, self.ctx.le).unwrap();
+            let text = Section32 {
+                sectname: name16("__text"),
+                segname: name16("__TEXT"),
+                addr: 0,
+                size: text_size as u32,
+                offset: text_offset as u32,
+                align: 4,
+                reloff: 0,
+                nreloc: 0,
+                flags: 0x8000_0400,
+                reserved1: 0,
+                reserved2: 0,
+            };
+            offset += bytes.pwrite_with(text, offset, self.ctx.le).unwrap();
+            let data = Section32 {
+                sectname: name16("__data"),
+                segname: name16("__DATA"),
+                addr: data_addr as u32,
+                size: data_size as u32,
+                offset: data_offset as u32,
+                align: 3,
+                flags: 0,
+                ..text
+            };
+            offset += bytes.pwrite_with(data, offset, self.ctx.le).unwrap();
+        }
+
+        let symtab = SymtabCommand {
+            symoff: symoff as u32,
+            nsyms: placed.len() as u32,
+            stroff: stroff as u32,
+            strsize: strtab.len() as u32,
+            ..SymtabCommand::new()
+        };
+        offset += bytes.pwrite_with(symtab, offset, self.ctx.le).unwrap();
+        let nlocal = placed
+            .iter()
+            .filter(|p| p.symbol.binding == Binding::Local)
+            .count();
+        let nextdef = placed
+            .iter()
+            .filter(|p| p.symbol.binding != Binding::Local && p.symbol.defined)
+            .count();
+        let dysymtab = DysymtabCommand {
+            cmd: LC_DYSYMTAB,
+            ilocalsym: 0,
+            nlocalsym: nlocal as u32,
+            iextdefsym: nlocal as u32,
+            nextdefsym: nextdef as u32,
+            iundefsym: (nlocal + nextdef) as u32,
+            nundefsym: (placed.len() - nlocal - nextdef) as u32,
+            ..DysymtabCommand::new()
+        };
+        bytes.pwrite_with(dysymtab, offset, self.ctx.le).unwrap();
+
+        for (i, p) in placed.iter().enumerate() {
+            let mut n_type = if p.symbol.defined { N_SECT } else { N_UNDF };
+            if p.symbol.binding != Binding::Local {
+                n_type |= N_EXT;
+            }
+            if p.symbol.hidden {
+                n_type |= N_PEXT;
+            }
+            let n_desc = match (p.symbol.binding, p.symbol.defined) {
+                (Binding::Weak, true) => N_WEAK_DEF,
+                (Binding::Weak, false) => N_WEAK_REF,
+                _ => 0,
+            };
+            let (n_sect, n_value) = match (p.symbol.defined, p.symbol.kind) {
+                (false, _) => (0, 0),
+                (true, Kind::Function) => (1, p.value),
+                (true, Kind::Object) => (2, data_addr + p.value),
+            };
+            let nlist = Nlist {
+                n_strx: p.name,
+                n_type,
+                n_sect,
+                n_desc,
+                n_value,
+            };
+            bytes
+                .pwrite_with(nlist, symoff + i * nlist_size, self.ctx)
+                .unwrap();
+        }
+        bytes[stroff..stroff + strtab.len()].copy_from_slice(&strtab);
+        bytes
+    }
+}
+
+/// An archive variant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ArchiveVariant {
+    Bsd,
+    Gnu,
+}
+
+/// Build an archive containing the provided members.
+pub fn build_archive(members: &[(&str, Vec<u8>)], variant: ArchiveVariant) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    {
+        let mut builder = crate::object::ArchiveBuilder::new(
+            &mut bytes,
+            match variant {
+                ArchiveVariant::Bsd => ar::Variant::BSD,
+                ArchiveVariant::Gnu => ar::Variant::GNU,
+            },
+            members
+                .iter()
+                .map(|(name, _)| name.as_bytes().to_vec())
+                .collect(),
+        );
+        for (name, data) in members {
+            let header = ar::Header::new(name.as_bytes().to_vec(), data.len() as u64);
+            builder.append(&header, data.as_slice()).unwrap();
+        }
+    }
+    bytes
+}
+
+fn align(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+fn name16(name: &str) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    bytes[..name.len()].copy_from_slice(name.as_bytes());
+    bytes
+}
@@ -0,0 +1,433 @@
+//! Manipulate WebAssembly objects, such as those produced by clang for wasm-ld.
+//!
+//! Symbols are described by the symbol table in the `linking` custom section.  Names are
+//! prefixed by their LEB128-encoded length rather than terminated by NUL bytes, so a shorter name
+//! is written in place by padding its length with continuation bytes.
+
+use crate::error::{Error, Result};
+use crate::object::{ForeignObject, ForeignSymbol, Object, ObjectFormat};
+use crate::patch::{Location, Patch, Rooted};
+use goblin::container::{Container, Ctx, Endian};
+use scroll::Uleb128;
+
+/// The magic number and version of a WebAssembly binary.
+const MAGIC: &[u8] = b"\0asm\x01\0\0\0";
+
+const SECTION_CUSTOM: u8 = 0;
+const SECTION_IMPORT: u8 = 2;
+
+const EXTERNAL_FUNCTION: u8 = 0;
+const EXTERNAL_TABLE: u8 = 1;
+const EXTERNAL_MEMORY: u8 = 2;
+const EXTERNAL_GLOBAL: u8 = 3;
+const EXTERNAL_TAG: u8 = 4;
+
+/// The version of the `linking` section which is recognized.
+const LINKING_VERSION: u64 = 2;
+
+/// The `linking` subsection containing the symbol table.
+const WASM_SYMBOL_TABLE: u8 = 8;
+
+const SYMTAB_FUNCTION: u8 = 0;
+const SYMTAB_DATA: u8 = 1;
+const SYMTAB_GLOBAL: u8 = 2;
+const SYMTAB_SECTION: u8 = 3;
+const SYMTAB_TAG: u8 = 4;
+const SYMTAB_TABLE: u8 = 5;
+
+const WASM_SYM_BINDING_LOCAL: u64 = 0x2;
+const WASM_SYM_VISIBILITY_HIDDEN: u64 = 0x4;
+const WASM_SYM_UNDEFINED: u64 = 0x10;
+const WASM_SYM_EXPLICIT_NAME: u64 = 0x40;
+
+/// The maximum size of a LEB128-encoded 32-bit integer.
+const MAX_LEB_SIZE: usize = 5;
+
+fn context() -> Ctx {
+    Ctx::new(Container::Little, Endian::Little)
+}
+
+/// Encode an integer as LEB128 in exactly `size` bytes, padding with continuation bytes.
+///
+/// Returns `None` if the integer doesn't fit.
+fn padded_leb(mut value: u64, size: usize) -> Option<Vec<u8>> {
+    let mut bytes = vec![0x80; size];
+    for byte in bytes.iter_mut() {
+        *byte |= (value & 0x7f) as u8;
+        value >>= 7;
+    }
+    *bytes.last_mut()? &= 0x7f;
+    if value == 0 {
+        Some(bytes)
+    } else {
+        None
+    }
+}
+
+/// The WebAssembly object format.
+///
+/// Linked modules, which have no `linking` section, are not recognized.
+pub struct WasmFormat;
+
+impl ObjectFormat for WasmFormat {
+    fn name(&self) -> &str {
+        "WebAssembly"
+    }
+
+    fn parse<'a>(&self, bytes: &'a [u8]) -> Result<Option<Object<'a>>> {
+        if !bytes.starts_with(MAGIC) {
+            return Ok(None);
+        }
+        Ok(WasmObject::parse(bytes)?.map(|object| Object::Other(Box::new(object))))
+    }
+}
+
+/// A LEB128-encoded integer.
+#[derive(Clone, Copy)]
+struct Leb {
+    value: u64,
+    offset: usize,
+    size: usize,
+}
+
+impl Leb {
+    fn location(&self) -> Location {
+        Location {
+            offset: self.offset,
+            size: self.size,
+            ctx: context(),
+        }
+    }
+
+    /// Construct a patch replacing the integer, keeping its size.
+    fn patch(&self, value: u64) -> Result<Patch> {
+        let bytes = padded_leb(value, self.size).ok_or(Error::PatchTooBig)?;
+        Patch::from_bytes(&self.location(), &bytes)
+    }
+}
+
+/// A name, prefixed by its length.
+#[derive(Clone, Copy)]
+struct Name<'a> {
+    length: Leb,
+    value: &'a str,
+}
+
+impl<'a> Name<'a> {
+    fn rooted(&self) -> Rooted<&'a str> {
+        let location = Location {
+            offset: self.length.offset + self.length.size,
+            size: self.value.len(),
+            ctx: context(),
+        };
+        Rooted::new(location, self.value)
+    }
+}
+
+/// Read the contents of a section or subsection.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    end: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn at_end(&self) -> bool {
+        self.offset >= self.end
+    }
+
+    fn contents(&self) -> &'a [u8] {
+        &self.bytes[..self.end]
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        let byte = *self
+            .contents()
+            .get(self.offset)
+            .ok_or_else(|| Error::Malformed("Unexpected end of section".to_string()))?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn leb(&mut self) -> Result<Leb> {
+        let offset = self.offset;
+        let value = Uleb128::read(self.contents(), &mut self.offset)?;
+        Ok(Leb {
+            value,
+            offset,
+            size: self.offset - offset,
+        })
+    }
+
+    fn name(&mut self) -> Result<Name<'a>> {
+        let length = self.leb()?;
+        let value = self.section(length.value)?;
+        let value = std::str::from_utf8(&self.bytes[value.offset..value.end])
+            .map_err(|_| Error::Malformed("Name is not valid UTF-8".to_string()))?;
+        Ok(Name { length, value })
+    }
+
+    /// Read the following `size` bytes as a section.
+    fn section(&mut self, size: u64) -> Result<Reader<'a>> {
+        let end = (size as usize)
+            .checked_add(self.offset)
+            .filter(|end| *end <= self.end)
+            .ok_or_else(|| Error::Malformed("Section extends past its parent".to_string()))?;
+        let section = Reader {
+            bytes: self.bytes,
+            offset: self.offset,
+            end,
+        };
+        self.offset = end;
+        Ok(section)
+    }
+
+    fn limits(&mut self) -> Result<()> {
+        let flags = self.u8()?;
+        self.leb()?;
+        if flags & 1 != 0 {
+            self.leb()?;
+        }
+        Ok(())
+    }
+}
+
+/// An entry in the import section, which names undefined symbols.
+struct Import<'a> {
+    kind: u8,
+    field: Name<'a>,
+}
+
+fn parse_imports<'a>(mut section: Reader<'a>) -> Result<Vec<Import<'a>>> {
+    let count = section.leb()?.value;
+    let mut imports = Vec::new();
+    for _ in 0..count {
+        section.name()?;
+        let field = section.name()?;
+        let kind = section.u8()?;
+        match kind {
+            EXTERNAL_FUNCTION => {
+                section.leb()?;
+            }
+            EXTERNAL_TABLE => {
+                section.u8()?;
+                section.limits()?;
+            }
+            EXTERNAL_MEMORY => section.limits()?,
+            EXTERNAL_GLOBAL => {
+                section.u8()?;
+                section.u8()?;
+            }
+            EXTERNAL_TAG => {
+                section.u8()?;
+                section.leb()?;
+            }
+            _ => return Err(Error::Malformed(format!("Unknown import kind {}", kind))),
+        }
+        imports.push(Import { kind, field });
+    }
+    Ok(imports)
+}
+
+/// A symbol in the symbol table of the `linking` section.
+struct WasmSymbol<'a> {
+    flags: Leb,
+    name: Name<'a>,
+}
+
+impl<'a> WasmSymbol<'a> {
+    fn is_defined(&self) -> bool {
+        self.flags.value & WASM_SYM_UNDEFINED == 0
+    }
+}
+
+/// Parse the symbol table, returning `None` for section symbols, which have no name.
+fn parse_symbol_table<'a>(
+    mut section: Reader<'a>,
+    imports: &[Import<'a>],
+) -> Result<Vec<Option<WasmSymbol<'a>>>> {
+    let count = section.leb()?.value;
+    let mut symbols = Vec::new();
+    for _ in 0..count {
+        let kind = section.u8()?;
+        let flags = section.leb()?;
+        let defined = flags.value & WASM_SYM_UNDEFINED == 0;
+        let name = match kind {
+            SYMTAB_FUNCTION | SYMTAB_GLOBAL | SYMTAB_TAG | SYMTAB_TABLE => {
+                let index = section.leb()?.value as usize;
+                if defined || flags.value & WASM_SYM_EXPLICIT_NAME != 0 {
+                    section.name()?
+                } else {
+                    // Undefined symbols are named by their import
+                    let import_kind = match kind {
+                        SYMTAB_FUNCTION => EXTERNAL_FUNCTION,
+                        SYMTAB_GLOBAL => EXTERNAL_GLOBAL,
+                        SYMTAB_TAG => EXTERNAL_TAG,
+                        _ => EXTERNAL_TABLE,
+                    };
+                    imports
+                        .iter()
+                        .filter(|import| import.kind == import_kind)
+                        .nth(index)
+                        .map(|import| import.field)
+                        .ok_or_else(|| {
+                            Error::Malformed(format!("Symbol refers to missing import {}", index))
+                        })?
+                }
+            }
+            SYMTAB_DATA => {
+                let name = section.name()?;
+                if defined {
+                    // Segment index, offset and size
+                    for _ in 0..3 {
+                        section.leb()?;
+                    }
+                }
+                name
+            }
+            SYMTAB_SECTION => {
+                section.leb()?;
+                symbols.push(None);
+                continue;
+            }
+            _ => return Err(Error::Malformed(format!("Unknown symbol kind {}", kind))),
+        };
+        symbols.push(Some(WasmSymbol { flags, name }));
+    }
+    Ok(symbols)
+}
+
+fn parse_linking<'a>(
+    mut section: Reader<'a>,
+    imports: &[Import<'a>],
+) -> Result<Vec<Option<WasmSymbol<'a>>>> {
+    let version = section.leb()?.value;
+    if version != LINKING_VERSION {
+        return Err(Error::Unsupported(format!(
+            "Linking section version {} is not supported",
+            version
+        )));
+    }
+    while !section.at_end() {
+        let kind = section.u8()?;
+        let size = section.leb()?.value;
+        let subsection = section.section(size)?;
+        if kind == WASM_SYMBOL_TABLE {
+            return parse_symbol_table(subsection, imports);
+        }
+    }
+    Ok(Vec::new())
+}
+
+/// A WebAssembly object.
+pub struct WasmObject<'a> {
+    /// The symbol table, indexed like the object's
+    symbols: Vec<Option<WasmSymbol<'a>>>,
+}
+
+impl<'a> WasmObject<'a> {
+    /// Parse an object, returning `None` for a module without a `linking` section.
+    fn parse(bytes: &'a [u8]) -> Result<Option<Self>> {
+        let mut reader = Reader {
+            bytes,
+            offset: MAGIC.len(),
+            end: bytes.len(),
+        };
+        let mut imports = Vec::new();
+        while !reader.at_end() {
+            let id = reader.u8()?;
+            let size = reader.leb()?.value;
+            let mut section = reader.section(size)?;
+            match id {
+                SECTION_IMPORT => imports = parse_imports(section)?,
+                SECTION_CUSTOM if section.name()?.value == "linking" => {
+                    let symbols = parse_linking(section, &imports)?;
+                    return Ok(Some(Self { symbols }));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    fn symbol(&self, symbol: &ForeignSymbol<'a>) -> &WasmSymbol<'a> {
+        self.symbols[symbol.index]
+            .as_ref()
+            .expect("section symbols are not exposed")
+    }
+}
+
+impl<'a> ForeignObject<'a> for WasmObject<'a> {
+    fn symbols(&self) -> Result<Vec<ForeignSymbol<'a>>> {
+        Ok(self
+            .symbols
+            .iter()
+            .enumerate()
+            .filter_map(|(index, symbol)| {
+                let symbol = symbol.as_ref()?;
+                Some(ForeignSymbol {
+                    name: symbol.name.rooted(),
+                    index,
+                    global: symbol.flags.value & WASM_SYM_BINDING_LOCAL == 0,
+                    hidden: symbol.flags.value & WASM_SYM_VISIBILITY_HIDDEN != 0,
+                })
+            })
+            .collect())
+    }
+
+    /// Set or clear `WASM_SYM_VISIBILITY_HIDDEN`.  Undefined symbols are left unchanged.
+    fn set_hidden(&self, symbol: &ForeignSymbol<'a>, hidden: bool) -> Result<Option<Patch>> {
+        let symbol = self.symbol(symbol);
+        let flags = if hidden {
+            symbol.flags.value | WASM_SYM_VISIBILITY_HIDDEN
+        } else {
+            symbol.flags.value & !WASM_SYM_VISIBILITY_HIDDEN
+        };
+        if !symbol.is_defined() || flags == symbol.flags.value {
+            return Ok(None);
+        }
+        Ok(Some(symbol.flags.patch(flags)?))
+    }
+
+    /// Set or clear `WASM_SYM_BINDING_LOCAL` of a defined symbol.
+    fn set_global(&self, symbol: &ForeignSymbol<'a>, global: bool) -> Result<Option<Patch>> {
+        let symbol = self.symbol(symbol);
+        let flags = if global {
+            symbol.flags.value & !WASM_SYM_BINDING_LOCAL
+        } else {
+            symbol.flags.value | WASM_SYM_BINDING_LOCAL
+        };
+        if !symbol.is_defined() || flags == symbol.flags.value {
+            return Ok(None);
+        }
+        Ok(Some(symbol.flags.patch(flags)?))
+    }
+
+    /// Rewrite the name and its length, which is padded to keep the symbol table in place.
+    ///
+    /// Undefined symbols are renamed by renaming their import.
+    fn rename(&self, symbol: &ForeignSymbol<'a>, new_name: &str) -> Result<Patch> {
+        let name = self.symbol(symbol).name;
+        if new_name.len() > name.value.len() {
+            return Err(Error::PatchTooBig);
+        }
+        let size = name.length.size + name.value.len() - new_name.len();
+        let mut bytes = padded_leb(new_name.len() as u64, size)
+            .filter(|_| size <= MAX_LEB_SIZE)
+            .ok_or_else(|| {
+                Error::Unsupported(format!(
+                    "Can't rename '{}' to '{}': names in WebAssembly objects can be shortened by at most {} bytes",
+                    name.value,
+                    new_name,
+                    MAX_LEB_SIZE - name.length.size
+                ))
+            })?;
+        bytes.extend_from_slice(new_name.as_bytes());
+        let location = Location {
+            offset: name.length.offset,
+            size: bytes.len(),
+            ctx: context(),
+        };
+        Patch::from_bytes(&location, &bytes)
+    }
+}
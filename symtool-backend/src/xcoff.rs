@@ -0,0 +1,254 @@
+//! Manipulate XCOFF objects, the object format of AIX.
+//!
+//! A function `foo` has two symbols: the function descriptor `foo`, and the entry point `.foo`
+//! which calls are made to.  Renaming a descriptor also renames its entry point, but visibility
+//! rules see both symbols, so patterns should match the entry point as well.
+
+use crate::error::{Error, Result};
+use crate::object::{ForeignObject, ForeignSymbol, Object, ObjectFormat};
+use crate::patch::{Location, Patch, Rooted};
+use goblin::container::{Container, Ctx, Endian};
+use scroll::ctx::StrCtx;
+use scroll::{Pread, BE};
+use std::collections::HashMap;
+
+const XCOFF_MAGIC_32: u16 = 0x01df;
+const XCOFF_MAGIC_64: u16 = 0x01f7;
+
+/// The file header flags of executables and shared objects, which aren't recognized.
+const F_EXEC: u16 = 0x0002;
+const F_SHROBJ: u16 = 0x2000;
+
+/// The size of a symbol table entry, including auxiliary entries.
+const SYMBOL_SIZE: usize = 18;
+
+/// The offset of `n_type` in a symbol table entry, which is the same in 32- and 64-bit objects.
+const TYPE_OFFSET: usize = 14;
+
+/// The offset of `n_sclass` in a symbol table entry.
+const STORAGE_CLASS_OFFSET: usize = 16;
+
+const C_EXT: u8 = 2;
+const C_HIDEXT: u8 = 107;
+const C_WEAKEXT: u8 = 111;
+
+const SYM_V_MASK: u16 = 0x7000;
+const SYM_V_INTERNAL: u16 = 0x1000;
+const SYM_V_HIDDEN: u16 = 0x2000;
+
+fn context(is_64: bool) -> Ctx {
+    let container = if is_64 {
+        Container::Big
+    } else {
+        Container::Little
+    };
+    Ctx::new(container, Endian::Big)
+}
+
+/// The XCOFF object format.
+///
+/// Executables and shared objects, whose loader section has a symbol table of its own, are not
+/// recognized.
+pub struct XcoffFormat;
+
+impl ObjectFormat for XcoffFormat {
+    fn name(&self) -> &str {
+        "XCOFF"
+    }
+
+    fn parse<'a>(&self, bytes: &'a [u8]) -> Result<Option<Object<'a>>> {
+        let is_64 = match bytes.pread_with::<u16>(0, BE) {
+            Ok(XCOFF_MAGIC_32) => false,
+            Ok(XCOFF_MAGIC_64) => true,
+            _ => return Ok(None),
+        };
+        let object = XcoffObject::parse(bytes, is_64)?;
+        if object.flags & (F_EXEC | F_SHROBJ) != 0 {
+            return Ok(None);
+        }
+        Ok(Some(Object::Other(Box::new(object))))
+    }
+}
+
+/// A symbol table entry, excluding the name.
+struct Entry {
+    scnum: i16,
+    ty: u16,
+    sclass: u8,
+    numaux: u8,
+}
+
+/// An XCOFF object.
+pub struct XcoffObject<'a> {
+    bytes: &'a [u8],
+    is_64: bool,
+    flags: u16,
+    symoff: usize,
+    nsyms: usize,
+
+    /// The offset of the string table, which follows the symbol table
+    strtab: usize,
+
+    /// The indices of function entry points by name, such as `.foo`
+    entry_points: HashMap<&'a str, usize>,
+}
+
+impl<'a> XcoffObject<'a> {
+    fn parse(bytes: &'a [u8], is_64: bool) -> Result<Self> {
+        let (flags, symoff, nsyms) = if is_64 {
+            let symoff: u64 = bytes.pread_with(8, BE)?;
+            let flags: u16 = bytes.pread_with(18, BE)?;
+            let nsyms: u32 = bytes.pread_with(20, BE)?;
+            (flags, symoff as usize, nsyms as usize)
+        } else {
+            let symoff: u32 = bytes.pread_with(8, BE)?;
+            let nsyms: u32 = bytes.pread_with(12, BE)?;
+            let flags: u16 = bytes.pread_with(18, BE)?;
+            (flags, symoff as usize, nsyms as usize)
+        };
+        let strtab = nsyms
+            .checked_mul(SYMBOL_SIZE)
+            .and_then(|size| size.checked_add(symoff))
+            .filter(|strtab| symoff == 0 || *strtab <= bytes.len())
+            .ok_or_else(|| {
+                Error::Malformed("Symbol table extends past the end of the file".to_string())
+            })?;
+        let mut object = Self {
+            bytes,
+            is_64,
+            flags,
+            symoff,
+            nsyms: if symoff == 0 { 0 } else { nsyms },
+            strtab,
+            entry_points: HashMap::new(),
+        };
+        let mut entry_points = HashMap::new();
+        for symbol in object.symbols()? {
+            if symbol.name.starts_with('.') {
+                entry_points.insert(symbol.name.value, symbol.index);
+            }
+        }
+        object.entry_points = entry_points;
+        Ok(object)
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        self.symoff + index * SYMBOL_SIZE
+    }
+
+    fn entry(&self, index: usize) -> Result<Entry> {
+        let offset = self.offset(index);
+        Ok(Entry {
+            scnum: self.bytes.pread_with(offset + 12, BE)?,
+            ty: self.bytes.pread_with(offset + TYPE_OFFSET, BE)?,
+            sclass: self.bytes.pread(offset + STORAGE_CLASS_OFFSET)?,
+            numaux: self.bytes.pread(offset + 17)?,
+        })
+    }
+
+    /// The name of a symbol, which is stored in the entry if it is short enough for a 32-bit
+    /// object, and in the string table otherwise.
+    fn name(&self, index: usize) -> Result<Rooted<&'a str>> {
+        let offset = self.offset(index);
+        let (offset, name) = if !self.is_64 && self.bytes.pread_with::<u32>(offset, BE)? != 0 {
+            let name: &str = self
+                .bytes
+                .pread_with(offset, StrCtx::DelimiterUntil(0, 8))?;
+            (offset, name)
+        } else {
+            let name_offset = if self.is_64 { offset + 8 } else { offset + 4 };
+            let name_offset: u32 = self.bytes.pread_with(name_offset, BE)?;
+            let offset = self.strtab + name_offset as usize;
+            let name: &str = self.bytes.pread(offset)?;
+            (offset, name)
+        };
+        let location = Location {
+            offset,
+            size: name.len(),
+            ctx: context(self.is_64),
+        };
+        Ok(Rooted::new(location, name))
+    }
+
+    fn patch_entry(&self, index: usize, offset: usize, bytes: &[u8]) -> Result<Patch> {
+        let location = Location {
+            offset: self.offset(index) + offset,
+            size: bytes.len(),
+            ctx: context(self.is_64),
+        };
+        Patch::from_bytes(&location, bytes)
+    }
+}
+
+impl<'a> ForeignObject<'a> for XcoffObject<'a> {
+    /// External symbols and the names of local csects.  Files, sections and debugging symbols
+    /// are skipped.
+    fn symbols(&self) -> Result<Vec<ForeignSymbol<'a>>> {
+        let mut symbols = Vec::new();
+        let mut index = 0;
+        while index < self.nsyms {
+            let entry = self.entry(index)?;
+            let symbol_index = index;
+            index += 1 + entry.numaux as usize;
+            if !matches!(entry.sclass, C_EXT | C_HIDEXT | C_WEAKEXT) {
+                continue;
+            }
+            let visibility = entry.ty & SYM_V_MASK;
+            symbols.push(ForeignSymbol {
+                name: self.name(symbol_index)?,
+                index: symbol_index,
+                global: entry.sclass != C_HIDEXT,
+                hidden: visibility == SYM_V_HIDDEN || visibility == SYM_V_INTERNAL,
+            });
+        }
+        Ok(symbols)
+    }
+
+    /// Set the visibility in `n_type` of a defined symbol to `SYM_V_HIDDEN`, or clear it.
+    fn set_hidden(&self, symbol: &ForeignSymbol<'a>, hidden: bool) -> Result<Option<Patch>> {
+        let entry = self.entry(symbol.index)?;
+        let ty = if hidden {
+            entry.ty & !SYM_V_MASK | SYM_V_HIDDEN
+        } else {
+            entry.ty & !SYM_V_MASK
+        };
+        if entry.scnum <= 0 || ty == entry.ty {
+            return Ok(None);
+        }
+        Ok(Some(self.patch_entry(
+            symbol.index,
+            TYPE_OFFSET,
+            &ty.to_be_bytes(),
+        )?))
+    }
+
+    /// Change the storage class of a defined symbol between `C_EXT` and `C_HIDEXT`.
+    fn set_global(&self, symbol: &ForeignSymbol<'a>, global: bool) -> Result<Option<Patch>> {
+        let entry = self.entry(symbol.index)?;
+        let sclass = match (global, entry.sclass) {
+            _ if entry.scnum <= 0 => return Ok(None),
+            (true, C_HIDEXT) => C_EXT,
+            (false, C_EXT) => C_HIDEXT,
+            _ => return Ok(None),
+        };
+        Ok(Some(self.patch_entry(
+            symbol.index,
+            STORAGE_CLASS_OFFSET,
+            &[sclass],
+        )?))
+    }
+
+    /// Rename the entry point of a function along with its descriptor.
+    fn rename_references(&self, symbol: &ForeignSymbol<'a>, new_name: &str) -> Result<Vec<Patch>> {
+        let entry_point = match self.entry_points.get(format!(".{}", *symbol.name).as_str()) {
+            Some(index) => ForeignSymbol {
+                name: self.name(*index)?,
+                index: *index,
+                global: symbol.global,
+                hidden: symbol.hidden,
+            },
+            None => return Ok(Vec::new()),
+        };
+        Ok(vec![self.rename(&entry_point, &format!(".{}", new_name))?])
+    }
+}
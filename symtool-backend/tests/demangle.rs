@@ -0,0 +1,49 @@
+use symtool_backend::demangle::demangle;
+
+#[test]
+fn cpp_names() {
+    assert_eq!(demangle("_ZN2ns4openEv").as_deref(), Some("ns::open()"));
+    assert_eq!(
+        demangle("_ZN2ns4openEPKci").as_deref(),
+        Some("ns::open(char const*, int)")
+    );
+    assert_eq!(
+        demangle("_ZNSt6vectorIiSaIiEE9push_backERKi").as_deref(),
+        Some("std::vector<int, std::allocator<int> >::push_back(int const&)")
+    );
+    assert_eq!(demangle("_ZN3FooC1Ev").as_deref(), Some("Foo::Foo()"));
+    assert_eq!(demangle("_ZN3FooD2Ev").as_deref(), Some("Foo::~Foo()"));
+    assert_eq!(demangle("_ZZ4mainE5local").as_deref(), Some("main::local"));
+}
+
+#[test]
+fn mach_o_underscore() {
+    assert_eq!(demangle("__ZN2ns4openEv").as_deref(), Some("ns::open()"));
+    assert_eq!(
+        demangle("__ZN4core3fmt5write17h0123456789abcdefE").as_deref(),
+        Some("core::fmt::write")
+    );
+}
+
+#[test]
+fn rust_names() {
+    assert_eq!(
+        demangle("_ZN4core3fmt5write17h0123456789abcdefE").as_deref(),
+        Some("core::fmt::write")
+    );
+    assert_eq!(
+        demangle("_ZN66_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..drop..Drop$GT$4drop17h0123456789abcdefE").as_deref(),
+        Some("<alloc::vec::Vec<T> as core::ops::drop::Drop>::drop")
+    );
+    assert_eq!(
+        demangle("_RNvCs1234_7mycrate3foo").as_deref(),
+        Some("mycrate::foo")
+    );
+}
+
+#[test]
+fn other_names_unchanged() {
+    for name in &["main", "i", "_start", "_Z", "_Zfoo", "__ZZ", "ns::open"] {
+        assert_eq!(demangle(name), None, "{}", name);
+    }
+}
@@ -0,0 +1,922 @@
+use goblin::container::{Container, Endian};
+use std::io::Cursor;
+use symtool_backend::object::{transform, transform_object, Object};
+use symtool_backend::patch::Patch;
+use symtool_backend::testing::{
+    build_archive, ArchiveVariant, Binding, Format, Kind, ObjectBuilder, Symbol,
+};
+
+fn builder() -> ObjectBuilder {
+    ObjectBuilder::new()
+        .symbol(Symbol::new("local").binding(Binding::Local))
+        .symbol(Symbol::new("function"))
+        .symbol(Symbol::new("object").kind(Kind::Object).hidden())
+        .symbol(Symbol::new("weak").binding(Binding::Weak))
+        .symbol(Symbol::new("undefined").undefined())
+}
+
+fn names(bytes: &[u8]) -> Vec<String> {
+    let mut names = Vec::new();
+    match goblin::Object::parse(bytes).unwrap() {
+        goblin::Object::Elf(elf) => {
+            for item in symtool_backend::elf::SymtabIter::symtab_from_elf(bytes, &elf)
+                .unwrap()
+                .unwrap()
+            {
+                if let (Some(name), _) = item.unwrap() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        goblin::Object::Mach(goblin::mach::Mach::Binary(mach)) => {
+            for item in symtool_backend::mach::SymtabIter::from_mach(bytes, &mach).unwrap() {
+                if let (Some(name), _) = item.unwrap() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        _ => panic!("unexpected object type"),
+    }
+    names.sort();
+    names
+}
+
+fn transformed<F>(bytes: &[u8], f: F) -> Vec<u8>
+where
+    F: Fn(&[u8], Object) -> Result<Vec<Patch>, std::io::Error> + 'static,
+{
+    let mut output = Vec::new();
+    transform_object(&mut Cursor::new(bytes), &mut output, &f).unwrap();
+    output
+}
+
+fn transformed_names<F>(format: Format, container: Container, f: F) -> Vec<String>
+where
+    F: Fn(&[u8], Object) -> Result<Vec<Patch>, std::io::Error> + 'static,
+{
+    names(&transformed(
+        &builder().container(container).build(format),
+        f,
+    ))
+}
+
+#[test]
+fn objects_round_trip() {
+    let expected = vec!["function", "local", "object", "undefined", "weak"];
+    for format in &[Format::Elf, Format::MachO] {
+        for container in &[Container::Little, Container::Big] {
+            for endian in &[Endian::Little, Endian::Big] {
+                let bytes = builder()
+                    .container(*container)
+                    .endian(*endian)
+                    .build(*format);
+                assert_eq!(names(&bytes), expected);
+            }
+        }
+    }
+}
+
+#[test]
+fn archives_transform() {
+    for variant in &[ArchiveVariant::Bsd, ArchiveVariant::Gnu] {
+        let archive = build_archive(
+            &[
+                ("elf.o", builder().build(Format::Elf)),
+                ("macho.o", builder().build(Format::MachO)),
+            ],
+            *variant,
+        );
+        let mut output = Vec::new();
+        let transform = |_: &[u8], object: Object| -> Result<Vec<Patch>, std::io::Error> {
+            match object {
+                Object::Elf(_) | Object::MachO(_) => Ok(Vec::new()),
+                Object::Other(_) => panic!("unexpected object format"),
+            }
+        };
+        transform_object(&mut Cursor::new(&archive), &mut output, &transform).unwrap();
+        assert_eq!(archive, output);
+    }
+}
+
+#[test]
+fn archives_transform_with_context() {
+    let archive = build_archive(
+        &[
+            ("elf.o", builder().build(Format::Elf)),
+            ("macho.o", builder().build(Format::MachO)),
+        ],
+        ArchiveVariant::Gnu,
+    );
+    let mut output = Vec::new();
+    let mut members = Vec::new();
+    transform(&mut Cursor::new(&archive), &mut output, |context| {
+        members.push(context.member.map(str::to_string));
+        Ok::<_, std::io::Error>(())
+    })
+    .unwrap();
+    assert_eq!(
+        members,
+        vec![Some("elf.o".to_string()), Some("macho.o".to_string())]
+    );
+    assert_eq!(archive, output);
+}
+
+#[test]
+fn objects_with_extended_section_numbering() {
+    // The first count fits in e_shnum, the second requires extended section numbering
+    for extra_sections in &[0xff00 - 7, 70000] {
+        let bytes = builder().extra_sections(*extra_sections).build(Format::Elf);
+        let elf = symtool_backend::elf::parse(&bytes).unwrap();
+        assert_eq!(elf.section_headers.len(), extra_sections + 6);
+        assert!(symtool_backend::elf::section_by_name(&elf, ".shstrtab").is_some());
+        assert_eq!(
+            names(&bytes),
+            vec!["function", "local", "object", "undefined", "weak"]
+        );
+    }
+}
+
+#[test]
+fn elf_symbols_patched_in_object_byte_order() {
+    for container in &[Container::Little, Container::Big] {
+        for endian in &[Endian::Little, Endian::Big] {
+            let bytes = builder()
+                .container(*container)
+                .endian(*endian)
+                .build(Format::Elf);
+            let output = transformed(&bytes, |bytes, object| {
+                let elf = match object {
+                    Object::Elf(elf) => elf,
+                    _ => panic!("unexpected object format"),
+                };
+                let mut patches = Vec::new();
+                for item in symtool_backend::elf::SymtabIter::symtab_from_elf(bytes, &elf)
+                    .unwrap()
+                    .unwrap()
+                {
+                    let (name, sym) = item.unwrap();
+                    if name.as_deref() == Some(&"function") {
+                        let mut new_sym = sym.value;
+                        new_sym.st_value = 0x1234_5678;
+                        new_sym.st_size = 0x0102;
+                        new_sym.st_info = (goblin::elf::sym::STB_WEAK << 4) | new_sym.st_type();
+                        patches.push(sym.patch_with(new_sym).unwrap());
+                    }
+                }
+                Ok(patches)
+            });
+            let elf = goblin::elf::Elf::parse(&output).unwrap();
+            let sym = elf
+                .syms
+                .iter()
+                .find(|sym| elf.strtab.get(sym.st_name).unwrap().unwrap() == "function")
+                .unwrap();
+            assert_eq!(sym.st_value, 0x1234_5678);
+            assert_eq!(sym.st_size, 0x0102);
+            assert_eq!(sym.st_bind(), goblin::elf::sym::STB_WEAK);
+        }
+    }
+}
+
+#[test]
+fn unneeded_symbols_stripped() {
+    for format in &[Format::Elf, Format::MachO] {
+        for container in &[Container::Little, Container::Big] {
+            let names = transformed_names(*format, *container, |bytes, object| {
+                let (_, patches) = match object {
+                    Object::Elf(elf) => {
+                        symtool_backend::elf::strip_unneeded(bytes, &elf, &|_| false)
+                    }
+                    Object::MachO(mach) => {
+                        symtool_backend::mach::strip_unneeded(bytes, &mach, &|_| false)
+                    }
+                    Object::Other(_) => panic!("unexpected object format"),
+                }
+                .unwrap();
+                Ok(patches)
+            });
+            assert_eq!(names, vec!["function", "object", "weak"]);
+        }
+    }
+}
+
+#[test]
+fn local_symbols_discarded() {
+    for format in &[Format::Elf, Format::MachO] {
+        for container in &[Container::Little, Container::Big] {
+            let names = transformed_names(*format, *container, |bytes, object| {
+                let (_, patches) = match object {
+                    Object::Elf(elf) => {
+                        symtool_backend::elf::discard_locals(bytes, &elf, &|_| false)
+                    }
+                    Object::MachO(mach) => {
+                        symtool_backend::mach::discard_locals(bytes, &mach, &|_| false)
+                    }
+                    Object::Other(_) => panic!("unexpected object format"),
+                }
+                .unwrap();
+                Ok(patches)
+            });
+            assert_eq!(names, vec!["function", "object", "undefined", "weak"]);
+        }
+    }
+}
+
+/// Strip the `.symtab` symbols of an ELF object with the names in `strip`.
+fn elf_symbols_stripped(
+    bytes: &[u8],
+    strip: &'static [&'static str],
+) -> symtool_backend::error::Result<(usize, Vec<Patch>)> {
+    let elf = goblin::elf::Elf::parse(bytes).unwrap();
+    symtool_backend::elf::strip_symbols(bytes, &elf, &|_, sym| {
+        strip.contains(&elf.strtab.get(sym.st_name).unwrap().unwrap())
+    })
+}
+
+#[test]
+fn elf_symbols_stripped_with_relocations_renumbered() {
+    for container in &[Container::Little, Container::Big] {
+        for endian in &[Endian::Little, Endian::Big] {
+            let bytes = builder()
+                .relocation("weak")
+                .relocation("undefined")
+                .container(*container)
+                .endian(*endian)
+                .build(Format::Elf);
+            let (count, patches) = elf_symbols_stripped(&bytes, &["local", "object"]).unwrap();
+            assert_eq!(count, 2);
+            let output = apply(&bytes, &patches);
+            assert_eq!(names(&output), vec!["function", "undefined", "weak"]);
+            let elf = goblin::elf::Elf::parse(&output).unwrap();
+            assert_eq!(relocation_names(&elf), vec!["weak", "undefined"]);
+
+            // Symbols relocations refer to can't be removed
+            assert!(elf_symbols_stripped(&bytes, &["weak"]).is_err());
+        }
+    }
+}
+
+#[test]
+fn mach_stabs_stripped() {
+    for container in &[Container::Little, Container::Big] {
+        let bytes = builder().container(*container).build(Format::MachO);
+        let output = transformed(&bytes, |bytes, object| {
+            let mach = match object {
+                Object::MachO(mach) => mach,
+                _ => panic!("unexpected object format"),
+            };
+            // Turn "local" into a debugging (stab) entry, as the debug map ld leaves
+            let mut patches = Vec::new();
+            for item in symtool_backend::mach::SymtabIter::from_mach(bytes, &mach).unwrap() {
+                if let (Some(name), nlist) = item.unwrap() {
+                    if *name == "local" {
+                        let stab = goblin::mach::symbols::Nlist {
+                            n_type: goblin::mach::symbols::N_FUN,
+                            ..(*nlist).clone()
+                        };
+                        patches.push(nlist.patch_with(stab).unwrap());
+                    }
+                }
+            }
+            let patched = apply(bytes, &patches);
+            let mach = goblin::mach::MachO::parse(&patched, 0).unwrap();
+            let (count, stab_patches) =
+                symtool_backend::mach::strip_stabs(&patched, &mach).unwrap();
+            assert_eq!(count, 1);
+            patches.extend(stab_patches);
+            Ok(patches)
+        });
+        assert_eq!(
+            names(&output),
+            vec!["function", "object", "undefined", "weak"]
+        );
+
+        let mach = goblin::mach::MachO::parse(&output, 0).unwrap();
+        let dysymtab = mach
+            .load_commands
+            .iter()
+            .find_map(|command| match command.command {
+                goblin::mach::load_command::CommandVariant::Dysymtab(dysymtab) => Some(dysymtab),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!((dysymtab.ilocalsym, dysymtab.nlocalsym), (0, 0));
+        assert_eq!(
+            symtool_backend::mach::strip_stabs(&output, &mach)
+                .unwrap()
+                .0,
+            0
+        );
+    }
+}
+
+#[test]
+fn elf_symbols_added() {
+    for container in &[Container::Little, Container::Big] {
+        for endian in &[Endian::Little, Endian::Big] {
+            let bytes = builder()
+                .container(*container)
+                .endian(*endian)
+                .build(Format::Elf);
+            let output = transformed(&bytes, |bytes, object| {
+                let elf = match object {
+                    Object::Elf(elf) => elf,
+                    _ => panic!("unexpected object format"),
+                };
+                let sym = goblin::elf::sym::Sym {
+                    st_info: goblin::elf::sym::STB_GLOBAL << 4,
+                    st_shndx: goblin::elf::section_header::SHN_ABS as usize,
+                    st_value: 0x1234_5678,
+                    ..Default::default()
+                };
+                Ok(symtool_backend::elf::add_symbols(bytes, &elf, &[("added", sym)]).unwrap())
+            });
+            assert_eq!(
+                names(&output),
+                vec!["added", "function", "local", "object", "undefined", "weak"]
+            );
+            let elf = goblin::elf::Elf::parse(&output).unwrap();
+            let sym = elf
+                .syms
+                .iter()
+                .find(|sym| elf.strtab.get(sym.st_name).unwrap().unwrap() == "added")
+                .unwrap();
+            assert_eq!(sym.st_value, 0x1234_5678);
+        }
+    }
+}
+
+#[test]
+fn mach_symbols_sorted_into_groups() {
+    for container in &[Container::Little, Container::Big] {
+        let bytes = builder().container(*container).build(Format::MachO);
+        let output = transformed(&bytes, |bytes, object| {
+            let mach = match object {
+                Object::MachO(mach) => mach,
+                _ => panic!("unexpected object format"),
+            };
+            // Make "function" local in place, leaving it in the external defined group
+            let mut patches = Vec::new();
+            for item in symtool_backend::mach::SymtabIter::from_mach(bytes, &mach).unwrap() {
+                if let (Some(name), nlist) = item.unwrap() {
+                    if *name == "function" {
+                        let local = goblin::mach::symbols::Nlist {
+                            n_type: nlist.n_type & !goblin::mach::symbols::N_EXT,
+                            ..(*nlist).clone()
+                        };
+                        patches.push(nlist.patch_with(local).unwrap());
+                    }
+                }
+            }
+            let mut patched = bytes.to_vec();
+            for patch in patches.iter() {
+                patch.apply(&mut patched);
+            }
+            let mach = goblin::mach::MachO::parse(&patched, 0).unwrap();
+            patches.extend(symtool_backend::mach::sort_symbols(&patched, &mach).unwrap());
+            Ok(patches)
+        });
+        assert_eq!(
+            names(&output),
+            vec!["function", "local", "object", "undefined", "weak"]
+        );
+
+        let mach = goblin::mach::MachO::parse(&output, 0).unwrap();
+        let dysymtab = mach
+            .load_commands
+            .iter()
+            .find_map(|command| match command.command {
+                goblin::mach::load_command::CommandVariant::Dysymtab(dysymtab) => Some(dysymtab),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            (
+                dysymtab.ilocalsym,
+                dysymtab.nlocalsym,
+                dysymtab.iextdefsym,
+                dysymtab.nextdefsym
+            ),
+            (0, 2, 2, 2)
+        );
+        assert_eq!((dysymtab.iundefsym, dysymtab.nundefsym), (4, 1));
+        let symbols: Vec<(String, bool)> =
+            symtool_backend::mach::SymtabIter::from_mach(&output, &mach)
+                .unwrap()
+                .map(|item| {
+                    let (name, nlist) = item.unwrap();
+                    (
+                        name.unwrap().to_string(),
+                        nlist.n_type & goblin::mach::symbols::N_EXT != 0,
+                    )
+                })
+                .collect();
+        assert!(symbols[..2].iter().all(|(_, external)| !external));
+        assert!(symbols[2..].iter().all(|(_, external)| *external));
+    }
+}
+
+#[test]
+fn string_tables_compacted() {
+    for format in &[Format::Elf, Format::MachO] {
+        for container in &[Container::Little, Container::Big] {
+            let bytes = builder()
+                .symbol(Symbol::new("discarded_local").binding(Binding::Local))
+                .container(*container)
+                .build(*format);
+            let output = transformed(&bytes, |bytes, object| {
+                let (_, mut patches) = match object {
+                    Object::Elf(elf) => {
+                        symtool_backend::elf::discard_locals(bytes, &elf, &|_| false)
+                    }
+                    Object::MachO(mach) => {
+                        symtool_backend::mach::discard_locals(bytes, &mach, &|_| false)
+                    }
+                    Object::Other(_) => panic!("unexpected object format"),
+                }
+                .unwrap();
+                let mut patched = bytes.to_vec();
+                for patch in patches.iter() {
+                    patch.apply(&mut patched);
+                }
+                let (size, new_size, compact_patches) =
+                    match goblin::Object::parse(&patched).unwrap() {
+                        goblin::Object::Elf(elf) => {
+                            symtool_backend::elf::compact_strtab(&patched, &elf)
+                        }
+                        goblin::Object::Mach(goblin::mach::Mach::Binary(mach)) => {
+                            symtool_backend::mach::compact_strtab(&patched, &mach)
+                        }
+                        _ => panic!("unexpected object format"),
+                    }
+                    .unwrap();
+                // The names of the discarded local symbols are dropped
+                assert!(new_size < size);
+                patches.extend(compact_patches);
+                Ok(patches)
+            });
+            assert_eq!(
+                names(&output),
+                vec!["function", "object", "undefined", "weak"]
+            );
+        }
+    }
+}
+
+#[test]
+fn elf_tables_rebuilt() {
+    fn add(bytes: &[u8], object: Object) -> Vec<Patch> {
+        let elf = match object {
+            Object::Elf(elf) => elf,
+            _ => panic!("unexpected object format"),
+        };
+        let sym = goblin::elf::sym::Sym {
+            st_info: goblin::elf::sym::STB_GLOBAL << 4,
+            st_shndx: goblin::elf::section_header::SHN_ABS as usize,
+            ..Default::default()
+        };
+        symtool_backend::elf::add_symbols(bytes, &elf, &[("added", sym)]).unwrap()
+    }
+
+    for container in &[Container::Little, Container::Big] {
+        for endian in &[Endian::Little, Endian::Big] {
+            let bytes = builder()
+                .container(*container)
+                .endian(*endian)
+                .build(Format::Elf);
+            let appended = transformed(&bytes, |bytes, object| Ok(add(bytes, object)));
+            let output = transformed(&bytes, |bytes, object| {
+                let mut patches = add(bytes, object);
+                let mut patched = bytes.to_vec();
+                for patch in patches.iter() {
+                    patch.apply(&mut patched);
+                }
+                let elf = goblin::elf::Elf::parse(&patched).unwrap();
+                patches.extend(symtool_backend::elf::rebuild_tables(&patched, &elf).unwrap());
+                Ok(patches)
+            });
+            assert_eq!(
+                names(&output),
+                vec!["added", "function", "local", "object", "undefined", "weak"]
+            );
+            // The tables replaced by the appended ones are dropped
+            assert!(output.len() < appended.len());
+            let elf = goblin::elf::Elf::parse(&output).unwrap();
+            let mut ranges = elf
+                .section_headers
+                .iter()
+                .filter(|section| section.sh_type != goblin::elf::section_header::SHT_NOBITS)
+                .map(|section| (section.sh_offset, section.sh_offset + section.sh_size))
+                .collect::<Vec<_>>();
+            ranges.sort();
+            for pair in ranges.windows(2) {
+                assert!(pair[0].1 <= pair[1].0);
+            }
+            assert!(ranges.last().unwrap().1 <= elf.header.e_shoff);
+        }
+    }
+}
+
+fn apply(bytes: &[u8], patches: &[Patch]) -> Vec<u8> {
+    let mut patched = bytes.to_vec();
+    for patch in patches {
+        patch.apply(&mut patched);
+    }
+    patched
+}
+
+fn elf_object(object: Object) -> Box<goblin::elf::Elf> {
+    match object {
+        Object::Elf(elf) => elf,
+        _ => panic!("unexpected object format"),
+    }
+}
+
+/// The names of the symbols the relocations of `elf` refer to, those of `.symtab` first.
+fn relocation_names(elf: &goblin::elf::Elf) -> Vec<String> {
+    let mut names = Vec::new();
+    for (index, relocs) in &elf.shdr_relocs {
+        let link = elf.section_headers[*index].sh_link as usize;
+        if elf.section_headers[link].sh_type != goblin::elf::section_header::SHT_SYMTAB {
+            continue;
+        }
+        for reloc in relocs.iter() {
+            let sym = elf.syms.get(reloc.r_sym).unwrap();
+            names.push(elf.strtab.get(sym.st_name).unwrap().unwrap().to_string());
+        }
+    }
+    for reloc in elf.dynrelas.iter() {
+        let sym = elf.dynsyms.get(reloc.r_sym).unwrap();
+        names.push(elf.dynstrtab.get(sym.st_name).unwrap().unwrap().to_string());
+    }
+    names
+}
+
+fn assert_sections_disjoint(elf: &goblin::elf::Elf) {
+    let mut ranges = elf
+        .section_headers
+        .iter()
+        .filter(|section| section.sh_type != goblin::elf::section_header::SHT_NOBITS)
+        .map(|section| (section.sh_offset, section.sh_offset + section.sh_size))
+        .collect::<Vec<_>>();
+    ranges.sort();
+    for pair in ranges.windows(2) {
+        assert!(pair[0].1 <= pair[1].0);
+    }
+    assert!(ranges.last().unwrap().1 <= elf.header.e_shoff);
+}
+
+#[test]
+fn elf_relocations_renumbered_when_tables_rebuilt() {
+    for container in &[Container::Little, Container::Big] {
+        for endian in &[Endian::Little, Endian::Big] {
+            let bytes = builder()
+                .relocation("function")
+                .relocation("undefined")
+                .container(*container)
+                .endian(*endian)
+                .build(Format::Elf);
+            let output = transformed(&bytes, |bytes, object| {
+                let elf = elf_object(object);
+                let sym = goblin::elf::sym::Sym {
+                    st_info: goblin::elf::sym::STB_LOCAL << 4,
+                    st_shndx: goblin::elf::section_header::SHN_ABS as usize,
+                    ..Default::default()
+                };
+                let mut patches =
+                    symtool_backend::elf::add_symbols(bytes, &elf, &[("added", sym)]).unwrap();
+                // The local symbol is moved before the global ones the relocations refer to
+                let patched = apply(bytes, &patches);
+                let elf = goblin::elf::Elf::parse(&patched).unwrap();
+                patches.extend(symtool_backend::elf::sort_locals(&patched, &elf).unwrap());
+                let patched = apply(bytes, &patches);
+                let elf = goblin::elf::Elf::parse(&patched).unwrap();
+                patches.extend(symtool_backend::elf::rebuild_tables(&patched, &elf).unwrap());
+                Ok(patches)
+            });
+            assert_eq!(
+                names(&output),
+                vec!["added", "function", "local", "object", "undefined", "weak"]
+            );
+            let elf = goblin::elf::Elf::parse(&output).unwrap();
+            assert_eq!(relocation_names(&elf), vec!["function", "undefined"]);
+            // .rela.text is placed after the tables, which it follows again
+            let rela = symtool_backend::elf::section_by_name(&elf, ".rela.text").unwrap();
+            let strtab = symtool_backend::elf::section_by_name(&elf, ".strtab").unwrap();
+            assert!(rela.sh_offset >= strtab.sh_offset + strtab.sh_size);
+            assert_sections_disjoint(&elf);
+        }
+    }
+}
+
+#[test]
+fn elf_loaded_sections_kept_when_tables_rebuilt() {
+    for container in &[Container::Little, Container::Big] {
+        for endian in &[Endian::Little, Endian::Big] {
+            let bytes = builder()
+                .relocation("function")
+                .container(*container)
+                .endian(*endian)
+                .build_shared_elf();
+            let output = transformed(&bytes, |bytes, object| {
+                let elf = elf_object(object);
+                let mut strtab = symtool_backend::elf::rebuild_strtab(bytes, &elf)
+                    .unwrap()
+                    .unwrap();
+                let mut patches = Vec::new();
+                for item in symtool_backend::elf::SymtabIter::symtab_from_elf(bytes, &elf)
+                    .unwrap()
+                    .unwrap()
+                {
+                    let (name, sym) = item.unwrap();
+                    if let Some(name) = name {
+                        let mut new_sym = sym.value;
+                        new_sym.st_name = strtab.add(&format!("{}_renamed", *name));
+                        patches.push(sym.patch_with(new_sym).unwrap());
+                    }
+                }
+                patches.extend(strtab.patches().unwrap());
+                let patched = apply(bytes, &patches);
+                let elf = goblin::elf::Elf::parse(&patched).unwrap();
+                patches.extend(symtool_backend::elf::rebuild_tables(&patched, &elf).unwrap());
+                Ok(patches)
+            });
+            assert_eq!(
+                names(&output),
+                vec![
+                    "function_renamed",
+                    "local_renamed",
+                    "object_renamed",
+                    "undefined_renamed",
+                    "weak_renamed"
+                ]
+            );
+            let (before, after) = (
+                goblin::elf::Elf::parse(&bytes).unwrap(),
+                goblin::elf::Elf::parse(&output).unwrap(),
+            );
+            assert_eq!(before.program_headers, after.program_headers);
+            let loaded = |elf: &goblin::elf::Elf, bytes: &[u8]| {
+                elf.section_headers
+                    .iter()
+                    .filter(|section| {
+                        section.sh_flags & u64::from(goblin::elf::section_header::SHF_ALLOC) != 0
+                    })
+                    .map(|section| {
+                        let range = section.file_range();
+                        (section.sh_offset, bytes[range].to_vec())
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(loaded(&before, &bytes), loaded(&after, &output));
+            assert_eq!(relocation_names(&after), vec!["function"]);
+            assert_sections_disjoint(&after);
+        }
+    }
+}
+
+/// Look up a symbol of `.dynsym` by name in the hash table of type `sh_type`.
+fn hash_lookup(bytes: &[u8], elf: &goblin::elf::Elf, sh_type: u32, name: &str) -> Option<usize> {
+    use goblin::elf::section_header::SHT_GNU_HASH;
+    use scroll::Pread;
+
+    let le = if elf.little_endian {
+        scroll::Endian::Little
+    } else {
+        scroll::Endian::Big
+    };
+    let section = elf
+        .section_headers
+        .iter()
+        .find(|section| section.sh_type == sh_type)
+        .unwrap();
+    let table = &bytes[section.file_range()];
+    let word = |index: usize| table.pread_with::<u32>(index * 4, le).unwrap();
+    let named = |index: usize| {
+        let sym = elf.dynsyms.get(index).unwrap();
+        elf.dynstrtab.get(sym.st_name).unwrap().unwrap() == name
+    };
+    if sh_type == SHT_GNU_HASH {
+        let hash = name.bytes().fold(5381u32, |h, c| {
+            h.wrapping_mul(33).wrapping_add(u32::from(c))
+        });
+        let (nbuckets, symoffset, bloom_size, shift) = (
+            word(0) as usize,
+            word(1) as usize,
+            word(2) as usize,
+            word(3),
+        );
+        let bits = if elf.is_64 { 64 } else { 32 };
+        let bloom = (hash as usize / bits) % bloom_size;
+        let bloom = if elf.is_64 {
+            table.pread_with::<u64>(16 + bloom * 8, le).unwrap()
+        } else {
+            u64::from(word(4 + bloom))
+        };
+        let mask = 1 << (hash as usize % bits) | 1 << ((hash >> shift) as usize % bits);
+        if bloom & mask != mask {
+            return None;
+        }
+        let buckets = 4 + bloom_size * bits / 32;
+        let chains = buckets + nbuckets;
+        let mut index = word(buckets + hash as usize % nbuckets) as usize;
+        if index == 0 {
+            return None;
+        }
+        loop {
+            let chain = word(chains + index - symoffset);
+            if chain | 1 == hash | 1 && named(index) {
+                return Some(index);
+            }
+            if chain & 1 != 0 {
+                return None;
+            }
+            index += 1;
+        }
+    } else {
+        let hash = name.bytes().fold(0u32, |h, c| {
+            let h = (h << 4) + u32::from(c);
+            (h ^ (h & 0xf000_0000) >> 24) & 0x0fff_ffff
+        });
+        let nbucket = word(0) as usize;
+        let mut index = word(2 + hash as usize % nbucket) as usize;
+        while index != 0 {
+            if named(index) {
+                return Some(index);
+            }
+            index = word(2 + nbucket + index) as usize;
+        }
+        None
+    }
+}
+
+#[test]
+fn elf_dynamic_symbols_renamed() {
+    use goblin::elf::dynamic::DT_STRTAB;
+    use goblin::elf::program_header::PT_LOAD;
+    use goblin::elf::section_header::{SHT_GNU_HASH, SHT_HASH};
+
+    for container in &[Container::Little, Container::Big] {
+        for endian in &[Endian::Little, Endian::Big] {
+            let bytes = builder()
+                .symbol(Symbol::new("other"))
+                .symbol(Symbol::new("other_object").kind(Kind::Object))
+                .relocation("function")
+                .relocation("undefined")
+                .relocation("other_object")
+                .container(*container)
+                .endian(*endian)
+                .build_shared_elf();
+            let output = transformed(&bytes, |bytes, object| {
+                let elf = elf_object(object);
+                let mut renames = Vec::new();
+                for (index, item) in symtool_backend::elf::SymtabIter::dynsym_from_elf(bytes, &elf)
+                    .unwrap()
+                    .unwrap()
+                    .enumerate()
+                {
+                    if let (Some(name), _) = item.unwrap() {
+                        if ["function", "undefined"].contains(&*name) {
+                            renames.push((index, format!("{}_with_a_longer_name", *name)));
+                        }
+                    }
+                }
+                let renames = renames
+                    .iter()
+                    .map(|(index, name)| (*index, name.as_str()))
+                    .collect::<Vec<_>>();
+                let mut patches =
+                    symtool_backend::elf::rename_dynamic_symbols(bytes, &elf, &renames).unwrap();
+                let patched = apply(bytes, &patches);
+                let elf = goblin::elf::Elf::parse(&patched).unwrap();
+                patches.extend(symtool_backend::elf::rebuild_tables(&patched, &elf).unwrap());
+                Ok(patches)
+            });
+            let elf = goblin::elf::Elf::parse(&output).unwrap();
+            assert_eq!(
+                relocation_names(&elf),
+                vec![
+                    "function_with_a_longer_name",
+                    "undefined_with_a_longer_name",
+                    "other_object"
+                ]
+            );
+
+            // Defined symbols are found by their new names only
+            for sh_type in &[SHT_HASH, SHT_GNU_HASH] {
+                for name in &["function_with_a_longer_name", "object", "other", "weak"] {
+                    assert!(
+                        hash_lookup(&output, &elf, *sh_type, name).is_some(),
+                        "{}",
+                        name
+                    );
+                }
+                assert_eq!(hash_lookup(&output, &elf, *sh_type, "function"), None);
+            }
+
+            // .dynstr is moved to a new segment starting with the program header table
+            let dynstr = symtool_backend::elf::section_by_name(&elf, ".dynstr").unwrap();
+            let strtab = elf
+                .dynamic
+                .as_ref()
+                .unwrap()
+                .dyns
+                .iter()
+                .find(|d| d.d_tag == DT_STRTAB);
+            assert_eq!(strtab.unwrap().d_val, dynstr.sh_addr);
+            let loads = elf
+                .program_headers
+                .iter()
+                .filter(|segment| segment.p_type == PT_LOAD)
+                .collect::<Vec<_>>();
+            assert_eq!(loads.len(), 3);
+            let load = loads[2];
+            assert_eq!(load.p_offset, elf.header.e_phoff);
+            assert!(dynstr.sh_offset + dynstr.sh_size <= load.p_offset + load.p_filesz);
+            assert_eq!(
+                dynstr.sh_addr - load.p_vaddr,
+                dynstr.sh_offset - load.p_offset
+            );
+            assert!(load.p_offset as usize >= bytes.len());
+            assert_sections_disjoint(&elf);
+        }
+    }
+}
+
+#[test]
+fn elf_versions_defined() {
+    use goblin::elf::dynamic::{DT_VERDEF, DT_VERSYM};
+
+    fn define(bytes: &[u8], versions: &'static [&'static str]) -> Vec<u8> {
+        transformed(bytes, move |bytes, object| {
+            let elf = elf_object(object);
+            let mut patches = symtool_backend::elf::define_versions(bytes, &elf, versions).unwrap();
+            let patched = apply(bytes, &patches);
+            let elf = goblin::elf::Elf::parse(&patched).unwrap();
+            let symbols = symtool_backend::elf::SymbolVersions::from_elf(&patched, &elf).unwrap();
+            for (index, item) in symtool_backend::elf::SymtabIter::dynsym_from_elf(&patched, &elf)
+                .unwrap()
+                .unwrap()
+                .enumerate()
+            {
+                if item.unwrap().0.as_deref() == Some(&"function") {
+                    let version = symbols.definition(versions[0]).unwrap();
+                    patches.push(symbols.set(index, version, false).unwrap());
+                }
+            }
+            let patched = apply(bytes, &patches);
+            let elf = goblin::elf::Elf::parse(&patched).unwrap();
+            patches.extend(symtool_backend::elf::rebuild_tables(&patched, &elf).unwrap());
+            Ok(patches)
+        })
+    }
+
+    fn version_of<'a>(bytes: &'a [u8], name: &str) -> Option<(&'a str, bool)> {
+        let elf = goblin::elf::Elf::parse(bytes).unwrap();
+        let versions = symtool_backend::elf::SymbolVersions::from_elf(bytes, &elf).unwrap();
+        let index = symtool_backend::elf::SymtabIter::dynsym_from_elf(bytes, &elf)
+            .unwrap()
+            .unwrap()
+            .position(|item| item.unwrap().0.as_deref() == Some(&name))
+            .unwrap();
+        versions
+            .get(index)
+            .unwrap()
+            .map(|version| (version.name, version.hidden))
+    }
+
+    for container in &[Container::Little, Container::Big] {
+        for endian in &[Endian::Little, Endian::Big] {
+            let bytes = builder()
+                .relocation("function")
+                .container(*container)
+                .endian(*endian)
+                .build_shared_elf();
+            let output = define(&bytes, &["LIB_1.0"]);
+            let elf = goblin::elf::Elf::parse(&output).unwrap();
+            let versions = symtool_backend::elf::SymbolVersions::from_elf(&output, &elf).unwrap();
+            assert_eq!(versions.definitions(), vec!["LIB_1.0"]);
+            assert_eq!(version_of(&output, "function"), Some(("LIB_1.0", false)));
+            assert_eq!(version_of(&output, "weak"), None);
+            assert_eq!(relocation_names(&elf), vec!["function"]);
+
+            // The new sections are located by the dynamic section
+            let dyns = &elf.dynamic.as_ref().unwrap().dyns;
+            for (name, tag) in &[(".gnu.version", DT_VERSYM), (".gnu.version_d", DT_VERDEF)] {
+                let section = symtool_backend::elf::section_by_name(&elf, name).unwrap();
+                let entry = dyns.iter().find(|entry| entry.d_tag == *tag).unwrap();
+                assert_eq!(entry.d_val, section.sh_addr);
+            }
+            assert_sections_disjoint(&elf);
+
+            // Versions are appended to the existing definitions
+            let output = define(&output, &["LIB_2.0", "LIB_1.0"]);
+            let elf = goblin::elf::Elf::parse(&output).unwrap();
+            let versions = symtool_backend::elf::SymbolVersions::from_elf(&output, &elf).unwrap();
+            assert_eq!(versions.definitions(), vec!["LIB_1.0", "LIB_2.0"]);
+            assert_eq!(version_of(&output, "function"), Some(("LIB_2.0", false)));
+            assert_eq!(version_of(&output, "object"), None);
+            assert_sections_disjoint(&elf);
+        }
+    }
+}
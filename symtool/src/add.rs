@@ -0,0 +1,240 @@
+//! Add symbols to the symbol table, like `objcopy --add-symbol`, or as aliases of existing
+//! symbols.
+
+use crate::error::Error;
+use clap::ArgMatches;
+use goblin::elf::section_header::{SHN_ABS, SHN_LORESERVE, SHN_UNDEF};
+use goblin::elf::sym::{
+    Sym, STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_FILE, STT_FUNC, STT_GNU_IFUNC, STT_NOTYPE, STT_OBJECT,
+};
+use goblin::elf::Elf;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use symtool_backend as backend;
+use symtool_backend::patch::Patch;
+
+/// A symbol given to `--add-symbol`.
+struct Addition {
+    name: String,
+
+    /// The section the symbol is defined in, or `None` for an absolute symbol
+    section: Option<String>,
+    value: u64,
+    bind: u8,
+    typ: u8,
+}
+
+/// Parse an unsigned integer, which is hexadecimal if prefixed with `0x`.
+pub(crate) fn parse_value(value: &str) -> Option<u64> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+impl std::str::FromStr for Addition {
+    type Err = Error;
+
+    /// Parse a symbol of the form `NAME=[SECTION:]VALUE[,FLAGS]`.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let invalid = || {
+            Error::Message(format!(
+                "Invalid value for --add-symbol: '{}' (expected NAME=[SECTION:]VALUE[,FLAGS])",
+                s
+            ))
+        };
+        let (name, definition) = s
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty())
+            .ok_or_else(invalid)?;
+        let mut fields = definition.split(',');
+        let location = fields.next().unwrap_or("");
+        let (section, value) = match location.rsplit_once(':') {
+            Some((section, value)) => (Some(section.to_string()), value),
+            None => (None, location),
+        };
+        let value = parse_value(value).ok_or_else(invalid)?;
+        let (mut bind, mut typ) = (STB_GLOBAL, STT_NOTYPE);
+        for flag in fields {
+            match flag {
+                "local" => bind = STB_LOCAL,
+                "global" => bind = STB_GLOBAL,
+                "weak" => bind = STB_WEAK,
+                "function" => typ = STT_FUNC,
+                "indirect-function" => typ = STT_GNU_IFUNC,
+                "object" => typ = STT_OBJECT,
+                "file" => typ = STT_FILE,
+                _ => {
+                    return Err(Error::Message(format!(
+                        "Unsupported --add-symbol flag '{}' (expected local, global, weak, function, indirect-function, object or file)",
+                        flag
+                    )))
+                }
+            }
+        }
+        Ok(Self {
+            name: name.to_string(),
+            section,
+            value,
+            bind,
+            typ,
+        })
+    }
+}
+
+/// Symbols to add to the symbol table of each object.
+pub struct SymbolAdditions {
+    symbols: Vec<Addition>,
+
+    /// The existing symbols given to `--alias`, with the names of their aliases
+    aliases: Vec<(String, String)>,
+
+    /// The existing symbols aliased so far
+    found: RefCell<HashSet<String>>,
+}
+
+impl SymbolAdditions {
+    pub fn from_matches(matches: &ArgMatches) -> Result<Option<Self>, Error> {
+        if !matches.is_present("add-symbol") && !matches.is_present("alias") {
+            return Ok(None);
+        }
+        let symbols = matches
+            .values_of("add-symbol")
+            .into_iter()
+            .flatten()
+            .map(str::parse)
+            .collect::<Result<Vec<Addition>, Error>>()?;
+        let aliases = matches
+            .values_of("alias")
+            .map(|values| values.collect::<Vec<_>>())
+            .unwrap_or_default()
+            .chunks(2)
+            .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+            .collect();
+        Ok(Some(Self {
+            symbols,
+            aliases,
+            found: RefCell::new(HashSet::new()),
+        }))
+    }
+
+    /// Returns the symbols given to `--alias` which weren't found in any object.
+    pub fn missing(&self) -> Vec<&str> {
+        let found = self.found.borrow();
+        self.aliases
+            .iter()
+            .map(|(existing, _)| existing.as_str())
+            .filter(|existing| !found.contains(*existing))
+            .collect()
+    }
+
+    /// Construct the aliases of the symbols defined in an ELF binary.
+    ///
+    /// An alias is a copy of the definition of the existing symbol, preferring a global one, with
+    /// the same value, size, section, binding, type and visibility.  Symbols the binary doesn't
+    /// define are skipped.
+    fn elf_aliases<'a>(
+        &'a self,
+        bytes: &[u8],
+        elf: &Elf,
+        verbose: bool,
+    ) -> Result<Vec<(&'a str, Sym)>, Error> {
+        let mut aliases = Vec::new();
+        if self.aliases.is_empty() {
+            return Ok(aliases);
+        }
+        let mut definitions = Vec::new();
+        if let Some(iter) = backend::elf::SymtabIter::symtab_from_elf(bytes, elf)? {
+            for item in iter {
+                if let (Some(name), sym) = item? {
+                    if sym.st_shndx != SHN_UNDEF as usize {
+                        definitions.push((name.to_string(), *sym));
+                    }
+                }
+            }
+        }
+        for (existing, alias) in &self.aliases {
+            let mut matching = definitions.iter().filter(|(name, _)| name == existing);
+            let sym = match matching
+                .clone()
+                .find(|(_, sym)| sym.st_bind() != STB_LOCAL)
+                .or_else(|| matching.next())
+            {
+                Some((_, sym)) => sym,
+                None => continue,
+            };
+            self.found.borrow_mut().insert(existing.clone());
+            if verbose {
+                println!("Add alias {}: {}", alias, existing);
+            }
+            aliases.push((alias.as_str(), Sym { st_name: 0, ..*sym }));
+        }
+        Ok(aliases)
+    }
+
+    /// Construct the patches adding the symbols to the `.symtab` of an ELF binary.
+    ///
+    /// `bytes` must include the other changes to the binary, since the symbol table is moved.
+    pub fn elf_patches(&self, bytes: &[u8], elf: &Elf, verbose: bool) -> Result<Vec<Patch>, Error> {
+        let mut symbols = Vec::with_capacity(self.symbols.len());
+        for symbol in &self.symbols {
+            let shndx = match &symbol.section {
+                None => SHN_ABS as usize,
+                Some(section) => {
+                    let mut matching =
+                        elf.section_headers.iter().enumerate().filter(|(_, header)| {
+                            matches!(elf.shdr_strtab.get(header.sh_name), Some(Ok(n)) if n == section)
+                        });
+                    let (index, _) = matching.next().ok_or_else(|| {
+                        Error::Message(format!(
+                            "Can't add '{}': the object has no section named '{}'",
+                            symbol.name, section
+                        ))
+                    })?;
+                    if matching.next().is_some() {
+                        return Err(Error::Message(format!(
+                            "Can't add '{}': the object has several sections named '{}'",
+                            symbol.name, section
+                        )));
+                    }
+                    if index >= SHN_LORESERVE as usize {
+                        return Err(Error::Message(format!(
+                            "Can't add '{}': section '{}' needs an extended section index",
+                            symbol.name, section
+                        )));
+                    }
+                    index
+                }
+            };
+            if verbose {
+                println!("Add symbol: {}", symbol.name);
+            }
+            let sym = Sym {
+                st_name: 0,
+                st_info: (symbol.bind << 4) | symbol.typ,
+                st_other: 0,
+                st_shndx: shndx,
+                st_value: symbol.value,
+                st_size: 0,
+            };
+            symbols.push((symbol.name.as_str(), sym));
+        }
+        symbols.extend(self.elf_aliases(bytes, elf, verbose)?);
+        if symbols.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut patches = backend::elf::add_symbols(bytes, elf, &symbols)?;
+        if symbols.iter().any(|(_, sym)| sym.st_bind() == STB_LOCAL) {
+            // Local symbols are appended after the global ones, and must be moved before them
+            patches.extend(crate::transform::apply_and_reparse_elf(
+                bytes,
+                &patches,
+                |patched, elf| Ok(backend::elf::sort_locals(patched, elf)?),
+            )?);
+        }
+        Ok(patches)
+    }
+}
@@ -0,0 +1,208 @@
+//! Cancel runs on Ctrl-C without leaving partially written outputs.
+
+use crate::error::Error;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use symtool_backend::object::CancellationToken;
+
+/// The exit status when a run is interrupted, as if killed by `SIGINT`.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Cancel the returned token on Ctrl-C or `SIGTERM`.
+///
+/// The run stops at the next check of the token, such as between passes or archive members, and
+/// the outputs staged so far are removed.
+pub fn install() -> CancellationToken {
+    TOKEN
+        .get_or_init(|| {
+            let token = CancellationToken::new();
+            let cancelled = token.clone();
+            // Without a handler, interrupts still end the run, just without cleaning up
+            let _ = ctrlc::set_handler(move || cancelled.cancel());
+            token
+        })
+        .clone()
+}
+
+/// Fail if the run has been interrupted.
+pub fn check() -> Result<(), Error> {
+    match TOKEN.get() {
+        Some(token) => Ok(token.check()?),
+        None => Ok(()),
+    }
+}
+
+/// Output files, written to temporary files until every output is complete.
+///
+/// Temporary files that haven't been committed are removed when dropped, so a cancelled or
+/// failed run leaves existing outputs untouched.
+#[derive(Default)]
+pub struct Outputs {
+    /// Pairs of temporary and final paths
+    staged: Vec<(PathBuf, PathBuf)>,
+
+    /// Locked outputs, whose open handles hold the locks until dropped
+    locks: Vec<Lock>,
+}
+
+/// An output locked for the whole run.
+struct Lock {
+    path: PathBuf,
+
+    /// The path with symbolic links resolved, identifying the file read through the lock
+    canonical: PathBuf,
+    file: File,
+
+    /// Whether the output was created empty to be locked, and must be removed unless committed
+    created: bool,
+}
+
+/// How often a run waiting for a lock checks whether it was interrupted.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A hidden file next to `path`, named after it.
+fn sibling(path: &Path, suffix: &str) -> Result<PathBuf, Error> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::Message(format!("{}: not a file name", path.display())))?;
+    Ok(path.with_file_name(format!(".{}.{}", name.to_string_lossy(), suffix)))
+}
+
+/// Replace the contents of an open file with those of `temp`.
+fn write_through(temp: &Path, mut file: &File) -> std::io::Result<()> {
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    std::io::copy(&mut File::open(temp)?, &mut file)?;
+    file.flush()
+}
+
+impl Outputs {
+    /// Take an advisory lock on an output, waiting for other runs writing it to finish.
+    ///
+    /// The output itself is locked, creating it empty if it doesn't exist yet, and removing it
+    /// again unless the run commits.  Outputs in directories that don't exist yet aren't locked.
+    /// Interrupting the run stops the wait.
+    pub fn lock(&mut self, path: &str, verbose: bool) -> Result<(), Error> {
+        let path = Path::new(path);
+        if self.locks.iter().any(|lock| lock.path == path) {
+            return Ok(());
+        }
+        let path_error = |e| Error::Path(path.display().to_string(), Box::new(e));
+        let mut options = OpenOptions::new();
+        options.read(true).write(true);
+        let (file, created) = match options.clone().create_new(true).open(path) {
+            Ok(file) => (file, true),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                (options.open(path).map_err(path_error)?, false)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(path_error(e)),
+        };
+        let canonical = path.canonicalize().map_err(path_error)?;
+        // Held before any error below, so a created output is removed
+        self.locks.push(Lock {
+            path: path.to_path_buf(),
+            canonical,
+            file,
+            created,
+        });
+        let file = &self.locks.last().unwrap().file;
+        let mut waiting = false;
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(()),
+                Err(TryLockError::WouldBlock) => {}
+                // Filesystems without locking are written unlocked
+                Err(TryLockError::Error(e)) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+                Err(TryLockError::Error(e)) => return Err(path_error(e)),
+            }
+            if verbose && !waiting {
+                println!("Waiting for lock on {}", path.display());
+            }
+            waiting = true;
+            check()?;
+            std::thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+
+    /// Read a file, which may be a locked output, such as INPUT when modifying it in place.
+    ///
+    /// Locked outputs are read through the handle holding the lock, since locks are mandatory
+    /// on Windows and other handles can't read them.
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let path_error = |e| Error::Path(path.to_string(), Box::new(e));
+        let canonical = Path::new(path).canonicalize().map_err(path_error)?;
+        let mut contents = Vec::new();
+        match self.locks.iter().find(|lock| lock.canonical == canonical) {
+            Some(lock) => {
+                let mut file = &lock.file;
+                file.seek(SeekFrom::Start(0)).map_err(path_error)?;
+                file.read_to_end(&mut contents).map_err(path_error)?;
+            }
+            None => contents = std::fs::read(path).map_err(path_error)?,
+        }
+        Ok(contents)
+    }
+
+    /// Write the contents of an output to a temporary file next to it, returning its path.
+    pub fn stage(&mut self, path: &str, contents: &[u8]) -> Result<PathBuf, Error> {
+        let path = Path::new(path);
+        let temp = sibling(path, &format!("symtool-{}.tmp", std::process::id()))?;
+        self.staged.push((temp.clone(), path.to_path_buf()));
+        std::fs::write(&temp, contents)
+            .map_err(|e| Error::Path(temp.display().to_string(), Box::new(e)))?;
+        Ok(temp)
+    }
+
+    /// Write every staged output into place, unless `token` has been cancelled.
+    ///
+    /// Existing outputs are rewritten in place, through the lock if they are locked, so
+    /// symbolic links and hard links to them see the new contents, and their permissions and
+    /// owners are kept.  New outputs are moved into place.
+    pub fn commit(mut self, token: &CancellationToken) -> Result<(), Error> {
+        token.check()?;
+        while !self.staged.is_empty() {
+            let (temp, path) = self.staged.remove(0);
+            let lock = self.locks.iter_mut().find(|lock| lock.path == path);
+            let result = match lock {
+                Some(lock) => {
+                    lock.created = false;
+                    write_through(&temp, &lock.file)
+                }
+                None if path.exists() => OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| write_through(&temp, &file)),
+                None => std::fs::rename(&temp, &path),
+            };
+            let _ = std::fs::remove_file(&temp);
+            result.map_err(|e| Error::Path(path.display().to_string(), Box::new(e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Outputs {
+    fn drop(&mut self) {
+        for (temp, _) in &self.staged {
+            let _ = std::fs::remove_file(temp);
+        }
+        for lock in std::mem::take(&mut self.locks) {
+            let Lock {
+                path,
+                file,
+                created,
+                ..
+            } = lock;
+            drop(file);
+            if created {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Detect inconsistencies between the static and dynamic ELF symbol tables.
+
+use crate::debug;
+use crate::error::Error;
+use clap::ArgMatches;
+use goblin::elf::sym::{bind_to_str, visibility_to_str, Sym, STB_LOCAL};
+use goblin::elf::Elf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use symtool_backend as backend;
+use symtool_backend::patch::{Patch, Rooted};
+
+/// A symbol whose binding or visibility differs between `.symtab` and `.dynsym`.
+pub struct Divergence<'a> {
+    pub name: &'a str,
+    pub symtab: Sym,
+    pub dynsym: Rooted<Sym>,
+}
+
+impl<'a> Divergence<'a> {
+    /// Construct a patch updating the `.dynsym` entry to match `.symtab`.
+    pub fn reconcile(&self) -> Result<Patch, Error> {
+        let info = (self.symtab.st_bind() << 4) | self.dynsym.st_type();
+        let other = (self.dynsym.st_other & 0xfc) | self.symtab.st_visibility();
+        Ok(self.dynsym.patch_with(Sym {
+            st_info: info,
+            st_other: other,
+            ..*self.dynsym
+        })?)
+    }
+}
+
+impl<'a> std::fmt::Display for Divergence<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "symbol '{}' is {} {} in .symtab but {} {} in .dynsym",
+            self.name,
+            bind_to_str(self.symtab.st_bind()),
+            visibility_to_str(self.symtab.st_visibility()),
+            bind_to_str(self.dynsym.st_bind()),
+            visibility_to_str(self.dynsym.st_visibility()),
+        )
+    }
+}
+
+/// Find all symbols whose binding or visibility differs between `.symtab` and `.dynsym`.
+///
+/// Symbols are matched by name and version, where `.symtab` names have version suffixes such as
+/// `foo@@VERS_1` and `.dynsym` versions are read from `.gnu.version`.  `.symtab` names without a
+/// suffix match every version.  Global and weak symbols in `.symtab` take precedence over local
+/// symbols with the same name.
+pub fn divergences<'a>(bytes: &'a [u8], elf: &Elf) -> Result<Vec<Divergence<'a>>, Error> {
+    match (
+        backend::elf::SymtabIter::symtab_from_elf(bytes, elf)?,
+        backend::elf::SymtabIter::dynsym_from_elf(bytes, elf)?,
+    ) {
+        (Some(symtab), Some(dynsym)) => {
+            let versions = backend::elf::SymbolVersions::from_elf(bytes, elf)?;
+            compare(symtab, dynsym, &versions)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Find all symbols whose binding or visibility differs between a `.symtab` and a `.dynsym`,
+/// which may be read from different files.
+///
+/// `versions` are the versions of the `.dynsym` entries.
+fn compare<'a>(
+    symtab: backend::elf::SymtabIter,
+    dynsym: backend::elf::SymtabIter<'a>,
+    versions: &backend::elf::SymbolVersions,
+) -> Result<Vec<Divergence<'a>>, Error> {
+    // Symbols by name and version, without the `@` characters
+    let mut symbols = HashMap::new();
+    for item in symtab {
+        if let (Some(name), sym) = item? {
+            let (name, version) = backend::elf::split_version(&name);
+            let key = (name, version.trim_start_matches('@'));
+            if sym.st_bind() != STB_LOCAL || !symbols.contains_key(&key) {
+                symbols.insert(key, *sym);
+            }
+        }
+    }
+
+    let mut divergences = Vec::new();
+    for (index, item) in dynsym.enumerate() {
+        if let (Some(name), sym) = item? {
+            let version = versions.get(index)?.map_or("", |version| version.name);
+            if let Some(symtab) = symbols
+                .get(&(*name, version))
+                .or_else(|| symbols.get(&(*name, "")))
+            {
+                if symtab.st_bind() != sym.st_bind()
+                    || symtab.st_visibility() != sym.st_visibility()
+                {
+                    divergences.push(Divergence {
+                        name: *name,
+                        symtab: *symtab,
+                        dynsym: sym,
+                    });
+                }
+            }
+        }
+    }
+    Ok(divergences)
+}
+
+/// Escape a value of a GitHub Actions workflow command, such as `::error file=...::message`.
+///
+/// Properties (such as `file`) are also separated by commas and colons, which are escaped too.
+fn escape_workflow_command(value: &str, property: bool) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '\r' => escaped.push_str("%0D"),
+            '\n' => escaped.push_str("%0A"),
+            ':' if property => escaped.push_str("%3A"),
+            ',' if property => escaped.push_str("%2C"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let path = matches.value_of("INPUT").unwrap();
+    let bytes = std::fs::read(path)?;
+    let elf = crate::parse_elf(path, &bytes)?;
+    let divergences = match backend::elf::SymtabIter::symtab_from_elf(&bytes, &elf)? {
+        Some(_) => divergences(&bytes, &elf)?,
+        // Stripped binaries are compared with the .symtab of their separate debug file
+        None => {
+            let debug_dirs: Vec<PathBuf> = matches
+                .values_of("debug-dir")
+                .into_iter()
+                .flatten()
+                .map(PathBuf::from)
+                .collect();
+            let dynsym = backend::elf::SymtabIter::dynsym_from_elf(&bytes, &elf)?;
+            let debug = debug::find_debug_file(Path::new(path), &bytes, &elf, &debug_dirs)?;
+            match (dynsym, debug) {
+                (Some(dynsym), Some((debug_path, debug_bytes))) => {
+                    let debug_elf = backend::elf::parse(&debug_bytes)?;
+                    match backend::elf::SymtabIter::symtab_from_elf(&debug_bytes, &debug_elf)? {
+                        Some(symtab) => {
+                            eprintln!("note: reading .symtab from {}", debug_path.display());
+                            let versions = backend::elf::SymbolVersions::from_elf(&bytes, &elf)?;
+                            compare(symtab, dynsym, &versions)?
+                        }
+                        None => {
+                            eprintln!("warning: {} has no .symtab", debug_path.display());
+                            Vec::new()
+                        }
+                    }
+                }
+                (Some(_), None) => {
+                    eprintln!(
+                        "warning: {} has no .symtab and no debug file was found",
+                        path
+                    );
+                    Vec::new()
+                }
+                (None, _) => Vec::new(),
+            }
+        }
+    };
+    for divergence in &divergences {
+        match matches.value_of("output-style") {
+            // Annotations are shown on the pull request next to the changed file
+            Some("github") => println!(
+                "::error file={},title={}::{}",
+                escape_workflow_command(path, true),
+                escape_workflow_command("symtool check", true),
+                escape_workflow_command(&divergence.to_string(), false)
+            ),
+            _ => println!("{}", divergence),
+        }
+    }
+    if divergences.is_empty() {
+        Ok(())
+    } else {
+        Err(Box::new(Error::Message(format!(
+            "{} symbol(s) differ between .symtab and .dynsym",
+            divergences.len()
+        ))))
+    }
+}
@@ -0,0 +1,103 @@
+//! Strip or replace the toolchain identification recorded in objects.
+
+use crate::error::Error;
+use clap::ArgMatches;
+use goblin::elf::Elf;
+use goblin::mach::MachO;
+use symtool_backend as backend;
+use symtool_backend::patch::Patch;
+
+/// A change to the toolchain identification of an object.
+pub enum Comment {
+    /// Remove the toolchain identification
+    Strip,
+
+    /// Replace the toolchain identification with a string
+    Set(String),
+}
+
+impl Comment {
+    pub fn from_matches(matches: &ArgMatches) -> Option<Self> {
+        if matches.is_present("strip-comment") {
+            Some(Self::Strip)
+        } else {
+            matches
+                .value_of("set-comment")
+                .map(|comment| Self::Set(comment.to_string()))
+        }
+    }
+
+    /// Construct patches changing the `.comment` section of an ELF binary.
+    pub fn elf_patches(&self, bytes: &[u8], elf: &Elf, verbose: bool) -> Result<Vec<Patch>, Error> {
+        let comment = match backend::elf::Comment::from_elf(bytes, elf)? {
+            Some(comment) => comment,
+            None => {
+                if let Self::Set(_) = self {
+                    eprintln!("warning: no .comment section to replace");
+                }
+                return Ok(Vec::new());
+            }
+        };
+        let contents = match self {
+            Self::Strip => Vec::new(),
+            Self::Set(string) => {
+                let mut contents = string.as_bytes().to_vec();
+                contents.push(0);
+                if contents.len() > comment.contents.len() {
+                    return Err(Error::Message(format!(
+                        "Comment \"{}\" does not fit in the existing .comment section ({} bytes)",
+                        string,
+                        comment.contents.len()
+                    )));
+                }
+                contents
+            }
+        };
+        if verbose {
+            for old in comment
+                .contents
+                .split(|b| *b == 0)
+                .filter(|s| !s.is_empty())
+            {
+                match self {
+                    Self::Strip => println!("Strip comment: {}", String::from_utf8_lossy(old)),
+                    Self::Set(new) => {
+                        println!("Set comment: {} -> {}", String::from_utf8_lossy(old), new)
+                    }
+                }
+            }
+        }
+        Ok(comment.replace(&contents)?)
+    }
+
+    /// Construct patches changing the tool versions in the `LC_BUILD_VERSION` load commands of a
+    /// Mach-O binary.
+    ///
+    /// Mach-O has no free-form comment, so the tool versions are cleared when stripping, and
+    /// setting a comment is not supported.
+    pub fn mach_patches(
+        &self,
+        bytes: &[u8],
+        mach: &MachO,
+        verbose: bool,
+    ) -> Result<Vec<Patch>, Error> {
+        match self {
+            Self::Strip => {
+                let mut patches = Vec::new();
+                for tool in backend::mach::build_tools(bytes, mach)? {
+                    if verbose {
+                        println!(
+                            "Strip build tool version: {} ({:#x})",
+                            tool.tool, *tool.version
+                        );
+                    }
+                    patches.push(tool.version.patch_with_primitive(0u32)?);
+                }
+                Ok(patches)
+            }
+            Self::Set(_) => Err(Error::Message(
+                "--set-comment is not supported for Mach-O objects".to_string(),
+            )),
+        }
+    }
+}
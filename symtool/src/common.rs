@@ -0,0 +1,132 @@
+//! Allocate common symbols in `.bss`, like `ld -d`.
+
+use crate::error::Error;
+use goblin::elf::header::ET_REL;
+use goblin::elf::section_header::{SectionHeader, SHF_ALLOC, SHF_WRITE, SHN_COMMON, SHT_NOBITS};
+use goblin::elf::sym::{Sym, STT_COMMON, STT_OBJECT};
+use goblin::elf::Elf;
+use symtool_backend as backend;
+use symtool_backend::elf::ExtendedIndexes;
+use symtool_backend::patch::{Patch, Rooted};
+
+/// Allocates common symbols at the end of the `.bss` section of a relocatable object.
+pub struct CommonAllocator<'a> {
+    /// The index of the `.bss` section
+    index: usize,
+
+    /// The `.bss` section header, or `None` if the object has no `.bss` and it is added
+    header: Option<Rooted<SectionHeader>>,
+
+    /// The size of the section, including allocated symbols
+    size: u64,
+
+    /// The alignment of the section, including allocated symbols
+    align: u64,
+
+    /// The extended section indexes of the symbol table, used if `.bss` has an extended index
+    indexes: ExtendedIndexes<'a>,
+
+    /// Patches to the extended section indexes of allocated symbols
+    index_patches: Vec<Patch>,
+}
+
+impl<'a> CommonAllocator<'a> {
+    /// Prepare to allocate the common symbols of an ELF object.
+    ///
+    /// Returns `None` if the object is not relocatable, since only relocatable objects contain
+    /// common symbols.  Objects without a `.bss` section get one after their other sections.
+    pub fn from_elf(elf: &Elf, indexes: ExtendedIndexes<'a>) -> Result<Option<Self>, Error> {
+        if elf.header.e_type != ET_REL {
+            return Ok(None);
+        }
+        let has_common = elf
+            .syms
+            .iter()
+            .any(|sym| sym.st_shndx == SHN_COMMON as usize);
+        if !has_common {
+            return Ok(None);
+        }
+        let (index, header) = match backend::elf::rooted_section_by_name(elf, ".bss") {
+            Some((index, header)) => (index, Some(header)),
+            None => (elf.section_headers.len(), None),
+        };
+        Ok(Some(Self {
+            index,
+            size: header.as_ref().map_or(0, |header| header.sh_size),
+            align: header
+                .as_ref()
+                .map_or(1, |header| header.sh_addralign.max(1)),
+            header,
+            indexes,
+            index_patches: Vec::new(),
+        }))
+    }
+
+    /// Define a symbol in `.bss` if it is a common symbol.
+    ///
+    /// `symbol` is the index of the symbol in the symbol table.  The value of a common symbol is
+    /// its required alignment.
+    pub fn define(
+        &mut self,
+        symbol: usize,
+        sym: &Sym,
+        name: &str,
+        verbose: bool,
+    ) -> Result<Option<Sym>, Error> {
+        if sym.st_shndx != SHN_COMMON as usize {
+            return Ok(None);
+        }
+        let align = sym.st_value.max(1);
+        let offset = self.size.div_ceil(align) * align;
+        self.size = offset + sym.st_size;
+        self.align = self.align.max(align);
+        if verbose {
+            println!(
+                "Define common: {} ({} bytes at .bss+{:#x})",
+                name, sym.st_size, offset
+            );
+        }
+        let st_type = match sym.st_type() {
+            STT_COMMON => STT_OBJECT,
+            st_type => st_type,
+        };
+        let (sym, patch) = self.indexes.set_section_index(
+            symbol,
+            Sym {
+                st_info: (sym.st_bind() << 4) | st_type,
+                st_value: offset,
+                ..*sym
+            },
+            self.index,
+        )?;
+        self.index_patches.extend(patch);
+        Ok(Some(sym))
+    }
+
+    /// Construct the patches growing `.bss` to contain the allocated symbols, and updating the
+    /// extended section indexes of allocated symbols.
+    ///
+    /// If the object has no `.bss`, the header of the section to add is returned instead of
+    /// growing it, so it can be added with `add_sections` after the other changes.
+    pub fn patches(self) -> Result<(Vec<Patch>, Option<SectionHeader>), Error> {
+        let mut patches = self.index_patches;
+        let header = match self.header {
+            Some(header) => {
+                patches.push(header.patch_with(SectionHeader {
+                    sh_size: self.size,
+                    sh_addralign: self.align,
+                    ..header.value
+                })?);
+                return Ok((patches, None));
+            }
+            None => SectionHeader {
+                sh_type: SHT_NOBITS,
+                sh_flags: u64::from(SHF_ALLOC | SHF_WRITE),
+                sh_size: self.size,
+                sh_addralign: self.align,
+                ..SectionHeader::new()
+            },
+        };
+        Ok((patches, Some(header)))
+    }
+}
@@ -0,0 +1,402 @@
+//! Read default options from a `.symtool.toml` file next to the code they apply to.
+//!
+//! The file is TOML.  Top-level keys are long option names, and each `[[rule]]` table groups a
+//! rule with its modifiers, which apply in the order written:
+//!
+//! ```toml
+//! allow-undefined-visibility = true
+//! exclude-symbols = "runtime-symbols.txt"
+//!
+//! [[rule]]
+//! hidden = "^foo_internal_"
+//! in-format = "elf"
+//!
+//! [[rule]]
+//! rename = [["foo_old", "foo_new"]]
+//! ignore-missing = true
+//! ```
+//!
+//! A boolean enables a flag, a string or integer gives an option one value, an array repeats the
+//! option for each element, and an array of arrays gives each repetition several values.  The
+//! options are inserted before the command line arguments, so rules from the command line are
+//! applied after (and take precedence over) those from the file.  Rule modifiers in the file
+//! only apply to the rules of the file, and relative paths are relative to the directory of the
+//! file.
+
+use crate::error::Error;
+use clap::{App, ArgMatches};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// The name of the configuration file searched for.
+pub const FILE_NAME: &str = ".symtool.toml";
+
+/// Options that may be given several times, so the file and command line are combined.
+///
+/// Other options given on the command line replace those in the file.
+const REPEATABLE: &[&str] = &[
+    "rename",
+    "rename-regex",
+    "rename-demangled",
+    "redefine-syms",
+    "prefix-symbols",
+    "prefix-regex",
+    "prefix-undefined",
+    "default",
+    "hidden",
+    "protected",
+    "internal",
+    "set",
+    "set-binding",
+    "set-type",
+    "localize-symbol",
+    "localize-regex",
+    "globalize-symbol",
+    "weaken-symbol",
+    "weaken-undefined",
+    "strip-symbol",
+    "strip-regex",
+    "max-matches",
+    "ignore-missing",
+    "in-member",
+    "in-format",
+    "only-type",
+    "only-defined",
+    "only-undefined",
+    "except",
+    "exclude-symbols",
+    "keep-global-symbols",
+    "keep-symbols",
+    "rebind-import",
+    "set-symbol-section",
+    "undefine-symbol",
+    "set-symbol-value",
+    "set-symbol-version",
+    "add-symbol",
+    "alias",
+    "scrub-paths",
+    "obfuscate-regex",
+];
+
+/// Options whose values are paths, which are resolved relative to the configuration file.
+const PATHS: &[&str] = &[
+    "redefine-syms",
+    "exclude-symbols",
+    "keep-global-symbols",
+    "keep-symbols",
+    "obfuscate-map",
+    "write-version-script",
+    "write-exported-symbols-list",
+    "write-response-file",
+    "debug-file",
+    "dwo",
+];
+
+/// The entries of a table, in the order they were written.
+type Table = Vec<(String, Value)>;
+
+/// Parse the top-level table followed by each `[[rule]]` table.
+fn parse(contents: &str) -> Result<Vec<Table>, Error> {
+    let document = contents.parse::<toml::Table>().map_err(|e| {
+        let line = e
+            .span()
+            .map_or(1, |span| contents[..span.start].matches('\n').count() + 1);
+        Error::Message(format!("line {}: {}", line, e.message()))
+    })?;
+    let mut top_level = Table::new();
+    let mut rules = Vec::new();
+    for (key, value) in document {
+        match (key.as_str(), value) {
+            ("rule", Value::Array(values)) => {
+                for value in values {
+                    match value {
+                        Value::Table(table) => rules.push(entries(table)?),
+                        _ => return Err(Error::Message("'rule' must be [[rule]] tables".into())),
+                    }
+                }
+            }
+            (_, Value::Table(_)) | ("rule", _) => {
+                return Err(Error::Message(format!(
+                    "unsupported table '{}', expected '[[rule]]'",
+                    key
+                )))
+            }
+            (_, value) => top_level.push((key, value)),
+        }
+    }
+    Ok(std::iter::once(top_level).chain(rules).collect())
+}
+
+/// The entries of a `[[rule]]` table, which can't contain other tables.
+fn entries(table: toml::Table) -> Result<Table, Error> {
+    table
+        .into_iter()
+        .map(|(key, value)| match value {
+            Value::Table(_) => Err(Error::Message(format!(
+                "unsupported table '{}' in [[rule]]",
+                key
+            ))),
+            value => Ok((key, value)),
+        })
+        .collect()
+}
+
+/// Convert a scalar to an option value, resolving paths relative to `dir`.
+fn scalar(key: &str, value: &Value, dir: &Path) -> Result<OsString, Error> {
+    match value {
+        Value::String(s) if PATHS.contains(&key) => Ok(dir.join(s).into()),
+        Value::String(s) => Ok(s.into()),
+        Value::Integer(i) => Ok(i.to_string().into()),
+        _ => Err(Error::Message(format!(
+            "'{}' must be a boolean, a string, an integer, or an array of them",
+            key
+        ))),
+    }
+}
+
+/// Convert the entries of a configuration file to command line arguments.
+///
+/// Options other than rules are skipped if they are present in `matches`.  Paths are resolved
+/// relative to `dir`, the directory of the file.
+fn arguments(tables: &[Table], matches: &ArgMatches, dir: &Path) -> Result<Vec<OsString>, Error> {
+    let mut args = Vec::new();
+    for (key, value) in tables.iter().flatten() {
+        if ["config", "no-config", "end-of-config"].contains(&key.as_str()) {
+            return Err(Error::Message(format!(
+                "'{}' can't be set in a configuration file",
+                key
+            )));
+        }
+        if !REPEATABLE.contains(&key.as_str()) && matches.is_present(key) {
+            continue;
+        }
+        let option = OsString::from(format!("--{}", key));
+        match value {
+            Value::Boolean(true) => args.push(option),
+            Value::Boolean(false) => {}
+            Value::Array(values) => {
+                for value in values {
+                    args.push(option.clone());
+                    match value {
+                        Value::Array(values) => {
+                            for value in values {
+                                args.push(scalar(key, value, dir)?);
+                            }
+                        }
+                        value => args.push(scalar(key, value, dir)?),
+                    }
+                }
+            }
+            value => args.extend(vec![option, scalar(key, value, dir)?]),
+        }
+    }
+    Ok(args)
+}
+
+/// Find the configuration file in `dir` or its closest ancestor.
+fn find(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Find the configuration file for an input, searching upward from the input's directory and
+/// then from the current directory.
+fn discover(input: &Path) -> Result<Option<PathBuf>, Error> {
+    let cwd = std::env::current_dir()?;
+    let dir = cwd.join(input.parent().unwrap_or_else(|| Path::new("")));
+    let dir = dir.canonicalize().unwrap_or(dir);
+    Ok(find(&dir).or_else(|| find(&cwd)))
+}
+
+/// Parse the command line again with the options of the configuration file inserted first,
+/// followed by `--end-of-config`.
+///
+/// Returns `matches` unchanged if there is no configuration file or `--no-config` is given.
+pub fn merge<'a>(app: App<'a, '_>, matches: ArgMatches<'a>) -> Result<ArgMatches<'a>, Error> {
+    if matches.is_present("no-config") {
+        return Ok(matches);
+    }
+    let path = match matches.value_of("config") {
+        Some(path) => PathBuf::from(path),
+        None => match discover(Path::new(matches.value_of("INPUT").unwrap()))? {
+            Some(path) => path,
+            None => return Ok(matches),
+        },
+    };
+    let path_error = |e: Box<dyn std::error::Error>| Error::Path(path.display().to_string(), e);
+    let contents = std::fs::read_to_string(&path).map_err(|e| path_error(Box::new(e)))?;
+    let tables = parse(&contents).map_err(|e| path_error(Box::new(e)))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let config_args = arguments(&tables, &matches, dir).map_err(|e| path_error(Box::new(e)))?;
+    if matches.is_present("verbose") {
+        println!("Using configuration {}", path.display());
+    }
+
+    let mut args = std::env::args_os();
+    let argv = args
+        .next()
+        .into_iter()
+        .chain(config_args)
+        .chain(Some("--end-of-config".into()))
+        .chain(args)
+        .collect::<Vec<_>>();
+    app.get_matches_from_safe(argv).map_err(|e| {
+        // Report the problem without the usage, which describes the command line
+        let message = e.message.lines().next().unwrap_or_default();
+        let message = message.trim_start_matches("error: ").to_string();
+        path_error(Box::new(Error::Message(message)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn matches(args: &[&str]) -> ArgMatches<'static> {
+        crate::app()
+            .get_matches_from_safe(
+                ["symtool"]
+                    .iter()
+                    .chain(args)
+                    .chain(&["input.o", "output.o"]),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn tables_parsed() {
+        let tables = parse(
+            "# Defaults\n\
+             verbose = true\n\
+             max-matches = 1_000\n\
+             \n\
+             [[rule]]  # first\n\
+             hidden = '^foo_\\d+$'\n\
+             rename = [\n  [\"a\", \"b\"],\n  [\"c\", \"\\u0064\"],\n]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            tables,
+            vec![
+                vec![
+                    ("verbose".to_string(), Value::Boolean(true)),
+                    ("max-matches".to_string(), Value::Integer(1000)),
+                ],
+                vec![
+                    ("hidden".to_string(), string("^foo_\\d+$")),
+                    (
+                        "rename".to_string(),
+                        Value::Array(vec![
+                            Value::Array(vec![string("a"), string("b")]),
+                            Value::Array(vec![string("c"), string("d")]),
+                        ])
+                    ),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_report_lines() {
+        for (contents, message) in &[
+            ("a = 1\na = 2\n", "line 2: duplicate key"),
+            ("[rules]\n", "unsupported table 'rules'"),
+            ("[[rule]]\n[rule.x]\n", "unsupported table 'x' in [[rule]]"),
+            ("\na = \"b\n", "line 2: "),
+            ("a = [1 2]\n", "line 1: "),
+            ("a = 1 b\n", "line 1: "),
+            ("a = yes\n", "line 1: "),
+        ] {
+            let error = parse(contents).unwrap_err().to_string();
+            assert!(error.starts_with(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn unsupported_values_rejected() {
+        let tables = parse("seed = 1.5\n").unwrap();
+        let error = arguments(&tables, &matches(&[]), Path::new(""))
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("'seed' must be"), "{}", error);
+    }
+
+    #[test]
+    fn tables_converted_to_arguments() {
+        let tables = parse(
+            "verbose = true\n\
+             rewrite = false\n\
+             seed = 'file'\n\
+             [[rule]]\n\
+             rename = [['a', 'b'], ['c', 'd']]\n\
+             hidden = ['^x_', '^y_']\n\
+             max-matches = 2\n",
+        )
+        .unwrap();
+        let args = arguments(
+            &tables,
+            &matches(&["--seed", "command-line"]),
+            Path::new("config"),
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--verbose",
+                "--rename",
+                "a",
+                "b",
+                "--rename",
+                "c",
+                "d",
+                "--hidden",
+                "^x_",
+                "--hidden",
+                "^y_",
+                "--max-matches",
+                "2",
+            ]
+            .iter()
+            .map(OsString::from)
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn config_options_rejected() {
+        let tables = parse("no-config = true\n").unwrap();
+        assert!(arguments(&tables, &matches(&[]), Path::new("")).is_err());
+        let tables = parse("end-of-config = true\n").unwrap();
+        assert!(arguments(&tables, &matches(&[]), Path::new("")).is_err());
+    }
+
+    #[test]
+    fn paths_relative_to_file() {
+        let tables = parse(
+            "exclude-symbols = 'keep.txt'\n\
+             dwo = [['a.dwo', 'b.dwo']]\n\
+             seed = 'keep.txt'\n",
+        )
+        .unwrap();
+        let dir = Path::new("dir");
+        let args = arguments(&tables, &matches(&[]), dir).unwrap();
+        assert_eq!(
+            args,
+            vec![
+                OsString::from("--exclude-symbols"),
+                dir.join("keep.txt").into(),
+                "--dwo".into(),
+                dir.join("a.dwo").into(),
+                dir.join("b.dwo").into(),
+                "--seed".into(),
+                "keep.txt".into(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,409 @@
+//! Keep separate debug files consistent with the binaries they describe.
+
+use crate::cancel::Outputs;
+use crate::error::Error;
+use goblin::mach::load_command::CommandVariant;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use symtool_backend as backend;
+use symtool_backend::dwarf::AccelTable;
+use symtool_backend::patch::Patch;
+use symtool_backend::strtab::StrtabIter;
+
+fn parse_elf(bytes: &[u8]) -> Result<Option<goblin::elf::Elf<'_>>, Error> {
+    if bytes.starts_with(goblin::elf::header::ELFMAG) {
+        return Ok(Some(backend::elf::parse(bytes)?));
+    }
+    goblin::Object::parse(bytes).map_err(backend::error::Error::from)?;
+    Ok(None)
+}
+
+/// The UUIDs in the `LC_UUID` commands of a Mach-O binary, one for each architecture of a fat
+/// binary, in sorted order.
+fn macho_uuids(bytes: &[u8]) -> Result<Vec<[u8; 16]>, Error> {
+    let machos = match goblin::mach::Mach::parse(bytes) {
+        Ok(goblin::mach::Mach::Binary(macho)) => vec![macho],
+        Ok(goblin::mach::Mach::Fat(fat)) => fat
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .map_err(backend::error::Error::from)?,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut uuids: Vec<_> = machos
+        .iter()
+        .flat_map(|macho| macho.load_commands.iter())
+        .filter_map(|command| match command.command {
+            CommandVariant::Uuid(uuid) => Some(uuid.uuid),
+            _ => None,
+        })
+        .collect();
+    uuids.sort_unstable();
+    Ok(uuids)
+}
+
+/// Ensure that the build IDs of a binary and its debug file match, if both have one.
+///
+/// Mach-O binaries and their dSYM DWARF files are identified by the UUIDs in `LC_UUID` instead.
+pub fn check_build_ids(binary: &[u8], debug: &[u8]) -> Result<(), Error> {
+    if let (Some(binary_elf), Some(debug_elf)) = (parse_elf(binary)?, parse_elf(debug)?) {
+        let binary_id = backend::elf::build_id(binary, &binary_elf);
+        let debug_id = backend::elf::build_id(debug, &debug_elf);
+        if let (Some(binary_id), Some(debug_id)) = (binary_id, debug_id) {
+            if binary_id != debug_id {
+                return Err(Error::Message(format!(
+                    "Build ID of debug file ({}) does not match the binary ({})",
+                    hex(debug_id),
+                    hex(binary_id)
+                )));
+            }
+        }
+    }
+    let (binary_uuids, debug_uuids) = (macho_uuids(binary)?, macho_uuids(debug)?);
+    if !binary_uuids.is_empty() && !debug_uuids.is_empty() && binary_uuids != debug_uuids {
+        let join = |uuids: &[[u8; 16]]| {
+            uuids
+                .iter()
+                .map(|uuid| hex(uuid))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        return Err(Error::Message(format!(
+            "UUID of debug file ({}) does not match the binary ({})",
+            join(&debug_uuids),
+            join(&binary_uuids)
+        )));
+    }
+    Ok(())
+}
+
+/// The directory of the DWARF file in a `.dSYM` bundle.
+const DSYM_DWARF_DIR: &str = "Contents/Resources/DWARF";
+
+/// The path of the DWARF file in a `.dSYM` bundle, relative to the bundle.
+///
+/// If the bundle contains several DWARF files, the one named after the bundle is used.
+fn dsym_dwarf_file(bundle: &Path) -> Result<PathBuf, Error> {
+    let dir = bundle.join(DSYM_DWARF_DIR);
+    let not_a_bundle = |e| {
+        Error::Message(format!(
+            "{} is a directory, but not a .dSYM bundle ({}: {})",
+            bundle.display(),
+            DSYM_DWARF_DIR,
+            e
+        ))
+    };
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(&dir).map_err(not_a_bundle)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            names.push(entry.file_name());
+        }
+    }
+    let stem = bundle.file_stem().unwrap_or_default();
+    let name = match names.as_slice() {
+        [name] => name,
+        _ => names.iter().find(|name| *name == stem).ok_or_else(|| {
+            Error::Message(format!(
+                "{} contains {} files, and none is named {}",
+                dir.display(),
+                names.len(),
+                stem.to_string_lossy()
+            ))
+        })?,
+    };
+    Ok(Path::new(DSYM_DWARF_DIR).join(name))
+}
+
+/// The paths of the separate debug file given with `--debug-file`.
+///
+/// When the input is a `.dSYM` bundle, the DWARF file in it is transformed, and the output is a
+/// bundle with the same layout.
+pub struct DebugPaths {
+    /// The debug file to transform
+    pub input: String,
+
+    /// The path the transformed debug file is written to
+    pub output: String,
+
+    /// The input and output bundles, if the input is a `.dSYM` bundle
+    bundles: Option<(PathBuf, PathBuf)>,
+}
+
+impl DebugPaths {
+    pub fn new(input: &str, output: &str) -> Result<Self, Error> {
+        let bundle = Path::new(input);
+        if !bundle.is_dir() {
+            return Ok(Self {
+                input: input.to_string(),
+                output: output.to_string(),
+                bundles: None,
+            });
+        }
+        let dwarf = dsym_dwarf_file(bundle)?;
+        let output_bundle = Path::new(output);
+        Ok(Self {
+            input: bundle.join(&dwarf).to_string_lossy().into_owned(),
+            output: output_bundle.join(&dwarf).to_string_lossy().into_owned(),
+            bundles: Some((bundle.to_path_buf(), output_bundle.to_path_buf())),
+        })
+    }
+
+    /// Stage copies of the other files of an input bundle, such as `Contents/Info.plist`, into
+    /// the output bundle, creating its directories.
+    pub fn stage_bundle(&self, outputs: &mut Outputs) -> Result<(), Error> {
+        let (input, output) = match &self.bundles {
+            Some(bundles) => bundles,
+            None => return Ok(()),
+        };
+        if output.canonicalize().ok() == input.canonicalize().ok() {
+            return Ok(());
+        }
+        let mut dirs = vec![PathBuf::new()];
+        while let Some(dir) = dirs.pop() {
+            std::fs::create_dir_all(output.join(&dir))?;
+            for entry in std::fs::read_dir(input.join(&dir))? {
+                let entry = entry?;
+                let relative = dir.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    dirs.push(relative);
+                } else if input.join(&relative) != Path::new(&self.input) {
+                    let contents = std::fs::read(input.join(&relative))?;
+                    outputs.stage(&output.join(&relative).to_string_lossy(), &contents)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The directory searched for separate debug files by default, as by GDB.
+pub const DEFAULT_DEBUG_DIR: &str = "/usr/lib/debug";
+
+/// Find the separate debug file of an ELF binary, and read it.
+///
+/// As GDB does, the build ID is looked up as `.build-id/xx/yyyy.debug` in each of `debug_dirs`,
+/// then the `.gnu_debuglink` file name is looked up next to the binary, in its `.debug`
+/// subdirectory, and under each of `debug_dirs` followed by the binary's directory.  Files whose
+/// build ID or CRC doesn't match are skipped with a warning.
+pub fn find_debug_file(
+    path: &Path,
+    bytes: &[u8],
+    elf: &goblin::elf::Elf,
+    debug_dirs: &[PathBuf],
+) -> Result<Option<(PathBuf, Vec<u8>)>, Error> {
+    if let Some(id) = backend::elf::build_id(bytes, elf).filter(|id| id.len() > 1) {
+        let (first, rest) = id.split_at(1);
+        for dir in debug_dirs {
+            let candidate = dir
+                .join(".build-id")
+                .join(hex(first))
+                .join(format!("{}.debug", hex(rest)));
+            if let Ok(debug) = std::fs::read(&candidate) {
+                match check_build_ids(bytes, &debug) {
+                    Ok(()) => return Ok(Some((candidate, debug))),
+                    Err(e) => eprintln!("warning: skipping {}: {}", candidate.display(), e),
+                }
+            }
+        }
+    }
+    if let Some(debuglink) = backend::elf::Debuglink::from_elf(bytes, elf)? {
+        let dir = std::env::current_dir()?.join(path.parent().unwrap_or_else(|| Path::new("")));
+        let dir = dir.canonicalize().unwrap_or(dir);
+        let mut candidates = vec![
+            dir.join(*debuglink.filename),
+            dir.join(".debug").join(*debuglink.filename),
+        ];
+        for debug_dir in debug_dirs {
+            // The binary's directory is absolute, so it's appended without its root
+            let relative = dir.strip_prefix("/").unwrap_or(&dir);
+            candidates.push(debug_dir.join(relative).join(*debuglink.filename));
+        }
+        for candidate in candidates {
+            if candidate == dir.join(path.file_name().unwrap_or_default()) {
+                continue;
+            }
+            if let Ok(debug) = std::fs::read(&candidate) {
+                let crc = crc32fast::hash(&debug);
+                if crc == *debuglink.crc {
+                    return Ok(Some((candidate, debug)));
+                }
+                eprintln!(
+                    "warning: skipping {}: CRC ({:08x}) does not match .gnu_debuglink ({:08x})",
+                    candidate.display(),
+                    crc,
+                    *debuglink.crc
+                );
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Update the CRC in the `.gnu_debuglink` section of a binary to match its debug file, which is
+/// written to `debug_path`.
+///
+/// Debuggers look the debug file up by the name in the section, which can't be changed in place,
+/// so a debug file written under another name is reported.
+pub fn update_debuglink(
+    binary: &mut Vec<u8>,
+    debug: &[u8],
+    debug_path: &str,
+    verbose: bool,
+) -> Result<(), Error> {
+    let patch = match parse_elf(binary)? {
+        Some(elf) => match backend::elf::Debuglink::from_elf(binary, &elf)? {
+            Some(debuglink) => {
+                let debug_name = Path::new(debug_path).file_name().unwrap_or_default();
+                if debug_name != *debuglink.filename {
+                    eprintln!(
+                        "warning: .gnu_debuglink names {}, so debuggers won't find the debug file written to {}",
+                        *debuglink.filename, debug_path
+                    );
+                }
+                let crc = crc32fast::hash(debug);
+                if verbose {
+                    println!(
+                        "Set .gnu_debuglink CRC: {} ({:08x} -> {:08x})",
+                        *debuglink.filename, *debuglink.crc, crc
+                    );
+                }
+                Some(debuglink.crc.patch_with_primitive(crc)?)
+            }
+            None => None,
+        },
+        None => None,
+    };
+    if let Some(patch) = patch {
+        patch.apply(binary);
+    }
+    Ok(())
+}
+
+/// Construct patches renaming the strings in a debug string table, such as `.debug_str`.
+///
+/// Every string exactly matching an old name in `renamed` is replaced, padded with NUL bytes.
+/// Strings whose new names are longer, from `--rewrite`, are left unchanged with a warning.
+pub fn rename_strings(
+    strings: StrtabIter,
+    renamed: &HashMap<&str, String>,
+    verbose: bool,
+) -> Result<Vec<Patch>, Error> {
+    let mut patches = Vec::new();
+    for string in strings {
+        let old = match std::str::from_utf8(&string) {
+            Ok(old) => old,
+            Err(_) => continue,
+        };
+        if let Some(new) = renamed.get(old) {
+            if new.len() > old.len() {
+                eprintln!(
+                    "warning: debug string {} can't be renamed in place to the longer {}",
+                    old, new
+                );
+                continue;
+            }
+            if verbose {
+                println!("Rename debug string: {} -> {}", old, new);
+            }
+            let mut bytes = new.as_bytes().to_vec();
+            bytes.resize(old.len(), 0);
+            patches.push(string.patch_with_bytes(&bytes)?);
+        }
+    }
+    Ok(patches)
+}
+
+/// The strings of an ELF debug string section, such as `.debug_str`.
+///
+/// Compressed sections can't be renamed in place, so they are skipped with a warning.
+pub fn elf_debug_strings<'a>(
+    bytes: &'a [u8],
+    elf: &goblin::elf::Elf,
+    name: &str,
+) -> Result<Option<StrtabIter<'a>>, Error> {
+    match backend::elf::section_strings(bytes, elf, name) {
+        Err(backend::error::Error::Unsupported(reason)) => {
+            eprintln!("warning: {}, so its strings aren't renamed", reason);
+            Ok(None)
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Rename the strings in the `.debug_str.dwo` section of a split DWARF file, a `.dwo` file or a
+/// `.dwp` package read from `path`, returning the patched file.
+///
+/// Split DWARF files have no symbol table, so strings are renamed by the names of the symbols
+/// renamed in the objects they describe.
+pub fn rename_dwo(
+    path: &str,
+    mut bytes: Vec<u8>,
+    renamed: &HashMap<&str, String>,
+    verbose: bool,
+) -> Result<Vec<u8>, Error> {
+    let patches = {
+        let elf = parse_elf(&bytes)?
+            .ok_or_else(|| Error::Message("split DWARF files must be ELF objects".to_string()))?;
+        match elf_debug_strings(&bytes, &elf, ".debug_str.dwo")? {
+            Some(strings) => rename_strings(strings, renamed, verbose)?,
+            None => {
+                eprintln!("warning: {} has no .debug_str.dwo section", path);
+                Vec::new()
+            }
+        }
+    };
+    for patch in patches {
+        patch.apply(&mut bytes);
+    }
+    Ok(bytes)
+}
+
+/// Construct patches updating accelerator tables after renaming debug strings.
+///
+/// `patches` are applied to a copy of `bytes` to obtain the renamed string table in `strings`.
+/// Tables that can't be updated in place are invalidated with a warning, so debuggers fall back to
+/// indexing the debug info directly.
+pub fn update_accel_tables(
+    bytes: &[u8],
+    patches: &[Patch],
+    strings: std::ops::Range<usize>,
+    tables: &[AccelTable],
+    verbose: bool,
+) -> Result<Vec<Patch>, Error> {
+    let mut patched = bytes.to_vec();
+    for patch in patches {
+        patch.apply(&mut patched);
+    }
+    let strings = &patched[strings];
+    let mut accel_patches = Vec::new();
+    for table in tables {
+        match table.rehash(&patched, strings) {
+            Ok(patches) => {
+                if verbose {
+                    println!("Rehash accelerator table: {}", table.section);
+                }
+                accel_patches.extend(patches);
+            }
+            Err(backend::error::Error::Unsupported(reason)) => match table.invalidate(bytes) {
+                Ok(patch) => {
+                    eprintln!(
+                        "warning: dropping accelerator table {}: {}",
+                        table.section, reason
+                    );
+                    accel_patches.push(patch);
+                }
+                Err(backend::error::Error::Unsupported(_)) => eprintln!(
+                    "warning: accelerator table {} is out of date: {}",
+                    table.section, reason
+                ),
+                Err(e) => return Err(e.into()),
+            },
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(accel_patches)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,151 @@
+//! Display the ELF dynamic section.
+
+use crate::error::Error;
+use clap::ArgMatches;
+use goblin::elf::dynamic::*;
+use goblin::elf::Elf;
+use serde_json::json;
+
+const DT_AUXILIARY: u64 = 0x7fff_fffd;
+const DT_FILTER: u64 = 0x7fff_ffff;
+
+const FLAGS: &[(u64, &str)] = &[
+    (DF_ORIGIN, "ORIGIN"),
+    (DF_SYMBOLIC, "SYMBOLIC"),
+    (DF_TEXTREL, "TEXTREL"),
+    (DF_BIND_NOW, "BIND_NOW"),
+    (DF_STATIC_TLS, "STATIC_TLS"),
+];
+
+const FLAGS_1: &[(u64, &str)] = &[
+    (DF_1_NOW, "NOW"),
+    (DF_1_GLOBAL, "GLOBAL"),
+    (DF_1_GROUP, "GROUP"),
+    (DF_1_NODELETE, "NODELETE"),
+    (DF_1_LOADFLTR, "LOADFLTR"),
+    (DF_1_INITFIRST, "INITFIRST"),
+    (DF_1_NOOPEN, "NOOPEN"),
+    (DF_1_ORIGIN, "ORIGIN"),
+    (DF_1_DIRECT, "DIRECT"),
+    (DF_1_TRANS, "TRANS"),
+    (DF_1_INTERPOSE, "INTERPOSE"),
+    (DF_1_NODEFLIB, "NODEFLIB"),
+    (DF_1_NODUMP, "NODUMP"),
+    (DF_1_CONFALT, "CONFALT"),
+    (DF_1_ENDFILTEE, "ENDFILTEE"),
+    (DF_1_DISPRELDNE, "DISPRELDNE"),
+    (DF_1_DISPRELPND, "DISPRELPND"),
+    (DF_1_NODIRECT, "NODIRECT"),
+    (DF_1_IGNMULDEF, "IGNMULDEF"),
+    (DF_1_NOKSYMS, "NOKSYMS"),
+    (DF_1_NOHDR, "NOHDR"),
+    (DF_1_EDITED, "EDITED"),
+    (DF_1_NORELOC, "NORELOC"),
+    (DF_1_SYMINTPOSE, "SYMINTPOSE"),
+    (DF_1_GLOBAUDIT, "GLOBAUDIT"),
+    (DF_1_SINGLETON, "SINGLETON"),
+];
+
+/// A decoded dynamic section entry.
+struct Entry<'a> {
+    tag: u64,
+    value: u64,
+    string: Option<&'a str>,
+    flags: Option<Vec<&'static str>>,
+}
+
+impl<'a> Entry<'a> {
+    fn new(elf: &Elf<'a>, dyn_: &Dyn) -> Result<Self, Error> {
+        let string = match dyn_.d_tag {
+            DT_NEEDED | DT_SONAME | DT_RPATH | DT_RUNPATH | DT_AUXILIARY | DT_FILTER => Some(
+                elf.dynstrtab
+                    .get(dyn_.d_val as usize)
+                    .transpose()
+                    .map_err(symtool_backend::error::Error::from)?
+                    .ok_or_else(|| {
+                        Error::Message(format!(
+                            "Dynamic string table offset {:#x} out of bounds",
+                            dyn_.d_val
+                        ))
+                    })?,
+            ),
+            _ => None,
+        };
+        let flags = match dyn_.d_tag {
+            DT_FLAGS => Some(flag_names(FLAGS, dyn_.d_val)),
+            DT_FLAGS_1 => Some(flag_names(FLAGS_1, dyn_.d_val)),
+            _ => None,
+        };
+        Ok(Self {
+            tag: dyn_.d_tag,
+            value: dyn_.d_val,
+            string,
+            flags,
+        })
+    }
+
+    fn tag_name(&self) -> String {
+        match self.tag {
+            DT_AUXILIARY => "AUXILIARY".to_string(),
+            DT_FILTER => "FILTER".to_string(),
+            tag => match tag_to_str(tag) {
+                "UNKNOWN_TAG" => format!("{:#x}", tag),
+                name => name.trim_start_matches("DT_").to_string(),
+            },
+        }
+    }
+}
+
+fn flag_names(names: &[(u64, &'static str)], value: u64) -> Vec<&'static str> {
+    names
+        .iter()
+        .filter(|(flag, _)| value & flag != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let path = matches.value_of("INPUT").unwrap();
+    let bytes = std::fs::read(path)?;
+    let elf = crate::parse_elf(path, &bytes)?;
+    let entries = match &elf.dynamic {
+        Some(dynamic) => dynamic
+            .dyns
+            .iter()
+            .map(|dyn_| Entry::new(&elf, dyn_))
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
+
+    if matches.is_present("json") {
+        let entries = entries
+            .iter()
+            .map(|entry| {
+                let mut object = json!({
+                    "tag": entry.tag_name(),
+                    "value": entry.value,
+                });
+                if let Some(string) = entry.string {
+                    object["string"] = json!(string);
+                }
+                if let Some(flags) = &entry.flags {
+                    object["flags"] = json!(flags);
+                }
+                object
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        for entry in &entries {
+            let value = if let Some(string) = entry.string {
+                string.to_string()
+            } else if let Some(flags) = &entry.flags {
+                format!("{:#x} ({})", entry.value, flags.join(" "))
+            } else {
+                format!("{:#x}", entry.value)
+            };
+            println!("{:<18} {}", entry.tag_name(), value);
+        }
+    }
+    Ok(())
+}
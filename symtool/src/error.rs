@@ -3,7 +3,9 @@ pub enum Error {
     Io(std::io::Error),
     SymTool(symtool_backend::error::Error),
     Regex(regex::Error),
+    Sqlite(rusqlite::Error),
     Message(String),
+    Path(String, Box<dyn std::error::Error>),
 }
 
 impl std::fmt::Display for Error {
@@ -12,7 +14,9 @@ impl std::fmt::Display for Error {
             Self::Io(e) => write!(f, "{}", e),
             Self::SymTool(e) => write!(f, "{}", e),
             Self::Regex(e) => write!(f, "{}", e),
+            Self::Sqlite(e) => write!(f, "{}", e),
             Self::Message(s) => write!(f, "{}", s),
+            Self::Path(path, e) => write!(f, "{}: {}", path, e),
         }
     }
 }
@@ -23,6 +27,8 @@ impl std::error::Error for Error {
             Self::Io(e) => Some(e),
             Self::SymTool(e) => Some(e),
             Self::Regex(e) => Some(e),
+            Self::Sqlite(e) => Some(e),
+            Self::Path(_, e) => Some(e.as_ref()),
             _ => None,
         }
     }
@@ -45,3 +51,9 @@ impl From<regex::Error> for Error {
         Self::Regex(err)
     }
 }
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Sqlite(err)
+    }
+}
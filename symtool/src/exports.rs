@@ -0,0 +1,176 @@
+//! Display the symbols a binary exports to the dynamic linker.
+//!
+//! Unlike the static symbol table, this is exactly the set of symbols other binaries can bind
+//! to: the dynamic symbol table and symbol versions of ELF binaries, the export trie of Mach-O
+//! binaries, and the export table of PE binaries.
+
+use crate::error::Error;
+use clap::ArgMatches;
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::sym::{STB_GLOBAL, STB_GNU_UNIQUE, STB_WEAK, STV_DEFAULT, STV_PROTECTED};
+use goblin::mach::exports::ExportInfo;
+use goblin::pe::export::Reexport;
+use serde_json::json;
+use symtool_backend as backend;
+use symtool_backend::mach::LdDirective;
+
+/// A symbol exported to the dynamic linker.
+struct Export {
+    name: String,
+
+    /// The symbol version, and whether it is the default version of the symbol
+    version: Option<(String, bool)>,
+
+    /// The library and symbol the export is forwarded to
+    reexport: Option<String>,
+}
+
+impl std::fmt::Display for Export {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        match &self.version {
+            Some((version, true)) => write!(f, "@@{}", version)?,
+            Some((version, false)) => write!(f, "@{}", version)?,
+            None => {}
+        }
+        if let Some(reexport) = &self.reexport {
+            write!(f, " -> {}", reexport)?;
+        }
+        if let Some(directive) = directive(&self.name) {
+            write!(f, " ({})", directive)?;
+        }
+        Ok(())
+    }
+}
+
+/// Describe the effect of a Mach-O linker directive.
+fn directive(name: &str) -> Option<String> {
+    Some(match LdDirective::parse(name)? {
+        LdDirective::Hide { os, symbol } => format!("hides {} when targeting OS {}", symbol, os),
+        LdDirective::Add { os, symbol } => format!("adds {} when targeting OS {}", symbol, os),
+        LdDirective::Weak { os, symbol } => {
+            format!("makes {} weak when targeting OS {}", symbol, os)
+        }
+        LdDirective::InstallName { os, path } => {
+            format!("installed as {} when targeting OS {}", path, os)
+        }
+        LdDirective::Other(_) => "linker directive".to_string(),
+    })
+}
+
+fn elf_exports(bytes: &[u8], elf: &goblin::elf::Elf) -> Result<Vec<Export>, Error> {
+    let iter = match backend::elf::SymtabIter::dynsym_from_elf(bytes, elf)? {
+        Some(iter) => iter,
+        None => return Ok(Vec::new()),
+    };
+    let versions = backend::elf::SymbolVersions::from_elf(bytes, elf)?;
+    let mut exports = Vec::new();
+    for (index, item) in iter.enumerate() {
+        let (name, sym) = item?;
+        let name = match name {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+        if sym.st_shndx == SHN_UNDEF as usize
+            || !matches!(sym.st_bind(), STB_GLOBAL | STB_WEAK | STB_GNU_UNIQUE)
+            || !matches!(sym.st_visibility(), STV_DEFAULT | STV_PROTECTED)
+        {
+            continue;
+        }
+        exports.push(Export {
+            name: name.to_string(),
+            version: versions
+                .get(index)?
+                .map(|version| (version.name.to_string(), !version.hidden)),
+            reexport: None,
+        });
+    }
+    Ok(exports)
+}
+
+fn mach_exports(bytes: &[u8], mach: &goblin::mach::MachO) -> Result<Vec<Export>, Error> {
+    Ok(backend::mach::exports(bytes, mach)?
+        .into_iter()
+        .map(|export| {
+            let reexport = match export.info {
+                ExportInfo::Reexport {
+                    lib,
+                    lib_symbol_name,
+                    ..
+                } => Some(format!(
+                    "{}:{}",
+                    lib,
+                    lib_symbol_name.unwrap_or(&export.name)
+                )),
+                _ => None,
+            };
+            Export {
+                name: export.name,
+                version: None,
+                reexport,
+            }
+        })
+        .collect())
+}
+
+fn pe_exports(pe: &goblin::pe::PE) -> Vec<Export> {
+    pe.exports
+        .iter()
+        .filter_map(|export| {
+            let reexport = export.reexport.as_ref().map(|reexport| match reexport {
+                Reexport::DLLName { export, lib } => format!("{}:{}", lib, export),
+                Reexport::DLLOrdinal { ordinal, lib } => format!("{}:#{}", lib, ordinal),
+            });
+            Some(Export {
+                name: export.name?.to_string(),
+                version: None,
+                reexport,
+            })
+        })
+        .collect()
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let path = matches.value_of("INPUT").unwrap();
+    let bytes = std::fs::read(path)?;
+    let mut exports = if bytes.starts_with(goblin::elf::header::ELFMAG) {
+        elf_exports(&bytes, &backend::elf::parse(&bytes)?)?
+    } else {
+        match goblin::Object::parse(&bytes).map_err(backend::error::Error::from)? {
+            goblin::Object::Mach(goblin::mach::Mach::Binary(mach)) => mach_exports(&bytes, &mach)?,
+            goblin::Object::PE(pe) => pe_exports(&pe),
+            _ => {
+                return Err(
+                    Error::Message(format!("{} is not an ELF, Mach-O or PE binary", path)).into(),
+                )
+            }
+        }
+    };
+    exports.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+    if matches.is_present("json") {
+        let exports: Vec<_> = exports
+            .iter()
+            .map(|export| {
+                json!({
+                    "name": export.name,
+                    "version": export.version.as_ref().map(|(version, _)| version),
+                    "default_version": export.version.as_ref().map(|(_, default)| default),
+                    "reexport": export.reexport,
+                    "directive": directive(&export.name),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&exports)?);
+    } else {
+        let terminator = if matches.is_present("null") {
+            '\0'
+        } else {
+            '\n'
+        };
+        for export in &exports {
+            print!("{}{}", export, terminator);
+        }
+    }
+    Ok(())
+}
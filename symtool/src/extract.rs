@@ -0,0 +1,77 @@
+//! Remove section contents, keeping only the symbols, like `objcopy --extract-symbol`.
+
+use crate::error::Error;
+use goblin::elf::section_header::{
+    SectionHeader, SHF_COMPRESSED, SHT_DYNSYM, SHT_NOBITS, SHT_NULL, SHT_STRTAB, SHT_SYMTAB,
+    SHT_SYMTAB_SHNDX,
+};
+use goblin::elf::Elf;
+use goblin::mach::MachO;
+use symtool_backend as backend;
+use symtool_backend::patch::Patch;
+
+/// Construct patches clearing every section of an ELF binary except the symbol and string tables.
+///
+/// Cleared sections are changed to `SHT_NOBITS`, keeping their sizes.
+pub fn elf_patches(bytes: &[u8], elf: &Elf, verbose: bool) -> Result<Vec<Patch>, Error> {
+    let mut patches = Vec::new();
+    for section in backend::elf::sections(bytes, elf)? {
+        match section.header.sh_type {
+            SHT_NULL | SHT_NOBITS | SHT_SYMTAB | SHT_DYNSYM | SHT_STRTAB | SHT_SYMTAB_SHNDX => {
+                continue
+            }
+            _ => {}
+        }
+        if verbose {
+            println!(
+                "Extract symbols: clear {} ({} bytes)",
+                section.name.as_deref().unwrap_or("unnamed section"),
+                section.contents.len()
+            );
+        }
+        patches.push(
+            section
+                .contents
+                .patch_with_bytes(&vec![0; section.contents.len()])?,
+        );
+        let mut header = SectionHeader {
+            sh_type: SHT_NOBITS,
+            ..section.header.value.clone()
+        };
+        // Keep the uncompressed size of compressed sections
+        if let Some(chdr) = backend::elf::compression_header(bytes, elf, &header)? {
+            header.sh_flags &= !u64::from(SHF_COMPRESSED);
+            header.sh_size = chdr.ch_size;
+            header.sh_addralign = chdr.ch_addralign;
+        }
+        patches.push(section.header.patch_with(header)?);
+    }
+    Ok(patches)
+}
+
+/// Construct patches clearing every section of a Mach-O binary.
+///
+/// The symbol and string tables are stored in the `__LINKEDIT` segment rather than in sections,
+/// so they are kept.
+pub fn mach_patches(bytes: &[u8], mach: &MachO, verbose: bool) -> Result<Vec<Patch>, Error> {
+    let mut patches = Vec::new();
+    for section in backend::mach::sections(bytes, mach)? {
+        if section.contents.is_empty() {
+            continue;
+        }
+        if verbose {
+            println!(
+                "Extract symbols: clear {},{} ({} bytes)",
+                section.segname,
+                section.sectname,
+                section.contents.len()
+            );
+        }
+        patches.push(
+            section
+                .contents
+                .patch_with_bytes(&vec![0; section.contents.len()])?,
+        );
+    }
+    Ok(patches)
+}
@@ -0,0 +1,346 @@
+//! Build an inventory of the symbols of every object in a directory tree.
+
+use crate::error::Error;
+use clap::ArgMatches;
+use goblin::elf::header::ET_DYN;
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::sym::{Sym, STT_FILE, STT_SECTION};
+use goblin::elf::Elf;
+use goblin::mach::header::MH_DYLIB;
+use goblin::mach::symbols::{N_EXT, N_PEXT, N_STAB, N_TYPE, N_UNDF, N_WEAK_DEF};
+use rusqlite::{params, Connection};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use symtool_backend as backend;
+use symtool_backend::mach::LdDirective;
+use symtool_backend::object::{CancellationToken, Object};
+
+const OBJECTS_SQL: &str = "CREATE TABLE objects(id INTEGER PRIMARY KEY, path TEXT NOT NULL, member TEXT, format TEXT NOT NULL, sha256 TEXT NOT NULL)";
+const SYMBOLS_SQL: &str = "CREATE TABLE symbols(object INTEGER NOT NULL REFERENCES objects(id), symtab TEXT NOT NULL, name TEXT NOT NULL, defined INTEGER, binding TEXT, visibility TEXT, type TEXT, thumb INTEGER)";
+
+/// A symbol recorded in the inventory.
+pub struct Symbol {
+    /// The symbol table containing the symbol, such as `.symtab` or `.dynsym`
+    pub symtab: &'static str,
+    pub name: String,
+    pub defined: Option<bool>,
+    pub binding: Option<&'static str>,
+    pub visibility: Option<&'static str>,
+    pub kind: Option<&'static str>,
+
+    /// The size of the symbol in bytes, if the format records it
+    pub size: Option<u64>,
+
+    /// Whether the symbol refers to Thumb code, for 32-bit ARM objects
+    pub thumb: Option<bool>,
+}
+
+/// An object recorded in the inventory, which may be an archive member.
+pub struct IndexedObject {
+    pub path: String,
+    pub member: Option<String>,
+    pub format: &'static str,
+
+    /// Whether the object is a shared library
+    pub shared_library: bool,
+
+    /// The SHA-256 digest of the file containing the object, in hex
+    pub sha256: String,
+    pub symbols: Vec<Symbol>,
+}
+
+fn elf_symbol(elf: &Elf, symtab: &'static str, name: &str, sym: &Sym) -> Option<Symbol> {
+    if name.is_empty() || sym.st_type() == STT_FILE || sym.st_type() == STT_SECTION {
+        return None;
+    }
+    let binding = match goblin::elf::sym::bind_to_str(sym.st_bind()) {
+        "LOCAL" => "local",
+        "GLOBAL" => "global",
+        "WEAK" => "weak",
+        "GNU_UNIQUE" => "unique",
+        _ => "other",
+    };
+    let visibility = match sym.st_visibility() {
+        goblin::elf::sym::STV_DEFAULT => "default",
+        goblin::elf::sym::STV_INTERNAL => "internal",
+        goblin::elf::sym::STV_HIDDEN => "hidden",
+        _ => "protected",
+    };
+    let kind = match goblin::elf::sym::type_to_str(sym.st_type()) {
+        "NOTYPE" => "notype",
+        "OBJECT" => "object",
+        "FUNC" => "func",
+        "COMMON" => "common",
+        "TLS" => "tls",
+        "GNU_IFUNC" => "ifunc",
+        _ => "other",
+    };
+    Some(Symbol {
+        symtab,
+        name: name.to_string(),
+        defined: Some(sym.st_shndx != SHN_UNDEF as usize),
+        binding: Some(binding),
+        visibility: Some(visibility),
+        kind: Some(kind),
+        size: Some(sym.st_size),
+        thumb: backend::elf::is_thumb(elf, sym),
+    })
+}
+
+impl Symbol {
+    /// Returns true if the symbol is defined, global or weak, and has default or protected
+    /// visibility.
+    pub fn is_exported(&self) -> bool {
+        is_exported(self.defined, self.binding, self.visibility)
+    }
+}
+
+/// Returns true if a symbol with these attributes, as recorded in the inventory, is defined,
+/// global or weak, and has default or protected visibility.
+pub fn is_exported(defined: Option<bool>, binding: Option<&str>, visibility: Option<&str>) -> bool {
+    defined != Some(false)
+        && matches!(binding, Some("global") | Some("weak") | Some("unique"))
+        && matches!(visibility, Some("default") | Some("protected"))
+}
+
+/// Returns true if an object is a shared library, rather than an executable or relocatable
+/// object.
+fn is_shared_library(object: &Object) -> bool {
+    match object {
+        // Position-independent executables are also ET_DYN, but have an interpreter and no
+        // SONAME (unlike libc, which has both)
+        Object::Elf(elf) => {
+            elf.header.e_type == ET_DYN && (elf.soname.is_some() || elf.interpreter.is_none())
+        }
+        Object::MachO(mach) => mach.header.filetype == MH_DYLIB,
+        Object::Other(_) => false,
+    }
+}
+
+/// Collect the symbols of an object.
+fn symbols(bytes: &[u8], object: &Object) -> Result<(&'static str, Vec<Symbol>), Error> {
+    let mut symbols = Vec::new();
+    let format = match object {
+        Object::Elf(elf) => {
+            let tables = [
+                (
+                    ".symtab",
+                    backend::elf::SymtabIter::symtab_from_elf(bytes, elf)?,
+                ),
+                (
+                    ".dynsym",
+                    backend::elf::SymtabIter::dynsym_from_elf(bytes, elf)?,
+                ),
+            ];
+            for (symtab, iter) in tables {
+                for item in iter.into_iter().flatten() {
+                    if let (Some(name), sym) = item? {
+                        symbols.extend(elf_symbol(elf, symtab, &name, &sym));
+                    }
+                }
+            }
+            "elf"
+        }
+        Object::MachO(mach) => {
+            for item in backend::mach::SymtabIter::from_mach(bytes, mach)
+                .into_iter()
+                .flatten()
+            {
+                let (name, nlist) = item?;
+                let name = match name {
+                    Some(name) if !name.is_empty() && nlist.n_type & N_STAB == 0 => name,
+                    _ => continue,
+                };
+                let binding = match (nlist.n_type & N_EXT != 0, nlist.n_desc & N_WEAK_DEF != 0) {
+                    (false, _) => "local",
+                    (true, false) => "global",
+                    (true, true) => "weak",
+                };
+                symbols.push(Symbol {
+                    symtab: "LC_SYMTAB",
+                    name: name.value.to_string(),
+                    defined: Some(nlist.n_type & N_TYPE != N_UNDF),
+                    binding: Some(binding),
+                    visibility: Some(if nlist.n_type & N_PEXT != 0 {
+                        "hidden"
+                    } else {
+                        "default"
+                    }),
+                    kind: LdDirective::parse(&name).map(|_| "directive"),
+                    size: None,
+                    thumb: backend::mach::is_thumb(mach, &nlist),
+                });
+            }
+            "mach-o"
+        }
+        Object::Other(object) => {
+            for symbol in object.symbols()? {
+                symbols.push(Symbol {
+                    symtab: "symbols",
+                    name: symbol.name.to_string(),
+                    defined: None,
+                    binding: Some(if symbol.global { "global" } else { "local" }),
+                    visibility: Some(if symbol.hidden { "hidden" } else { "default" }),
+                    kind: None,
+                    size: None,
+                    thumb: None,
+                });
+            }
+            "other"
+        }
+    };
+    Ok((format, symbols))
+}
+
+/// Index the objects in a file, which may be an archive.
+///
+/// Returns `None` if the file is not a supported object or archive.
+fn index_file(path: &Path, token: &CancellationToken) -> Result<Option<Vec<IndexedObject>>, Error> {
+    let bytes = std::fs::read(path)?;
+    index_bytes(path, &bytes, token)
+}
+
+/// Index the objects in the contents of a file, which may be an archive.
+///
+/// Returns `None` if the contents are not a supported object or archive.
+pub fn index_bytes(
+    path: &Path,
+    bytes: &[u8],
+    token: &CancellationToken,
+) -> Result<Option<Vec<IndexedObject>>, Error> {
+    let sha256 = format!("{:x}", Sha256::digest(bytes));
+    let mut objects = Vec::new();
+    let mut formats = backend::object::Formats::default();
+    formats
+        .skip_unrecognized_members(|_, _| {})
+        .cancel_with(token.clone());
+    let result = formats.transform(
+        &mut Cursor::new(bytes),
+        &mut std::io::sink(),
+        |context| -> Result<(), Error> {
+            let (format, symbols) = symbols(context.bytes, &context.object)?;
+            objects.push(IndexedObject {
+                path: path.display().to_string(),
+                member: context.member.map(str::to_string),
+                format,
+                shared_library: is_shared_library(&context.object),
+                sha256: sha256.clone(),
+                symbols,
+            });
+            Ok(())
+        },
+    );
+    match result {
+        Ok(()) => Ok(Some(objects)),
+        Err(backend::error::TransformError::SymTool(
+            backend::error::Error::UnknownObject | backend::error::Error::UnsupportedObject(_),
+        )) => Ok(None),
+        Err(e) => Err(Error::Path(path.display().to_string(), Box::new(e))),
+    }
+}
+
+/// List the regular files in a directory tree, in a stable order.
+///
+/// Symbolic links are not followed, so files aren't indexed once per link.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(|e| Error::Path(dir.display().to_string(), Box::new(e)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Index every object in a directory tree.
+pub fn index_tree(
+    dir: &Path,
+    verbose: bool,
+    token: &CancellationToken,
+) -> Result<Vec<IndexedObject>, Error> {
+    let mut files = Vec::new();
+    walk(dir, &mut files)?;
+    let mut objects = Vec::new();
+    for file in files {
+        match index_file(&file, token)? {
+            Some(indexed) => objects.extend(indexed),
+            None => {
+                if verbose {
+                    println!("Skip {}: not an object", file.display());
+                }
+            }
+        }
+    }
+    Ok(objects)
+}
+
+/// Write the inventory of `objects` to a new database, returning its contents.
+pub fn write_database(objects: &[IndexedObject], verbose: bool) -> Result<Vec<u8>, Error> {
+    let mut connection = Connection::open_in_memory()?;
+    let transaction = connection.transaction()?;
+    transaction.execute(OBJECTS_SQL, [])?;
+    transaction.execute(SYMBOLS_SQL, [])?;
+    {
+        let mut insert_object = transaction.prepare(
+            "INSERT INTO objects(id, path, member, format, sha256) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        let mut insert_symbol = transaction.prepare(
+            "INSERT INTO symbols(object, symtab, name, defined, binding, visibility, type, thumb) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for (id, object) in (1i64..).zip(objects) {
+            if verbose {
+                match &object.member {
+                    Some(member) => println!("Index {}({})", object.path, member),
+                    None => println!("Index {}", object.path),
+                }
+            }
+            insert_object.execute(params![
+                id,
+                object.path,
+                object.member,
+                object.format,
+                object.sha256,
+            ])?;
+            for symbol in &object.symbols {
+                insert_symbol.execute(params![
+                    id,
+                    symbol.symtab,
+                    symbol.name,
+                    symbol.defined,
+                    symbol.binding,
+                    symbol.visibility,
+                    symbol.kind,
+                    symbol.thumb,
+                ])?;
+            }
+        }
+    }
+    transaction.commit()?;
+    Ok(connection.serialize(rusqlite::MAIN_DB)?.to_vec())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let verbose = matches.is_present("verbose");
+    let dir = Path::new(matches.value_of("DIR").unwrap());
+    let token = crate::cancel::install();
+    let objects = index_tree(dir, verbose, &token)?;
+    let database = write_database(&objects, verbose)?;
+    let symbol_count: usize = objects.iter().map(|object| object.symbols.len()).sum();
+    let output = matches.value_of("output").unwrap();
+    let mut outputs = crate::cancel::Outputs::default();
+    outputs.stage(output, &database)?;
+    outputs.commit(&token)?;
+    println!(
+        "Indexed {} symbols in {} objects",
+        symbol_count,
+        objects.len()
+    );
+    Ok(())
+}
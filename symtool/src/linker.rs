@@ -0,0 +1,180 @@
+//! Write the export set of a patched object as linker inputs, so the same policy can be enforced
+//! when linking.
+
+use crate::error::Error;
+use crate::index::IndexedObject;
+use clap::ArgMatches;
+use std::collections::BTreeSet;
+use std::path::Path;
+use symtool_backend::elf::split_version;
+use symtool_backend::mach::LdDirective;
+use symtool_backend::object::CancellationToken;
+
+/// The linker argument files to write.
+pub struct LinkerArgs<'a> {
+    version_script: Option<&'a str>,
+    exported_symbols_list: Option<&'a str>,
+    response_file: Option<&'a str>,
+}
+
+/// The symbols exported by a set of objects, with their C names (without the leading underscore
+/// of Mach-O symbols).
+struct Exports {
+    names: BTreeSet<String>,
+
+    /// Mach-O linker directives, which have no leading underscore
+    directives: BTreeSet<String>,
+    mach: bool,
+}
+
+impl Exports {
+    fn from_objects(objects: &[IndexedObject]) -> Self {
+        let mut names = BTreeSet::new();
+        let mut directives = BTreeSet::new();
+        for object in objects {
+            // Rules change .symtab, so .dynsym only describes the policy when there's no .symtab
+            let has_symtab = object
+                .symbols
+                .iter()
+                .any(|symbol| symbol.symtab == ".symtab");
+            for symbol in &object.symbols {
+                if (has_symtab && symbol.symtab != ".symtab") || !symbol.is_exported() {
+                    continue;
+                }
+                if object.format == "mach-o" && LdDirective::parse(&symbol.name).is_some() {
+                    directives.insert(symbol.name.clone());
+                    continue;
+                }
+                // Version scripts name symbols without the version suffixes of .symver
+                let name = match object.format {
+                    "mach-o" => symbol.name.strip_prefix('_').unwrap_or(&symbol.name),
+                    _ => split_version(&symbol.name).0,
+                };
+                names.insert(name.to_string());
+            }
+        }
+        Self {
+            names,
+            directives,
+            mach: objects.iter().any(|object| object.format == "mach-o"),
+        }
+    }
+
+    /// A GNU ld version script exporting the symbols and hiding everything else.
+    fn version_script(&self) -> String {
+        let mut script = String::from("{\n  global:\n");
+        for name in &self.names {
+            script.push_str(&format!("    \"{}\";\n", name));
+        }
+        script.push_str("  local:\n    *;\n};\n");
+        script
+    }
+
+    /// A symbol list for the `-exported_symbols_list` option of Apple ld.
+    fn exported_symbols_list(&self) -> String {
+        self.names
+            .iter()
+            .map(|name| format!("_{}", name))
+            .chain(self.directives.iter().cloned())
+            .map(|name| format!("{}\n", name))
+            .collect()
+    }
+
+    /// A compiler driver response file passing the exports to the linker of the object's
+    /// platform.
+    ///
+    /// GNU ld has no option exporting a single symbol from a shared library, so for ELF objects
+    /// the response file passes the version script at `version_script` instead.
+    fn response_file(&self, version_script: Option<&Path>) -> Result<String, Error> {
+        if self.mach {
+            return Ok(self
+                .names
+                .iter()
+                .map(|name| format!("_{}", name))
+                .chain(self.directives.iter().cloned())
+                .map(|name| format!("{}\n", quote(&format!("-Wl,-exported_symbol,{}", name))))
+                .collect());
+        }
+        let version_script = version_script.ok_or_else(|| {
+            Error::Message(
+                "--write-response-file requires --write-version-script for ELF objects".to_string(),
+            )
+        })?;
+        Ok(format!(
+            "{}\n",
+            quote(&format!(
+                "-Wl,--version-script={}",
+                version_script.display()
+            ))
+        ))
+    }
+}
+
+/// Quote an argument for a response file.
+fn quote(arg: &str) -> String {
+    let mut quoted = String::new();
+    for c in arg.chars() {
+        if c.is_whitespace() || matches!(c, '\'' | '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+impl<'a> LinkerArgs<'a> {
+    pub fn from_matches(matches: &'a ArgMatches) -> Option<Self> {
+        let args = Self {
+            version_script: matches.value_of("write-version-script"),
+            exported_symbols_list: matches.value_of("write-exported-symbols-list"),
+            response_file: matches.value_of("write-response-file"),
+        };
+        if args.version_script.is_none()
+            && args.exported_symbols_list.is_none()
+            && args.response_file.is_none()
+        {
+            None
+        } else {
+            Some(args)
+        }
+    }
+
+    /// Stage the linker argument files for the patched contents `bytes` of `path`.
+    pub fn stage(
+        &self,
+        path: &str,
+        bytes: &[u8],
+        token: &CancellationToken,
+        outputs: &mut crate::cancel::Outputs,
+        verbose: bool,
+    ) -> Result<(), Error> {
+        let objects = crate::index::index_bytes(Path::new(path), bytes, token)?.unwrap_or_default();
+        let exports = Exports::from_objects(&objects);
+        let mut files = Vec::new();
+        if let Some(file) = self.version_script {
+            files.push((file, exports.version_script()));
+        }
+        if let Some(file) = self.exported_symbols_list {
+            files.push((file, exports.exported_symbols_list()));
+        }
+        if let Some(file) = self.response_file {
+            // The response file is read relative to the compiler's working directory
+            let version_script = match self.version_script {
+                Some(script) => Some(std::env::current_dir()?.join(script)),
+                None => None,
+            };
+            files.push((file, exports.response_file(version_script.as_deref())?));
+        }
+        for (file, contents) in files {
+            if verbose {
+                println!(
+                    "Write {} exported symbol(s) to {}",
+                    exports.names.len() + exports.directives.len(),
+                    file
+                );
+            }
+            outputs.stage(file, contents.as_bytes())?;
+        }
+        Ok(())
+    }
+}
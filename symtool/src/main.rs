@@ -1,24 +1,53 @@
 use clap::{
-    app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg, ArgMatches,
+    app_from_crate, crate_authors, crate_description, crate_name, crate_version, App, AppSettings,
+    Arg, ArgMatches, SubCommand,
 };
-use goblin::elf::sym::{Sym, STB_GLOBAL, STB_WEAK, STT_NOTYPE, STV_DEFAULT, STV_HIDDEN};
-use goblin::mach::symbols::{Nlist, N_PEXT, N_STAB};
-use regex::RegexSet;
-use std::collections::HashMap;
-use std::ops::Deref;
+use std::io::Cursor;
 
 use symtool_backend as backend;
 
+mod add;
+mod cancel;
+mod check;
+mod comment;
+mod common;
+mod config;
+mod debug;
+mod dynamic;
 mod error;
+mod exports;
+mod extract;
+mod index;
+mod linker;
+mod obfuscate;
+mod query;
+mod rebind;
+mod report;
+mod rules;
+mod scrub;
+mod section;
+#[cfg(feature = "smoke-test")]
+mod smoke;
+mod transform;
+mod value;
+mod version;
 use crate::error::Error;
+use crate::linker::LinkerArgs;
+use crate::transform::Transform;
 
-fn main() {
-    let matches = app_from_crate!()
+fn app() -> App<'static, 'static> {
+    let app = app_from_crate!()
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("verbose")
                 .long("verbose")
                 .short("v")
-                .help("Print information for each operation performed"),
+                .multiple(true)
+                .help("Print information for each operation performed")
+                .long_help(
+                    "Print information for each operation performed.  When given twice, also prints the byte offset, length, and old and new bytes of each patch.",
+                ),
         )
         .arg(
             Arg::with_name("rename")
@@ -27,23 +56,771 @@ fn main() {
                 .multiple(true)
                 .value_names(&["OLD-NAME", "NEW-NAME"])
                 .help("Renames symbols named OLD-NAME to NEW-NAME")
-                .long_help("Renames symbols named OLD-NAME to NEW-NAME. Since string tables are simply patched and not rewritten, NEW-NAME must not have more characters than OLD-NAME")
+                .long_help("Renames symbols named OLD-NAME to NEW-NAME. Since string tables are simply patched and not rewritten, NEW-NAME must not have more characters than OLD-NAME, unless --rewrite is given")
+        )
+        .arg(
+            Arg::with_name("rename-regex")
+                .long("rename-regex")
+                .number_of_values(2)
+                .multiple(true)
+                .value_names(&["PATTERN", "REPLACEMENT"])
+                .help("Renames symbols matching regex PATTERN to REPLACEMENT, expanding capture groups")
+                .long_help("Renames all symbols with names matching regex PATTERN, replacing the first match of PATTERN in each name with REPLACEMENT, such as --rename-regex '^foo_(.*)' 'bar_$1'.  REPLACEMENT may refer to capture groups as $1 or ${name}.  Like --rename, the new names must not have more characters than the old ones, unless --rewrite is given.  Failing to match any symbol is an error, unless followed by --ignore-missing.")
+        )
+        .arg(
+            Arg::with_name("rename-demangled")
+                .long("rename-demangled")
+                .number_of_values(2)
+                .multiple(true)
+                .value_names(&["NAME", "NEW-NAME"])
+                .help("Renames the symbol with the demangled source-level name NAME to NEW-NAME")
+                .long_help("Renames the symbol whose demangled name is NAME, such as 'ns::open', to NEW-NAME, without spelling out its mangling.  Parameter lists may be omitted from NAME, and extern \"C\" symbols match by their plain name.  The resolved symbol is printed with --verbose, and if NAME matches more than one symbol of an object, such as several overloads, the candidates are reported and nothing is renamed; use --rename with the mangled name instead.  Like --rename, NEW-NAME must not have more characters than the old name, unless --rewrite is given.  Failing to match any symbol is an error, unless followed by --ignore-missing.")
+        )
+        .arg(
+            Arg::with_name("redefine-syms")
+                .long("redefine-syms")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("FILE")
+                .help("Renames symbols as listed in FILE")
+                .long_help("Renames symbols as listed in FILE, like objcopy --redefine-syms.  Each line of FILE is an OLD-NAME and a NEW-NAME separated by whitespace, and is applied like --rename.  Blank lines and text following '#' are ignored.  Rule modifiers following this option apply to every rename in FILE.  May be given more than once.")
+        )
+        .arg(
+            Arg::with_name("prefix-symbols")
+                .long("prefix-symbols")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PREFIX")
+                .help("Prepends PREFIX to the names of all symbols")
+                .long_help("Prepends PREFIX to the names of all symbols, like objcopy --prefix-symbols, for vendoring libraries without symbol collisions.  Undefined symbols are prefixed too, so references between prefixed objects still resolve.  Section and file symbols are left unchanged, and renamed symbols are prefixed after renaming.  Prefixed names are longer, so --rewrite is required.")
+        )
+        .arg(
+            Arg::with_name("prefix-regex")
+                .long("prefix-regex")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(2)
+                .value_names(&["PATTERN", "PREFIX"])
+                .help("Prepends PREFIX to the names of all symbols matching regex PATTERN")
+                .long_help("Prepends PREFIX to the names of all symbols with names matching regex PATTERN, like --prefix-symbols.  When several prefix rules match a symbol, the last one takes precedence.  Requires --rewrite.")
+        )
+        .arg(
+            Arg::with_name("prefix-undefined")
+                .long("prefix-undefined")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PREFIX")
+                .help("Prepends PREFIX to the names of all undefined symbols")
+                .long_help("Prepends PREFIX to the names of all undefined symbols, leaving the names of definitions unchanged, to redirect the references of an object to wrapped implementations.  Like --prefix-symbols, requires --rewrite.")
+        )
+        .arg(
+            Arg::with_name("scrub-paths")
+                .long("scrub-paths")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PREFIX=REPLACEMENT")
+                .help("Replaces PREFIX with REPLACEMENT in the source paths recorded in objects")
+                .long_help("Replaces PREFIX with REPLACEMENT at the start of the source and object paths recorded in objects, like the compiler's -ffile-prefix-map, for privacy and reproducible builds.  On ELF, the names of file symbols (STT_FILE) are scrubbed, and on Mach-O, the N_SO, N_SOL and N_OSO debugging entries.  With --rename-debug-info, the paths in .debug_str and .debug_line_str are scrubbed in place too, leaving longer paths unchanged with a warning.  Shorter paths are padded with NUL bytes, and longer ones require --rewrite.  May be given more than once, and the last matching PREFIX takes precedence.")
+        )
+        .arg(
+            Arg::with_name("obfuscate")
+                .long("obfuscate")
+                .requires("obfuscate-map")
+                .help("Renames all defined local symbols to hashes of their names")
+                .long_help("Renames all defined local symbols to deterministic hashes of their names, so shipped binaries leak less about their implementation, and writes the original names to the map file given with --obfuscate-map, so crash reports can still be symbolicated.  Each name is replaced by 's' followed by the 16 hex digits of its hash, truncated to the length of the original name so symbols are renamed in place and the original names don't remain in the string table.  With --rewrite, names aren't truncated.  Fails if two symbols would share an obfuscated name, as truncated short names may.  Symbols renamed or prefixed by other rules, symbols excluded by --exclude-symbols, ELF mapping symbols such as $x, and Mach-O debugging entries keep their names; remove the latter with --strip-stabs.  With --rename-debug-info, matching debug info strings are obfuscated too.")
+        )
+        .arg(
+            Arg::with_name("obfuscate-regex")
+                .long("obfuscate-regex")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATTERN")
+                .requires("obfuscate-map")
+                .help("Renames all symbols matching regex PATTERN to hashes of their names")
+                .long_help("Renames all symbols with names matching regex PATTERN to deterministic hashes of their names, like --obfuscate, whatever their binding.  Obfuscating global symbols changes the names other objects must link against, so obfuscate every object of a program with the same --seed.  May be given more than once.")
+        )
+        .arg(
+            Arg::with_name("obfuscate-map")
+                .long("obfuscate-map")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Writes the names replaced by --obfuscate to FILE")
+                .long_help("Writes the names replaced by --obfuscate and --obfuscate-regex to FILE, one line per symbol, with the obfuscated name followed by a space and the original name, sorted by obfuscated name.  Mach-O names are written without their leading underscore with --auto-underscore.")
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .value_name("SEED")
+                .help("Hashes obfuscated names with SEED")
+                .long_help("Hashes the names obfuscated by --obfuscate and --obfuscate-regex with SEED, so the obfuscated names of one release can't be matched against those of another.  Without this option, the seed is empty.")
+        )
+        .arg(
+            Arg::with_name("rewrite")
+                .long("rewrite")
+                .help("Rebuilds the symbol string table so symbols can be renamed to longer names")
+                .long_help("Rebuilds the symbol string table instead of renaming symbols in place, so NEW-NAME may be longer than OLD-NAME.  On ELF, the string table of .symtab, the symbol table and the section header table are rebuilt with only the names in use, and the sections not loaded at run time are laid out again after the loaded ones, so no stale tables are left behind.  Binaries without a .symtab have the symbols of .dynsym renamed instead: the new names are appended to .dynstr, which is moved to a new loadable segment at the end of the file, and the hash tables are rebuilt, reordering .dynsym with its symbol versions and relocations to match.  On Mach-O, the string table is rebuilt at the end of the file and the old one is left in place, unreferenced; this is supported for objects, and unsigned images whose __LINKEDIT segment ends the file, which is grown to cover the new table.  Debug info strings are still renamed in place, and are left unchanged with a warning if the new name is longer.")
+        )
+        .arg(
+            Arg::with_name("compact-strtab")
+                .long("compact-strtab")
+                .help("Compacts the symbol string table in place")
+                .long_help("Rebuilds the symbol string table in place after the other changes, keeping only the names symbols still refer to, such as after stripping them.  Duplicate names are stored once, and names which are a suffix of another, such as 'bar' of 'foobar', share its tail.  The freed bytes at the end of the table are cleared and its size is reduced to match.  On ELF, section names are kept if the table is also the section name table.  With --rewrite, ELF string tables are always compacted, and the rebuilt Mach-O table at the end of the file is compacted.  Supported for ELF binaries with a .symtab whose string table isn't loaded at run time, and Mach-O binaries.")
+        )
+        .arg(
+            Arg::with_name("rename-debug-info")
+                .long("rename-debug-info")
+                .help("Renames strings in debug info matching renamed symbols")
+                .long_help("Renames strings in the debug info string table (.debug_str) matching renamed symbols, so DW_AT_name and DW_AT_linkage_name attributes refer to the new names, and rehashes accelerator tables (.debug_names and .apple_names).  Names stored inline in .debug_info are not renamed.  With --scrub-paths, paths in .debug_str and .debug_line_str are scrubbed too.")
         )
         .arg(
             Arg::with_name("hidden")
                 .long("hidden")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
                 .value_name("PATTERN")
-                .help("Sets all symbols with names matching regex PATTERN to hidden visibility"),
+                .help("Sets all symbols with names matching regex PATTERN to hidden visibility")
+                .long_help(
+                    "Sets all symbols with names matching regex PATTERN to hidden visibility.  Patterns prefixed with 'demangled:' match the demangled C++ or Rust name instead.",
+                ),
         )
         .arg(
             Arg::with_name("default")
                 .long("default")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
                 .value_name("PATTERN")
                 .help("Sets all symbols with names matching regex PATTERN to default visibility")
                 .long_help(
-                    "Sets all symbols with names matching regex PATTERN to default visibility.  --default takes precedance over --hidden when both patterns match a symbol name.",
+                    "Sets all symbols with names matching regex PATTERN to default visibility.  --default takes precedance over --hidden, --protected and --internal when both patterns match a symbol name.",
+                ),
+        )
+        .arg(
+            Arg::with_name("protected")
+                .long("protected")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATTERN")
+                .help("Sets all ELF symbols with names matching regex PATTERN to protected visibility")
+                .long_help(
+                    "Sets all ELF symbols with names matching regex PATTERN to protected visibility (STV_PROTECTED), so they are exported but references from within their shared object can't be interposed.  Equivalent to --set 'visibility=protected where PATTERN'.",
+                ),
+        )
+        .arg(
+            Arg::with_name("internal")
+                .long("internal")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATTERN")
+                .help("Sets all ELF symbols with names matching regex PATTERN to internal visibility")
+                .long_help(
+                    "Sets all ELF symbols with names matching regex PATTERN to internal visibility (STV_INTERNAL), which is hidden visibility that also promises the symbol is never called from outside its component.  Equivalent to --set 'visibility=internal where PATTERN'.",
+                ),
+        )
+        .arg(
+            Arg::with_name("set")
+                .long("set")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("RULE")
+                .help("Sets attributes of symbols, with RULE of the form 'ATTR=VALUE,... where PATTERN'")
+                .long_help(
+                    "Sets attributes of all symbols with names matching regex PATTERN, with RULE of the form 'ATTR=VALUE,... where PATTERN'.  Supported attributes are visibility (default, hidden, or for ELF, protected or internal), binding (local, global, or weak) and, for ELF, type (func, object, notype, or tls).  For example, --set 'visibility=hidden,binding=local where ^mylib_internal_'.  Later rules take precedence, except that default visibility takes precedence over the other visibilities.",
+                ),
+        )
+        .arg(
+            Arg::with_name("set-binding")
+                .long("set-binding")
+                .number_of_values(2)
+                .multiple(true)
+                .value_names(&["PATTERN", "BINDING"])
+                .help("Sets the binding of symbols matching regex PATTERN to global, local or weak")
+                .long_help(
+                    "Sets the binding of all defined symbols with names matching regex PATTERN to BINDING, which is global, local or weak.  Equivalent to --set 'binding=BINDING where PATTERN'.",
+                ),
+        )
+        .arg(
+            Arg::with_name("set-type")
+                .long("set-type")
+                .number_of_values(2)
+                .multiple(true)
+                .value_names(&["PATTERN", "TYPE"])
+                .help("Sets the ELF type of symbols matching regex PATTERN to func, object, notype or tls")
+                .long_help(
+                    "Sets the type of all ELF symbols with names matching regex PATTERN to TYPE, which is func (STT_FUNC), object (STT_OBJECT), notype (STT_NOTYPE) or tls (STT_TLS), such as for symbols defined in assembly without a .type directive.  Equivalent to --set 'type=TYPE where PATTERN'.",
+                ),
+        )
+        .arg(
+            Arg::with_name("strip-symbol")
+                .long("strip-symbol")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME")
+                .help("Removes the symbol NAME from the symbol table")
+                .long_help(
+                    "Removes the symbol NAME from the static symbol table.  The remaining symbols are moved to the start of the table, and renumbered in relocations, section groups and the indirect symbol table.  Symbols that relocations refer to can't be removed.  NAME is matched after --rename, and the name is left in the string table.",
+                ),
+        )
+        .arg(
+            Arg::with_name("strip-regex")
+                .long("strip-regex")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATTERN")
+                .help("Removes all symbols with names matching regex PATTERN from the symbol table")
+                .long_help(
+                    "Removes all symbols with names matching regex PATTERN from the static symbol table, like --strip-symbol.  Patterns prefixed with 'demangled:' match the demangled C++ or Rust name instead.",
+                ),
+        )
+        .arg(
+            Arg::with_name("globalize-symbol")
+                .long("globalize-symbol")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME")
+                .help("Makes the local symbol NAME global")
+                .long_help(
+                    "Makes the defined local symbol NAME global (STB_GLOBAL, or sets N_EXT on Mach-O), so it can be linked against.  Equivalent to --set 'binding=global where ^NAME$' without regex syntax.  On ELF, the symbol is moved after the local symbols in .symtab, and relocations are renumbered to match.",
+                ),
+        )
+        .arg(
+            Arg::with_name("localize-symbol")
+                .long("localize-symbol")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME")
+                .help("Makes the symbol NAME local")
+                .long_help(
+                    "Makes the defined symbol NAME local (STB_LOCAL, or clears N_EXT on Mach-O), so it can't be linked against.  Equivalent to --set 'binding=local where ^NAME$' without regex syntax.  On ELF, local symbols are moved before the global ones in .symtab, and on Mach-O into the local group LC_DYSYMTAB describes, and relocations are renumbered to match.",
+                ),
+        )
+        .arg(
+            Arg::with_name("localize-regex")
+                .long("localize-regex")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATTERN")
+                .help("Makes all symbols with names matching regex PATTERN local")
+                .long_help(
+                    "Makes all defined symbols with names matching regex PATTERN local, like --localize-symbol.  Equivalent to --set 'binding=local where PATTERN'.",
+                ),
+        )
+        .arg(
+            Arg::with_name("weaken-symbol")
+                .long("weaken-symbol")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("NAME")
+                .help("Makes the symbol NAME weak")
+                .long_help(
+                    "Makes the defined symbol NAME weak (STB_WEAK, or sets N_WEAK_DEF on Mach-O), so other definitions take precedence over it.  Equivalent to --set 'binding=weak where ^NAME$' without regex syntax.",
+                ),
+        )
+        .arg(
+            Arg::with_name("weaken")
+                .long("weaken")
+                .help("Makes all defined global symbols weak")
+                .long_help(
+                    "Makes all defined global symbols weak (STB_WEAK, or sets N_WEAK_DEF on Mach-O), for building interposable libraries and test shims.  Local symbols are unchanged, and symbols matched by a rule setting their binding keep that binding.",
+                ),
+        )
+        .arg(
+            Arg::with_name("localize-hidden")
+                .long("localize-hidden")
+                .help("Makes all defined hidden symbols local")
+                .long_help(
+                    "Makes all defined global and weak symbols with hidden or internal visibility local, like objcopy --localize-hidden, so hidden symbols of a relocatable object can't be linked against by other objects either.  Symbols hidden by rules such as --hidden are made local too.  On Mach-O, private external (N_PEXT) symbols are made local.  Symbols matched by a rule setting their binding keep that binding.",
+                ),
+        )
+        .arg(
+            Arg::with_name("weaken-undefined")
+                .long("weaken-undefined")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATTERN")
+                .help("Makes undefined references matching regex PATTERN weak")
+                .long_help(
+                    "Makes the undefined references with names matching regex PATTERN weak (STB_WEAK, or sets N_WEAK_REF on Mach-O), so the program still links and loads when no definition is found, and the symbol's address is null.  On ELF, only .symtab is changed unless --reconcile-dynsym is given.  Mach-O binaries bind weak imports according to their binding information, so only relocatable objects are supported.  May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("keep-global-symbols")
+                .long("keep-global-symbols")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("FILE")
+                .help("Makes all defined global symbols not listed in FILE local")
+                .long_help(
+                    "Makes all defined global and weak symbols not listed in FILE local, so only the listed symbols can be linked against.  FILE has the same format as for --exclude-symbols.  May be given more than once, keeping the symbols listed in any FILE.  Symbols matched by a rule setting their binding keep that binding.",
+                ),
+        )
+        .arg(
+            Arg::with_name("keep-symbols")
+                .long("keep-symbols")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("FILE")
+                .help("Keeps the symbols listed in FILE when stripping")
+                .long_help(
+                    "Keeps the symbols listed in FILE when stripping, so --strip-all and --strip-unneeded remove all other symbols, and --strip-symbol and --strip-regex don't remove them.  FILE has the same format as for --exclude-symbols.  Has no effect without a stripping option.  May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("max-matches")
+                .long("max-matches")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("N")
+                .help("Fails if the preceding rule matches more than N symbols")
+                .long_help(
+                    "Fails if the preceding rule, such as --rename, --hidden or --set, matches more than N symbols in any one object.  Each archive member and fat slice is counted separately.  When given before any rule, sets the limit for all rules.",
+                ),
+        )
+        .arg(
+            Arg::with_name("ignore-missing")
+                .long("ignore-missing")
+                .multiple(true)
+                .help("Allows the preceding rename's OLD-NAME to be missing from INPUT")
+                .long_help(
+                    "Allows the preceding --rename rule's OLD-NAME to be missing from INPUT.  Without this option, renaming a symbol that doesn't exist is an error.  When given before any rule, applies to all renames.  Renames that weren't applied are listed in a warning.",
+                ),
+        )
+        .arg(
+            Arg::with_name("in-member")
+                .long("in-member")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATTERN")
+                .help("Applies the preceding rule only to archive members matching regex PATTERN")
+                .long_help(
+                    "Applies the preceding rule only to archive members with names matching regex PATTERN, so different policies can be applied to different members of one archive.  Scoped rules don't apply to objects outside archives.  When given before any rule, applies to all rules.",
+                ),
+        )
+        .arg(
+            Arg::with_name("in-format")
+                .long("in-format")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("FORMAT")
+                .possible_values(&["elf", "mach-o"])
+                .help("Applies the preceding rule only to objects of FORMAT")
+                .long_help(
+                    "Applies the preceding rule only to objects of FORMAT (elf or mach-o), so one set of rules can be used for every platform.  Renames scoped to a format aren't reported missing if no object of that format was transformed.  When given before any rule, applies to all rules.",
+                ),
+        )
+        .arg(
+            Arg::with_name("except")
+                .long("except")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PATTERN")
+                .help("Excludes symbols matching PATTERN from the preceding rule")
+                .long_help(
+                    "Excludes symbols with names matching regex PATTERN from the preceding rule, such as --hidden '^mylib_' --except '^mylib_api_' to hide everything matching one pattern except what matches another, without a negative lookahead, which isn't supported.  PATTERN has the same syntax as the patterns of --hidden, and may be prefixed with demangled:.  May be given more than once per rule.  When given before any rule, applies to all rules.",
+                ),
+        )
+        .arg(
+            Arg::with_name("only-type")
+                .long("only-type")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("TYPE")
+                .possible_values(&["func", "object", "tls", "notype"])
+                .help("Applies the preceding rule only to symbols of TYPE")
+                .long_help(
+                    "Applies the preceding rule only to symbols of TYPE: functions (func), data objects (object), thread-local variables (tls) or, for ELF, untyped symbols (notype).  For example, --hidden '^mylib_' --only-type func hides functions without hiding data symbols that executables may refer to through copy relocations.  ELF symbols have the type of their st_type, and defined Mach-O symbols are functions if their section contains instructions, thread-local variables if it is a thread-local section, and data objects otherwise.  Section and file symbols, undefined Mach-O symbols and the symbols of other formats have no type, and never match.  When given before any rule, applies to all rules.",
+                ),
+        )
+        .arg(
+            Arg::with_name("only-defined")
+                .long("only-defined")
+                .multiple(true)
+                .help("Applies the preceding rule only to defined symbols")
+                .long_help(
+                    "Applies the preceding rule only to symbols defined in the object, such as --hidden '^mylib_' --only-defined to hide definitions without touching undefined references to them.  Common symbols count as defined.  Mach-O debugging entries and the symbols of other formats never match.  When given before any rule, applies to all rules.",
+                ),
+        )
+        .arg(
+            Arg::with_name("only-undefined")
+                .long("only-undefined")
+                .multiple(true)
+                .help("Applies the preceding rule only to undefined symbols")
+                .long_help(
+                    "Applies the preceding rule only to undefined symbols, the references to symbols defined elsewhere, such as --rename malloc xmalloc --only-undefined to redirect an object's calls to malloc without renaming a malloc it defines.  Mach-O debugging entries and the symbols of other formats never match.  When given before any rule, applies to all rules.",
+                ),
+        )
+        .arg(
+            Arg::with_name("arch")
+                .long("arch")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("ARCH")
+                .help("Transforms only Mach-O objects and fat binary slices for ARCH")
+                .long_help(
+                    "Transforms only Mach-O objects and fat binary slices for the architecture ARCH, such as arm64 or x86_64.  Mach-O objects for other architectures, including the other slices of fat binaries, are copied through untouched.  May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("auto-underscore")
+                .long("auto-underscore")
+                .help("Matches Mach-O symbols without their leading underscore")
+                .long_help(
+                    "Matches Mach-O symbols without their leading underscore, so the same rules apply to ELF and Mach-O objects.  Renamed Mach-O symbols keep the leading underscore.",
+                ),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("no-config")
+                .help("Reads default options from FILE")
+                .long_help(
+                    "Reads default options from FILE instead of the .symtool.toml file found by searching upward from the directory of INPUT, then from the current directory.",
+                ),
+        )
+        .arg(
+            Arg::with_name("no-config")
+                .long("no-config")
+                .help("Doesn't read default options from a .symtool.toml file"),
+        )
+        .arg(
+            // Inserted after the options of the configuration file, to scope its rule modifiers
+            Arg::with_name("end-of-config")
+                .long("end-of-config")
+                .hidden(true),
+        )
+        .arg(
+            Arg::with_name("debug-file")
+                .long("debug-file")
+                .number_of_values(2)
+                .value_names(&["DEBUG-INPUT", "DEBUG-OUTPUT"])
+                .help("Applies the same changes to the separate debug file DEBUG-INPUT")
+                .long_help("Applies the same changes to the separate debug file DEBUG-INPUT, writing it to DEBUG-OUTPUT.  The build IDs of INPUT and DEBUG-INPUT must match, and the CRC in the .gnu_debuglink section of OUTPUT is updated to match DEBUG-OUTPUT.  DEBUG-INPUT may also be a .dSYM bundle, in which case its DWARF file is transformed and DEBUG-OUTPUT is written as a bundle.  The LC_UUIDs of INPUT and the DWARF file must match, if both have them.")
+        )
+        .arg(
+            Arg::with_name("dwo")
+                .long("dwo")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(2)
+                .value_names(&["DWO-INPUT", "DWO-OUTPUT"])
+                .help("Renames strings in the split DWARF file DWO-INPUT matching renamed symbols")
+                .long_help("Renames strings in the .debug_str.dwo section of the split DWARF file DWO-INPUT, a .dwo file or a .dwp package, matching symbols renamed in INPUT, writing it to DWO-OUTPUT.  May be given more than once.")
+        )
+        .arg(
+            Arg::with_name("wildcard")
+                .long("wildcard")
+                .short("w")
+                .help("Matches rule patterns as shell-style wildcards instead of regexes")
+                .long_help(
+                    "Matches the patterns of --default, --hidden, --set, --localize-regex, --strip-regex and --prefix-regex as shell-style wildcards, like objcopy --wildcard.  Wildcards match the whole symbol name: '*' matches any string, '?' any character, and '[...]' any character in the set, which is negated by a leading '!'.  A backslash matches the following character literally.",
+                ),
+        )
+        .arg(
+            Arg::with_name("match")
+                .long("match")
+                .takes_value(true)
+                .value_name("MODE")
+                .possible_values(&["exact", "substring", "anchored"])
+                .help("Sets how much of a symbol name rule patterns must match")
+                .long_help(
+                    "Sets how much of a symbol name the patterns of the rules affected by --wildcard must match.  With 'exact', patterns are literal symbol names.  With 'substring', the default for regexes, patterns may match any part of the name.  With 'anchored', the default for wildcards, patterns must match the whole name, as if enclosed in ^...$.",
+                ),
+        )
+        .arg(
+            Arg::with_name("demangle")
+                .long("demangle")
+                .help("Matches rule patterns against demangled C++ and Rust names")
+                .long_help(
+                    "Matches the patterns of the rules affected by --wildcard against the demangled names of mangled C++ and Rust symbols, and against other names as they are, such as --hidden 'mynamespace::internal::.*'.  The mangled symbols are changed.  Unlike the 'demangled:' prefix, patterns still match unmangled names.",
+                ),
+        )
+        .arg(
+            Arg::with_name("allow-undefined-visibility")
+                .long("allow-undefined-visibility")
+                .help("Allows visibility rules to hide undefined symbols")
+                .long_help(
+                    "Allows visibility rules to hide undefined symbols.  By default, undefined symbols matching a hidden visibility rule are left unchanged, since hiding an import changes how the dynamic linker resolves it.",
+                ),
+        )
+        .arg(
+            Arg::with_name("exclude-symbols")
+                .long("exclude-symbols")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("FILE")
+                .help("Prevents every rule from changing the symbols listed in FILE")
+                .long_help(
+                    "Prevents every rule from changing the symbols listed in FILE, which takes precedence over all other options.  Each line of FILE is an exact symbol name, or a pattern prefixed with 'regex:' or 'demangled:'.  Blank lines and lines starting with '#' are ignored.",
+                ),
+        )
+        .arg(
+            Arg::with_name("no-lock")
+                .long("no-lock")
+                .help("Doesn't lock OUTPUT while writing it")
+                .long_help(
+                    "Doesn't lock OUTPUT.  By default, symtool locks each output for the whole run, creating it empty until the run finishes if it doesn't exist, so parallel runs writing the same output (or modifying a file in place) are serialized.  Outputs are written to temporary files first, then existing outputs are rewritten in place, so symbolic and hard links to them see the new contents.  Use this option on filesystems where locking misbehaves.",
+                ),
+        )
+        .arg(
+            Arg::with_name("rebind-import")
+                .long("rebind-import")
+                .takes_value(true)
+                .value_name("SYMBOL=LIBNAME")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Binds the Mach-O import SYMBOL from the dylib LIBNAME")
+                .long_help(
+                    "Binds the undefined symbol SYMBOL of a linked Mach-O binary from LIBNAME, which must be one of the dylibs it loads, given by install name, file name, or file name without extensions (such as libz for /usr/lib/libz.1.dylib).  The library ordinal is updated in the symbol table and in the bind information or chained fixups.  May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("reconcile-dynsym")
+                .long("reconcile-dynsym")
+                .help("Updates .dynsym to match the binding and visibility of .symtab")
+                .long_help(
+                    "Updates the binding and visibility of .dynsym entries to match the .symtab entries with the same name.  Symbols can't be made local in .dynsym, since local symbols must precede the global ones covered by its hash tables.  Without this option, symtool warns about symbols that differ between the tables after patching.",
+                ),
+        )
+        .arg(
+            Arg::with_name("skip-unknown-members")
+                .long("skip-unknown-members")
+                .takes_value(true)
+                .value_name("POLICY")
+                .possible_values(&["copy", "warn", "error"])
+                .help("Copies archive members that aren't supported objects, with or without a warning, or fails")
+                .long_help(
+                    "Sets the policy for archive members that aren't supported objects, such as the LLVM bitcode members of archives built with -flto: copy them through unmodified (copy), do so with a warning (warn, the default), or fail (error).",
+                ),
+        )
+        .arg(
+            Arg::with_name("strict-members")
+                .long("strict-members")
+                .help("Fails on archive members that aren't supported objects")
+                .long_help(
+                    "Fails on archive members that aren't supported objects.  By default, such members (for example linker scripts, metadata, or empty files) are copied through unmodified with a warning.  Equivalent to --skip-unknown-members error.",
+                ),
+        )
+        .arg(
+            Arg::with_name("strip-all")
+                .long("strip-all")
+                .conflicts_with("rewrite")
+                .help("Removes the static symbol table of linked binaries")
+                .long_help(
+                    "Removes the static symbol table of linked binaries, keeping the dynamic symbols.  On ELF, .symtab, its string table and .symtab_shndx are replaced with empty SHT_NULL sections, since sections can't be removed in place.  On Mach-O, all entries are removed from the symbol table except undefined symbols, symbols referred to by the indirect symbol table or relocations, and the exported symbols of dylibs and bundles.  Relocatable objects need their symbol table to be linked, and are rejected.",
+                ),
+        )
+        .arg(
+            Arg::with_name("strip-unneeded")
+                .long("strip-unneeded")
+                .help("Removes the symbols which aren't needed to link the object")
+                .long_help(
+                    "Removes the symbols which aren't needed to link the object, in addition to the debugging information removed by --strip-debug.  Local and undefined symbols are removed unless relocations, section groups or the indirect symbol table refer to them; ELF section symbols are kept.  The remaining symbols are renumbered in relocations.",
+                ),
+        )
+        .arg(
+            Arg::with_name("discard-all")
+                .long("discard-all")
+                .conflicts_with("strip-all")
+                .help("Removes the local symbols, keeping the global ones")
+                .long_help(
+                    "Removes the local symbols from the symbol table, keeping the global and undefined ones, like strip -x.  Local symbols are kept if relocations, section groups or the indirect symbol table refer to them, and ELF section symbols are kept.  On Mach-O, symbols without N_EXT and debugging (stab) entries are removed, and private externs are kept.  The remaining symbols are renumbered in relocations.",
+                ),
+        )
+        .arg(
+            Arg::with_name("strip-comment")
+                .long("strip-comment")
+                .help("Strips the toolchain identification from the .comment section")
+                .long_help(
+                    "Strips the toolchain identification from the .comment section.  On Mach-O, clears the tool versions recorded in LC_BUILD_VERSION instead.",
+                ),
+        )
+        .arg(
+            Arg::with_name("strip-debug")
+                .long("strip-debug")
+                .help("Removes debugging information, keeping the symbols needed for linking")
+                .long_help(
+                    "Removes debugging information, keeping the symbols needed for linking.  On ELF, the DWARF .debug_* sections, .stab sections and their relocation sections are replaced with empty SHT_NULL sections, and the symbols defined in them are removed from .symtab.  On Mach-O, the sections of the __DWARF segment are emptied and the debugging (stab) entries are removed from the symbol table, as with --strip-stabs.",
+                ),
+        )
+        .arg(
+            Arg::with_name("strip-file-symbols")
+                .long("strip-file-symbols")
+                .help("Removes the symbols naming source files")
+                .long_help(
+                    "Removes the symbols naming the source and object files a binary was built from, which often carry absolute build paths, so binaries built in different directories are identical.  On ELF, the file symbols (STT_FILE) are removed from .symtab.  On Mach-O, the N_SO, N_SOL and N_OSO debugging entries are removed, so dsymutil can no longer find the debug info of the linked objects.  The names of removed symbols are left in the string table unless --compact-strtab is given.",
+                ),
+        )
+        .arg(
+            Arg::with_name("strip-stabs")
+                .long("strip-stabs")
+                .help("Removes the debugging (stab) entries from Mach-O symbol tables")
+                .long_help(
+                    "Removes the debugging (stab) entries, such as the debug map written by ld, from Mach-O symbol tables.  The remaining entries are moved to the start of the table, and references to them from LC_DYSYMTAB, the indirect symbol table and relocations are renumbered.  The names of removed entries are left in the string table.",
+                ),
+        )
+        .arg(
+            Arg::with_name("strip-versions")
+                .long("strip-versions")
+                .help("Removes the symbol versioning of ELF binaries")
+                .long_help(
+                    "Removes the symbol versioning of ELF binaries, such as for a dynamic linker without version support.  The .gnu.version, .gnu.version_d and .gnu.version_r sections are replaced with empty SHT_NULL sections, clearing the version indices, and their entries are removed from the dynamic section.  Unversioned references bind to the default version of a symbol.",
+                ),
+        )
+        .arg(
+            Arg::with_name("set-symbol-section")
+                .long("set-symbol-section")
+                .takes_value(true)
+                .value_name("NAME=SECTION")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Moves the symbol NAME to SECTION, or makes it absolute or common if SECTION is ABS or COMMON")
+                .long_help(
+                    "Moves the defined symbol NAME to the section SECTION, or makes it absolute (SHN_ABS or N_ABS) if SECTION is ABS.  If SECTION is COMMON, the global symbol of an ELF relocatable object becomes a common symbol (SHN_COMMON), aligned like the section it was defined in.  Mach-O sections are given as SEGNAME,SECTNAME, or SECTNAME if it is unique.  The section must exist, and the symbol's value is unchanged.  May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("add-symbol")
+                .long("add-symbol")
+                .takes_value(true)
+                .value_name("NAME=[SECTION:]VALUE[,FLAGS]")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Adds the symbol NAME to the ELF symbol table, like objcopy --add-symbol")
+                .long_help(
+                    "Adds the symbol NAME with value VALUE to the ELF symbol table, defined in SECTION, or absolute if no SECTION is given.  FLAGS is a comma-separated list of local, global (the default) or weak, and function, indirect-function, object or file, like objcopy --add-symbol.  The string table is rebuilt like with --rewrite, and the symbol table is moved to the end of the file.  May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("alias")
+                .long("alias")
+                .number_of_values(2)
+                .multiple(true)
+                .value_names(&["EXISTING", "NEW"])
+                .help("Adds the symbol NEW to the ELF symbol table as an alias of EXISTING")
+                .long_help(
+                    "Adds the symbol NEW to the ELF symbol table with the same value, size, section, binding, type and visibility as the defined symbol EXISTING, so both names refer to the same definition, such as while migrating an API to new names.  The symbol table is moved like with --add-symbol.  May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("set-symbol-value")
+                .long("set-symbol-value")
+                .takes_value(true)
+                .value_name("NAME=VALUE")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Sets the value of the defined symbol NAME to VALUE")
+                .long_help(
+                    "Sets the value (st_value or n_value) of the defined symbol NAME to VALUE in place, such as for absolute symbols holding configuration constants.  VALUE is decimal, or hexadecimal if prefixed with 0x.  The symbol's section is unchanged.  May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("set-symbol-version")
+                .long("set-symbol-version")
+                .takes_value(true)
+                .value_name("NAME@@VERSION")
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with("strip-versions")
+                .help("Moves the dynamic symbol NAME to VERSION")
+                .long_help(
+                    "Moves the dynamic symbol NAME defined by an ELF shared object to VERSION, which becomes the default version of NAME, or a hidden version if given as NAME@VERSION.  Without --rewrite, only versions already defined in .gnu.version_d can be assigned.  With --rewrite, versions the object doesn't define are added to .gnu.version_d, which is created along with .gnu.version for an unversioned library, so a legacy library can be given versions such as LIBFOO_1.0 without linking it again; the version names are added to .dynstr, which is moved to a new loadable segment with .dynamic and the new sections.  May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("undefine-symbol")
+                .long("undefine-symbol")
+                .takes_value(true)
+                .value_name("NAME")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Turns the defined symbol NAME into an undefined reference")
+                .long_help(
+                    "Turns the global symbol NAME defined in a relocatable object into an undefined reference (SHN_UNDEF or N_UNDF), so the link must resolve it against another definition.  The symbol's value and size are cleared, and its code or data is left in place, so references resolved to the section at compile time still reach the old definition.  May be given more than once.",
+                ),
+        )
+        .arg(
+            Arg::with_name("set-comment")
+                .long("set-comment")
+                .takes_value(true)
+                .value_name("STRING")
+                .conflicts_with("strip-comment")
+                .help("Replaces the contents of the .comment section with STRING")
+                .long_help(
+                    "Replaces the contents of the .comment section with STRING.  Since the section is patched in place, STRING must fit in the existing section.",
+                ),
+        )
+        .arg(
+            Arg::with_name("define-common")
+                .long("define-common")
+                .help("Allocates common symbols in .bss")
+                .long_help(
+                    "Allocates common symbols in the .bss section of relocatable ELF objects, like ld -d, growing the section and turning the symbols into definitions.  Objects without a .bss section get one, appended to a copy of the section header table at the end of the file.",
+                ),
+        )
+        .arg(
+            Arg::with_name("extract-symbol")
+                .long("extract-symbol")
+                .help("Clears all section contents, keeping only the symbols")
+                .long_help(
+                    "Clears all section contents, keeping only the symbols, like objcopy --extract-symbol.  Section sizes are preserved.  On ELF, cleared sections are changed to SHT_NOBITS.",
+                ),
+        )
+        .arg(
+            Arg::with_name("write-version-script")
+                .long("write-version-script")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Writes the exported symbols of OUTPUT as a GNU ld version script")
+                .long_help(
+                    "Writes the symbols exported by OUTPUT after applying the rules to FILE, as a GNU ld version script (for --version-script) which exports them and hides all other symbols.",
+                ),
+        )
+        .arg(
+            Arg::with_name("write-exported-symbols-list")
+                .long("write-exported-symbols-list")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Writes the exported symbols of OUTPUT as an Apple ld symbol list")
+                .long_help(
+                    "Writes the symbols exported by OUTPUT after applying the rules to FILE, as a symbol list for the -exported_symbols_list option of Apple ld.",
+                ),
+        )
+        .arg(
+            Arg::with_name("write-response-file")
+                .long("write-response-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Writes the exported symbols of OUTPUT as compiler driver flags")
+                .long_help(
+                    "Writes the symbols exported by OUTPUT after applying the rules to FILE, as a response file of -Wl flags for the compiler driver (such as cc @FILE).  For Mach-O objects, each symbol is passed with -exported_symbol, which hides all other symbols.  For ELF objects, the version script written with --write-version-script is passed with --version-script, since GNU ld has no option exporting a single symbol from a shared library.",
                 ),
         )
         .arg(
@@ -58,183 +835,332 @@ fn main() {
                 .required(true)
                 .index(2),
         )
-        .get_matches();
+        .subcommand(
+            SubCommand::with_name("dynamic")
+                .about("Displays the entries of the ELF dynamic section")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints entries as JSON"),
+                )
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("Path to ELF object")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("exports")
+                .about("Displays the symbols exported to the dynamic linker")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints exports as JSON"),
+                )
+                .arg(
+                    Arg::with_name("null")
+                        .short("0")
+                        .long("null")
+                        .conflicts_with("json")
+                        .help("Terminates each export with a NUL character instead of a newline"),
+                )
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("Path to ELF, Mach-O or PE binary")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Checks for symbols that differ between .symtab and .dynsym")
+                .arg(
+                    Arg::with_name("output-style")
+                        .long("output-style")
+                        .value_name("STYLE")
+                        .takes_value(true)
+                        .possible_values(&["plain", "github"])
+                        .default_value("plain")
+                        .help("Prints differences as plain text, or as GitHub Actions annotations"),
+                )
+                .arg(
+                    Arg::with_name("debug-dir")
+                        .long("debug-dir")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .default_value(debug::DEFAULT_DEBUG_DIR)
+                        .help("Searches DIR for the debug files of stripped binaries"),
+                )
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("Path to ELF object")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("index")
+                .about("Stores the symbols of every object in a directory tree in a SQLite database")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("DATABASE")
+                        .help("Path to output database")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("verbose")
+                        .long("verbose")
+                        .short("v")
+                        .help("Prints the objects being indexed"),
+                )
+                .arg(
+                    Arg::with_name("DIR")
+                        .help("Path to directory to index")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("query")
+                .about("Prints the symbols in a database written by the index subcommand that match a query")
+                .arg(
+                    Arg::with_name("database")
+                        .short("d")
+                        .long("database")
+                        .value_name("DATABASE")
+                        .help("Path to database")
+                        .takes_value(true)
+                        .default_value("symbols.db"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints symbols as JSON"),
+                )
+                .arg(
+                    Arg::with_name("null")
+                        .short("0")
+                        .long("null")
+                        .conflicts_with("json")
+                        .help("Terminates each symbol with a NUL character instead of a newline"),
+                )
+                .arg(
+                    Arg::with_name("QUERY")
+                        .help("Query, such as 'exported AND name ~ \"^foo_\"'")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("Reports the exported symbols of the shared libraries in a directory tree")
+                .arg(
+                    Arg::with_name("largest")
+                        .long("largest")
+                        .value_name("COUNT")
+                        .help("Number of largest exports to list for each library")
+                        .takes_value(true)
+                        .default_value("5"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints the report as JSON"),
+                )
+                .arg(
+                    Arg::with_name("DIR")
+                        .help("Path to directory to report on")
+                        .required(true)
+                        .index(1),
+                ),
+        );
+    #[cfg(feature = "smoke-test")]
+    let app = app.arg(smoke::arg());
+    app
+}
+
+fn main() {
+    let mut matches = app().get_matches();
+    if matches.subcommand_name().is_none() {
+        matches = config::merge(app(), matches).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(-1)
+        });
+    }
 
-    run(&matches).unwrap_or_else(|e| {
+    let result = match matches.subcommand() {
+        ("check", Some(matches)) => check::run(matches),
+        ("index", Some(matches)) => index::run(matches),
+        ("query", Some(matches)) => query::run(matches),
+        ("report", Some(matches)) => report::run(matches),
+        ("dynamic", Some(matches)) => dynamic::run(matches),
+        ("exports", Some(matches)) => exports::run(matches),
+        _ => run(&matches),
+    };
+    result.unwrap_or_else(|e| {
         eprintln!("error: {}", e);
-        std::process::exit(-1)
+        std::process::exit(exit_status(e.as_ref()))
     });
 }
 
-fn make_sym_hidden(s: &Sym, name: &str, verbose: bool) -> Sym {
-    if verbose {
-        println!("Set visibility hidden: {}", name);
+/// Parse the ELF object INPUT of a subcommand.
+pub(crate) fn parse_elf<'a>(path: &str, bytes: &'a [u8]) -> Result<goblin::elf::Elf<'a>, Error> {
+    if bytes.starts_with(goblin::elf::header::ELFMAG) {
+        return Ok(backend::elf::parse(bytes)?);
     }
-    Sym {
-        st_other: (s.st_other & 0xfc) | STV_HIDDEN,
-        ..s.clone()
+    match (
+        goblin::Object::parse(bytes),
+        backend::detect::describe(bytes),
+    ) {
+        (_, Some(description)) => Err(Error::Path(
+            path.to_string(),
+            Box::new(backend::error::Error::UnsupportedObject(description)),
+        )),
+        (Ok(_), None) => Err(Error::Message(format!("{} is not an ELF object", path))),
+        (Err(e), None) => Err(backend::error::Error::from(e).into()),
     }
 }
 
-fn make_sym_default(s: &Sym, name: &str, verbose: bool) -> Sym {
-    if verbose {
-        println!("Set visibility default: {}", name);
-    }
-    Sym {
-        st_other: (s.st_other & 0xfc) | STV_DEFAULT,
-        ..s.clone()
-    }
-}
+/// The exit status when an input is not a supported object.
+const EXIT_UNSUPPORTED_OBJECT: i32 = 2;
 
-fn change_sym_vis(
-    sym: &Sym,
-    name: &str,
-    verbose: bool,
-    hidden_regex: &Option<RegexSet>,
-    default_regex: &Option<RegexSet>,
-) -> Option<Sym> {
-    if (sym.st_bind() != STB_GLOBAL && sym.st_bind() != STB_WEAK) || sym.st_type() == STT_NOTYPE {
-        return None;
-    }
-    if default_regex.is_some() && default_regex.as_ref().unwrap().is_match(name) {
-        Some(make_sym_default(sym, name, verbose))
-    } else if hidden_regex.is_some() && hidden_regex.as_ref().unwrap().is_match(name) {
-        Some(make_sym_hidden(sym, name, verbose))
-    } else {
-        None
+/// The exit status for an error, identifying unsupported objects and interrupted runs.
+fn exit_status(mut error: &(dyn std::error::Error + 'static)) -> i32 {
+    loop {
+        match error.downcast_ref::<backend::error::Error>() {
+            Some(backend::error::Error::UnknownObject)
+            | Some(backend::error::Error::UnsupportedObject(_))
+            | Some(backend::error::Error::FatBinaryUnsupported) => return EXIT_UNSUPPORTED_OBJECT,
+            Some(backend::error::Error::Cancelled) => return cancel::EXIT_INTERRUPTED,
+            Some(backend::error::Error::Member(..))
+            | Some(backend::error::Error::Slice(..))
+            | None => {}
+            Some(_) => return -1,
+        }
+        match error.source() {
+            Some(source) => error = source,
+            None => return -1,
+        }
     }
 }
 
-fn make_nlist_hidden(s: &Nlist, name: &str, verbose: bool) -> Option<Nlist> {
-    if s.n_type & N_STAB != 0u8 {
-        None
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let verbose = matches.is_present("verbose");
+    let transform = Transform::from_matches(matches)?;
+
+    let token = cancel::install();
+    let mut formats = backend::object::Formats::default();
+    formats.cancel_with(token.clone());
+    let policy = if matches.is_present("strict-members") {
+        "error"
     } else {
-        if verbose {
-            println!("Set visibility hidden: {}", name);
+        matches.value_of("skip-unknown-members").unwrap_or("warn")
+    };
+    match policy {
+        "copy" => {
+            formats.skip_unrecognized_members(|_, _| {});
         }
-        Some(Nlist {
-            n_type: s.n_type | N_PEXT,
-            ..s.clone()
-        })
+        "warn" => {
+            formats.skip_unrecognized_members(|member, e| {
+                eprintln!("warning: skipping archive member {}: {}", member, e)
+            });
+        }
+        _ => {}
     }
-}
 
-fn make_nlist_default(s: &Nlist, name: &str, verbose: bool) -> Option<Nlist> {
-    if s.n_type & N_STAB != 0u8 {
-        None
-    } else {
-        if verbose {
-            println!("Set visibility default: {}", name);
+    // Lock outputs before reading inputs, so runs that modify a file in place are serialized
+    let debug_paths = matches
+        .values_of("debug-file")
+        .map(|mut paths| debug::DebugPaths::new(paths.next().unwrap(), paths.next().unwrap()))
+        .transpose()?;
+    let mut outputs = cancel::Outputs::default();
+    if !matches.is_present("no-lock") {
+        outputs.lock(matches.value_of("OUTPUT").unwrap(), verbose)?;
+        if let Some(debug_paths) = &debug_paths {
+            outputs.lock(&debug_paths.output, verbose)?;
+        }
+        for dwo_output in matches
+            .values_of("dwo")
+            .into_iter()
+            .flatten()
+            .skip(1)
+            .step_by(2)
+        {
+            outputs.lock(dwo_output, verbose)?;
         }
-        Some(Nlist {
-            n_type: s.n_type & !N_PEXT,
-            ..s.clone()
-        })
     }
-}
 
-fn change_nlist_vis(
-    nlist: &Nlist,
-    name: &str,
-    verbose: bool,
-    hidden_regex: &Option<RegexSet>,
-    default_regex: &Option<RegexSet>,
-) -> Option<Nlist> {
-    if !nlist.is_global() {
-        return None;
-    }
-    if default_regex.is_some() && default_regex.as_ref().unwrap().is_match(name) {
-        make_nlist_default(nlist, name, verbose)
-    } else if hidden_regex.is_some() && hidden_regex.as_ref().unwrap().is_match(name) {
-        make_nlist_hidden(nlist, name, verbose)
-    } else {
-        None
+    let input_path = matches.value_of("INPUT").unwrap();
+    let input = outputs.read(input_path)?;
+    transform.set_path(input_path);
+    let mut output = Vec::new();
+    formats
+        .transform(&mut Cursor::new(&input), &mut output, |context| {
+            transform.apply(context)
+        })
+        .map_err(|e| Error::Path(input_path.to_string(), Box::new(e)))?;
+    let missing = transform.check_missing(input_path)?;
+
+    if let Some(debug_paths) = &debug_paths {
+        let (debug_input_path, debug_output_path) =
+            (debug_paths.input.as_str(), debug_paths.output.as_str());
+        let debug_input = outputs.read(debug_input_path)?;
+        debug::check_build_ids(&input, &debug_input)?;
+        transform.set_path(debug_input_path);
+        let mut debug_output = Vec::new();
+        formats
+            .transform(
+                &mut Cursor::new(&debug_input),
+                &mut debug_output,
+                |context| transform.apply(context),
+            )
+            .map_err(|e| Error::Path(debug_input_path.to_string(), Box::new(e)))?;
+        debug::update_debuglink(&mut output, &debug_output, debug_output_path, verbose)?;
+        debug_paths.stage_bundle(&mut outputs)?;
+        outputs.stage(debug_output_path, &debug_output)?;
     }
-}
 
-pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
-    let verbose = matches.is_present("verbose");
-    let hidden_regex = matches.values_of("hidden").map(RegexSet::new).transpose()?;
-    let default_regex = matches
-        .values_of("default")
-        .map(RegexSet::new)
-        .transpose()?;
-    let mut rename_map = HashMap::new();
-    if let Some(rename) = matches.values_of("rename") {
-        let original = rename.clone().step_by(2);
-        let renamed = rename.skip(1).step_by(2);
-        for (old, new) in original.zip(renamed) {
-            if new.len() > old.len() {
-                return Err(Box::new(Error::Message(format!("Replacement symbol names cannot have more characters than the original name. Symbol '{}' cannot be renamed to '{}'.", old, new))));
-            }
-            rename_map.insert(old.to_string(), new.to_string());
+    if let Some(dwo) = matches.values_of("dwo") {
+        let all_renamed = transform.renamed();
+        let renamed = all_renamed
+            .iter()
+            .map(|(name, new_name)| (name.as_str(), new_name.clone()))
+            .collect();
+        for paths in dwo.collect::<Vec<_>>().chunks(2) {
+            let dwo_input = outputs.read(paths[0])?;
+            let dwo_output = debug::rename_dwo(paths[0], dwo_input, &renamed, verbose)
+                .map_err(|e| Error::Path(paths[0].to_string(), Box::new(e)))?;
+            outputs.stage(paths[1], &dwo_output)?;
         }
     }
 
-    let transform: Box<backend::object::ObjectTransform<crate::error::Error>> =
-        Box::new(move |bytes, object| {
-            let mut patches = Vec::new();
-            match object {
-                backend::object::Object::Elf(elf) => {
-                    if let Some(iter) = backend::elf::SymtabIter::symtab_from_elf(bytes, &elf)? {
-                        for (ref name, ref sym) in
-                            iter.collect::<backend::error::Result<Vec<_>>>()?
-                        {
-                            let (new_name, new_sym) = if let Some(name) = name {
-                                let new_name = rename_map.get(*name.deref());
-                                let new_sym = change_sym_vis(
-                                    sym,
-                                    name,
-                                    verbose,
-                                    &hidden_regex,
-                                    &default_regex,
-                                );
-                                (new_name, new_sym)
-                            } else {
-                                (None, None)
-                            };
-                            if let (Some(name), Some(new_name)) = (name, new_name) {
-                                patches.push(name.patch_with_bytes(new_name.as_bytes())?);
-                            }
-                            if let Some(new_sym) = new_sym {
-                                patches.push(sym.patch_with(new_sym)?);
-                            }
-                        }
-                    }
-                }
-                backend::object::Object::MachO(mach) => {
-                    if let Some(iter) = backend::mach::SymtabIter::from_mach(bytes, &mach) {
-                        for (ref name, ref nlist) in
-                            iter.collect::<backend::error::Result<Vec<_>>>()?
-                        {
-                            let (new_name, new_nlist) = if let Some(name) = name {
-                                let new_name = rename_map.get(*name.deref());
-                                let new_nlist = change_nlist_vis(
-                                    nlist,
-                                    name,
-                                    verbose,
-                                    &hidden_regex,
-                                    &default_regex,
-                                );
-                                (new_name, new_nlist)
-                            } else {
-                                (None, None)
-                            };
-                            if let (Some(name), Some(new_name)) = (name, new_name) {
-                                patches.push(name.patch_with_bytes(new_name.as_bytes())?);
-                            }
-                            if let Some(new_nlist) = new_nlist {
-                                patches.push(nlist.patch_with(new_nlist)?);
-                            }
-                        }
-                    }
-                }
-            }
-            Ok(patches)
-        });
-
-    let mut input = std::fs::File::open(matches.value_of("INPUT").unwrap())?;
-    let mut output = std::fs::File::create(matches.value_of("OUTPUT").unwrap())?;
-    backend::object::transform_object(&mut input, &mut output, &transform)?;
+    let output_path = matches.value_of("OUTPUT").unwrap();
+    let _staged = outputs.stage(output_path, &output)?;
+    #[cfg(feature = "smoke-test")]
+    if matches.value_of("smoke-test") == Some("dlopen") {
+        smoke::dlopen(&_staged, &output)
+            .map_err(|e| Error::Path(output_path.to_string(), Box::new(e)))?;
+    }
+    if let Some(linker_args) = LinkerArgs::from_matches(matches) {
+        linker_args.stage(output_path, &output, &token, &mut outputs, verbose)?;
+    }
+    if let Some(obfuscation) = &transform.obfuscation {
+        obfuscation.stage(&mut outputs, verbose)?;
+    }
+    outputs.commit(&token)?;
+    if !missing.is_empty() {
+        eprintln!(
+            "warning: {} rename(s) not applied, symbol(s) not found in {}: {}",
+            missing.len(),
+            input_path,
+            missing.join(", ")
+        );
+    }
     Ok(())
 }
@@ -0,0 +1,162 @@
+//! Rename symbols to deterministic hashes, keeping a map back to their original names.
+
+use crate::cancel::Outputs;
+use crate::error::Error;
+use clap::ArgMatches;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+/// The FNV-1a offset basis and prime for 64-bit hashes.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Symbols renamed by `--obfuscate` and `--obfuscate-regex`, and the map file they are written to.
+pub struct Obfuscation<'a> {
+    seed: &'a str,
+    map: &'a str,
+
+    /// The obfuscated names of the symbols renamed so far, by original name
+    names: RefCell<BTreeMap<String, String>>,
+
+    /// The original names of the symbols renamed so far, by obfuscated name
+    originals: RefCell<HashMap<String, String>>,
+}
+
+impl<'a> Obfuscation<'a> {
+    pub fn from_matches(matches: &'a ArgMatches) -> Option<Self> {
+        if !matches.is_present("obfuscate") && !matches.is_present("obfuscate-regex") {
+            return None;
+        }
+        Some(Self {
+            seed: matches.value_of("seed").unwrap_or(""),
+            // Required by both options
+            map: matches.value_of("obfuscate-map").unwrap(),
+            names: RefCell::new(BTreeMap::new()),
+            originals: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the obfuscated name of a symbol, recording it in the map.
+    ///
+    /// The name is the FNV-1a hash of the seed and the original name, as `s` followed by 16 hex
+    /// digits.  Names renamed `in_place` are truncated to the length of the original name.  The
+    /// same name always has the same obfuscated name for the same seed, in every object.
+    ///
+    /// Fails if another symbol already has the same obfuscated name, which would make the map
+    /// ambiguous and could make distinct definitions link as one.
+    pub fn name(&self, name: &str, in_place: bool, verbose: bool) -> Result<String, Error> {
+        let mut obfuscated = hash_name(self.seed, name);
+        if in_place {
+            obfuscated.truncate(name.len());
+        }
+        let mut originals = self.originals.borrow_mut();
+        match originals.get(&obfuscated) {
+            Some(original) if original != name => {
+                return Err(Error::Message(format!(
+                    "Symbols '{}' and '{}' would both be obfuscated to '{}'{}",
+                    original,
+                    name,
+                    obfuscated,
+                    if in_place {
+                        ", since names obfuscated in place are truncated to their original length (give --rewrite to use full-length names)"
+                    } else {
+                        ""
+                    }
+                )));
+            }
+            Some(_) => {}
+            None => {
+                originals.insert(obfuscated.clone(), name.to_string());
+            }
+        }
+        if verbose {
+            println!("Obfuscate symbol: {} -> {}", name, obfuscated);
+        }
+        self.names
+            .borrow_mut()
+            .insert(name.to_string(), obfuscated.clone());
+        Ok(obfuscated)
+    }
+
+    /// Stage the map file, listing each obfuscated name and the original name it replaced, sorted
+    /// by obfuscated name.
+    pub fn stage(&self, outputs: &mut Outputs, verbose: bool) -> Result<(), Error> {
+        let names = self.names.borrow();
+        let mut entries: Vec<_> = names
+            .iter()
+            .map(|(name, obfuscated)| (obfuscated, name))
+            .collect();
+        entries.sort();
+        let contents: String = entries
+            .into_iter()
+            .map(|(obfuscated, name)| format!("{} {}\n", obfuscated, name))
+            .collect();
+        if verbose {
+            println!("Write {} obfuscated symbol(s) to {}", names.len(), self.map);
+        }
+        outputs.stage(self.map, contents.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// The full-length obfuscated name of `name`, hashed with `seed`.
+fn hash_name(seed: &str, name: &str) -> String {
+    let mut hash = FNV_OFFSET_BASIS;
+    // The NUL separator keeps seeds from running into names
+    for byte in seed.bytes().chain(Some(0)).chain(name.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    format!("s{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obfuscation(seed: &str) -> Obfuscation<'_> {
+        Obfuscation {
+            seed,
+            map: "map",
+            names: RefCell::new(BTreeMap::new()),
+            originals: RefCell::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn names_are_deterministic() {
+        let first = obfuscation("1.0");
+        let name = first.name("internal_function", true, false).unwrap();
+        assert_eq!(name.len(), "internal_function".len());
+        assert!(name.starts_with('s'));
+        assert_eq!(first.name("internal_function", true, false).unwrap(), name);
+        assert_eq!(
+            obfuscation("1.0")
+                .name("internal_function", true, false)
+                .unwrap(),
+            name
+        );
+        assert_ne!(
+            obfuscation("2.0")
+                .name("internal_function", true, false)
+                .unwrap(),
+            name
+        );
+    }
+
+    #[test]
+    fn names_rewritten_at_full_length() {
+        let obfuscation = obfuscation("");
+        assert_eq!(obfuscation.name("a", false, false).unwrap().len(), 17);
+        assert!(obfuscation.name("b", false, false).is_ok());
+    }
+
+    #[test]
+    fn collisions_rejected() {
+        let obfuscation = obfuscation("");
+        assert_eq!(obfuscation.name("a", true, false).unwrap(), "s");
+        assert_eq!(obfuscation.name("a", true, false).unwrap(), "s");
+        let error = obfuscation.name("b", true, false).unwrap_err();
+        assert!(error.to_string().contains("'a' and 'b'"), "{}", error);
+    }
+}
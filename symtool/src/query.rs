@@ -0,0 +1,461 @@
+//! Query the symbol inventory written by `symtool index`.
+//!
+//! Queries are filter expressions over the symbols in the inventory, such as
+//! `exported AND name ~ "^curl_" AND NOT file ~ "libcurl"`.
+
+use crate::error::Error;
+use crate::index;
+use clap::ArgMatches;
+use regex::Regex;
+use rusqlite::Connection;
+use serde_json::json;
+use std::io::Write;
+
+/// A symbol attribute which can be compared in a query.
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    File,
+    Member,
+    Format,
+    Symtab,
+    Name,
+    Binding,
+    Visibility,
+    Type,
+}
+
+impl Field {
+    fn from_str(name: &str) -> Option<Self> {
+        Some(match name {
+            "file" => Self::File,
+            "member" => Self::Member,
+            "format" => Self::Format,
+            "symtab" => Self::Symtab,
+            "name" => Self::Name,
+            "binding" => Self::Binding,
+            "visibility" => Self::Visibility,
+            "type" => Self::Type,
+            _ => return None,
+        })
+    }
+}
+
+/// A query expression.
+#[derive(Debug)]
+enum Expr {
+    /// The symbol is defined, global or weak, and has default or protected visibility
+    Exported,
+
+    /// The symbol is defined in its object
+    Defined,
+
+    /// The symbol is referenced but not defined in its object
+    Undefined,
+
+    /// The symbol refers to Thumb code
+    Thumb,
+
+    /// The field is equal to a string
+    Equal(Field, String),
+
+    /// The field matches a regular expression
+    Match(Field, Regex),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    String(String),
+    Equal,
+    NotEqual,
+    Match,
+    NotMatch,
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "'{}'", word),
+            Self::String(string) => write!(f, "\"{}\"", string),
+            Self::Equal => write!(f, "'='"),
+            Self::NotEqual => write!(f, "'!='"),
+            Self::Match => write!(f, "'~'"),
+            Self::NotMatch => write!(f, "'!~'"),
+            Self::Open => write!(f, "'('"),
+            Self::Close => write!(f, "')'"),
+        }
+    }
+}
+
+fn invalid(message: impl std::fmt::Display) -> Error {
+    Error::Message(format!("Invalid query: {}", message))
+}
+
+fn tokenize(query: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = query.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' => Token::Equal,
+            '~' => Token::Match,
+            '!' => match chars.next() {
+                Some('=') => Token::NotEqual,
+                Some('~') => Token::NotMatch,
+                _ => return Err(invalid("expected != or !~")),
+            },
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') => string.push(c),
+                            // Keep other escapes, which are meaningful in regular expressions
+                            Some(c) => {
+                                string.push('\\');
+                                string.push(c);
+                            }
+                            None => return Err(invalid("unterminated string")),
+                        },
+                        Some(c) => string.push(c),
+                        None => return Err(invalid("unterminated string")),
+                    }
+                }
+                Token::String(string)
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                Token::Word(word)
+            }
+            c => return Err(invalid(format!("unexpected '{}'", c))),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser for query expressions.
+///
+/// `OR` binds more loosely than `AND`, which binds more loosely than `NOT`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, Error> {
+        if self.keyword("not") {
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.atom()
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, Error> {
+        let word = match self.next() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                return match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(invalid("expected ')'")),
+                };
+            }
+            Some(Token::Word(word)) => word.to_ascii_lowercase(),
+            Some(token) => return Err(invalid(format!("unexpected {}", token))),
+            None => return Err(invalid("unexpected end of query")),
+        };
+        match word.as_str() {
+            "exported" => return Ok(Expr::Exported),
+            "defined" => return Ok(Expr::Defined),
+            "undefined" => return Ok(Expr::Undefined),
+            "thumb" => return Ok(Expr::Thumb),
+            _ => {}
+        }
+        let field =
+            Field::from_str(&word).ok_or_else(|| invalid(format!("unknown field '{}'", word)))?;
+        let operator = self.next();
+        let value = match self.next() {
+            Some(Token::String(value)) | Some(Token::Word(value)) => value,
+            _ => {
+                return Err(invalid(format!(
+                    "expected a value to compare {} with",
+                    word
+                )))
+            }
+        };
+        Ok(match operator {
+            Some(Token::Equal) => Expr::Equal(field, value),
+            Some(Token::NotEqual) => Expr::Not(Box::new(Expr::Equal(field, value))),
+            Some(Token::Match) => Expr::Match(field, Regex::new(&value)?),
+            Some(Token::NotMatch) => Expr::Not(Box::new(Expr::Match(field, Regex::new(&value)?))),
+            _ => return Err(invalid(format!("expected =, !=, ~ or !~ after {}", word))),
+        })
+    }
+}
+
+fn parse(query: &str) -> Result<Expr, Error> {
+    let mut parser = Parser {
+        tokens: tokenize(query)?,
+        position: 0,
+    };
+    let expr = parser.or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(invalid(format!("unexpected {}", token))),
+    }
+}
+
+/// A symbol read from the inventory.
+struct Entry {
+    file: String,
+    member: Option<String>,
+    format: String,
+    symtab: String,
+    name: String,
+    defined: Option<bool>,
+    binding: Option<String>,
+    visibility: Option<String>,
+    kind: Option<String>,
+    thumb: Option<bool>,
+}
+
+impl Entry {
+    fn field(&self, field: Field) -> Option<&str> {
+        match field {
+            Field::File => Some(&self.file),
+            Field::Member => self.member.as_deref(),
+            Field::Format => Some(&self.format),
+            Field::Symtab => Some(&self.symtab),
+            Field::Name => Some(&self.name),
+            Field::Binding => self.binding.as_deref(),
+            Field::Visibility => self.visibility.as_deref(),
+            Field::Type => self.kind.as_deref(),
+        }
+    }
+
+    fn matches(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Exported => index::is_exported(
+                self.defined,
+                self.binding.as_deref(),
+                self.visibility.as_deref(),
+            ),
+            Expr::Defined => self.defined == Some(true),
+            Expr::Undefined => self.defined == Some(false),
+            Expr::Thumb => self.thumb == Some(true),
+            Expr::Equal(field, value) => self.field(*field) == Some(value.as_str()),
+            Expr::Match(field, regex) => self.field(*field).is_some_and(|v| regex.is_match(v)),
+            Expr::Not(expr) => !self.matches(expr),
+            Expr::And(lhs, rhs) => self.matches(lhs) && self.matches(rhs),
+            Expr::Or(lhs, rhs) => self.matches(lhs) || self.matches(rhs),
+        }
+    }
+}
+
+/// Read every symbol from an inventory.
+fn read_entries(bytes: &[u8]) -> Result<Vec<Entry>, Error> {
+    let mut connection = Connection::open_in_memory()?;
+    connection.deserialize_read_exact(rusqlite::MAIN_DB, bytes, bytes.len(), true)?;
+    let mut statement = connection.prepare(
+        "SELECT objects.path, objects.member, objects.format, symbols.symtab, symbols.name, symbols.defined, symbols.binding, symbols.visibility, symbols.type, symbols.thumb FROM symbols JOIN objects ON symbols.object = objects.id ORDER BY symbols.rowid",
+    )?;
+    let entries = statement
+        .query_map([], |row| {
+            Ok(Entry {
+                file: row.get(0)?,
+                member: row.get(1)?,
+                format: row.get(2)?,
+                symtab: row.get(3)?,
+                name: row.get(4)?,
+                defined: row.get(5)?,
+                binding: row.get(6)?,
+                visibility: row.get(7)?,
+                kind: row.get(8)?,
+                thumb: row.get(9)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(entries)
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let expr = parse(matches.value_of("QUERY").unwrap())?;
+    let database = matches.value_of("database").unwrap();
+    let bytes =
+        std::fs::read(database).map_err(|e| Error::Path(database.to_string(), Box::new(e)))?;
+    let entries =
+        read_entries(&bytes).map_err(|e| Error::Path(database.to_string(), Box::new(e)))?;
+    let results = entries.iter().filter(|entry| entry.matches(&expr));
+
+    // A closed pipe, as when piping into `head`, ends the output without an error
+    let stdout = std::io::stdout();
+    match write_results(&mut stdout.lock(), results, matches) {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+        result => result?,
+    }
+    Ok(())
+}
+
+/// Write the symbols matching a query, as JSON or as one line per symbol.
+fn write_results<'a>(
+    out: &mut impl Write,
+    results: impl Iterator<Item = &'a Entry>,
+    matches: &ArgMatches,
+) -> std::io::Result<()> {
+    if matches.is_present("json") {
+        let results: Vec<_> = results
+            .map(|entry| {
+                json!({
+                    "file": entry.file,
+                    "member": entry.member,
+                    "format": entry.format,
+                    "symtab": entry.symtab,
+                    "name": entry.name,
+                    "defined": entry.defined,
+                    "binding": entry.binding,
+                    "visibility": entry.visibility,
+                    "type": entry.kind,
+                    "thumb": entry.thumb,
+                })
+            })
+            .collect();
+        writeln!(out, "{}", serde_json::to_string_pretty(&results)?)?;
+    } else {
+        let terminator = if matches.is_present("null") {
+            '\0'
+        } else {
+            '\n'
+        };
+        for entry in results {
+            match &entry.member {
+                Some(member) => write!(
+                    out,
+                    "{}({}): {}{}",
+                    entry.file, member, entry.name, terminator
+                )?,
+                None => write!(out, "{}: {}{}", entry.file, entry.name, terminator)?,
+            }
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index;
+    use sha2::{Digest, Sha256};
+    use std::path::Path;
+    use symtool_backend::object::CancellationToken;
+    use symtool_backend::testing::{Binding, Format, ObjectBuilder, Symbol};
+
+    fn object() -> Vec<u8> {
+        ObjectBuilder::new()
+            .symbol(Symbol::new("exported_fn"))
+            .symbol(Symbol::new("local_fn").binding(Binding::Local))
+            .symbol(Symbol::new("hidden_fn").hidden())
+            .symbol(Symbol::new("imported").undefined())
+            .build(Format::Elf)
+    }
+
+    fn database(bytes: &[u8]) -> Vec<u8> {
+        let objects = index::index_bytes(Path::new("lib/a.o"), bytes, &CancellationToken::new())
+            .unwrap()
+            .unwrap();
+        index::write_database(&objects, false).unwrap()
+    }
+
+    fn query(entries: &[Entry], query: &str) -> Vec<String> {
+        let expr = parse(query).unwrap();
+        entries
+            .iter()
+            .filter(|entry| entry.matches(&expr))
+            .map(|entry| entry.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn inventory_round_trip() {
+        let entries = read_entries(&database(&object())).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.file == "lib/a.o" && entry.format == "elf"));
+        assert_eq!(query(&entries, "exported"), ["exported_fn"]);
+        assert_eq!(query(&entries, "undefined"), ["imported"]);
+        assert_eq!(
+            query(&entries, "binding = local OR visibility = hidden"),
+            ["local_fn", "hidden_fn"]
+        );
+    }
+
+    #[test]
+    fn objects_record_content_digest() {
+        let bytes = object();
+        let mut connection = Connection::open_in_memory().unwrap();
+        let database = database(&bytes);
+        connection
+            .deserialize_read_exact(rusqlite::MAIN_DB, &database[..], database.len(), true)
+            .unwrap();
+        let digest: String = connection
+            .query_row("SELECT sha256 FROM objects", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(digest, format!("{:x}", Sha256::digest(&bytes)));
+    }
+
+    #[test]
+    fn other_files_rejected() {
+        assert!(read_entries(b"not a database").is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Rebind imports of linked Mach-O binaries to another dylib they load.
+
+use crate::error::Error;
+use clap::ArgMatches;
+use goblin::mach::header::MH_TWOLEVEL;
+use goblin::mach::symbols::Nlist;
+use goblin::mach::MachO;
+use std::collections::HashMap;
+use std::path::Path;
+use symtool_backend as backend;
+use symtool_backend::patch::Patch;
+
+/// The largest ordinal of a dylib in `n_desc`, above which ordinals are special.
+const MAX_LIBRARY_ORDINAL: u16 = 0xfd;
+
+/// Imports to bind from another dylib.
+pub struct Rebinds {
+    /// Symbol names with the dylibs to bind them from
+    rebinds: Vec<(String, String)>,
+}
+
+/// Returns true if the install name `path` of a dylib matches `name`, which is either the full
+/// path, the file name, or the file name up to the first dot, as in `libz` for
+/// `/usr/lib/libz.1.dylib`.
+fn library_matches(path: &str, name: &str) -> bool {
+    let file_name = Path::new(path)
+        .file_name()
+        .and_then(|file_name| file_name.to_str())
+        .unwrap_or(path);
+    path == name || file_name == name || file_name.split('.').next() == Some(name)
+}
+
+impl Rebinds {
+    pub fn from_matches(matches: &ArgMatches) -> Result<Option<Self>, Error> {
+        let values = match matches.values_of("rebind-import") {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        let auto_underscore = matches.is_present("auto-underscore");
+        let mut rebinds = Vec::new();
+        for value in values {
+            let (symbol, library) = match value.split_once('=') {
+                Some((symbol, library)) if !symbol.is_empty() && !library.is_empty() => {
+                    (symbol, library)
+                }
+                _ => {
+                    return Err(Error::Message(format!(
+                        "Invalid value for --rebind-import: '{}' (expected SYMBOL=LIBNAME)",
+                        value
+                    )))
+                }
+            };
+            let symbol = if auto_underscore {
+                format!("_{}", symbol)
+            } else {
+                symbol.to_string()
+            };
+            rebinds.push((symbol, library.to_string()));
+        }
+        Ok(Some(Self { rebinds }))
+    }
+
+    /// Find the ordinal of the dylib each import is rebound to.
+    pub fn resolve(&self, mach: &MachO) -> Result<HashMap<&str, u16>, Error> {
+        if mach.header.flags & MH_TWOLEVEL == 0 {
+            return Err(Error::Message(
+                "--rebind-import requires a linked binary using the two-level namespace"
+                    .to_string(),
+            ));
+        }
+        let mut ordinals = HashMap::new();
+        for (symbol, library) in &self.rebinds {
+            // libs[0] is the binary itself
+            let matching: Vec<_> = mach
+                .libs
+                .iter()
+                .enumerate()
+                .skip(1)
+                .filter(|(_, path)| library_matches(path, library))
+                .collect();
+            let ordinal = match matching.as_slice() {
+                [(ordinal, _)] => *ordinal,
+                [] => {
+                    return Err(Error::Message(format!(
+                        "Can't rebind '{}': the binary doesn't load a dylib named '{}'",
+                        symbol, library
+                    )))
+                }
+                _ => {
+                    let paths: Vec<_> = matching.iter().map(|(_, path)| **path).collect();
+                    return Err(Error::Message(format!(
+                        "Can't rebind '{}': '{}' matches several dylibs: {}",
+                        symbol,
+                        library,
+                        paths.join(", ")
+                    )));
+                }
+            };
+            if ordinal > MAX_LIBRARY_ORDINAL as usize {
+                return Err(Error::Message(format!(
+                    "Can't rebind '{}': the ordinal of '{}' is too large",
+                    symbol, library
+                )));
+            }
+            ordinals.insert(symbol.as_str(), ordinal as u16);
+        }
+        Ok(ordinals)
+    }
+
+    /// Construct patches rebinding imports in the bind information or chained fixups.
+    ///
+    /// Fails if an import isn't bound, or shares its ordinal with other imports.
+    pub fn bind_patches(
+        &self,
+        bytes: &[u8],
+        mach: &MachO,
+        ordinals: &HashMap<&str, u16>,
+        verbose: bool,
+    ) -> Result<Vec<Patch>, Error> {
+        let imports = backend::mach::import_ordinals(bytes, mach)?;
+        let mut patches = Vec::new();
+        for (symbol, _) in &self.rebinds {
+            let ordinal = &ordinals[symbol.as_str()];
+            let mut found = false;
+            for import in imports.iter().filter(|import| import.symbol == symbol) {
+                found = true;
+                if import.ordinal == i32::from(*ordinal) {
+                    continue;
+                }
+                if import.shared {
+                    return Err(Error::Message(format!(
+                        "Can't rebind '{}': its bind information shares a library ordinal with other imports",
+                        symbol
+                    )));
+                }
+                patches.push(import.patch(*ordinal)?);
+            }
+            if !found {
+                return Err(Error::Message(format!(
+                    "Can't rebind '{}': the binary doesn't import it",
+                    symbol
+                )));
+            }
+            if verbose {
+                println!(
+                    "Rebind import: {} -> {}",
+                    symbol, mach.libs[*ordinal as usize]
+                );
+            }
+        }
+        Ok(patches)
+    }
+}
+
+/// Set the library ordinal of an undefined symbol, which is the high byte of `n_desc`.
+pub fn set_nlist_ordinal(nlist: &Nlist, ordinal: u16) -> Nlist {
+    Nlist {
+        n_desc: nlist.n_desc & 0x00ff | ordinal << 8,
+        ..nlist.clone()
+    }
+}
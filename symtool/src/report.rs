@@ -0,0 +1,121 @@
+//! Report the exported symbols of the shared libraries in a directory tree.
+//!
+//! The report lists the number of exported symbols and the largest exports of each library,
+//! and the symbols exported by more than one library, to guide visibility hardening.
+
+use crate::error::Error;
+use crate::index::{IndexedObject, Symbol};
+use clap::ArgMatches;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The exported symbols of a shared library.
+struct Library<'a> {
+    path: &'a str,
+    exports: Vec<&'a Symbol>,
+}
+
+impl<'a> Library<'a> {
+    fn from_object(object: &'a IndexedObject) -> Self {
+        // The export surface of an ELF shared library is .dynsym, which .symtab repeats
+        let exports = object
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.symtab != ".symtab" && symbol.is_exported())
+            .collect();
+        Self {
+            path: &object.path,
+            exports,
+        }
+    }
+
+    /// Returns the `count` largest exports, largest first.
+    fn largest(&self, count: usize) -> Vec<(&'a str, u64)> {
+        let mut sizes: Vec<_> = self
+            .exports
+            .iter()
+            .filter_map(|symbol| Some((symbol.name.as_str(), symbol.size?)))
+            .filter(|(_, size)| *size > 0)
+            .collect();
+        sizes.sort_by(|(a_name, a_size), (b_name, b_size)| {
+            b_size.cmp(a_size).then(a_name.cmp(b_name))
+        });
+        sizes.truncate(count);
+        sizes
+    }
+}
+
+/// Map each symbol exported by more than one library to the libraries exporting it.
+fn overlaps<'a>(libraries: &[Library<'a>]) -> BTreeMap<&'a str, Vec<&'a str>> {
+    let mut exporters: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for library in libraries {
+        for symbol in &library.exports {
+            let paths = exporters.entry(&symbol.name).or_default();
+            if paths.last() != Some(&library.path) {
+                paths.push(library.path);
+            }
+        }
+    }
+    exporters.retain(|_, paths| paths.len() > 1);
+    exporters
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = Path::new(matches.value_of("DIR").unwrap());
+    let value = matches.value_of("largest").unwrap();
+    let largest = value
+        .parse::<usize>()
+        .map_err(|_| Error::Message(format!("Invalid value for --largest: '{}'", value)))?;
+    let token = crate::cancel::install();
+    let objects = crate::index::index_tree(dir, false, &token)?;
+    let libraries: Vec<_> = objects
+        .iter()
+        .filter(|object| object.shared_library)
+        .map(Library::from_object)
+        .collect();
+    let overlaps = overlaps(&libraries);
+
+    if matches.is_present("json") {
+        let libraries: Vec<_> = libraries
+            .iter()
+            .map(|library| {
+                let largest: Vec<_> = library
+                    .largest(largest)
+                    .into_iter()
+                    .map(|(name, size)| json!({ "name": name, "size": size }))
+                    .collect();
+                json!({
+                    "path": library.path,
+                    "exports": library.exports.len(),
+                    "largest": largest,
+                })
+            })
+            .collect();
+        let overlaps: Vec<_> = overlaps
+            .iter()
+            .map(|(name, paths)| json!({ "name": name, "libraries": paths }))
+            .collect();
+        let report = json!({ "libraries": libraries, "overlaps": overlaps });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for library in &libraries {
+            println!(
+                "{}: {} exported symbols",
+                library.path,
+                library.exports.len()
+            );
+            for (name, size) in library.largest(largest) {
+                println!("{:>10} {}", size, name);
+            }
+        }
+        if !overlaps.is_empty() {
+            println!();
+            println!("Exported by multiple libraries:");
+            for (name, paths) in &overlaps {
+                println!("{}: {}", name, paths.join(", "));
+            }
+        }
+    }
+    Ok(())
+}
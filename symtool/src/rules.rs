@@ -0,0 +1,1742 @@
+//! Rules selecting which symbols are modified, and how.
+
+use crate::error::Error;
+use clap::ArgMatches;
+use regex::{Regex, RegexSet};
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use symtool_backend::demangle::demangle;
+use symtool_backend::elf::split_version;
+
+/// A symbol visibility.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Default,
+    Hidden,
+    Protected,
+    Internal,
+}
+
+impl std::str::FromStr for Visibility {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "default" => Ok(Self::Default),
+            "hidden" => Ok(Self::Hidden),
+            "protected" => Ok(Self::Protected),
+            "internal" => Ok(Self::Internal),
+            _ => Err(Error::Message(format!("Unknown visibility: '{}'", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for Visibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::Hidden => write!(f, "hidden"),
+            Self::Protected => write!(f, "protected"),
+            Self::Internal => write!(f, "internal"),
+        }
+    }
+}
+
+/// A symbol binding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Local,
+    Global,
+    Weak,
+}
+
+impl std::str::FromStr for Binding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "local" => Ok(Self::Local),
+            "global" => Ok(Self::Global),
+            "weak" => Ok(Self::Weak),
+            _ => Err(Error::Message(format!("Unknown binding: '{}'", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Local => write!(f, "local"),
+            Self::Global => write!(f, "global"),
+            Self::Weak => write!(f, "weak"),
+        }
+    }
+}
+
+/// An ELF symbol type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SymbolType {
+    Func,
+    Object,
+    NoType,
+    Tls,
+}
+
+impl std::str::FromStr for SymbolType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "func" => Ok(Self::Func),
+            "object" => Ok(Self::Object),
+            "notype" => Ok(Self::NoType),
+            "tls" => Ok(Self::Tls),
+            _ => Err(Error::Message(format!("Unknown symbol type: '{}'", s))),
+        }
+    }
+}
+
+impl std::fmt::Display for SymbolType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Func => write!(f, "func"),
+            Self::Object => write!(f, "object"),
+            Self::NoType => write!(f, "notype"),
+            Self::Tls => write!(f, "tls"),
+        }
+    }
+}
+
+/// What rules restricted with `--only-type`, `--only-defined` or `--only-undefined` know about a
+/// symbol.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SymbolKind {
+    /// The type of the symbol, if known
+    pub symbol_type: Option<SymbolType>,
+
+    /// Whether the symbol is defined, if known
+    pub defined: Option<bool>,
+}
+
+/// An object format, which rules can be restricted to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Elf,
+    MachO,
+}
+
+impl Format {
+    /// The name of the format in messages.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Elf => "ELF",
+            Self::MachO => "Mach-O",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "elf" => Ok(Self::Elf),
+            "mach-o" => Ok(Self::MachO),
+            _ => Err(Error::Message(format!("Unknown format: '{}'", s))),
+        }
+    }
+}
+
+/// Symbol attributes set by a rule.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Attributes {
+    pub visibility: Option<Visibility>,
+    pub binding: Option<Binding>,
+    pub symbol_type: Option<SymbolType>,
+
+    /// Whether undefined references are made weak, so they may remain unresolved
+    pub weak_reference: bool,
+}
+
+impl Attributes {
+    /// Combine with the attributes of a later matching rule.
+    ///
+    /// Default visibility takes precedence over the other visibilities, otherwise later rules take
+    /// precedence.
+    fn merge(&mut self, other: &Self) {
+        if self.visibility != Some(Visibility::Default) {
+            self.visibility = other.visibility.or(self.visibility);
+        }
+        self.binding = other.binding.or(self.binding);
+        self.symbol_type = other.symbol_type.or(self.symbol_type);
+        self.weak_reference |= other.weak_reference;
+    }
+}
+
+impl std::str::FromStr for Attributes {
+    type Err = Error;
+
+    /// Parse a list of attributes, such as `visibility=hidden,binding=local,type=func`.
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut attributes = Self::default();
+        for assignment in s.split(',') {
+            let (attribute, value) = assignment
+                .split_once('=')
+                .map(|(attribute, value)| (attribute.trim(), value.trim()))
+                .ok_or_else(|| {
+                    Error::Message(format!("Expected ATTR=VALUE, found '{}'", assignment))
+                })?;
+            match attribute {
+                "visibility" => attributes.visibility = Some(value.parse()?),
+                "binding" => attributes.binding = Some(value.parse()?),
+                "type" => attributes.symbol_type = Some(value.parse()?),
+                _ => {
+                    return Err(Error::Message(format!(
+                        "Unknown attribute: '{}'",
+                        attribute
+                    )))
+                }
+            }
+        }
+        Ok(attributes)
+    }
+}
+
+/// A string prepended to symbol names by a rule.
+pub struct Prefix {
+    pub prefix: String,
+
+    /// Whether only undefined symbols are prefixed
+    pub undefined_only: bool,
+}
+
+/// A pattern matched against symbol names.
+#[derive(Clone)]
+pub enum Pattern {
+    /// Matches a symbol with exactly this name
+    Exact(String),
+
+    /// Matches any symbol name containing a match of the regex
+    Regex(Regex),
+
+    /// Matches any mangled symbol name whose demangled form contains a match of the regex
+    Demangled(Regex),
+
+    /// Matches the demangled form of mangled symbol names, and other names as they are, like
+    /// `Regex`
+    Readable(Regex),
+
+    /// Matches mangled symbols whose demangled name, without its parameter list, is the string,
+    /// and other symbols with exactly that name.  The regex is compiled from the string.
+    SourceName(String, Regex),
+}
+
+/// The syntax of the patterns given to rules such as `--hidden`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// Unanchored Perl-style regexes
+    Regex,
+
+    /// Shell-style wildcards, selected by `--wildcard`
+    Wildcard,
+}
+
+/// How much of a symbol name a pattern must match, selected by `--match`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Matching {
+    /// The pattern is the literal name of the symbol
+    Exact,
+
+    /// The pattern matches any part of the name, the default for regexes
+    Substring,
+
+    /// The pattern matches the whole name, the default for wildcards
+    Anchored,
+}
+
+/// How the patterns given to rules such as `--hidden` are parsed.
+#[derive(Copy, Clone, Debug)]
+pub struct PatternOptions {
+    pub syntax: Syntax,
+    pub matching: Matching,
+
+    /// Whether patterns match demangled names, selected by `--demangle`
+    pub demangle: bool,
+}
+
+impl PatternOptions {
+    /// The options selected on the command line.
+    fn from_matches(matches: &ArgMatches) -> Result<Self, Error> {
+        let syntax = match matches.is_present("wildcard") {
+            true => Syntax::Wildcard,
+            false => Syntax::Regex,
+        };
+        let matching = match (matches.value_of("match"), syntax) {
+            (Some(matching), _) => matching.parse()?,
+            (None, Syntax::Regex) => Matching::Substring,
+            (None, Syntax::Wildcard) => Matching::Anchored,
+        };
+        Ok(Self {
+            syntax,
+            matching,
+            demangle: matches.is_present("demangle"),
+        })
+    }
+}
+
+impl std::str::FromStr for Matching {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "exact" => Ok(Self::Exact),
+            "substring" => Ok(Self::Substring),
+            "anchored" => Ok(Self::Anchored),
+            _ => Err(Error::Message(format!("Unknown match mode: '{}'", s))),
+        }
+    }
+}
+
+impl Pattern {
+    /// Parse a regex pattern, which matches demangled names if prefixed with `demangled:`.
+    pub fn regex(pattern: &str) -> Result<Self, Error> {
+        let options = PatternOptions {
+            syntax: Syntax::Regex,
+            matching: Matching::Substring,
+            demangle: false,
+        };
+        Self::parse(pattern, options)
+    }
+
+    /// Parse a pattern as selected by `options`, which matches only demangled names if prefixed
+    /// with `demangled:`.
+    pub fn parse(pattern: &str, options: PatternOptions) -> Result<Self, Error> {
+        let (demangled, pattern) = match pattern.strip_prefix("demangled:") {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        let regex = match (options.syntax, options.matching) {
+            (_, Matching::Exact) if !demangled && !options.demangle => {
+                return Ok(Self::Exact(pattern.to_string()))
+            }
+            (_, Matching::Exact) => format!("^{}$", regex::escape(pattern)),
+            (Syntax::Regex, Matching::Substring) => pattern.to_string(),
+            (Syntax::Regex, Matching::Anchored) => format!("^(?:{})$", pattern),
+            (Syntax::Wildcard, Matching::Substring) => wildcard_regex(pattern)?,
+            (Syntax::Wildcard, Matching::Anchored) => format!("^{}$", wildcard_regex(pattern)?),
+        };
+        let regex = Regex::new(&regex)?;
+        Ok(match (demangled, options.demangle) {
+            (true, _) => Self::Demangled(regex),
+            (false, true) => Self::Readable(regex),
+            (false, false) => Self::Regex(regex),
+        })
+    }
+
+    /// Build a pattern matching a source-level name, as given to `--rename-demangled`.
+    ///
+    /// Mangled symbols match whatever their parameter list, so the name of an overloaded
+    /// function matches all of its overloads.
+    pub fn source_name(name: &str) -> Result<Self, Error> {
+        let regex = Regex::new(&format!(r"^{}(?:\(.*)?$", regex::escape(name)))?;
+        Ok(Self::SourceName(name.to_string(), regex))
+    }
+}
+
+/// Translate a shell-style wildcard pattern into an unanchored regex.
+///
+/// `*` matches any string, `?` any character, and `[...]` any character in the set, which is
+/// negated by a leading `!`.  A backslash matches the following character literally.
+fn wildcard_regex(pattern: &str) -> Result<String, Error> {
+    let mut regex = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                regex.push('[');
+                let mut set = chars.clone();
+                if set.next() == Some('!') {
+                    regex.push('^');
+                    chars = set;
+                }
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('\\') => {
+                            let c = chars.next().unwrap_or('\\');
+                            regex.push_str(&regex::escape(&c.to_string()));
+                        }
+                        Some(c @ ('[' | '&' | '~' | '^')) => {
+                            regex.push('\\');
+                            regex.push(c);
+                        }
+                        Some(c) => regex.push(c),
+                        None => {
+                            return Err(Error::Message(format!(
+                                "Unterminated '[' in wildcard pattern '{}'",
+                                pattern
+                            )))
+                        }
+                    }
+                }
+                regex.push(']');
+            }
+            '\\' => {
+                let c = chars.next().unwrap_or('\\');
+                regex.push_str(&regex::escape(&c.to_string()));
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    Ok(regex)
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Exact(s) | Self::SourceName(s, _) => write!(f, "{}", s),
+            Self::Regex(r) | Self::Readable(r) => write!(f, "{}", r),
+            Self::Demangled(r) => write!(f, "demangled:{}", r),
+        }
+    }
+}
+
+/// A symbol name being matched, which is demangled at most once.
+struct Name<'a> {
+    name: &'a str,
+
+    /// The name without its ELF version suffix, such as `foo` for `foo@@VERS_1`, which is the
+    /// whole name for unversioned names
+    base: &'a str,
+    demangled: OnceCell<Option<String>>,
+}
+
+impl<'a> Name<'a> {
+    fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            base: name,
+            demangled: OnceCell::new(),
+        }
+    }
+
+    /// A name which may have an ELF version suffix, so patterns match it with or without the
+    /// suffix.
+    fn versioned(name: &'a str) -> Self {
+        Self {
+            name,
+            base: split_version(name).0,
+            demangled: OnceCell::new(),
+        }
+    }
+
+    /// The version suffix of the name, including the `@` characters, or an empty string.
+    fn version(&self) -> &'a str {
+        &self.name[self.base.len()..]
+    }
+
+    /// The names matched by patterns: the whole name, and the name without its version suffix.
+    fn candidates(&self) -> impl Iterator<Item = &'a str> {
+        std::iter::once(self.name).chain(Some(self.base).filter(|base| *base != self.name))
+    }
+
+    fn demangled(&self) -> Option<&str> {
+        self.demangled
+            .get_or_init(|| demangle(self.base))
+            .as_deref()
+    }
+}
+
+/// A list of patterns compiled to match a symbol name against all of them at once.
+///
+/// Exact names are looked up in a map, and regexes are combined into a `RegexSet`, so the cost
+/// of a symbol doesn't grow with the number of patterns.
+#[derive(Default)]
+struct PatternSet {
+    /// The indices of the exact patterns with each name
+    exact: HashMap<String, Vec<usize>>,
+    regexes: Option<(RegexSet, Vec<usize>)>,
+    demangled: Option<(RegexSet, Vec<usize>)>,
+    readable: Option<(RegexSet, Vec<usize>)>,
+}
+
+impl PatternSet {
+    fn new<'a>(patterns: impl Iterator<Item = &'a Pattern>) -> Result<Self, Error> {
+        let mut exact: HashMap<String, Vec<usize>> = HashMap::new();
+        let (mut regexes, mut demangled, mut readable) = (Vec::new(), Vec::new(), Vec::new());
+        for (index, pattern) in patterns.enumerate() {
+            match pattern {
+                Pattern::Exact(name) => exact.entry(name.clone()).or_default().push(index),
+                Pattern::Regex(regex) => regexes.push((index, regex.as_str())),
+                Pattern::Demangled(regex) => demangled.push((index, regex.as_str())),
+                Pattern::Readable(regex) | Pattern::SourceName(_, regex) => {
+                    readable.push((index, regex.as_str()))
+                }
+            }
+        }
+        let compile = |patterns: Vec<(usize, &str)>| -> Result<_, Error> {
+            if patterns.is_empty() {
+                return Ok(None);
+            }
+            let set = RegexSet::new(patterns.iter().map(|(_, pattern)| pattern))?;
+            Ok(Some((
+                set,
+                patterns.into_iter().map(|(index, _)| index).collect(),
+            )))
+        };
+        Ok(Self {
+            exact,
+            regexes: compile(regexes)?,
+            demangled: compile(demangled)?,
+            readable: compile(readable)?,
+        })
+    }
+
+    /// Returns the indices of the patterns matching a name, in ascending order.
+    fn matches(&self, name: &Name) -> Vec<usize> {
+        let mut matches = Vec::new();
+        for candidate in name.candidates() {
+            matches.extend(self.exact.get(candidate).into_iter().flatten());
+            if let Some((set, indices)) = &self.regexes {
+                matches.extend(set.matches(candidate).iter().map(|i| indices[i]));
+            }
+        }
+        if let (Some((set, indices)), Some(demangled)) = (&self.demangled, name.demangled()) {
+            matches.extend(set.matches(demangled).iter().map(|i| indices[i]));
+        }
+        if let Some((set, indices)) = &self.readable {
+            match name.demangled() {
+                Some(demangled) => {
+                    matches.extend(set.matches(demangled).iter().map(|i| indices[i]))
+                }
+                None => {
+                    for candidate in name.candidates() {
+                        matches.extend(set.matches(candidate).iter().map(|i| indices[i]));
+                    }
+                }
+            }
+        }
+        matches.sort_unstable();
+        matches.dedup();
+        matches
+    }
+
+    /// Returns true if the set has no patterns.
+    fn is_empty(&self) -> bool {
+        self.exact.is_empty()
+            && self.regexes.is_none()
+            && self.demangled.is_none()
+            && self.readable.is_none()
+    }
+
+    /// Returns true if any pattern matches a name.
+    fn is_match(&self, name: &Name) -> bool {
+        name.candidates().any(|candidate| {
+            self.exact.contains_key(candidate)
+                || self
+                    .regexes
+                    .as_ref()
+                    .is_some_and(|(set, _)| set.is_match(candidate))
+        }) || self.demangled.as_ref().is_some_and(|(set, _)| {
+            name.demangled()
+                .is_some_and(|demangled| set.is_match(demangled))
+        }) || self
+            .readable
+            .as_ref()
+            .is_some_and(|(set, _)| match name.demangled() {
+                Some(demangled) => set.is_match(demangled),
+                None => name.candidates().any(|candidate| set.is_match(candidate)),
+            })
+    }
+}
+
+/// Modifiers restricting a rule, given after the rule or before any rule as the default for
+/// all rules.
+#[derive(Default)]
+pub struct RuleModifiers {
+    pub max_matches: Option<usize>,
+    pub ignore_missing: bool,
+
+    /// Restricts the rule to archive members with identifiers matching the regex
+    pub member: Option<Regex>,
+
+    /// Restricts the rule to objects of a format
+    pub format: Option<Format>,
+
+    /// Restricts the rule to symbols of a type
+    pub symbol_type: Option<SymbolType>,
+
+    /// Restricts the rule to defined (`true`) or undefined (`false`) symbols
+    pub defined: Option<bool>,
+
+    /// Patterns of symbols the rule never applies to, given to `--except`, and their compiled set
+    pub except: Vec<Pattern>,
+    except_set: PatternSet,
+}
+
+impl RuleModifiers {
+    /// Fill in the modifiers not given to the rule from the defaults, and compile the `--except`
+    /// patterns.
+    fn inherit(&mut self, defaults: &Self) -> Result<(), Error> {
+        self.max_matches = self.max_matches.or(defaults.max_matches);
+        self.ignore_missing |= defaults.ignore_missing;
+        self.member = self.member.take().or_else(|| defaults.member.clone());
+        self.format = self.format.or(defaults.format);
+        self.symbol_type = self.symbol_type.or(defaults.symbol_type);
+        self.defined = self.defined.or(defaults.defined);
+        self.except.extend(defaults.except.iter().cloned());
+        self.except_set = PatternSet::new(self.except.iter())?;
+        Ok(())
+    }
+}
+
+/// A rule applying an action to every symbol matching a pattern.
+pub struct Rule<A> {
+    pub pattern: Pattern,
+    pub action: A,
+    pub modifiers: RuleModifiers,
+    matches: Cell<usize>,
+
+    /// The number of symbols matched in the object being transformed, which `--max-matches`
+    /// limits, since each archive member or fat slice defines its own symbols
+    object_matches: Cell<usize>,
+}
+
+impl<A> Rule<A> {
+    fn new(pattern: Pattern, action: A) -> Self {
+        Self {
+            pattern,
+            action,
+            modifiers: RuleModifiers::default(),
+            matches: Cell::new(0),
+            object_matches: Cell::new(0),
+        }
+    }
+
+    /// Count a match against the rule's limit.
+    fn record_match(&self) -> Result<(), Error> {
+        let object_matches = self.object_matches.get() + 1;
+        if let Some(max_matches) = self.modifiers.max_matches {
+            if object_matches > max_matches {
+                return Err(Error::Message(format!(
+                    "Pattern '{}' matched more than {} symbol(s) (limit set by --max-matches).",
+                    self.pattern, max_matches
+                )));
+            }
+        }
+        self.object_matches.set(object_matches);
+        self.matches.set(self.matches.get() + 1);
+        Ok(())
+    }
+
+    /// The number of symbols matched since the last reset.
+    pub fn matches(&self) -> usize {
+        self.matches.get()
+    }
+
+    /// Returns true if the rule applies to a symbol of `kind` matching its pattern, unless the
+    /// symbol matches an `--except` pattern.
+    fn applies_to(&self, name: &Name, kind: SymbolKind) -> bool {
+        let modifiers = &self.modifiers;
+        (modifiers.symbol_type.is_none() || modifiers.symbol_type == kind.symbol_type)
+            && (modifiers.defined.is_none() || modifiers.defined == kind.defined)
+            && !modifiers.except_set.is_match(name)
+    }
+}
+
+/// The set of rules applied to an object.
+pub struct Rules {
+    pub renames: Vec<Rule<String>>,
+    pub attributes: Vec<Rule<Attributes>>,
+
+    /// Rules removing symbols from the symbol table
+    pub strips: Vec<Rule<()>>,
+
+    /// Rules prepending a string to the names of symbols
+    pub prefixes: Vec<Rule<Prefix>>,
+
+    /// Whether visibility rules may hide undefined symbols
+    pub allow_undefined_visibility: bool,
+
+    /// Whether defined global symbols not matched by a binding rule are made weak
+    pub weaken: bool,
+
+    /// Whether hidden global symbols not matched by a binding rule are made local
+    pub localize_hidden: bool,
+
+    /// Whether symbols may be renamed to longer names, since the string table is rebuilt
+    rewrite: bool,
+
+    /// Symbols that stay global when `--keep-global-symbols` is given; other defined global
+    /// symbols not matched by a binding rule are made local
+    keep_global: Option<PatternSet>,
+
+    /// Symbols that are never removed from the symbol table
+    keep: PatternSet,
+
+    /// Whether defined local symbols are obfuscated by `--obfuscate`
+    obfuscate_locals: bool,
+
+    /// Symbols obfuscated by `--obfuscate-regex`, whatever their binding
+    obfuscate_patterns: PatternSet,
+
+    /// Symbols that no rule may change
+    excluded: PatternSet,
+
+    /// The patterns of `renames`, `attributes`, `strips` and `prefixes`, compiled once for the
+    /// whole run
+    rename_patterns: PatternSet,
+    attribute_patterns: PatternSet,
+    strip_patterns: PatternSet,
+    prefix_patterns: PatternSet,
+
+    /// Whether each rule of `renames`, `attributes`, `strips` and `prefixes` applies to the object
+    /// being transformed
+    renames_in_scope: RefCell<Vec<bool>>,
+    attributes_in_scope: RefCell<Vec<bool>>,
+    strips_in_scope: RefCell<Vec<bool>>,
+    prefixes_in_scope: RefCell<Vec<bool>>,
+
+    /// The formats of the objects transformed since the last reset
+    formats_seen: RefCell<Vec<Format>>,
+
+    /// Whether the object being transformed is ELF, whose symbol names may have version suffixes
+    versioned_names: Cell<bool>,
+
+    /// The symbols each `--rename-demangled` rule resolved to in the object being transformed,
+    /// by rule index, in the order they were found
+    resolved: RefCell<HashMap<usize, Vec<String>>>,
+}
+
+/// Identifies the most recently parsed rule, which receives any rule modifiers.
+#[derive(Copy, Clone)]
+enum Last {
+    /// The renames from the first index to the second, read from one `--rename` or
+    /// `--redefine-syms`
+    Renames(usize, usize),
+    Attributes(usize),
+    Strip(usize),
+    Prefix(usize),
+}
+
+/// A command line argument contributing to the rule set.
+enum RuleArg<'a> {
+    Rename(&'a str, &'a str),
+    RenameRegex(&'a str, &'a str),
+    RenameDemangled(&'a str, &'a str),
+    RedefineSyms(&'a str),
+    Visibility(Visibility, &'a str),
+    Set(&'a str),
+    Binding(Binding, Pattern),
+    Type(SymbolType, Pattern),
+    WeakReference(Pattern),
+    Strip(Pattern),
+    Prefix(Pattern, Prefix),
+    MaxMatches(&'a str),
+    IgnoreMissing,
+    InMember(&'a str),
+    InFormat(&'a str),
+    OnlyType(&'a str),
+    OnlyDefined(bool),
+    Except(&'a str),
+    EndOfConfig,
+}
+
+fn indexed_values<'a>(matches: &'a ArgMatches, name: &str) -> Vec<(usize, &'a str)> {
+    match (matches.indices_of(name), matches.values_of(name)) {
+        (Some(indices), Some(values)) => indices.zip(values).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Parse a symbol list, as given to `--exclude-symbols`, `--keep-global-symbols` or
+/// `--keep-symbols`.
+///
+/// Each line is an exact symbol name, or a pattern prefixed with `regex:` or `demangled:`.
+/// Blank lines and lines starting with `#` are ignored.
+fn parse_symbol_list(contents: &str) -> Result<Vec<Pattern>, Error> {
+    let mut patterns = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        patterns.push(match line.strip_prefix("regex:") {
+            Some(pattern) => Pattern::regex(pattern)?,
+            None if line.starts_with("demangled:") => Pattern::regex(line)?,
+            None => Pattern::Exact(line.to_string()),
+        });
+    }
+    Ok(patterns)
+}
+
+/// Parse a symbol redefinition list, as given to `--redefine-syms`.
+///
+/// Each line is an old and a new symbol name separated by whitespace, like for objcopy.  Blank
+/// lines and text following `#` are ignored.
+fn parse_redefinitions(contents: &str) -> Result<Vec<(&str, &str)>, Error> {
+    let mut redefinitions = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            [] => continue,
+            [old, new] => redefinitions.push((old, new)),
+            _ => {
+                return Err(Error::Message(format!(
+                    "line {}: expected 'OLD-NAME NEW-NAME', found '{}'",
+                    number + 1,
+                    line.trim()
+                )))
+            }
+        }
+    }
+    Ok(redefinitions)
+}
+
+/// Construct the rule renaming `old` to `new`.
+fn rename_rule(old: &str, new: &str, matches: &ArgMatches) -> Result<Rule<String>, Error> {
+    if new.len() > old.len() && !matches.is_present("rewrite") {
+        return Err(Error::Message(format!("Replacement symbol names cannot have more characters than the original name, unless --rewrite is given. Symbol '{}' cannot be renamed to '{}'.", old, new)));
+    }
+    Ok(Rule::new(Pattern::Exact(old.to_string()), new.to_string()))
+}
+
+/// Read the symbol lists in the files given to the option `name`.
+fn read_symbol_lists(matches: &ArgMatches, name: &str) -> Result<Vec<Pattern>, Error> {
+    let mut patterns = Vec::new();
+    for path in matches.values_of(name).into_iter().flatten() {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::Path(path.to_string(), Box::new(e)))?;
+        patterns.extend(
+            parse_symbol_list(&contents).map_err(|e| Error::Path(path.to_string(), Box::new(e)))?,
+        );
+    }
+    Ok(patterns)
+}
+
+impl Rules {
+    /// Build the rule set from the command line.
+    ///
+    /// Rule modifiers (such as `--max-matches`) apply to the rule immediately preceding them.
+    /// Modifiers given before any rule set the default for all rules.  The options of a
+    /// configuration file are followed by `--end-of-config`, so the modifiers given before any
+    /// rule in the file only set the default for the rules of the file, and modifiers on the
+    /// command line never apply to a rule of the file.
+    pub fn from_matches(matches: &ArgMatches) -> Result<Self, Error> {
+        let mut args = Vec::new();
+        for pair in indexed_values(matches, "rename").chunks(2) {
+            args.push((pair[0].0, RuleArg::Rename(pair[0].1, pair[1].1)));
+        }
+        for pair in indexed_values(matches, "rename-regex").chunks(2) {
+            args.push((pair[0].0, RuleArg::RenameRegex(pair[0].1, pair[1].1)));
+        }
+        for pair in indexed_values(matches, "rename-demangled").chunks(2) {
+            args.push((pair[0].0, RuleArg::RenameDemangled(pair[0].1, pair[1].1)));
+        }
+        for (index, path) in indexed_values(matches, "redefine-syms") {
+            args.push((index, RuleArg::RedefineSyms(path)));
+        }
+        let options = PatternOptions::from_matches(matches)?;
+        for (index, pattern) in indexed_values(matches, "default") {
+            args.push((index, RuleArg::Visibility(Visibility::Default, pattern)));
+        }
+        for (index, pattern) in indexed_values(matches, "hidden") {
+            args.push((index, RuleArg::Visibility(Visibility::Hidden, pattern)));
+        }
+        for (index, pattern) in indexed_values(matches, "protected") {
+            args.push((index, RuleArg::Visibility(Visibility::Protected, pattern)));
+        }
+        for (index, pattern) in indexed_values(matches, "internal") {
+            args.push((index, RuleArg::Visibility(Visibility::Internal, pattern)));
+        }
+        for (index, rule) in indexed_values(matches, "set") {
+            args.push((index, RuleArg::Set(rule)));
+        }
+        for (index, name) in indexed_values(matches, "localize-symbol") {
+            let pattern = Pattern::Exact(name.to_string());
+            args.push((index, RuleArg::Binding(Binding::Local, pattern)));
+        }
+        for (index, pattern) in indexed_values(matches, "localize-regex") {
+            args.push((
+                index,
+                RuleArg::Binding(Binding::Local, Pattern::parse(pattern, options)?),
+            ));
+        }
+        for pair in indexed_values(matches, "set-binding").chunks(2) {
+            args.push((
+                pair[0].0,
+                RuleArg::Binding(pair[1].1.parse()?, Pattern::parse(pair[0].1, options)?),
+            ));
+        }
+        for pair in indexed_values(matches, "set-type").chunks(2) {
+            args.push((
+                pair[0].0,
+                RuleArg::Type(pair[1].1.parse()?, Pattern::parse(pair[0].1, options)?),
+            ));
+        }
+        for (index, name) in indexed_values(matches, "globalize-symbol") {
+            let pattern = Pattern::Exact(name.to_string());
+            args.push((index, RuleArg::Binding(Binding::Global, pattern)));
+        }
+        for (index, name) in indexed_values(matches, "weaken-symbol") {
+            let pattern = Pattern::Exact(name.to_string());
+            args.push((index, RuleArg::Binding(Binding::Weak, pattern)));
+        }
+        for (index, pattern) in indexed_values(matches, "weaken-undefined") {
+            args.push((
+                index,
+                RuleArg::WeakReference(Pattern::parse(pattern, options)?),
+            ));
+        }
+        for (index, name) in indexed_values(matches, "strip-symbol") {
+            args.push((index, RuleArg::Strip(Pattern::Exact(name.to_string()))));
+        }
+        for (index, pattern) in indexed_values(matches, "strip-regex") {
+            args.push((index, RuleArg::Strip(Pattern::parse(pattern, options)?)));
+        }
+        let prefix = |prefix: &str, undefined_only| Prefix {
+            prefix: prefix.to_string(),
+            undefined_only,
+        };
+        for (index, value) in indexed_values(matches, "prefix-symbols") {
+            args.push((
+                index,
+                RuleArg::Prefix(Pattern::regex("")?, prefix(value, false)),
+            ));
+        }
+        for pair in indexed_values(matches, "prefix-regex").chunks(2) {
+            let pattern = Pattern::parse(pair[0].1, options)?;
+            args.push((
+                pair[0].0,
+                RuleArg::Prefix(pattern, prefix(pair[1].1, false)),
+            ));
+        }
+        for (index, value) in indexed_values(matches, "prefix-undefined") {
+            args.push((
+                index,
+                RuleArg::Prefix(Pattern::regex("")?, prefix(value, true)),
+            ));
+        }
+        for (index, value) in indexed_values(matches, "max-matches") {
+            args.push((index, RuleArg::MaxMatches(value)));
+        }
+        for index in matches.indices_of("ignore-missing").into_iter().flatten() {
+            args.push((index, RuleArg::IgnoreMissing));
+        }
+        for (index, pattern) in indexed_values(matches, "in-member") {
+            args.push((index, RuleArg::InMember(pattern)));
+        }
+        for (index, format) in indexed_values(matches, "in-format") {
+            args.push((index, RuleArg::InFormat(format)));
+        }
+        for (index, symbol_type) in indexed_values(matches, "only-type") {
+            args.push((index, RuleArg::OnlyType(symbol_type)));
+        }
+        for index in matches.indices_of("only-defined").into_iter().flatten() {
+            args.push((index, RuleArg::OnlyDefined(true)));
+        }
+        for index in matches.indices_of("only-undefined").into_iter().flatten() {
+            args.push((index, RuleArg::OnlyDefined(false)));
+        }
+        for (index, pattern) in indexed_values(matches, "except") {
+            args.push((index, RuleArg::Except(pattern)));
+        }
+        for index in matches.indices_of("end-of-config").into_iter().flatten() {
+            args.push((index, RuleArg::EndOfConfig));
+        }
+        args.sort_by_key(|(index, _)| *index);
+
+        let mut rules = Self {
+            renames: Vec::new(),
+            attributes: Vec::new(),
+            strips: Vec::new(),
+            prefixes: Vec::new(),
+            allow_undefined_visibility: matches.is_present("allow-undefined-visibility"),
+            weaken: matches.is_present("weaken"),
+            localize_hidden: matches.is_present("localize-hidden"),
+            rewrite: matches.is_present("rewrite"),
+            keep_global: None,
+            keep: PatternSet::default(),
+            obfuscate_locals: matches.is_present("obfuscate"),
+            obfuscate_patterns: PatternSet::default(),
+            excluded: PatternSet::default(),
+            rename_patterns: PatternSet::default(),
+            attribute_patterns: PatternSet::default(),
+            strip_patterns: PatternSet::default(),
+            prefix_patterns: PatternSet::default(),
+            renames_in_scope: RefCell::new(Vec::new()),
+            attributes_in_scope: RefCell::new(Vec::new()),
+            strips_in_scope: RefCell::new(Vec::new()),
+            prefixes_in_scope: RefCell::new(Vec::new()),
+            formats_seen: RefCell::new(Vec::new()),
+            versioned_names: Cell::new(false),
+            resolved: RefCell::new(HashMap::new()),
+        };
+        let mut defaults = RuleModifiers::default();
+        let mut last = None;
+        for (_, arg) in args {
+            match arg {
+                RuleArg::Rename(old, new) => {
+                    let start = rules.renames.len();
+                    rules.renames.push(rename_rule(old, new, matches)?);
+                    last = Some(Last::Renames(start, rules.renames.len()));
+                }
+                RuleArg::RenameRegex(pattern, replacement) => {
+                    let pattern = match Pattern::regex(pattern)? {
+                        Pattern::Demangled(_) => {
+                            return Err(Error::Message(format!(
+                                "--rename-regex can't match demangled names, found '{}'",
+                                pattern
+                            )))
+                        }
+                        pattern => pattern,
+                    };
+                    let start = rules.renames.len();
+                    rules
+                        .renames
+                        .push(Rule::new(pattern, replacement.to_string()));
+                    last = Some(Last::Renames(start, rules.renames.len()));
+                }
+                RuleArg::RenameDemangled(name, new) => {
+                    let start = rules.renames.len();
+                    rules
+                        .renames
+                        .push(Rule::new(Pattern::source_name(name)?, new.to_string()));
+                    last = Some(Last::Renames(start, rules.renames.len()));
+                }
+                RuleArg::RedefineSyms(path) => {
+                    let contents = std::fs::read_to_string(path)
+                        .map_err(|e| Error::Path(path.to_string(), Box::new(e)))?;
+                    let start = rules.renames.len();
+                    for (old, new) in parse_redefinitions(&contents)
+                        .map_err(|e| Error::Path(path.to_string(), Box::new(e)))?
+                    {
+                        rules.renames.push(rename_rule(old, new, matches)?);
+                    }
+                    last = Some(Last::Renames(start, rules.renames.len()));
+                }
+                RuleArg::Visibility(visibility, pattern) => {
+                    last = Some(Last::Attributes(rules.attributes.len()));
+                    let attributes = Attributes {
+                        visibility: Some(visibility),
+                        ..Attributes::default()
+                    };
+                    rules
+                        .attributes
+                        .push(Rule::new(Pattern::parse(pattern, options)?, attributes));
+                }
+                RuleArg::Set(rule) => {
+                    let (attributes, pattern) = rule.split_once(" where ").ok_or_else(|| {
+                        Error::Message(format!(
+                            "Expected 'ATTR=VALUE,... where PATTERN', found '{}'",
+                            rule
+                        ))
+                    })?;
+                    last = Some(Last::Attributes(rules.attributes.len()));
+                    rules.attributes.push(Rule::new(
+                        Pattern::parse(pattern.trim(), options)?,
+                        attributes.parse()?,
+                    ));
+                }
+                RuleArg::Binding(binding, pattern) => {
+                    last = Some(Last::Attributes(rules.attributes.len()));
+                    let attributes = Attributes {
+                        binding: Some(binding),
+                        ..Attributes::default()
+                    };
+                    rules.attributes.push(Rule::new(pattern, attributes));
+                }
+                RuleArg::Type(symbol_type, pattern) => {
+                    last = Some(Last::Attributes(rules.attributes.len()));
+                    let attributes = Attributes {
+                        symbol_type: Some(symbol_type),
+                        ..Attributes::default()
+                    };
+                    rules.attributes.push(Rule::new(pattern, attributes));
+                }
+                RuleArg::WeakReference(pattern) => {
+                    last = Some(Last::Attributes(rules.attributes.len()));
+                    let attributes = Attributes {
+                        weak_reference: true,
+                        ..Attributes::default()
+                    };
+                    rules.attributes.push(Rule::new(pattern, attributes));
+                }
+                RuleArg::Strip(pattern) => {
+                    last = Some(Last::Strip(rules.strips.len()));
+                    rules.strips.push(Rule::new(pattern, ()));
+                }
+                RuleArg::Prefix(pattern, prefix) => {
+                    if !prefix.prefix.is_empty() && !matches.is_present("rewrite") {
+                        return Err(Error::Message(format!("Prefixed symbol names have more characters than the original name, so prefixing symbols with '{}' requires --rewrite.", prefix.prefix)));
+                    }
+                    last = Some(Last::Prefix(rules.prefixes.len()));
+                    rules.prefixes.push(Rule::new(pattern, prefix));
+                }
+                RuleArg::MaxMatches(value) => {
+                    let max_matches = value.parse::<usize>().map_err(|_| {
+                        Error::Message(format!("Invalid value for --max-matches: '{}'", value))
+                    })?;
+                    for modifiers in rules.last_modifiers(last, &mut defaults) {
+                        modifiers.max_matches = Some(max_matches);
+                    }
+                }
+                RuleArg::IgnoreMissing => match last {
+                    Some(Last::Renames(..)) | None => {
+                        for modifiers in rules.last_modifiers(last, &mut defaults) {
+                            modifiers.ignore_missing = true;
+                        }
+                    }
+                    Some(_) => {
+                        return Err(Error::Message(
+                            "--ignore-missing must follow a --rename rule".to_string(),
+                        ))
+                    }
+                },
+                RuleArg::InMember(pattern) => {
+                    let member = Regex::new(pattern)?;
+                    for modifiers in rules.last_modifiers(last, &mut defaults) {
+                        modifiers.member = Some(member.clone());
+                    }
+                }
+                RuleArg::InFormat(format) => {
+                    let format = format.parse()?;
+                    for modifiers in rules.last_modifiers(last, &mut defaults) {
+                        modifiers.format = Some(format);
+                    }
+                }
+                RuleArg::OnlyType(symbol_type) => {
+                    let symbol_type = symbol_type.parse()?;
+                    for modifiers in rules.last_modifiers(last, &mut defaults) {
+                        modifiers.symbol_type = Some(symbol_type);
+                    }
+                }
+                RuleArg::OnlyDefined(defined) => {
+                    for modifiers in rules.last_modifiers(last, &mut defaults) {
+                        modifiers.defined = Some(defined);
+                    }
+                }
+                RuleArg::Except(pattern) => {
+                    let pattern = Pattern::parse(pattern, options)?;
+                    for modifiers in rules.last_modifiers(last, &mut defaults) {
+                        modifiers.except.push(pattern.clone());
+                    }
+                }
+                RuleArg::EndOfConfig => {
+                    for modifiers in rules.all_modifiers() {
+                        modifiers.inherit(&defaults)?;
+                    }
+                    defaults = RuleModifiers::default();
+                    last = None;
+                }
+            }
+        }
+        for modifiers in rules.all_modifiers() {
+            modifiers.inherit(&defaults)?;
+        }
+        rules.excluded = PatternSet::new(read_symbol_lists(matches, "exclude-symbols")?.iter())?;
+        if matches.is_present("keep-global-symbols") {
+            let keep_global = read_symbol_lists(matches, "keep-global-symbols")?;
+            rules.keep_global = Some(PatternSet::new(keep_global.iter())?);
+        }
+        rules.keep = PatternSet::new(read_symbol_lists(matches, "keep-symbols")?.iter())?;
+        let obfuscate = matches
+            .values_of("obfuscate-regex")
+            .into_iter()
+            .flatten()
+            .map(|pattern| Pattern::parse(pattern, options))
+            .collect::<Result<Vec<_>, _>>()?;
+        rules.obfuscate_patterns = PatternSet::new(obfuscate.iter())?;
+        rules.rename_patterns = PatternSet::new(rules.renames.iter().map(|rule| &rule.pattern))?;
+        rules.attribute_patterns =
+            PatternSet::new(rules.attributes.iter().map(|rule| &rule.pattern))?;
+        rules.strip_patterns = PatternSet::new(rules.strips.iter().map(|rule| &rule.pattern))?;
+        rules.prefix_patterns = PatternSet::new(rules.prefixes.iter().map(|rule| &rule.pattern))?;
+        rules.set_object(None, None);
+        for rule in &rules.renames {
+            if let Pattern::Exact(name) = &rule.pattern {
+                if rules.is_excluded(&Name::new(name)) {
+                    return Err(Error::Message(format!(
+                        "Symbol '{}' cannot be renamed, since it is excluded by --exclude-symbols",
+                        name
+                    )));
+                }
+            }
+        }
+        Ok(rules)
+    }
+
+    /// The modifiers of every rule.
+    fn all_modifiers(&mut self) -> impl Iterator<Item = &mut RuleModifiers> {
+        (self.renames.iter_mut().map(|rule| &mut rule.modifiers))
+            .chain(self.attributes.iter_mut().map(|rule| &mut rule.modifiers))
+            .chain(self.strips.iter_mut().map(|rule| &mut rule.modifiers))
+            .chain(self.prefixes.iter_mut().map(|rule| &mut rule.modifiers))
+    }
+
+    /// The modifiers of the most recently parsed rule, or `defaults` before any rule.
+    ///
+    /// A `--redefine-syms` file parses to several rules, which all receive its modifiers.
+    fn last_modifiers<'a>(
+        &'a mut self,
+        last: Option<Last>,
+        defaults: &'a mut RuleModifiers,
+    ) -> Vec<&'a mut RuleModifiers> {
+        match last {
+            Some(Last::Renames(start, end)) => self.renames[start..end]
+                .iter_mut()
+                .map(|rule| &mut rule.modifiers)
+                .collect(),
+            Some(Last::Attributes(i)) => vec![&mut self.attributes[i].modifiers],
+            Some(Last::Strip(i)) => vec![&mut self.strips[i].modifiers],
+            Some(Last::Prefix(i)) => vec![&mut self.prefixes[i].modifiers],
+            None => vec![defaults],
+        }
+    }
+
+    /// The name of a symbol of the object being transformed, as matched by patterns.
+    fn name<'a>(&self, name: &'a str) -> Name<'a> {
+        match self.versioned_names.get() {
+            true => Name::versioned(name),
+            false => Name::new(name),
+        }
+    }
+
+    /// Returns true if `name` is excluded from every rule.
+    fn is_excluded(&self, name: &Name) -> bool {
+        self.excluded.is_match(name)
+    }
+
+    /// Reset the match counts of all rules, before applying them to another file.
+    pub fn reset(&self) {
+        for rule in &self.renames {
+            rule.matches.set(0);
+        }
+        for rule in &self.attributes {
+            rule.matches.set(0);
+        }
+        for rule in &self.strips {
+            rule.matches.set(0);
+        }
+        for rule in &self.prefixes {
+            rule.matches.set(0);
+        }
+        self.formats_seen.borrow_mut().clear();
+    }
+
+    /// Set the identifier of the archive member (or `None` outside archives) and the format (or
+    /// `None` for other formats) of the object being transformed.
+    ///
+    /// Rules scoped to members never apply to objects outside archives, and rules scoped to a
+    /// format never apply to objects of other formats.  Match limits start again for each object.
+    pub fn set_object(&self, member: Option<&str>, format: Option<Format>) {
+        for rule in &self.renames {
+            rule.object_matches.set(0);
+        }
+        for rule in &self.attributes {
+            rule.object_matches.set(0);
+        }
+        for rule in &self.strips {
+            rule.object_matches.set(0);
+        }
+        for rule in &self.prefixes {
+            rule.object_matches.set(0);
+        }
+        self.versioned_names.set(format == Some(Format::Elf));
+        if let Some(format) = format {
+            let mut formats_seen = self.formats_seen.borrow_mut();
+            if !formats_seen.contains(&format) {
+                formats_seen.push(format);
+            }
+        }
+        fn in_scope<A>(rule: &Rule<A>, member: Option<&str>, format: Option<Format>) -> bool {
+            if rule.modifiers.format.is_some() && rule.modifiers.format != format {
+                return false;
+            }
+            match (&rule.modifiers.member, member) {
+                (None, _) => true,
+                (Some(pattern), Some(member)) => pattern.is_match(member),
+                (Some(_), None) => false,
+            }
+        }
+        *self.renames_in_scope.borrow_mut() = self
+            .renames
+            .iter()
+            .map(|rule| in_scope(rule, member, format))
+            .collect();
+        *self.attributes_in_scope.borrow_mut() = self
+            .attributes
+            .iter()
+            .map(|rule| in_scope(rule, member, format))
+            .collect();
+        *self.strips_in_scope.borrow_mut() = self
+            .strips
+            .iter()
+            .map(|rule| in_scope(rule, member, format))
+            .collect();
+        *self.prefixes_in_scope.borrow_mut() = self
+            .prefixes
+            .iter()
+            .map(|rule| in_scope(rule, member, format))
+            .collect();
+        self.resolved.borrow_mut().clear();
+    }
+
+    /// Returns the rename rules that haven't matched any symbol since the last reset.
+    ///
+    /// Rules scoped to a format are only missing if an object of that format was transformed.
+    pub fn missing_renames(&self) -> impl Iterator<Item = &Rule<String>> {
+        let formats_seen = self.formats_seen.borrow();
+        self.renames.iter().filter(move |rule| {
+            rule.matches() == 0
+                && rule
+                    .modifiers
+                    .format
+                    .is_none_or(|format| formats_seen.contains(&format))
+        })
+    }
+
+    /// Returns the new name for a symbol, if it is renamed.
+    ///
+    /// Regex rules replace the first match of their pattern in the name, expanding capture groups
+    /// such as `$1` in the replacement.  Restricted rules only match symbols of the `kind` they
+    /// are restricted to.  The last matching rule takes precedence.
+    pub fn rename<'a>(
+        &'a self,
+        name: &'a str,
+        kind: SymbolKind,
+    ) -> Result<Option<Cow<'a, str>>, Error> {
+        let name = self.name(name);
+        let matches = self.rename_patterns.matches(&name);
+        if matches.is_empty() || self.is_excluded(&name) {
+            return Ok(None);
+        }
+        let in_scope = self.renames_in_scope.borrow();
+        let mut new_name = None;
+        for index in matches.into_iter().filter(|index| in_scope[*index]) {
+            let rule = &self.renames[index];
+            if !rule.applies_to(&name, kind) {
+                continue;
+            }
+            if let Pattern::SourceName(..) = &rule.pattern {
+                let mut resolved = self.resolved.borrow_mut();
+                let symbols = resolved.entry(index).or_default();
+                if !symbols.iter().any(|symbol| symbol == name.name) {
+                    symbols.push(name.name.to_string());
+                }
+            }
+            rule.record_match()?;
+            // Rules matching the name without its version suffix keep the suffix
+            let version = name.version();
+            new_name = Some(match &rule.pattern {
+                Pattern::Regex(regex) if !version.is_empty() && regex.is_match(name.base) => {
+                    Cow::Owned(format!(
+                        "{}{}",
+                        regex.replace(name.base, rule.action.as_str()),
+                        version
+                    ))
+                }
+                Pattern::Regex(regex) => regex.replace(name.name, rule.action.as_str()),
+                Pattern::Exact(exact) if exact == name.name => Cow::Borrowed(rule.action.as_str()),
+                _ if !version.is_empty() => Cow::Owned(format!("{}{}", rule.action, version)),
+                _ => Cow::Borrowed(rule.action.as_str()),
+            });
+        }
+        if let Some(new_name) = &new_name {
+            if new_name.len() > name.name.len() && !self.rewrite {
+                return Err(Error::Message(format!("Replacement symbol names cannot have more characters than the original name, unless --rewrite is given. Symbol '{}' cannot be renamed to '{}'.", name.name, new_name)));
+            }
+        }
+        Ok(new_name)
+    }
+
+    /// Returns the symbols each `--rename-demangled` rule resolved to in the object being
+    /// transformed, as the source name and the symbol name.
+    pub fn resolutions(&self) -> Vec<(String, String)> {
+        let resolved = self.resolved.borrow();
+        let mut indices: Vec<_> = resolved.keys().copied().collect();
+        indices.sort_unstable();
+        indices
+            .into_iter()
+            .flat_map(|index| {
+                let source_name = self.renames[index].pattern.to_string();
+                resolved[&index]
+                    .iter()
+                    .map(move |symbol| (source_name.clone(), symbol.clone()))
+            })
+            .collect()
+    }
+
+    /// Fail if a `--rename-demangled` rule matched more than one symbol of the object being
+    /// transformed, listing all of them.
+    pub fn check_ambiguous(&self) -> Result<(), Error> {
+        let resolved = self.resolved.borrow();
+        let mut indices: Vec<_> = resolved.keys().copied().collect();
+        indices.sort_unstable();
+        for index in indices {
+            let symbols = &resolved[&index];
+            if symbols.len() > 1 {
+                let candidates = symbols
+                    .iter()
+                    .map(|symbol| format!("'{}'", symbol))
+                    .collect::<Vec<_>>();
+                return Err(Error::Message(format!(
+                    "'{}' is ambiguous, matching {}. Rename the intended symbol with --rename instead.",
+                    self.renames[index].pattern,
+                    candidates.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if any rule prefixes only undefined symbols.
+    pub fn prefixes_undefined(&self) -> bool {
+        self.prefixes.iter().any(|rule| rule.action.undefined_only)
+    }
+
+    /// Returns the string prepended to the name of a symbol, if it is prefixed.
+    ///
+    /// Symbols are matched by their original names, and the prefix is prepended after renaming.
+    /// Rules for undefined symbols only match undefined symbols, and restricted rules only match
+    /// symbols of the `kind` they are restricted to.  The last matching rule takes precedence.
+    pub fn prefix(&self, name: &str, kind: SymbolKind) -> Result<Option<&str>, Error> {
+        let name = self.name(name);
+        let matches = self.prefix_patterns.matches(&name);
+        if matches.is_empty() || self.is_excluded(&name) {
+            return Ok(None);
+        }
+        let in_scope = self.prefixes_in_scope.borrow();
+        let mut prefix = None;
+        for index in matches.into_iter().filter(|index| in_scope[*index]) {
+            let rule = &self.prefixes[index];
+            if rule.action.undefined_only && kind.defined != Some(false)
+                || !rule.applies_to(&name, kind)
+            {
+                continue;
+            }
+            rule.record_match()?;
+            prefix = Some(rule.action.prefix.as_str());
+        }
+        Ok(prefix)
+    }
+
+    /// Returns true if any rule applying to the object being transformed (or `--weaken`,
+    /// `--localize-hidden` or `--keep-global-symbols`) sets the binding of symbols.
+    pub fn sets_binding(&self) -> bool {
+        let in_scope = self.attributes_in_scope.borrow();
+        self.weaken
+            || self.localize_hidden
+            || self.keep_global.is_some()
+            || self
+                .attributes
+                .iter()
+                .zip(in_scope.iter())
+                .any(|(rule, in_scope)| *in_scope && rule.action.binding.is_some())
+    }
+
+    /// Returns true if any rule applying to the object being transformed sets the type of
+    /// symbols.
+    pub fn sets_type(&self) -> bool {
+        let in_scope = self.attributes_in_scope.borrow();
+        self.attributes
+            .iter()
+            .zip(in_scope.iter())
+            .any(|(rule, in_scope)| *in_scope && rule.action.symbol_type.is_some())
+    }
+
+    /// Returns true if any rule applying to the object being transformed makes undefined
+    /// references weak.
+    pub fn weakens_undefined(&self) -> bool {
+        let in_scope = self.attributes_in_scope.borrow();
+        self.attributes
+            .iter()
+            .zip(in_scope.iter())
+            .any(|(rule, in_scope)| *in_scope && rule.action.weak_reference)
+    }
+
+    /// Returns the attributes to set on a symbol.
+    ///
+    /// Restricted rules only match symbols of the `kind` they are restricted to.  See
+    /// `Attributes::merge` for the precedence of multiple matching rules.
+    pub fn attributes(&self, name: &str, kind: SymbolKind) -> Result<Attributes, Error> {
+        let mut attributes = Attributes::default();
+        let name = self.name(name);
+        let matches = self.attribute_patterns.matches(&name);
+        if matches.is_empty() || self.is_excluded(&name) {
+            return Ok(attributes);
+        }
+        let in_scope = self.attributes_in_scope.borrow();
+        for index in matches.into_iter().filter(|index| in_scope[*index]) {
+            let rule = &self.attributes[index];
+            if !rule.applies_to(&name, kind) {
+                continue;
+            }
+            rule.record_match()?;
+            attributes.merge(&rule.action);
+        }
+        Ok(attributes)
+    }
+
+    /// Returns the binding of a defined symbol not matched by any binding rule.
+    ///
+    /// `global` is true for global and weak symbols, `weak` for weak symbols, and `hidden` for
+    /// symbols with hidden or internal visibility, after visibility rules are applied.  Hidden
+    /// symbols are made local by `--localize-hidden`, global symbols missing from the
+    /// `--keep-global-symbols` lists are made local, and the others are made weak by `--weaken`.
+    pub fn default_binding(
+        &self,
+        name: &str,
+        global: bool,
+        weak: bool,
+        hidden: bool,
+    ) -> Option<Binding> {
+        if !global || self.is_excluded(&self.name(name)) {
+            return None;
+        }
+        match &self.keep_global {
+            _ if self.localize_hidden && hidden => Some(Binding::Local),
+            Some(keep_global) if !keep_global.is_match(&self.name(name)) => Some(Binding::Local),
+            _ if self.weaken && !weak => Some(Binding::Weak),
+            _ => None,
+        }
+    }
+
+    /// Returns true if a symbol is renamed by `--obfuscate` or `--obfuscate-regex`.
+    ///
+    /// `local` is true for defined local symbols, which `--obfuscate` selects.  Symbols excluded
+    /// by `--exclude-symbols` are never obfuscated.
+    pub fn obfuscates(&self, name: &str, local: bool) -> bool {
+        let name = self.name(name);
+        ((self.obfuscate_locals && local) || self.obfuscate_patterns.is_match(&name))
+            && !self.is_excluded(&name)
+    }
+
+    /// Returns true if any symbols are listed by `--keep-symbols`.
+    pub fn keeps_symbols(&self) -> bool {
+        !self.keep.is_empty()
+    }
+
+    /// Returns true if a symbol is listed by `--keep-symbols`, so it is never stripped.
+    pub fn keep(&self, name: &str) -> bool {
+        self.keep.is_match(&self.name(name))
+    }
+
+    /// Returns true if a symbol is removed from the symbol table.
+    ///
+    /// Symbols listed by `--keep-symbols` are never removed.  Restricted rules only match symbols
+    /// of the `kind` they are restricted to.
+    pub fn strip(&self, name: &str, kind: SymbolKind) -> Result<bool, Error> {
+        let name = self.name(name);
+        let matches = self.strip_patterns.matches(&name);
+        if matches.is_empty() || self.is_excluded(&name) || self.keep.is_match(&name) {
+            return Ok(false);
+        }
+        let in_scope = self.strips_in_scope.borrow();
+        let mut strip = false;
+        for index in matches.into_iter().filter(|index| in_scope[*index]) {
+            let rule = &self.strips[index];
+            if !rule.applies_to(&name, kind) {
+                continue;
+            }
+            rule.record_match()?;
+            strip = true;
+        }
+        Ok(strip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(args: &[&str]) -> Result<Rules, Error> {
+        let matches = crate::app()
+            .get_matches_from_safe(
+                ["symtool"]
+                    .iter()
+                    .chain(args)
+                    .chain(&["input.o", "output.o"]),
+            )
+            .unwrap();
+        Rules::from_matches(&matches)
+    }
+
+    const DEFINED_FUNCTION: SymbolKind = SymbolKind {
+        symbol_type: Some(SymbolType::Func),
+        defined: Some(true),
+    };
+    const UNDEFINED: SymbolKind = SymbolKind {
+        symbol_type: None,
+        defined: Some(false),
+    };
+
+    #[test]
+    fn rules_parsed() {
+        let rules = rules(&[
+            "--rename",
+            "foo",
+            "bar",
+            "--hidden",
+            "^internal_",
+            "--strip-symbol",
+            "debug_only",
+        ])
+        .unwrap();
+        rules.set_object(None, Some(Format::Elf));
+        assert_eq!(
+            rules.rename("foo", DEFINED_FUNCTION).unwrap().as_deref(),
+            Some("bar")
+        );
+        assert_eq!(rules.rename("foobar", DEFINED_FUNCTION).unwrap(), None);
+        assert_eq!(
+            rules
+                .attributes("internal_helper", DEFINED_FUNCTION)
+                .unwrap(),
+            Attributes {
+                visibility: Some(Visibility::Hidden),
+                ..Attributes::default()
+            }
+        );
+        assert!(rules.strip("debug_only", DEFINED_FUNCTION).unwrap());
+        assert!(!rules.strip("foo", DEFINED_FUNCTION).unwrap());
+    }
+
+    #[test]
+    fn longer_names_need_rewrite() {
+        let error = rules(&["--rename", "foo", "longer_foo"])
+            .and_then(|rules| {
+                rules.set_object(None, Some(Format::Elf));
+                rules.rename("foo", DEFINED_FUNCTION).map(|_| ())
+            })
+            .unwrap_err();
+        assert!(error.to_string().contains("--rewrite"), "{}", error);
+    }
+
+    #[test]
+    fn modifiers_apply_to_preceding_rule() {
+        let rules = rules(&["--hidden", "^a_", "--max-matches", "1", "--hidden", "^b_"]).unwrap();
+        assert_eq!(rules.attributes[0].modifiers.max_matches, Some(1));
+        assert_eq!(rules.attributes[1].modifiers.max_matches, None);
+        rules.set_object(None, Some(Format::Elf));
+        rules.attributes("a_1", DEFINED_FUNCTION).unwrap();
+        assert!(rules.attributes("a_2", DEFINED_FUNCTION).is_err());
+        rules.attributes("b_1", DEFINED_FUNCTION).unwrap();
+        rules.attributes("b_2", DEFINED_FUNCTION).unwrap();
+    }
+
+    #[test]
+    fn modifiers_before_rules_are_defaults() {
+        let rules = rules(&[
+            "--only-defined",
+            "--except",
+            "^a_kept$",
+            "--hidden",
+            "^a_",
+            "--hidden",
+            "^b_",
+            "--only-undefined",
+        ])
+        .unwrap();
+        rules.set_object(None, Some(Format::Elf));
+        let hidden = |name, kind| {
+            rules.attributes(name, kind).unwrap().visibility == Some(Visibility::Hidden)
+        };
+        assert!(hidden("a_1", DEFINED_FUNCTION));
+        assert!(!hidden("a_1", UNDEFINED));
+        assert!(!hidden("a_kept", DEFINED_FUNCTION));
+        // Modifiers given to a rule replace the defaults, and add to the exceptions
+        assert!(hidden("b_1", UNDEFINED));
+        assert!(!hidden("b_1", DEFINED_FUNCTION));
+        assert!(!hidden("a_kept", UNDEFINED));
+    }
+
+    #[test]
+    fn configuration_modifiers_scoped_to_its_rules() {
+        let rules = rules(&[
+            "--only-defined",
+            "--hidden",
+            "^a_",
+            "--end-of-config",
+            "--ignore-missing",
+            "--rename",
+            "nope",
+            "nop",
+            "--hidden",
+            "^b_",
+        ])
+        .unwrap();
+        // Command line modifiers before any rule are defaults for every rule
+        assert!(rules.renames[0].modifiers.ignore_missing);
+        assert!(rules.attributes[0].modifiers.ignore_missing);
+        assert_eq!(rules.attributes[0].modifiers.defined, Some(true));
+        assert_eq!(rules.attributes[1].modifiers.defined, None);
+    }
+
+    #[test]
+    fn max_matches_counted_per_object() {
+        let rules = rules(&["--strip-regex", "^a_", "--max-matches", "1"]).unwrap();
+        rules.set_object(Some("first.o"), Some(Format::Elf));
+        assert!(rules.strip("a_1", DEFINED_FUNCTION).unwrap());
+        rules.set_object(Some("second.o"), Some(Format::Elf));
+        assert!(rules.strip("a_1", DEFINED_FUNCTION).unwrap());
+        assert!(rules.strip("a_2", DEFINED_FUNCTION).is_err());
+        assert_eq!(rules.strips[0].matches(), 2);
+    }
+
+    #[test]
+    fn binding_rules_scoped_to_objects() {
+        let rules = rules(&["--set-binding", "^a_", "weak", "--in-format", "mach-o"]).unwrap();
+        rules.set_object(None, Some(Format::Elf));
+        assert!(!rules.sets_binding());
+        rules.set_object(None, Some(Format::MachO));
+        assert!(rules.sets_binding());
+    }
+
+    #[test]
+    fn rules_scoped_to_members_and_formats() {
+        let rules = rules(&[
+            "--strip-symbol",
+            "a",
+            "--in-member",
+            "^first",
+            "--strip-symbol",
+            "b",
+            "--in-format",
+            "mach-o",
+        ])
+        .unwrap();
+        rules.set_object(Some("first.o"), Some(Format::Elf));
+        assert!(rules.strip("a", DEFINED_FUNCTION).unwrap());
+        assert!(!rules.strip("b", DEFINED_FUNCTION).unwrap());
+        rules.set_object(None, Some(Format::MachO));
+        assert!(!rules.strip("a", DEFINED_FUNCTION).unwrap());
+        assert!(rules.strip("b", DEFINED_FUNCTION).unwrap());
+    }
+
+    #[test]
+    fn ambiguous_source_names_list_all_candidates() {
+        let rules = rules(&["--rename-demangled", "ns::open", "ns_open"]).unwrap();
+        rules.set_object(None, Some(Format::Elf));
+        for name in &["_ZN2ns4openEv", "_ZN2ns4openEi", "_ZN2ns4openEPKc"] {
+            rules.rename(name, DEFINED_FUNCTION).unwrap();
+        }
+        let error = rules.check_ambiguous().unwrap_err().to_string();
+        assert!(
+            error.contains("'_ZN2ns4openEv', '_ZN2ns4openEi', '_ZN2ns4openEPKc'"),
+            "{}",
+            error
+        );
+        assert_eq!(rules.resolutions().len(), 3);
+        rules.set_object(None, Some(Format::Elf));
+        rules.rename("_ZN2ns4openEv", DEFINED_FUNCTION).unwrap();
+        assert!(rules.check_ambiguous().is_ok());
+        assert_eq!(
+            rules.resolutions(),
+            vec![("ns::open".to_string(), "_ZN2ns4openEv".to_string())]
+        );
+    }
+}
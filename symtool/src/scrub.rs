@@ -0,0 +1,104 @@
+//! Rewrite the build paths recorded in objects.
+
+use crate::error::Error;
+use clap::ArgMatches;
+use symtool_backend::patch::Patch;
+use symtool_backend::strtab::StrtabIter;
+
+/// Path prefixes replaced by `--scrub-paths`.
+pub struct PathScrubs {
+    prefixes: Vec<(String, String)>,
+
+    /// Whether paths may be replaced by longer ones, since the string table is rebuilt
+    rewrite: bool,
+}
+
+impl PathScrubs {
+    pub fn from_matches(matches: &ArgMatches) -> Result<Option<Self>, Error> {
+        let values = match matches.values_of("scrub-paths") {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        let mut prefixes = Vec::new();
+        for value in values {
+            let (prefix, replacement) = value
+                .split_once('=')
+                .filter(|(prefix, _)| !prefix.is_empty())
+                .ok_or_else(|| {
+                    Error::Message(format!(
+                        "Invalid value for --scrub-paths: '{}' (expected PREFIX=REPLACEMENT)",
+                        value
+                    ))
+                })?;
+            prefixes.push((prefix.to_string(), replacement.to_string()));
+        }
+        Ok(Some(Self {
+            prefixes,
+            rewrite: matches.is_present("rewrite"),
+        }))
+    }
+
+    /// Returns the scrubbed path, if it starts with one of the prefixes.
+    ///
+    /// Like the compiler's `-ffile-prefix-map`, the last matching prefix takes precedence.
+    fn scrub(&self, path: &str) -> Option<String> {
+        self.prefixes
+            .iter()
+            .rev()
+            .find_map(|(prefix, replacement)| {
+                let rest = path.strip_prefix(prefix.as_str())?;
+                Some(format!("{}{}", replacement, rest))
+            })
+    }
+
+    /// Returns the new name of a symbol naming a path, such as an ELF file symbol, if it is
+    /// scrubbed.
+    ///
+    /// Scrubbed paths longer than the original require `--rewrite`, like renames.
+    pub fn symbol_name(&self, path: &str, verbose: bool) -> Result<Option<String>, Error> {
+        let scrubbed = match self.scrub(path) {
+            Some(scrubbed) => scrubbed,
+            None => return Ok(None),
+        };
+        if scrubbed.len() > path.len() && !self.rewrite {
+            return Err(Error::Message(format!(
+                "Scrubbed paths cannot have more characters than the original path, unless --rewrite is given. Path '{}' cannot be replaced by '{}'.",
+                path, scrubbed
+            )));
+        }
+        if verbose {
+            println!("Scrub path: {} -> {}", path, scrubbed);
+        }
+        Ok(Some(scrubbed))
+    }
+
+    /// Construct patches scrubbing the paths in a debug string table, such as `.debug_str`.
+    ///
+    /// Strings are replaced in place, padded with NUL bytes, like in `debug::rename_strings`.
+    /// Strings whose scrubbed paths are longer are left unchanged with a warning.
+    pub fn strings(&self, strings: StrtabIter, verbose: bool) -> Result<Vec<Patch>, Error> {
+        let mut patches = Vec::new();
+        for string in strings {
+            let path = match std::str::from_utf8(&string) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            if let Some(scrubbed) = self.scrub(path) {
+                if scrubbed.len() > path.len() {
+                    eprintln!(
+                        "warning: debug string {} can't be scrubbed in place to the longer {}",
+                        path, scrubbed
+                    );
+                    continue;
+                }
+                if verbose {
+                    println!("Scrub debug string: {} -> {}", path, scrubbed);
+                }
+                let mut bytes = scrubbed.into_bytes();
+                bytes.resize(path.len(), 0);
+                patches.push(string.patch_with_bytes(&bytes)?);
+            }
+        }
+        Ok(patches)
+    }
+}
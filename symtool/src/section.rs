@@ -0,0 +1,346 @@
+//! Move symbols to another section, make them absolute or common, or undefine them.
+
+use crate::error::Error;
+use clap::ArgMatches;
+use goblin::elf::header::ET_REL;
+use goblin::elf::section_header::{SHN_ABS, SHN_COMMON, SHN_UNDEF};
+use goblin::elf::sym::{Sym, STB_LOCAL};
+use goblin::elf::Elf;
+use goblin::mach::header::MH_OBJECT;
+use goblin::mach::symbols::{Nlist, N_ABS, N_EXT, N_PEXT, N_SECT, N_TYPE, N_UNDF};
+use goblin::mach::MachO;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use symtool_backend as backend;
+use symtool_backend::elf::ExtendedIndexes;
+use symtool_backend::patch::Patch;
+
+/// The section a symbol is moved to.
+enum Target {
+    /// The symbol becomes absolute (`SHN_ABS` or `N_ABS`)
+    Absolute,
+
+    /// The symbol becomes a common symbol (`SHN_COMMON`), aligned like the section it was
+    /// defined in.  Only supported for ELF, since Mach-O symbols have no size.
+    Common,
+
+    /// The symbol is defined in the named section, which for Mach-O is `segname,sectname` or
+    /// just `sectname`
+    Section(String),
+
+    /// The symbol becomes an undefined reference (`SHN_UNDEF` or `N_UNDF`), given to
+    /// `--undefine-symbol`
+    Undefined,
+}
+
+impl Target {
+    /// The verb describing the change, for error messages.
+    fn action(&self) -> &'static str {
+        match self {
+            Self::Undefined => "undefine",
+            _ => "move",
+        }
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Absolute => write!(f, "ABS"),
+            Self::Common => write!(f, "COMMON"),
+            Self::Section(name) => write!(f, "{}", name),
+            Self::Undefined => write!(f, "UND"),
+        }
+    }
+}
+
+/// Symbols to move to another section, or to undefine.
+pub struct SectionMoves {
+    moves: Vec<(String, Target)>,
+
+    /// The names of the symbols moved so far
+    found: RefCell<HashSet<String>>,
+}
+
+fn ambiguous(name: &str, section: &str) -> Error {
+    Error::Message(format!(
+        "Can't move '{}': the object has several sections named '{}'",
+        name, section
+    ))
+}
+
+fn missing(name: &str, section: &str) -> Error {
+    Error::Message(format!(
+        "Can't move '{}': the object has no section named '{}'",
+        name, section
+    ))
+}
+
+impl SectionMoves {
+    pub fn from_matches(matches: &ArgMatches) -> Result<Option<Self>, Error> {
+        if !matches.is_present("set-symbol-section") && !matches.is_present("undefine-symbol") {
+            return Ok(None);
+        }
+        let mut moves = Vec::new();
+        for value in matches
+            .values_of("set-symbol-section")
+            .into_iter()
+            .flatten()
+        {
+            let (name, section) = match value.split_once('=') {
+                Some((name, section)) if !name.is_empty() && !section.is_empty() => (name, section),
+                _ => {
+                    return Err(Error::Message(format!(
+                        "Invalid value for --set-symbol-section: '{}' (expected NAME=SECTION)",
+                        value
+                    )))
+                }
+            };
+            let target = match section {
+                "ABS" => Target::Absolute,
+                "COMMON" => Target::Common,
+                section => Target::Section(section.to_string()),
+            };
+            moves.push((name.to_string(), target));
+        }
+        for name in matches.values_of("undefine-symbol").into_iter().flatten() {
+            moves.push((name.to_string(), Target::Undefined));
+        }
+        Ok(Some(Self {
+            moves,
+            found: RefCell::new(HashSet::new()),
+        }))
+    }
+
+    fn target(&self, name: &str) -> Option<&Target> {
+        let (_, target) = self.moves.iter().find(|(symbol, _)| symbol == name)?;
+        self.found.borrow_mut().insert(name.to_string());
+        Some(target)
+    }
+
+    /// Returns true if any symbol is undefined, which moves it to another group of the Mach-O
+    /// symbol table.
+    pub fn undefines(&self) -> bool {
+        self.moves
+            .iter()
+            .any(|(_, target)| matches!(target, Target::Undefined))
+    }
+
+    /// Returns the symbols which weren't found in any object, with the verb describing their
+    /// change.
+    pub fn missing(&self) -> Vec<(&str, &'static str)> {
+        let found = self.found.borrow();
+        self.moves
+            .iter()
+            .filter(|(name, _)| !found.contains(name))
+            .map(|(name, target)| (name.as_str(), target.action()))
+            .collect()
+    }
+
+    /// Move an ELF symbol, which is entry `index` of its symbol table.
+    ///
+    /// Returns the changed symbol and a patch to its extended section index entry, or `None` if
+    /// the symbol isn't moved.
+    pub fn elf_sym(
+        &self,
+        elf: &Elf,
+        indexes: &ExtendedIndexes,
+        index: usize,
+        sym: &Sym,
+        name: &str,
+        verbose: bool,
+    ) -> Result<Option<(Sym, Option<Patch>)>, Error> {
+        let target = match self.target(name) {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        let action = target.action();
+        match sym.st_shndx as u32 {
+            SHN_UNDEF => {
+                return Err(Error::Message(format!(
+                    "Can't {} '{}': the symbol is undefined",
+                    action, name
+                )))
+            }
+            SHN_COMMON => {
+                return Err(Error::Message(format!(
+                    "Can't {} '{}': the symbol is common (allocate it with --define-common)",
+                    action, name
+                )))
+            }
+            _ => {}
+        }
+        let (new_sym, patch) = match target {
+            Target::Absolute => {
+                // Clear the extended section index entry, if the symbol has one
+                let (new_sym, patch) = indexes.set_section_index(index, *sym, 0)?;
+                (
+                    Sym {
+                        st_shndx: SHN_ABS as usize,
+                        ..new_sym
+                    },
+                    patch,
+                )
+            }
+            Target::Common => {
+                if elf.header.e_type != ET_REL {
+                    return Err(Error::Message(format!(
+                        "Can't move '{}': common symbols are only supported in relocatable objects",
+                        name
+                    )));
+                }
+                if sym.st_bind() == STB_LOCAL {
+                    return Err(Error::Message(format!(
+                        "Can't move '{}': the symbol is local, and common symbols must be global",
+                        name
+                    )));
+                }
+                // The value of a common symbol is its alignment
+                let alignment = elf
+                    .section_headers
+                    .get(indexes.section_index(index, sym)?)
+                    .map_or(1, |header| header.sh_addralign.max(1));
+                let (new_sym, patch) = indexes.set_section_index(index, *sym, 0)?;
+                (
+                    Sym {
+                        st_shndx: SHN_COMMON as usize,
+                        st_value: alignment,
+                        ..new_sym
+                    },
+                    patch,
+                )
+            }
+            Target::Section(section) => {
+                let mut matching = elf.section_headers.iter().enumerate().filter(|(_, header)| {
+                    matches!(elf.shdr_strtab.get(header.sh_name), Some(Ok(n)) if n == section)
+                });
+                let (section_index, _) = matching.next().ok_or_else(|| missing(name, section))?;
+                if matching.next().is_some() {
+                    return Err(ambiguous(name, section));
+                }
+                indexes.set_section_index(index, *sym, section_index)?
+            }
+            Target::Undefined => {
+                if elf.header.e_type != ET_REL {
+                    return Err(Error::Message(format!(
+                        "Can't undefine '{}': --undefine-symbol is only supported for relocatable objects",
+                        name
+                    )));
+                }
+                if sym.st_bind() == STB_LOCAL {
+                    return Err(Error::Message(format!(
+                        "Can't undefine '{}': the symbol is local, and undefined symbols must be global",
+                        name
+                    )));
+                }
+                let (new_sym, patch) = indexes.set_section_index(index, *sym, 0)?;
+                (
+                    Sym {
+                        st_shndx: SHN_UNDEF as usize,
+                        st_value: 0,
+                        st_size: 0,
+                        ..new_sym
+                    },
+                    patch,
+                )
+            }
+        };
+        if verbose {
+            match target {
+                Target::Undefined => println!("Undefine: {}", name),
+                _ => println!("Set section {}: {}", target, name),
+            }
+        }
+        Ok(Some((new_sym, patch)))
+    }
+
+    /// Move a Mach-O symbol.
+    ///
+    /// Returns the changed symbol, or `None` if the symbol isn't moved.
+    pub fn nlist(
+        &self,
+        bytes: &[u8],
+        mach: &MachO,
+        nlist: &Nlist,
+        name: &str,
+        verbose: bool,
+    ) -> Result<Option<Nlist>, Error> {
+        let target = match self.target(name) {
+            Some(target) => target,
+            None => return Ok(None),
+        };
+        if !matches!(nlist.n_type & N_TYPE, N_SECT | N_ABS) {
+            return Err(Error::Message(format!(
+                "Can't {} '{}': the symbol isn't defined in a section",
+                target.action(),
+                name
+            )));
+        }
+        let (n_type, n_sect) = match target {
+            Target::Absolute => (N_ABS, 0),
+            Target::Common => {
+                return Err(Error::Message(format!(
+                    "Can't move '{}': COMMON is only supported for ELF objects",
+                    name
+                )))
+            }
+            Target::Undefined => {
+                if mach.header.filetype != MH_OBJECT {
+                    return Err(Error::Message(format!(
+                        "Can't undefine '{}': --undefine-symbol is only supported for relocatable objects",
+                        name
+                    )));
+                }
+                if nlist.n_type & N_EXT == 0 {
+                    return Err(Error::Message(format!(
+                        "Can't undefine '{}': the symbol is local, and undefined symbols must be external",
+                        name
+                    )));
+                }
+                if verbose {
+                    println!("Undefine: {}", name);
+                }
+                // Undefined references have no private extern bit and no definition flags such as
+                // N_WEAK_DEF
+                return Ok(Some(Nlist {
+                    n_type: nlist.n_type & !(N_TYPE | N_PEXT) | N_UNDF,
+                    n_sect: 0,
+                    n_desc: 0,
+                    n_value: 0,
+                    ..nlist.clone()
+                }));
+            }
+            Target::Section(section) => {
+                let (segname, sectname) = match section.split_once(',') {
+                    Some((segname, sectname)) => (Some(segname), sectname),
+                    None => (None, section.as_str()),
+                };
+                let sections = backend::mach::sections(bytes, mach)?;
+                // Sections are numbered from 1 in the order of the load commands
+                let mut matching = sections.iter().enumerate().filter(|(_, candidate)| {
+                    candidate.sectname == sectname
+                        && segname.is_none_or(|segname| candidate.segname == segname)
+                });
+                let (ordinal, _) = matching.next().ok_or_else(|| missing(name, section))?;
+                if matching.next().is_some() {
+                    return Err(ambiguous(name, section));
+                }
+                if ordinal + 1 > u8::MAX as usize {
+                    return Err(Error::Message(format!(
+                        "Can't move '{}': section '{}' has no section number",
+                        name, section
+                    )));
+                }
+                (N_SECT, ordinal + 1)
+            }
+        };
+        if verbose {
+            println!("Set section {}: {}", target, name);
+        }
+        Ok(Some(Nlist {
+            n_type: nlist.n_type & !N_TYPE | n_type,
+            n_sect,
+            ..nlist.clone()
+        }))
+    }
+}
@@ -0,0 +1,97 @@
+//! Load patched shared libraries before they replace the output, to catch loader errors (such
+//! as a corrupt hash table or a missing symbol version) at patch time rather than deployment.
+
+use crate::error::Error;
+use clap::Arg;
+use std::ffi::CString;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+
+pub fn arg() -> Arg<'static, 'static> {
+    Arg::with_name("smoke-test")
+        .long("smoke-test")
+        .takes_value(true)
+        .value_name("TEST")
+        .possible_values(&["dlopen"])
+        .help("Loads the patched shared library before writing OUTPUT")
+        .long_help(
+            "Loads the patched shared library in a child process with dlopen before writing OUTPUT, and fails if the dynamic loader reports an error.  The library must be loadable on this host, and its initializers are run in the child process.",
+        )
+}
+
+/// Returns true if an object can be loaded with `dlopen`.
+fn is_loadable(bytes: &[u8]) -> bool {
+    match goblin::Object::parse(bytes) {
+        Ok(goblin::Object::Elf(elf)) => elf.header.e_type == goblin::elf::header::ET_DYN,
+        Ok(goblin::Object::Mach(goblin::mach::Mach::Binary(mach))) => matches!(
+            mach.header.filetype,
+            goblin::mach::header::MH_DYLIB | goblin::mach::header::MH_BUNDLE
+        ),
+        _ => false,
+    }
+}
+
+/// Load the shared library at `path`, with contents `bytes`, in a child process.
+pub fn dlopen(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    if !is_loadable(bytes) {
+        return Err(Error::Message(
+            "--smoke-test dlopen requires a shared library".to_string(),
+        ));
+    }
+    // dlopen searches the library path for names without a slash
+    let path = if path.parent() == Some(Path::new("")) {
+        Path::new(".").join(path)
+    } else {
+        path.to_path_buf()
+    };
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| Error::Message("Path contains a NUL byte".to_string()))?;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        // Only async-signal-safe calls until exit, apart from the loader itself
+        unsafe {
+            libc::close(fds[0]);
+            if libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL).is_null() {
+                let message = libc::dlerror();
+                if !message.is_null() {
+                    libc::write(fds[1], message.cast(), libc::strlen(message));
+                }
+                libc::_exit(1);
+            }
+            libc::_exit(0);
+        }
+    }
+    unsafe { libc::close(fds[1]) };
+    let mut reader = unsafe { std::fs::File::from_raw_fd(fds[0]) };
+    if pid < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let mut message = Vec::new();
+    reader.read_to_end(&mut message)?;
+    let mut status = 0;
+    if unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let status = std::process::ExitStatus::from_raw(status);
+    if let Some(signal) = status.signal() {
+        return Err(Error::Message(format!(
+            "Smoke test crashed while loading the library (signal {})",
+            signal
+        )));
+    }
+    if !status.success() {
+        return Err(Error::Message(format!(
+            "Smoke test failed to load: {}",
+            String::from_utf8_lossy(&message)
+        )));
+    }
+    Ok(())
+}
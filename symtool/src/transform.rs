@@ -0,0 +1,451 @@
+//! Apply the rules and other changes given on the command line to each object of INPUT.
+//!
+//! The passes for each object format are in the submodules.  Options only some formats support
+//! are checked once for each object, before any pass runs.
+
+mod elf;
+mod mach;
+mod other;
+
+use crate::add::SymbolAdditions;
+use crate::cancel;
+use crate::comment::Comment;
+use crate::error::Error;
+use crate::obfuscate::Obfuscation;
+use crate::rebind::Rebinds;
+use crate::rules::{Format, Pattern, Rules, SymbolKind};
+use crate::scrub::PathScrubs;
+use crate::section::SectionMoves;
+use crate::value::SymbolValues;
+use crate::version::VersionAssignments;
+use clap::ArgMatches;
+use goblin::mach::constants::cputype::{get_arch_from_flag, CPU_SUBTYPE_MASK};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use symtool_backend as backend;
+use symtool_backend::object::{Object, TransformContext};
+use symtool_backend::patch::{Patch, Rooted};
+
+/// The changes applied to each object, read from the command line.
+pub struct Transform<'a> {
+    verbose: bool,
+    print_patch_bytes: bool,
+    reconcile_dynsym: bool,
+    rename_debug_info: bool,
+    rewrite: bool,
+    auto_underscore: bool,
+    define_common: bool,
+    extract_symbol: bool,
+    strip_all: bool,
+    strip_unneeded: bool,
+    discard_all: bool,
+    strip_debug: bool,
+    strip_stabs: bool,
+    strip_file_symbols: bool,
+    strip_versions: bool,
+    compact_strtab: bool,
+
+    /// The architectures of fat binaries transformed with `--arch`, or `None` for all of them
+    archs: Option<Vec<(u32, u32)>>,
+
+    pub rules: Rules,
+    comment: Option<Comment>,
+    rebinds: Option<Rebinds>,
+    section_moves: Option<SectionMoves>,
+    additions: Option<SymbolAdditions>,
+    symbol_values: Option<SymbolValues>,
+    version_assignments: Option<VersionAssignments>,
+    path_scrubs: Option<PathScrubs>,
+    pub obfuscation: Option<Obfuscation<'a>>,
+
+    /// The file being transformed, which is INPUT or DEBUG-INPUT
+    current_path: Cell<&'a str>,
+
+    /// The symbols renamed in every object of INPUT, for split DWARF files
+    all_renamed: RefCell<HashMap<String, String>>,
+}
+
+/// The formats supporting an option.
+const ELF: &[Format] = &[Format::Elf];
+const MACH_O: &[Format] = &[Format::MachO];
+const ELF_AND_MACH_O: &[Format] = &[Format::Elf, Format::MachO];
+
+impl<'a> Transform<'a> {
+    pub fn from_matches(matches: &'a ArgMatches) -> Result<Self, Error> {
+        let strip_unneeded = matches.is_present("strip-unneeded");
+        let archs = matches
+            .values_of("arch")
+            .map(|names| {
+                names
+                    .map(|name| {
+                        get_arch_from_flag(name).ok_or_else(|| {
+                            Error::Message(format!("Unknown architecture '{}'", name))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        Ok(Self {
+            verbose: matches.is_present("verbose"),
+            print_patch_bytes: matches.occurrences_of("verbose") > 1,
+            reconcile_dynsym: matches.is_present("reconcile-dynsym"),
+            rename_debug_info: matches.is_present("rename-debug-info"),
+            rewrite: matches.is_present("rewrite"),
+            auto_underscore: matches.is_present("auto-underscore"),
+            define_common: matches.is_present("define-common"),
+            extract_symbol: matches.is_present("extract-symbol"),
+            strip_all: matches.is_present("strip-all"),
+            strip_unneeded,
+            discard_all: matches.is_present("discard-all"),
+            strip_debug: matches.is_present("strip-debug") || strip_unneeded,
+            strip_stabs: matches.is_present("strip-stabs"),
+            strip_file_symbols: matches.is_present("strip-file-symbols"),
+            strip_versions: matches.is_present("strip-versions"),
+            compact_strtab: matches.is_present("compact-strtab"),
+            archs,
+            rules: Rules::from_matches(matches)?,
+            comment: Comment::from_matches(matches),
+            rebinds: Rebinds::from_matches(matches)?,
+            section_moves: SectionMoves::from_matches(matches)?,
+            additions: SymbolAdditions::from_matches(matches)?,
+            symbol_values: SymbolValues::from_matches(matches)?,
+            version_assignments: VersionAssignments::from_matches(matches)?,
+            path_scrubs: PathScrubs::from_matches(matches)?,
+            obfuscation: Obfuscation::from_matches(matches),
+            current_path: Cell::new(""),
+            all_renamed: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Set the path of the file transformed next, and reset the rules for it.
+    pub fn set_path(&self, path: &'a str) {
+        self.rules.reset();
+        self.current_path.set(path);
+    }
+
+    /// The symbols renamed in every object of INPUT, by old name.
+    pub fn renamed(&self) -> std::cell::Ref<'_, HashMap<String, String>> {
+        self.all_renamed.borrow()
+    }
+
+    /// The options given which only some formats support, and the formats supporting them.
+    ///
+    /// Rules are scoped to the object being transformed, so this is only valid after
+    /// `Rules::set_object`.
+    fn format_specific_options(&self) -> Vec<(&'static str, &'static [Format])> {
+        let options = [
+            (self.archs.is_some(), "--arch", MACH_O),
+            (self.rebinds.is_some(), "--rebind-import", MACH_O),
+            (self.strip_stabs, "--strip-stabs", MACH_O),
+            (self.additions.is_some(), "--add-symbol and --alias", ELF),
+            (self.rules.sets_type(), "--set-type", ELF),
+            (
+                self.version_assignments.is_some(),
+                "--set-symbol-version",
+                ELF,
+            ),
+            (self.strip_versions, "--strip-versions", ELF),
+            (self.rewrite, "--rewrite", ELF_AND_MACH_O),
+            (self.compact_strtab, "--compact-strtab", ELF_AND_MACH_O),
+            (self.strip_all, "--strip-all", ELF_AND_MACH_O),
+            (
+                self.strip_file_symbols,
+                "--strip-file-symbols",
+                ELF_AND_MACH_O,
+            ),
+            (self.path_scrubs.is_some(), "--scrub-paths", ELF_AND_MACH_O),
+            (self.obfuscation.is_some(), "--obfuscate", ELF_AND_MACH_O),
+            (
+                self.rules.prefixes_undefined(),
+                "--prefix-undefined",
+                ELF_AND_MACH_O,
+            ),
+            (
+                self.rules.weakens_undefined(),
+                "--weaken-undefined",
+                ELF_AND_MACH_O,
+            ),
+            (self.strip_unneeded, "--strip-unneeded", ELF_AND_MACH_O),
+            (self.discard_all, "--discard-all", ELF_AND_MACH_O),
+            (self.strip_debug, "--strip-debug", ELF_AND_MACH_O),
+            (self.extract_symbol, "--extract-symbol", ELF_AND_MACH_O),
+            (
+                self.symbol_values.is_some(),
+                "--set-symbol-value",
+                ELF_AND_MACH_O,
+            ),
+            (
+                self.section_moves.is_some(),
+                "--set-symbol-section and --undefine-symbol",
+                ELF_AND_MACH_O,
+            ),
+        ];
+        options
+            .iter()
+            .filter(|(given, _, _)| *given)
+            .map(|(_, option, formats)| (*option, *formats))
+            .collect()
+    }
+
+    /// Fail if an option given isn't supported for objects of `format`, or `None` for the other
+    /// formats.
+    fn check_supported(&self, format: Option<Format>) -> Result<(), Error> {
+        for (option, formats) in self.format_specific_options() {
+            if format.is_none_or(|format| !formats.contains(&format)) {
+                let names = formats
+                    .iter()
+                    .map(|format| format.name())
+                    .collect::<Vec<_>>();
+                let verb = if option.contains(" and ") {
+                    "are"
+                } else {
+                    "is"
+                };
+                return Err(Error::Message(format!(
+                    "{} {} only supported for {} objects",
+                    option,
+                    verb,
+                    names.join(" and ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Transform one object, pushing the patches to `context`.
+    pub fn apply(&self, context: &mut TransformContext) -> Result<(), Error> {
+        let format = match context.object {
+            Object::Elf(_) => Some(Format::Elf),
+            Object::MachO(_) => Some(Format::MachO),
+            Object::Other(_) => None,
+        };
+        self.rules.set_object(context.member, format);
+        self.check_supported(format)?;
+        if let (Some(archs), Object::MachO(mach)) = (&self.archs, &context.object) {
+            let arch = (
+                mach.header.cputype,
+                mach.header.cpusubtype & !CPU_SUBTYPE_MASK,
+            );
+            if !archs.contains(&arch) {
+                if self.verbose {
+                    println!(
+                        "Skip architecture {}",
+                        backend::mach::arch_name(arch.0, arch.1)
+                    );
+                }
+                return Ok(());
+            }
+        }
+        let bytes = context.bytes;
+        let patches = &mut context.patches;
+        let mut renamed = HashMap::new();
+        match &context.object {
+            Object::Elf(elf) => {
+                elf::transform(self, bytes, elf, context.member, patches, &mut renamed)?
+            }
+            Object::MachO(mach) => mach::transform(self, bytes, mach, patches, &mut renamed)?,
+            Object::Other(object) => other::transform(self, object.as_ref(), patches)?,
+        }
+        if self.verbose {
+            for (source_name, symbol) in self.rules.resolutions() {
+                println!("Resolve {}: {}", source_name, symbol);
+            }
+        }
+        self.rules.check_ambiguous()?;
+        self.all_renamed.borrow_mut().extend(
+            renamed
+                .into_iter()
+                .map(|(name, new_name)| (name.to_string(), new_name)),
+        );
+        if self.print_patch_bytes {
+            print_patches(self.current_path.get(), context.member, bytes, patches);
+        }
+        Ok(())
+    }
+
+    /// Fail if a symbol named by an option wasn't found in `path`, after transforming it.
+    ///
+    /// Returns the names of renamed symbols that are allowed to be missing but weren't found.
+    pub fn check_missing(&self, path: &str) -> Result<Vec<String>, Error> {
+        let missing = check_missing_renames(&self.rules, path)?;
+        let symbols = [
+            (
+                "to alias",
+                self.additions
+                    .iter()
+                    .flat_map(|additions| additions.missing())
+                    .next(),
+            ),
+            (
+                "to set the value of",
+                self.symbol_values
+                    .iter()
+                    .flat_map(|values| values.missing())
+                    .next(),
+            ),
+            (
+                "to set the version of",
+                self.version_assignments
+                    .iter()
+                    .flat_map(|assignments| assignments.missing())
+                    .next(),
+            ),
+        ];
+        if let Some((name, action)) = self
+            .section_moves
+            .iter()
+            .flat_map(|moves| moves.missing())
+            .next()
+        {
+            return Err(Error::Message(format!(
+                "Symbol '{}' to {} was not found in {}",
+                name, action, path
+            )));
+        }
+        for (action, name) in &symbols {
+            if let Some(name) = name {
+                return Err(Error::Message(format!(
+                    "Symbol '{}' {} was not found in {}",
+                    name, action, path
+                )));
+            }
+        }
+        Ok(missing)
+    }
+}
+
+/// Apply `patches` to a copy of an ELF object, and call `f` with the patched object.
+///
+/// Passes which move or remove symbols or sections run on the output of the passes before them,
+/// so their patches stay consistent with the earlier ones.  Interrupted runs stop here, between
+/// passes.
+pub(crate) fn apply_and_reparse_elf<T>(
+    bytes: &[u8],
+    patches: &[Patch],
+    f: impl FnOnce(&[u8], &goblin::elf::Elf) -> Result<T, Error>,
+) -> Result<T, Error> {
+    cancel::check()?;
+    let mut patched = bytes.to_vec();
+    for patch in patches {
+        patch.apply(&mut patched);
+    }
+    let elf = backend::elf::parse(&patched)?;
+    f(&patched, &elf)
+}
+
+/// Apply `patches` to a copy of a Mach-O binary, and call `f` with the patched binary, like
+/// `apply_and_reparse_elf`.
+pub(super) fn apply_and_reparse_mach<T>(
+    bytes: &[u8],
+    patches: &[Patch],
+    f: impl FnOnce(&[u8], &goblin::mach::MachO) -> Result<T, Error>,
+) -> Result<T, Error> {
+    cancel::check()?;
+    let mut patched = bytes.to_vec();
+    for patch in patches {
+        patch.apply(&mut patched);
+    }
+    let mach = goblin::mach::MachO::parse(&patched, 0).map_err(backend::error::Error::from)?;
+    f(&patched, &mach)
+}
+
+/// Returns the new name of a symbol after `--rename` and `--prefix-symbols`, if it changes.
+///
+/// `prefixable` is false for symbols whose names aren't symbol names, such as file symbols, and
+/// `kind` is what is known about the symbol, such as whether it is undefined.
+pub(super) fn new_name(
+    rules: &Rules,
+    name: &str,
+    prefixable: bool,
+    kind: SymbolKind,
+) -> Result<Option<String>, Error> {
+    let renamed = rules.rename(name, kind)?;
+    let prefix = match prefixable && !name.is_empty() {
+        true => rules.prefix(name, kind)?,
+        false => None,
+    };
+    Ok(match prefix {
+        Some(prefix) => Some(format!("{}{}", prefix, renamed.as_deref().unwrap_or(name))),
+        None => renamed.map(Cow::into_owned),
+    })
+}
+
+/// Construct a patch renaming a symbol, padding the new name with NUL bytes.
+pub(super) fn rename_patch(name: &Rooted<&str>, new_name: &str) -> Result<Patch, Error> {
+    let mut bytes = new_name.as_bytes().to_vec();
+    bytes.resize(name.len(), 0);
+    Ok(name.patch_with_bytes(&bytes)?)
+}
+
+/// Leave an undefined symbol matching a hidden visibility rule unchanged.
+///
+/// A hidden reference must be resolved within the link, so hiding an import breaks linking
+/// against the shared object that defines it.
+pub(super) fn keep_undefined<T>(name: &str, verbose: bool) -> Option<T> {
+    if verbose {
+        println!("Keep undefined symbol visible: {}", name);
+    }
+    None
+}
+
+/// The number of bytes of a patch printed before eliding the rest.
+const PATCH_BYTES_PRINTED: usize = 16;
+
+/// Format bytes as hex, eliding those past `PATCH_BYTES_PRINTED`.
+fn hex(bytes: &[u8]) -> String {
+    let mut hex: String = bytes
+        .iter()
+        .take(PATCH_BYTES_PRINTED)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if bytes.len() > PATCH_BYTES_PRINTED {
+        hex.push_str("...");
+    }
+    hex
+}
+
+/// Print the location and contents of the patches to an object, for `-vv`.
+fn print_patches(path: &str, member: Option<&str>, bytes: &[u8], patches: &[Patch]) {
+    let object = match member {
+        Some(member) => format!("{}({})", path, member),
+        None => path.to_string(),
+    };
+    for patch in patches {
+        let data = patch.data();
+        let old = bytes
+            .get(patch.offset()..patch.offset() + data.len())
+            .unwrap_or_default();
+        println!(
+            "Patch {} at {:#x}, {} byte(s): {} -> {}",
+            object,
+            patch.offset(),
+            data.len(),
+            hex(old),
+            hex(data)
+        );
+    }
+}
+
+/// Fail if a rename rule didn't match any symbol, unless the rule allows it.
+///
+/// Returns the names that are allowed to be missing but weren't found.
+fn check_missing_renames(rules: &Rules, path: &str) -> Result<Vec<String>, Error> {
+    let mut missing = Vec::new();
+    for rule in rules.missing_renames() {
+        if !rule.modifiers.ignore_missing {
+            return Err(Error::Message(match rule.pattern {
+                Pattern::Exact(_) => format!(
+                    "Symbol '{}' to be renamed was not found in {} (allow this with --ignore-missing)",
+                    rule.pattern, path
+                ),
+                _ => format!(
+                    "No symbol matching '{}' to be renamed was found in {} (allow this with --ignore-missing)",
+                    rule.pattern, path
+                ),
+            }));
+        }
+        missing.push(rule.pattern.to_string());
+    }
+    Ok(missing)
+}
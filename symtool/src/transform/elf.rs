@@ -0,0 +1,570 @@
+//! The passes applied to ELF objects.
+
+use super::{apply_and_reparse_elf, keep_undefined, new_name, rename_patch, Transform};
+use crate::check;
+use crate::common::CommonAllocator;
+use crate::debug;
+use crate::error::Error;
+use crate::extract;
+use crate::rules::{Binding, Rules, SymbolKind, SymbolType, Visibility};
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::sym::{
+    Sym, STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_COMMON, STT_FILE, STT_FUNC, STT_GNU_IFUNC,
+    STT_NOTYPE, STT_OBJECT, STT_SECTION, STT_TLS, STV_DEFAULT, STV_HIDDEN, STV_INTERNAL,
+    STV_PROTECTED,
+};
+use goblin::elf::Elf;
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use symtool_backend as backend;
+use symtool_backend::elf::SymtabIter;
+use symtool_backend::patch::Patch;
+
+/// Transform an ELF object.
+///
+/// The passes after the first run on the output of the passes before them, so their patches stay
+/// consistent.  `renamed` receives the symbols renamed, by old name.
+pub(super) fn transform<'a>(
+    t: &Transform,
+    bytes: &'a [u8],
+    elf: &Elf,
+    member: Option<&str>,
+    patches: &mut Vec<Patch>,
+    renamed: &mut HashMap<&'a str, String>,
+) -> Result<(), Error> {
+    if let Some(symtab) = elf_symtab(bytes, elf, member)? {
+        change_symbols(t, bytes, elf, symtab, patches, renamed)?;
+    }
+    if let Some(comment) = &t.comment {
+        patches.extend(comment.elf_patches(bytes, elf, t.verbose)?);
+    }
+    if t.rename_debug_info {
+        rename_debug_info(t, bytes, elf, patches, renamed)?;
+    }
+    if !elf.dynsyms.is_empty() {
+        // A rebuilt string table moves the names of .symtab
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            reconcile_dynsym(t, patched, elf)
+        })?);
+    }
+    if t.extract_symbol {
+        patches.extend(extract::elf_patches(bytes, elf, t.verbose)?);
+    }
+    if t.rules.sets_binding() {
+        // Symbols made local in place must be moved before the global ones
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            Ok(backend::elf::sort_locals(patched, elf)?)
+        })?);
+    }
+    if t.strip_debug {
+        // Symbols are moved, so they must include the other changes
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            let (names, debug_patches) = backend::elf::strip_debug(patched, elf)?;
+            if t.verbose {
+                for name in names {
+                    println!("Strip debug: {}", name);
+                }
+            }
+            Ok(debug_patches)
+        })?);
+    }
+    if t.strip_versions {
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            let (names, version_patches) = backend::elf::strip_versions(patched, elf)?;
+            if t.verbose {
+                for name in names {
+                    println!("Strip versions: {}", name);
+                }
+            }
+            Ok(version_patches)
+        })?);
+    }
+    if let Some(assignments) = &t.version_assignments {
+        if t.rewrite {
+            patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+                assignments.elf_definition_patches(patched, elf, t.verbose)
+            })?);
+        }
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            assignments.elf_patches(patched, elf, t.verbose)
+        })?);
+    }
+    if t.strip_unneeded {
+        // Relocations are scanned after the debug sections are removed
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            let kept = elf_kept_symbols(patched, elf, &t.rules)?;
+            let (count, unneeded_patches) =
+                backend::elf::strip_unneeded(patched, elf, &|index| kept.contains(&index))?;
+            if t.verbose && count > 0 {
+                println!("Strip unneeded: {} symbol(s)", count);
+            }
+            Ok(unneeded_patches)
+        })?);
+    }
+    if t.discard_all {
+        // Relocations are scanned after the other symbols are removed
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            let kept = elf_kept_symbols(patched, elf, &t.rules)?;
+            let (count, discard_patches) =
+                backend::elf::discard_locals(patched, elf, &|index| kept.contains(&index))?;
+            if t.verbose && count > 0 {
+                println!("Discard locals: {} symbol(s)", count);
+            }
+            Ok(discard_patches)
+        })?);
+    }
+    if !t.rules.strips.is_empty() {
+        // Symbols are matched by their new names, after the other changes
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            strip_symbols(t, patched, elf)
+        })?);
+    }
+    if t.strip_file_symbols {
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            let (count, file_patches) = backend::elf::strip_file_symbols(patched, elf)?;
+            if t.verbose && count > 0 {
+                println!("Strip file symbols: {} symbol(s)", count);
+            }
+            Ok(file_patches)
+        })?);
+    }
+    if t.strip_all {
+        // Symbols kept by --keep-symbols are moved like other stripped symbols
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            let kept = elf_kept_symbols(patched, elf, &t.rules)?;
+            let (count, strip_patches) =
+                backend::elf::strip_all(patched, elf, &|index| kept.contains(&index))?;
+            if t.verbose && count > 0 {
+                println!("Strip all: {} symbol(s)", count);
+            }
+            Ok(strip_patches)
+        })?);
+    }
+    if let Some(additions) = &t.additions {
+        // The symbol table is moved, so it must include the other changes
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            additions.elf_patches(patched, elf, t.verbose)
+        })?);
+    }
+    if t.compact_strtab {
+        // Names are kept only if the symbols left after the other changes use them
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            let (size, new_size, compact_patches) = backend::elf::compact_strtab(patched, elf)?;
+            if t.verbose && new_size < size {
+                println!("Compact string table: {} -> {} bytes", size, new_size);
+            }
+            Ok(compact_patches)
+        })?);
+    }
+    if t.rewrite {
+        // Tables appended by the other changes are moved into place, and the
+        // tables they replace are dropped
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            Ok(backend::elf::rebuild_tables(patched, elf)?)
+        })?);
+    }
+    Ok(())
+}
+
+/// Apply the rules and the other changes to each symbol of `table`, `.symtab` or `.dynsym`.
+fn change_symbols<'a>(
+    t: &Transform,
+    bytes: &[u8],
+    elf: &Elf,
+    (table, iter): (&str, SymtabIter<'a>),
+    patches: &mut Vec<Patch>,
+    renamed: &mut HashMap<&'a str, String>,
+) -> Result<(), Error> {
+    let indexes = iter.extended_indexes();
+    let mut common = if t.define_common {
+        CommonAllocator::from_elf(elf, indexes.clone())?
+    } else {
+        None
+    };
+    let mut strtab = if t.rewrite && table == ".symtab" {
+        backend::elf::rebuild_strtab(bytes, elf)?
+    } else {
+        None
+    };
+    let mut dynamic_renames = Vec::new();
+    for (index, (ref name, ref sym)) in iter
+        .collect::<backend::error::Result<Vec<_>>>()?
+        .into_iter()
+        .enumerate()
+    {
+        let (new_name, mut new_sym) = if let Some(name) = name {
+            let prefixable = sym.st_type() != STT_FILE && sym.st_type() != STT_SECTION;
+            let new_name = new_name(&t.rules, name.deref(), prefixable, elf_symbol_kind(sym))?;
+            // Mapping symbols, such as $x on AArch64, must keep their names
+            let local = sym.st_bind() == STB_LOCAL
+                && sym.st_shndx != SHN_UNDEF as usize
+                && !name.starts_with('$');
+            let new_name = match &t.obfuscation {
+                Some(obfuscation)
+                    if new_name.is_none()
+                        && prefixable
+                        && !name.is_empty()
+                        && t.rules.obfuscates(name, local) =>
+                {
+                    Some(obfuscation.name(name, !t.rewrite, t.verbose)?)
+                }
+                _ => new_name,
+            };
+            let new_name = match &t.path_scrubs {
+                Some(scrubs) if sym.st_type() == STT_FILE => scrubs
+                    .symbol_name(new_name.as_deref().unwrap_or(name), t.verbose)?
+                    .or(new_name),
+                _ => new_name,
+            };
+            if new_name.is_some() && table == ".dynsym" && !t.rewrite {
+                return Err(Error::Message(format!(
+                    "Can't rename '{}': the object has no .symtab, and renaming symbols in .dynsym in place would invalidate its hash tables, so it requires --rewrite",
+                    name.value
+                )));
+            }
+            let mut new_sym = change_sym(sym, name, t.verbose, &t.rules)?;
+            if let (".dynsym", Some(new_sym)) = (table, &new_sym) {
+                check_dynsym_binding(sym, new_sym, name)?;
+            }
+            if let Some(common) = &mut common {
+                let current = new_sym.unwrap_or(**sym);
+                new_sym = common.define(index, &current, name, t.verbose)?.or(new_sym);
+            }
+            if let Some(moves) = &t.section_moves {
+                let current = new_sym.unwrap_or(**sym);
+                if let Some((moved, index_patch)) =
+                    moves.elf_sym(elf, &indexes, index, &current, name, t.verbose)?
+                {
+                    new_sym = Some(moved);
+                    patches.extend(index_patch);
+                }
+            }
+            if let Some(values) = &t.symbol_values {
+                let current = new_sym.unwrap_or(**sym);
+                new_sym = values.elf_sym(&current, name, t.verbose)?.or(new_sym);
+            }
+            (new_name, new_sym)
+        } else {
+            (None, None)
+        };
+        if let (Some(name), Some(new_name)) = (name, new_name) {
+            match &mut strtab {
+                Some(strtab) => {
+                    let mut current = new_sym.unwrap_or(**sym);
+                    current.st_name = strtab.add(&new_name);
+                    new_sym = Some(current);
+                }
+                None if t.rewrite => dynamic_renames.push((index, new_name.clone())),
+                None => patches.push(rename_patch(name, &new_name)?),
+            }
+            renamed.insert(name.value, new_name);
+        }
+        if let Some(new_sym) = new_sym {
+            patches.push(sym.patch_with(new_sym)?);
+        }
+    }
+    let bss = match common {
+        Some(common) => {
+            let (common_patches, bss) = common.patches()?;
+            patches.extend(common_patches);
+            bss
+        }
+        None => None,
+    };
+    if let Some(strtab) = strtab {
+        patches.extend(strtab.patches()?);
+    }
+    if let Some(bss) = bss {
+        // .bss is added after the tables appended by the other changes
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            if t.verbose {
+                println!("Add section: .bss");
+            }
+            Ok(backend::elf::add_sections(patched, elf, &[(".bss", bss)])?)
+        })?);
+    }
+    if !dynamic_renames.is_empty() {
+        // .dynsym is rewritten with its hash tables, so it must include the other changes
+        patches.extend(apply_and_reparse_elf(bytes, patches, |patched, elf| {
+            let renames = dynamic_renames
+                .iter()
+                .map(|(index, name)| (*index, name.as_str()))
+                .collect::<Vec<_>>();
+            Ok(backend::elf::rename_dynamic_symbols(
+                patched, elf, &renames,
+            )?)
+        })?);
+    }
+    Ok(())
+}
+
+/// Rename the symbols renamed by the rules in the debug info, and scrub its paths.
+fn rename_debug_info(
+    t: &Transform,
+    bytes: &[u8],
+    elf: &Elf,
+    patches: &mut Vec<Patch>,
+    renamed: &HashMap<&str, String>,
+) -> Result<(), Error> {
+    if let Some(strings) = debug::elf_debug_strings(bytes, elf, ".debug_str")? {
+        let range = strings.range();
+        let debug_patches = debug::rename_strings(strings, renamed, t.verbose)?;
+        if !debug_patches.is_empty() {
+            let tables = backend::dwarf::AccelTable::from_elf(elf);
+            patches.extend(debug::update_accel_tables(
+                bytes,
+                &debug_patches,
+                range,
+                &tables,
+                t.verbose,
+            )?);
+        }
+        patches.extend(debug_patches);
+    }
+    // Split DWARF sections kept in the object with -gsplit-dwarf=single
+    if let Some(strings) = debug::elf_debug_strings(bytes, elf, ".debug_str.dwo")? {
+        patches.extend(debug::rename_strings(strings, renamed, t.verbose)?);
+    }
+    if let Some(scrubs) = &t.path_scrubs {
+        // DWARF 5 keeps the paths of the line table in .debug_line_str
+        for section in &[".debug_str", ".debug_line_str"] {
+            if let Some(strings) = debug::elf_debug_strings(bytes, elf, section)? {
+                patches.extend(scrubs.strings(strings, t.verbose)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Report the symbols whose attributes differ between `.symtab` and `.dynsym`, or reconcile them
+/// with `--reconcile-dynsym`.
+fn reconcile_dynsym(t: &Transform, bytes: &[u8], elf: &Elf) -> Result<Vec<Patch>, Error> {
+    let mut reconciled = Vec::new();
+    for divergence in check::divergences(bytes, elf)? {
+        if t.reconcile_dynsym {
+            if t.verbose {
+                println!("Reconcile .dynsym: {}", divergence.name);
+            }
+            check_dynsym_binding(&divergence.dynsym, &divergence.symtab, divergence.name)?;
+            reconciled.push(divergence.reconcile()?);
+        } else {
+            eprintln!("warning: {}", divergence);
+        }
+    }
+    Ok(reconciled)
+}
+
+/// Remove the symbols matched by `--strip-symbol` and `--strip-regex`.
+fn strip_symbols(t: &Transform, bytes: &[u8], elf: &Elf) -> Result<Vec<Patch>, Error> {
+    let referenced = backend::elf::referenced_symbols(bytes, elf)?;
+    let mut stripped = HashSet::new();
+    if let Some(iter) = backend::elf::SymtabIter::symtab_from_elf(bytes, elf)? {
+        for (index, item) in iter.enumerate() {
+            let (name, sym) = match item? {
+                (Some(name), sym) => (name, sym),
+                (None, _) => continue,
+            };
+            if !t.rules.strip(&name, elf_symbol_kind(&sym))? {
+                continue;
+            }
+            if referenced.contains(&index) {
+                return Err(Error::Message(format!(
+                    "Can't strip '{}', since relocations or section groups refer to it",
+                    *name
+                )));
+            }
+            if t.verbose {
+                println!("Strip symbol: {}", *name);
+            }
+            stripped.insert(index);
+        }
+    }
+    let (_, strip_patches) =
+        backend::elf::strip_symbols(bytes, elf, &|index, _| stripped.contains(&index))?;
+    Ok(strip_patches)
+}
+
+/// Fail if a change to a `.dynsym` entry would make it local.
+///
+/// Local symbols must precede the global ones counted by the `sh_info` of `.dynsym`, and moving
+/// them would invalidate its hash and version tables, so they can't be made local in place.
+pub(crate) fn check_dynsym_binding(sym: &Sym, new_sym: &Sym, name: &str) -> Result<(), Error> {
+    if sym.st_bind() != STB_LOCAL && new_sym.st_bind() == STB_LOCAL {
+        return Err(Error::Message(format!(
+            "Can't make '{}' local in .dynsym: local symbols can't be moved before the global ones without invalidating its hash tables",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Find the symbol table to edit in an ELF object, and the name of its section.
+///
+/// Shared objects stripped of `.symtab` still have `.dynsym`, which is edited instead.
+fn elf_symtab<'a>(
+    bytes: &'a [u8],
+    elf: &goblin::elf::Elf,
+    member: Option<&str>,
+) -> Result<Option<(&'static str, backend::elf::SymtabIter<'a>)>, Error> {
+    let prefix = member.map_or(String::new(), |member| format!("{}: ", member));
+    if let Some(iter) = backend::elf::SymtabIter::symtab_from_elf(bytes, elf)? {
+        return Ok(Some((".symtab", iter)));
+    }
+    if let Some(iter) = backend::elf::SymtabIter::dynsym_from_elf(bytes, elf)? {
+        eprintln!("note: {}no .symtab, editing .dynsym instead", prefix);
+        return Ok(Some((".dynsym", iter)));
+    }
+    eprintln!(
+        "warning: {}no .symtab or .dynsym, no symbols changed",
+        prefix
+    );
+    Ok(None)
+}
+
+/// The indices of the `.symtab` symbols listed by `--keep-symbols`.
+fn elf_kept_symbols(
+    bytes: &[u8],
+    elf: &goblin::elf::Elf,
+    rules: &Rules,
+) -> Result<HashSet<usize>, Error> {
+    let mut kept = HashSet::new();
+    if !rules.keeps_symbols() {
+        return Ok(kept);
+    }
+    if let Some(iter) = backend::elf::SymtabIter::symtab_from_elf(bytes, elf)? {
+        for (index, item) in iter.enumerate() {
+            if let (Some(name), _) = item? {
+                if rules.keep(&name) {
+                    kept.insert(index);
+                }
+            }
+        }
+    }
+    Ok(kept)
+}
+
+fn set_sym_visibility(s: &Sym, visibility: Visibility, name: &str, verbose: bool) -> Sym {
+    if verbose {
+        println!("Set visibility {}: {}", visibility, name);
+    }
+    let other = match visibility {
+        Visibility::Default => STV_DEFAULT,
+        Visibility::Hidden => STV_HIDDEN,
+        Visibility::Protected => STV_PROTECTED,
+        Visibility::Internal => STV_INTERNAL,
+    };
+    // Only the visibility bits change, keeping the value, whose bit 0 marks Thumb functions
+    Sym {
+        st_other: (s.st_other & 0xfc) | other,
+        ..*s
+    }
+}
+
+fn set_sym_binding(s: &Sym, binding: Binding, name: &str, verbose: bool) -> Sym {
+    if verbose {
+        println!("Set binding {}: {}", binding, name);
+    }
+    let bind = match binding {
+        Binding::Local => STB_LOCAL,
+        Binding::Global => STB_GLOBAL,
+        Binding::Weak => STB_WEAK,
+    };
+    Sym {
+        st_info: (bind << 4) | (s.st_info & 0xf),
+        ..*s
+    }
+}
+
+fn set_sym_type(s: &Sym, symbol_type: SymbolType, name: &str, verbose: bool) -> Sym {
+    if verbose {
+        println!("Set type {}: {}", symbol_type, name);
+    }
+    let typ = match symbol_type {
+        SymbolType::Func => STT_FUNC,
+        SymbolType::Object => STT_OBJECT,
+        SymbolType::NoType => STT_NOTYPE,
+        SymbolType::Tls => STT_TLS,
+    };
+    Sym {
+        st_info: (s.st_info & 0xf0) | typ,
+        ..*s
+    }
+}
+
+/// The kind of an ELF symbol, as matched by restricted rules.  Section and file symbols have no
+/// type.
+fn elf_symbol_kind(sym: &Sym) -> SymbolKind {
+    let symbol_type = match sym.st_type() {
+        STT_FUNC | STT_GNU_IFUNC => Some(SymbolType::Func),
+        STT_OBJECT | STT_COMMON => Some(SymbolType::Object),
+        STT_TLS => Some(SymbolType::Tls),
+        STT_NOTYPE => Some(SymbolType::NoType),
+        _ => None,
+    };
+    SymbolKind {
+        symbol_type,
+        defined: Some(sym.st_shndx != SHN_UNDEF as usize),
+    }
+}
+
+fn change_sym(sym: &Sym, name: &str, verbose: bool, rules: &Rules) -> Result<Option<Sym>, Error> {
+    let visibility_applies =
+        (sym.st_bind() == STB_GLOBAL || sym.st_bind() == STB_WEAK) && sym.st_type() != STT_NOTYPE;
+    let binding_applies = rules.sets_binding()
+        && sym.st_shndx != SHN_UNDEF as usize
+        && sym.st_type() != STT_FILE
+        && sym.st_type() != STT_SECTION;
+    let type_applies =
+        rules.sets_type() && sym.st_type() != STT_FILE && sym.st_type() != STT_SECTION;
+    let weak_reference_applies = rules.weakens_undefined()
+        && sym.st_shndx == SHN_UNDEF as usize
+        && sym.st_bind() == STB_GLOBAL;
+    if !visibility_applies && !binding_applies && !type_applies && !weak_reference_applies {
+        return Ok(None);
+    }
+    let attributes = rules.attributes(name, elf_symbol_kind(sym))?;
+    let mut new_sym = None;
+    if visibility_applies {
+        new_sym = match attributes.visibility {
+            Some(Visibility::Default) => {
+                Some(set_sym_visibility(sym, Visibility::Default, name, verbose))
+            }
+            Some(_) if sym.st_shndx == SHN_UNDEF as usize && !rules.allow_undefined_visibility => {
+                keep_undefined(name, verbose)
+            }
+            Some(visibility) => Some(set_sym_visibility(sym, visibility, name, verbose)),
+            None => None,
+        };
+    }
+    let binding = attributes.binding.or_else(|| {
+        let global = sym.st_bind() == STB_GLOBAL || sym.st_bind() == STB_WEAK;
+        let visibility = new_sym.unwrap_or(*sym).st_visibility();
+        let hidden = visibility == STV_HIDDEN || visibility == STV_INTERNAL;
+        rules.default_binding(name, global, sym.st_bind() == STB_WEAK, hidden)
+    });
+    if let (true, Some(binding)) = (binding_applies, binding) {
+        new_sym = Some(set_sym_binding(
+            &new_sym.unwrap_or(*sym),
+            binding,
+            name,
+            verbose,
+        ));
+    }
+    if let (true, Some(symbol_type)) = (type_applies, attributes.symbol_type) {
+        new_sym = Some(set_sym_type(
+            &new_sym.unwrap_or(*sym),
+            symbol_type,
+            name,
+            verbose,
+        ));
+    }
+    if weak_reference_applies && attributes.weak_reference {
+        new_sym = Some(set_sym_binding(
+            &new_sym.unwrap_or(*sym),
+            Binding::Weak,
+            name,
+            verbose,
+        ));
+    }
+    Ok(new_sym)
+}
@@ -0,0 +1,540 @@
+//! The passes applied to Mach-O binaries.
+
+use super::{apply_and_reparse_mach, keep_undefined, new_name, rename_patch, Transform};
+use crate::debug;
+use crate::error::Error;
+use crate::extract;
+use crate::rebind;
+use crate::rules::{Binding, Rules, SymbolKind, SymbolType, Visibility};
+use goblin::mach::constants::{
+    SECTION_TYPE, S_ATTR_PURE_INSTRUCTIONS, S_ATTR_SOME_INSTRUCTIONS,
+    S_THREAD_LOCAL_INIT_FUNCTION_POINTERS, S_THREAD_LOCAL_REGULAR, S_THREAD_LOCAL_VARIABLES,
+    S_THREAD_LOCAL_VARIABLE_POINTERS, S_THREAD_LOCAL_ZEROFILL,
+};
+use goblin::mach::header::MH_OBJECT;
+use goblin::mach::symbols::{
+    Nlist, N_EXT, N_FUN, N_GSYM, N_LCSYM, N_OSO, N_PEXT, N_SECT, N_SO, N_SOL, N_STAB, N_STSYM,
+    N_TYPE, N_UNDF, N_WEAK_DEF, N_WEAK_REF,
+};
+use goblin::mach::MachO;
+use std::collections::{HashMap, HashSet};
+use symtool_backend as backend;
+use symtool_backend::mach::LdDirective;
+use symtool_backend::patch::Patch;
+
+/// Transform a Mach-O binary.
+///
+/// Like ELF objects, the passes after the first run on the output of the passes before them.
+/// `renamed` receives the symbols renamed, by old name.
+pub(super) fn transform<'a>(
+    t: &Transform,
+    bytes: &'a [u8],
+    mach: &MachO,
+    patches: &mut Vec<Patch>,
+    renamed: &mut HashMap<&'a str, String>,
+) -> Result<(), Error> {
+    let rebind_ordinals = match &t.rebinds {
+        Some(rebinds) => rebinds.resolve(mach)?,
+        None => HashMap::new(),
+    };
+    change_symbols(t, bytes, mach, &rebind_ordinals, patches, renamed)?;
+    if let Some(rebinds) = &t.rebinds {
+        patches.extend(rebinds.bind_patches(bytes, mach, &rebind_ordinals, t.verbose)?);
+    }
+    if let Some(comment) = &t.comment {
+        patches.extend(comment.mach_patches(bytes, mach, t.verbose)?);
+    }
+    if t.rename_debug_info {
+        rename_debug_info(t, bytes, mach, patches, renamed)?;
+    }
+    if t.extract_symbol {
+        patches.extend(extract::mach_patches(bytes, mach, t.verbose)?);
+    }
+    let undefines = t
+        .section_moves
+        .as_ref()
+        .is_some_and(|moves| moves.undefines());
+    if t.rules.sets_binding() || undefines {
+        // Symbols whose binding changed in place must be moved to their group
+        patches.extend(apply_and_reparse_mach(bytes, patches, |patched, mach| {
+            Ok(backend::mach::sort_symbols(patched, mach)?)
+        })?);
+    }
+    if t.strip_stabs || t.strip_debug || t.strip_all || t.discard_all {
+        // The symbol table is rewritten, so it must include the other changes
+        patches.extend(apply_and_reparse_mach(bytes, patches, |patched, mach| {
+            strip_debugging_symbols(t, patched, mach)
+        })?);
+    }
+    if !t.rules.strips.is_empty() {
+        // Symbols are matched by their new names, after the other changes
+        patches.extend(apply_and_reparse_mach(bytes, patches, |patched, mach| {
+            strip_symbols(t, patched, mach)
+        })?);
+    }
+    if t.strip_file_symbols {
+        patches.extend(apply_and_reparse_mach(bytes, patches, |patched, mach| {
+            let (count, file_patches) = backend::mach::strip_file_symbols(patched, mach)?;
+            if t.verbose && count > 0 {
+                println!("Strip file symbols: {} debugging symbol(s)", count);
+            }
+            Ok(file_patches)
+        })?);
+    }
+    if t.strip_debug {
+        patches.extend(apply_and_reparse_mach(bytes, patches, |patched, mach| {
+            let (names, dwarf_patches) = backend::mach::strip_dwarf(patched, mach)?;
+            if t.verbose {
+                for name in names {
+                    println!("Strip debug: __DWARF,{}", name);
+                }
+            }
+            Ok(dwarf_patches)
+        })?);
+    }
+    if t.compact_strtab {
+        // Names are kept only if the symbols left after the other changes use them
+        patches.extend(apply_and_reparse_mach(bytes, patches, |patched, mach| {
+            let (size, new_size, compact_patches) = backend::mach::compact_strtab(patched, mach)?;
+            if t.verbose && new_size < size {
+                println!("Compact string table: {} -> {} bytes", size, new_size);
+            }
+            Ok(compact_patches)
+        })?);
+    }
+    Ok(())
+}
+
+/// Apply the rules and the other changes to each symbol, and rename the linker directives
+/// naming renamed symbols.
+fn change_symbols<'a>(
+    t: &Transform,
+    bytes: &'a [u8],
+    mach: &MachO,
+    rebind_ordinals: &HashMap<&str, u16>,
+    patches: &mut Vec<Patch>,
+    renamed: &mut HashMap<&'a str, String>,
+) -> Result<(), Error> {
+    let relocatable = mach.header.filetype == MH_OBJECT;
+    let section_types = mach_section_types(mach)?;
+    let iter = match backend::mach::SymtabIter::from_mach(bytes, mach) {
+        Some(iter) => iter,
+        None => return Ok(()),
+    };
+    let symbols = iter.collect::<backend::error::Result<Vec<_>>>()?;
+    let mut strtab = if t.rewrite {
+        backend::mach::rebuild_strtab(bytes, mach)?
+    } else {
+        None
+    };
+    let mut directives = Vec::new();
+    for (name, nlist) in &symbols {
+        let (new_name, mut new_nlist) = if let Some(name) = name {
+            // Rules never apply to linker directives, which must keep their
+            // names and stay exported
+            if let Some(directive) = LdDirective::parse(name) {
+                directives.push((name, nlist, directive));
+                continue;
+            }
+            // Match C names without the leading underscore, and keep it when
+            // renaming
+            let (prefix, rule_name) = match (t.auto_underscore, name.strip_prefix('_')) {
+                (true, Some(stripped)) => ("_", stripped),
+                _ => ("", name.value),
+            };
+            // Debugging entries only name symbols for functions and variables
+            let prefixable = nlist.n_type & N_STAB == 0
+                || [N_FUN, N_GSYM, N_STSYM, N_LCSYM].contains(&nlist.n_type);
+            let kind = nlist_symbol_kind(nlist, &section_types);
+            let new_name = new_name(&t.rules, rule_name, prefixable, kind)?
+                .map(|new_name| format!("{}{}", prefix, new_name));
+            let local = nlist.n_type & N_STAB == 0
+                && nlist.n_type & N_EXT == 0
+                && nlist.n_type & N_TYPE != N_UNDF;
+            let new_name = match &t.obfuscation {
+                Some(obfuscation)
+                    if new_name.is_none()
+                        && nlist.n_type & N_STAB == 0
+                        && !rule_name.is_empty()
+                        && t.rules.obfuscates(rule_name, local) =>
+                {
+                    let obfuscated = obfuscation.name(rule_name, strtab.is_none(), t.verbose)?;
+                    Some(format!("{}{}", prefix, obfuscated))
+                }
+                _ => new_name,
+            };
+            // Debugging entries naming source and object files
+            let new_name = match &t.path_scrubs {
+                Some(scrubs) if [N_SO, N_SOL, N_OSO].contains(&nlist.n_type) => scrubs
+                    .symbol_name(new_name.as_deref().unwrap_or(name), t.verbose)?
+                    .or(new_name),
+                _ => new_name,
+            };
+            let mut new_nlist =
+                change_nlist(nlist, rule_name, t.verbose, &t.rules, relocatable, kind)?;
+            if let (N_UNDF, Some(ordinal)) =
+                (nlist.n_type & N_TYPE, rebind_ordinals.get(name.value))
+            {
+                let current = new_nlist.unwrap_or_else(|| (**nlist).clone());
+                new_nlist = Some(rebind::set_nlist_ordinal(&current, *ordinal));
+            }
+            if let Some(moves) = &t.section_moves {
+                let current = new_nlist.clone().unwrap_or_else(|| (**nlist).clone());
+                if let Some(moved) = moves.nlist(bytes, mach, &current, rule_name, t.verbose)? {
+                    new_nlist = Some(moved);
+                }
+            }
+            if let Some(values) = &t.symbol_values {
+                let current = new_nlist.clone().unwrap_or_else(|| (**nlist).clone());
+                if let Some(changed) = values.nlist(&current, rule_name, t.verbose)? {
+                    new_nlist = Some(changed);
+                }
+            }
+            (new_name, new_nlist)
+        } else {
+            (None, None)
+        };
+        if let (Some(name), Some(new_name)) = (name, new_name) {
+            match &mut strtab {
+                Some(strtab) => {
+                    let mut current = new_nlist.unwrap_or_else(|| (**nlist).clone());
+                    current.n_strx = strtab.add(&new_name);
+                    new_nlist = Some(current);
+                }
+                None => patches.push(rename_patch(name, &new_name)?),
+            }
+            renamed.insert(name.value, new_name);
+        }
+        if let Some(new_nlist) = new_nlist {
+            patches.push(nlist.patch_with(new_nlist)?);
+        }
+    }
+    // Directives follow renames of the symbol they apply to
+    for (name, nlist, directive) in directives {
+        let new_symbol = match directive.symbol() {
+            Some(symbol) => renamed.get(symbol),
+            None => None,
+        };
+        if let Some(new_symbol) = new_symbol {
+            let new_name = directive.with_symbol(new_symbol).to_string();
+            if t.verbose {
+                println!("Rename linker directive: {} -> {}", **name, new_name);
+            }
+            match &mut strtab {
+                Some(strtab) => patches.push(nlist.patch_with(Nlist {
+                    n_strx: strtab.add(&new_name),
+                    ..(**nlist).clone()
+                })?),
+                None => patches.push(rename_patch(name, &new_name)?),
+            }
+        }
+    }
+    if let Some(strtab) = strtab {
+        patches.extend(strtab.patches()?);
+    }
+    Ok(())
+}
+
+/// Rename the symbols renamed by the rules in the debug info, and scrub its paths.
+fn rename_debug_info(
+    t: &Transform,
+    bytes: &[u8],
+    mach: &MachO,
+    patches: &mut Vec<Patch>,
+    renamed: &HashMap<&str, String>,
+) -> Result<(), Error> {
+    if let Some(strings) = backend::mach::section_strings(bytes, mach, "__DWARF", "__debug_str")? {
+        // Debug info refers to C symbols without the leading underscore
+        let renamed = renamed
+            .iter()
+            .filter_map(|(old, new)| {
+                Some((old.strip_prefix('_')?, new.strip_prefix('_')?.to_string()))
+            })
+            .collect();
+        let range = strings.range();
+        let debug_patches = debug::rename_strings(strings, &renamed, t.verbose)?;
+        if !debug_patches.is_empty() {
+            let tables = backend::dwarf::AccelTable::from_mach(mach)?;
+            patches.extend(debug::update_accel_tables(
+                bytes,
+                &debug_patches,
+                range,
+                &tables,
+                t.verbose,
+            )?);
+        }
+        patches.extend(debug_patches);
+    }
+    if let Some(scrubs) = &t.path_scrubs {
+        for section in &["__debug_str", "__debug_line_str"] {
+            if let Some(strings) = backend::mach::section_strings(bytes, mach, "__DWARF", section)?
+            {
+                patches.extend(scrubs.strings(strings, t.verbose)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remove symbols with `--strip-all`, `--strip-unneeded` or `--discard-all`, or otherwise
+/// debugging entries with `--strip-stabs` or `--strip-debug`.
+fn strip_debugging_symbols(t: &Transform, bytes: &[u8], mach: &MachO) -> Result<Vec<Patch>, Error> {
+    let kept = mach_kept_symbols(bytes, mach, &t.rules)?;
+    if t.strip_all {
+        let (count, strip_patches) =
+            backend::mach::strip_all(bytes, mach, &|index| kept.contains(&index))?;
+        if t.verbose && count > 0 {
+            println!("Strip all: {} symbol(s)", count);
+        }
+        Ok(strip_patches)
+    } else if t.strip_unneeded {
+        let (count, unneeded_patches) =
+            backend::mach::strip_unneeded(bytes, mach, &|index| kept.contains(&index))?;
+        if t.verbose && count > 0 {
+            println!("Strip unneeded: {} symbol(s)", count);
+        }
+        Ok(unneeded_patches)
+    } else if t.discard_all {
+        let (count, discard_patches) =
+            backend::mach::discard_locals(bytes, mach, &|index| kept.contains(&index))?;
+        if t.verbose && count > 0 {
+            println!("Discard locals: {} symbol(s)", count);
+        }
+        Ok(discard_patches)
+    } else {
+        let (count, stab_patches) = backend::mach::strip_stabs(bytes, mach)?;
+        if t.verbose && count > 0 {
+            println!("Strip stabs: {} debugging symbol(s)", count);
+        }
+        Ok(stab_patches)
+    }
+}
+
+/// Remove the symbols matched by `--strip-symbol` and `--strip-regex`.
+fn strip_symbols(t: &Transform, bytes: &[u8], mach: &MachO) -> Result<Vec<Patch>, Error> {
+    let referenced = backend::mach::referenced_symbols(bytes, mach)?;
+    let mut stripped = HashSet::new();
+    if let Some(iter) = backend::mach::SymtabIter::from_mach(bytes, mach) {
+        let section_types = mach_section_types(mach)?;
+        for (index, item) in iter.enumerate() {
+            let (name, nlist) = match item? {
+                (Some(name), nlist) => (name, nlist),
+                (None, _) => continue,
+            };
+            if !t
+                .rules
+                .strip(&name, nlist_symbol_kind(&nlist, &section_types))?
+            {
+                continue;
+            }
+            if referenced.contains(&index) {
+                return Err(Error::Message(format!(
+                    "Can't strip '{}', since relocations or the indirect symbol table refer to it",
+                    *name
+                )));
+            }
+            if t.verbose {
+                println!("Strip symbol: {}", *name);
+            }
+            stripped.insert(index);
+        }
+    }
+    let (_, strip_patches) =
+        backend::mach::strip_symbols(bytes, mach, &|index, _| stripped.contains(&index))?;
+    Ok(strip_patches)
+}
+
+/// The indices of the Mach-O symbols listed by `--keep-symbols`, ignoring debugging entries.
+fn mach_kept_symbols(
+    bytes: &[u8],
+    mach: &goblin::mach::MachO,
+    rules: &Rules,
+) -> Result<HashSet<usize>, Error> {
+    let mut kept = HashSet::new();
+    if !rules.keeps_symbols() {
+        return Ok(kept);
+    }
+    if let Some(iter) = backend::mach::SymtabIter::from_mach(bytes, mach) {
+        for (index, item) in iter.enumerate() {
+            if let (Some(name), nlist) = item? {
+                if nlist.n_type & N_STAB == 0 && rules.keep(&name) {
+                    kept.insert(index);
+                }
+            }
+        }
+    }
+    Ok(kept)
+}
+
+/// The types of the symbols defined in each section of a Mach-O binary, in section number
+/// order, since Mach-O symbols have no type of their own.
+///
+/// Symbols in sections containing instructions are functions, symbols in thread-local sections
+/// are TLS, and the others are objects.
+fn mach_section_types(mach: &goblin::mach::MachO) -> Result<Vec<SymbolType>, Error> {
+    let mut types = Vec::new();
+    for segment in &mach.segments {
+        for (section, _) in segment.sections().map_err(backend::error::Error::from)? {
+            let thread_local = matches!(
+                section.flags & SECTION_TYPE,
+                S_THREAD_LOCAL_REGULAR
+                    | S_THREAD_LOCAL_ZEROFILL
+                    | S_THREAD_LOCAL_VARIABLES
+                    | S_THREAD_LOCAL_VARIABLE_POINTERS
+                    | S_THREAD_LOCAL_INIT_FUNCTION_POINTERS
+            );
+            types.push(if thread_local {
+                SymbolType::Tls
+            } else if section.flags & (S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS) != 0 {
+                SymbolType::Func
+            } else {
+                SymbolType::Object
+            });
+        }
+    }
+    Ok(types)
+}
+
+/// The kind of a Mach-O symbol, as matched by restricted rules.  Undefined and absolute symbols
+/// have no type, and debugging entries are neither defined nor undefined.
+fn nlist_symbol_kind(nlist: &Nlist, section_types: &[SymbolType]) -> SymbolKind {
+    if nlist.n_type & N_STAB != 0 {
+        return SymbolKind::default();
+    }
+    // Common symbols are undefined with a size, and are definitions
+    let common = nlist.n_type & N_TYPE == N_UNDF && nlist.n_value != 0;
+    let symbol_type = match nlist.n_type & N_TYPE {
+        N_SECT => nlist
+            .n_sect
+            .checked_sub(1)
+            .and_then(|index| section_types.get(index))
+            .copied(),
+        _ if common => Some(SymbolType::Object),
+        _ => None,
+    };
+    SymbolKind {
+        symbol_type,
+        defined: Some(nlist.n_type & N_TYPE != N_UNDF || common),
+    }
+}
+
+fn make_nlist_hidden(s: &Nlist, name: &str, verbose: bool) -> Option<Nlist> {
+    if s.n_type & N_STAB != 0u8 {
+        None
+    } else {
+        if verbose {
+            println!("Set visibility hidden: {}", name);
+        }
+        Some(Nlist {
+            n_type: s.n_type | N_PEXT,
+            ..s.clone()
+        })
+    }
+}
+
+fn make_nlist_default(s: &Nlist, name: &str, verbose: bool) -> Option<Nlist> {
+    if s.n_type & N_STAB != 0u8 {
+        None
+    } else {
+        if verbose {
+            println!("Set visibility default: {}", name);
+        }
+        Some(Nlist {
+            n_type: s.n_type & !N_PEXT,
+            ..s.clone()
+        })
+    }
+}
+
+fn set_nlist_binding(s: &Nlist, binding: Binding, name: &str, verbose: bool) -> Nlist {
+    if verbose {
+        println!("Set binding {}: {}", binding, name);
+    }
+    // Only N_WEAK_DEF changes, keeping flags such as N_ARM_THUMB_DEF
+    let (n_type, n_desc) = match binding {
+        Binding::Local => (s.n_type & !N_EXT, s.n_desc & !N_WEAK_DEF),
+        Binding::Global => (s.n_type | N_EXT, s.n_desc & !N_WEAK_DEF),
+        Binding::Weak => (s.n_type | N_EXT, s.n_desc | N_WEAK_DEF),
+    };
+    Nlist {
+        n_type,
+        n_desc,
+        ..s.clone()
+    }
+}
+
+/// Change the attributes of a Mach-O symbol.
+///
+/// `relocatable` is true for `MH_OBJECT` files, and `kind` is the kind of the symbol as found by
+/// `nlist_symbol_kind`.
+fn change_nlist(
+    nlist: &Nlist,
+    name: &str,
+    verbose: bool,
+    rules: &Rules,
+    relocatable: bool,
+    kind: SymbolKind,
+) -> Result<Option<Nlist>, Error> {
+    if nlist.n_type & N_STAB != 0u8 {
+        return Ok(None);
+    }
+    let visibility_applies = nlist.is_global();
+    let binding_applies = rules.sets_binding() && nlist.n_type & N_TYPE != N_UNDF;
+    // Common symbols are N_UNDF with a nonzero size, and are definitions
+    let weak_reference_applies = rules.weakens_undefined()
+        && nlist.n_type & N_TYPE == N_UNDF
+        && nlist.n_type & N_EXT != 0
+        && nlist.n_value == 0;
+    if !visibility_applies && !binding_applies && !weak_reference_applies {
+        return Ok(None);
+    }
+    let attributes = rules.attributes(name, kind)?;
+    let mut new_nlist = None;
+    if visibility_applies {
+        new_nlist = match attributes.visibility {
+            Some(Visibility::Default) => make_nlist_default(nlist, name, verbose),
+            // Common symbols are N_UNDF with a nonzero size, and are definitions
+            Some(Visibility::Hidden)
+                if nlist.n_type & N_TYPE == N_UNDF
+                    && nlist.n_value == 0
+                    && !rules.allow_undefined_visibility =>
+            {
+                keep_undefined(name, verbose)
+            }
+            Some(Visibility::Hidden) => make_nlist_hidden(nlist, name, verbose),
+            Some(visibility) => {
+                return Err(Error::Message(format!(
+                    "{} visibility is only supported for ELF objects, found rule matching '{}'",
+                    visibility, name
+                )))
+            }
+            None => None,
+        };
+    }
+    let binding = attributes.binding.or_else(|| {
+        let weak = nlist.n_desc & N_WEAK_DEF != 0;
+        let hidden = new_nlist.as_ref().unwrap_or(nlist).n_type & N_PEXT != 0;
+        rules.default_binding(name, nlist.n_type & N_EXT != 0, weak, hidden)
+    });
+    if let (true, Some(binding)) = (binding_applies, binding) {
+        let current = new_nlist.unwrap_or_else(|| nlist.clone());
+        new_nlist = Some(set_nlist_binding(&current, binding, name, verbose));
+    }
+    if weak_reference_applies && attributes.weak_reference {
+        if !relocatable {
+            return Err(Error::Message(format!(
+                "Can't weaken '{}': --weaken-undefined is only supported for relocatable Mach-O objects",
+                name
+            )));
+        }
+        if verbose {
+            println!("Set weak reference: {}", name);
+        }
+        let current = new_nlist.unwrap_or_else(|| nlist.clone());
+        new_nlist = Some(Nlist {
+            n_desc: current.n_desc | N_WEAK_REF,
+            ..current
+        });
+    }
+    Ok(new_nlist)
+}
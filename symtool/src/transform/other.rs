@@ -0,0 +1,81 @@
+//! The passes applied to objects of the other formats, through `ForeignObject`.
+
+use super::{new_name, Transform};
+use crate::error::Error;
+use crate::rules::{Binding, SymbolKind, Visibility};
+use symtool_backend::object::ForeignObject;
+use symtool_backend::patch::Patch;
+
+/// Transform an object of a format registered by a `ForeignObject`.
+///
+/// Only renaming, binding and visibility rules apply, and the other options are refused by
+/// `Transform::check_supported` before this runs.
+pub(super) fn transform(
+    t: &Transform,
+    object: &dyn ForeignObject,
+    patches: &mut Vec<Patch>,
+) -> Result<(), Error> {
+    for symbol in object.symbols()? {
+        if t.rules.strip(&symbol.name, SymbolKind::default())? {
+            return Err(Error::Message(format!(
+                "Can't strip '{}': --strip-symbol is only supported for ELF and Mach-O objects",
+                *symbol.name
+            )));
+        }
+        if let Some(new_name) = new_name(&t.rules, &symbol.name, true, SymbolKind::default())? {
+            patches.push(object.rename(&symbol, &new_name)?);
+            patches.extend(object.rename_references(&symbol, &new_name)?);
+        }
+        if !symbol.global && !t.rules.sets_binding() {
+            continue;
+        }
+        let attributes = t.rules.attributes(&symbol.name, SymbolKind::default())?;
+        let binding = attributes.binding.or_else(|| {
+            t.rules
+                .default_binding(&symbol.name, symbol.global, false, false)
+        });
+        if let Some(binding) = binding {
+            let global = match binding {
+                Binding::Local => false,
+                Binding::Global => true,
+                Binding::Weak => {
+                    return Err(Error::Message(format!(
+                        "Can't make '{}' weak: binding=weak is only supported for ELF and Mach-O objects",
+                        *symbol.name
+                    )))
+                }
+            };
+            if let Some(patch) = object.set_global(&symbol, global)? {
+                if t.verbose {
+                    println!("Set binding {}: {}", binding, *symbol.name);
+                }
+                patches.push(patch);
+            }
+        }
+        if !symbol.global {
+            continue;
+        }
+        let hidden = match attributes.visibility {
+            Some(Visibility::Default) => false,
+            Some(Visibility::Hidden) => true,
+            Some(visibility) => {
+                return Err(Error::Message(format!(
+                    "{} visibility is only supported for ELF objects, found rule matching '{}'",
+                    visibility, *symbol.name
+                )))
+            }
+            None => continue,
+        };
+        if let Some(patch) = object.set_hidden(&symbol, hidden)? {
+            if t.verbose {
+                println!(
+                    "Set visibility {}: {}",
+                    if hidden { "hidden" } else { "default" },
+                    *symbol.name
+                );
+            }
+            patches.push(patch);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! Set the values of symbols.
+
+use crate::add::parse_value;
+use crate::error::Error;
+use clap::ArgMatches;
+use goblin::elf::section_header::{SHN_COMMON, SHN_UNDEF};
+use goblin::elf::sym::Sym;
+use goblin::mach::symbols::{Nlist, N_STAB, N_TYPE, N_UNDF};
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+/// Symbols whose values are set by `--set-symbol-value`.
+pub struct SymbolValues {
+    values: Vec<(String, u64)>,
+
+    /// The names of the symbols changed so far
+    found: RefCell<HashSet<String>>,
+}
+
+impl SymbolValues {
+    pub fn from_matches(matches: &ArgMatches) -> Result<Option<Self>, Error> {
+        let values = match matches.values_of("set-symbol-value") {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        let mut symbols = Vec::new();
+        for value in values {
+            let (name, symbol_value) = value
+                .split_once('=')
+                .filter(|(name, _)| !name.is_empty())
+                .and_then(|(name, symbol_value)| Some((name, parse_value(symbol_value)?)))
+                .ok_or_else(|| {
+                    Error::Message(format!(
+                        "Invalid value for --set-symbol-value: '{}' (expected NAME=VALUE)",
+                        value
+                    ))
+                })?;
+            symbols.push((name.to_string(), symbol_value));
+        }
+        Ok(Some(Self {
+            values: symbols,
+            found: RefCell::new(HashSet::new()),
+        }))
+    }
+
+    fn value(&self, name: &str) -> Option<u64> {
+        let (_, value) = self.values.iter().find(|(symbol, _)| symbol == name)?;
+        self.found.borrow_mut().insert(name.to_string());
+        Some(*value)
+    }
+
+    /// Returns the symbols which weren't found in any object.
+    pub fn missing(&self) -> Vec<&str> {
+        let found = self.found.borrow();
+        self.values
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| !found.contains(*name))
+            .collect()
+    }
+
+    /// Set the value of an ELF symbol.
+    ///
+    /// Returns the changed symbol, or `None` if its value isn't set.
+    pub fn elf_sym(&self, sym: &Sym, name: &str, verbose: bool) -> Result<Option<Sym>, Error> {
+        let value = match self.value(name) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match sym.st_shndx as u32 {
+            SHN_UNDEF => {
+                return Err(Error::Message(format!(
+                    "Can't set the value of '{}': the symbol is undefined",
+                    name
+                )))
+            }
+            // The value of a common symbol is its alignment
+            SHN_COMMON => {
+                return Err(Error::Message(format!(
+                    "Can't set the value of '{}': the symbol is common",
+                    name
+                )))
+            }
+            _ => {}
+        }
+        if verbose {
+            println!("Set value {:#x}: {}", value, name);
+        }
+        Ok(Some(Sym {
+            st_value: value,
+            ..*sym
+        }))
+    }
+
+    /// Set the value of a Mach-O symbol.
+    ///
+    /// Returns the changed symbol, or `None` if its value isn't set.  Debugging entries are never
+    /// changed.
+    pub fn nlist(&self, nlist: &Nlist, name: &str, verbose: bool) -> Result<Option<Nlist>, Error> {
+        if nlist.n_type & N_STAB != 0 {
+            return Ok(None);
+        }
+        let value = match self.value(name) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        // Common symbols are undefined with a size, and are definitions
+        if nlist.n_type & N_TYPE == N_UNDF {
+            return Err(Error::Message(format!(
+                "Can't set the value of '{}': the symbol is {}",
+                name,
+                if nlist.n_value == 0 {
+                    "undefined"
+                } else {
+                    "common"
+                }
+            )));
+        }
+        if verbose {
+            println!("Set value {:#x}: {}", value, name);
+        }
+        Ok(Some(Nlist {
+            n_value: value,
+            ..nlist.clone()
+        }))
+    }
+}
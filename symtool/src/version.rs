@@ -0,0 +1,170 @@
+//! Set the versions of ELF dynamic symbols.
+
+use crate::error::Error;
+use clap::ArgMatches;
+use goblin::elf::header::ET_REL;
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::Elf;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use symtool_backend as backend;
+use symtool_backend::elf::split_version;
+use symtool_backend::patch::Patch;
+
+/// A version given to `--set-symbol-version`.
+struct Assignment {
+    name: String,
+    version: String,
+
+    /// Whether the version is hidden (`NAME@VERSION`) rather than the default (`NAME@@VERSION`)
+    hidden: bool,
+}
+
+/// Symbols whose versions are set by `--set-symbol-version`.
+pub struct VersionAssignments {
+    assignments: Vec<Assignment>,
+
+    /// The names of the symbols changed so far
+    found: RefCell<HashSet<String>>,
+}
+
+impl VersionAssignments {
+    pub fn from_matches(matches: &ArgMatches) -> Result<Option<Self>, Error> {
+        let values = match matches.values_of("set-symbol-version") {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        let mut assignments = Vec::new();
+        for value in values {
+            let (name, suffix) = split_version(value);
+            let (version, hidden) = match suffix.strip_prefix("@@") {
+                Some(version) => (version, false),
+                None => (suffix.trim_start_matches('@'), true),
+            };
+            if version.is_empty() || version.contains('@') {
+                return Err(Error::Message(format!(
+                    "Invalid value for --set-symbol-version: '{}' (expected NAME@@VERSION or NAME@VERSION)",
+                    value
+                )));
+            }
+            assignments.push(Assignment {
+                name: name.to_string(),
+                version: version.to_string(),
+                hidden,
+            });
+        }
+        Ok(Some(Self {
+            assignments,
+            found: RefCell::new(HashSet::new()),
+        }))
+    }
+
+    /// Returns the symbols which weren't found in any object.
+    pub fn missing(&self) -> Vec<&str> {
+        let found = self.found.borrow();
+        self.assignments
+            .iter()
+            .map(|assignment| assignment.name.as_str())
+            .filter(|name| !found.contains(*name))
+            .collect()
+    }
+
+    /// Construct the patches defining the versions the object doesn't define yet, with
+    /// `--rewrite`.
+    ///
+    /// The definitions are added to `.gnu.version_d`, which is created with `.gnu.version` for
+    /// unversioned objects.  Objects without dynamic symbols are left unchanged.
+    pub fn elf_definition_patches(
+        &self,
+        bytes: &[u8],
+        elf: &Elf,
+        verbose: bool,
+    ) -> Result<Vec<Patch>, Error> {
+        if backend::elf::SymtabIter::dynsym_from_elf(bytes, elf)?.is_none() {
+            return Ok(Vec::new());
+        }
+        let versions = backend::elf::SymbolVersions::from_elf(bytes, elf)?;
+        let mut names = Vec::new();
+        for assignment in &self.assignments {
+            let name = assignment.version.as_str();
+            if versions.definition(name).is_none() && !names.contains(&name) {
+                if verbose {
+                    println!("Define version: {}", name);
+                }
+                names.push(name);
+            }
+        }
+        Ok(backend::elf::define_versions(bytes, elf, &names)?)
+    }
+
+    /// Construct the patches setting the versions of the `.dynsym` entries of an ELF binary.
+    ///
+    /// Versions can't be added in place, so without `--rewrite` they must already be defined in
+    /// `.gnu.version_d`.
+    pub fn elf_patches(&self, bytes: &[u8], elf: &Elf, verbose: bool) -> Result<Vec<Patch>, Error> {
+        let mut patches = Vec::new();
+        let iter = match backend::elf::SymtabIter::dynsym_from_elf(bytes, elf)? {
+            Some(iter) => iter,
+            None => {
+                if elf.header.e_type == ET_REL {
+                    if let Some(assignment) = self.assignments.first() {
+                        return Err(Error::Message(format!(
+                            "Can't set the version of '{}': relocatable objects have no dynamic symbols, rename it to '{}@@{}' with --rewrite --rename instead",
+                            assignment.name, assignment.name, assignment.version
+                        )));
+                    }
+                }
+                return Ok(patches);
+            }
+        };
+        let versions = backend::elf::SymbolVersions::from_elf(bytes, elf)?;
+        for (index, item) in iter.enumerate() {
+            let (name, sym) = match item? {
+                (Some(name), sym) => (name, sym),
+                (None, _) => continue,
+            };
+            let assignment = match self
+                .assignments
+                .iter()
+                .find(|assignment| assignment.name == *name)
+            {
+                Some(assignment) => assignment,
+                None => continue,
+            };
+            self.found.borrow_mut().insert(assignment.name.clone());
+            if sym.st_shndx == SHN_UNDEF as usize {
+                return Err(Error::Message(format!(
+                    "Can't set the version of '{}': the symbol is undefined",
+                    assignment.name
+                )));
+            }
+            let version = match versions.definition(&assignment.version) {
+                Some(version) => version,
+                None if versions.definitions().is_empty() => {
+                    return Err(Error::Message(format!(
+                        "Can't set the version of '{}': the object defines no versions, and they can't be added in place. Give --rewrite to add {}, or link it with a version script defining it instead.",
+                        assignment.name, assignment.version
+                    )))
+                }
+                None => {
+                    return Err(Error::Message(format!(
+                        "Can't set the version of '{}': version '{}' isn't defined by the object, which defines {}, and can only be added with --rewrite",
+                        assignment.name,
+                        assignment.version,
+                        versions.definitions().join(", ")
+                    )))
+                }
+            };
+            if verbose {
+                println!(
+                    "Set version {}{}: {}",
+                    if assignment.hidden { "@" } else { "@@" },
+                    assignment.version,
+                    assignment.name
+                );
+            }
+            patches.push(versions.set(index, version, assignment.hidden)?);
+        }
+        Ok(patches)
+    }
+}
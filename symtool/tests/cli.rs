@@ -0,0 +1,524 @@
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process::Command;
+use symtool_backend::object::transform;
+use symtool_backend::testing::{
+    build_archive, ArchiveVariant, Binding, Format, Kind, ObjectBuilder, Symbol,
+};
+
+/// A directory for the files of one test, removed when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("symtool-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.0.join(name).to_str().unwrap().to_string()
+    }
+}
+
+impl TempDir {
+    /// The names of the files in the directory, sorted.
+    fn files(&self) -> Vec<String> {
+        let mut files: Vec<_> = std::fs::read_dir(&self.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Run symtool with `args`, returning its standard output or the error message.
+fn run(args: &[&str]) -> Result<String, String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_symtool"))
+        .args(args)
+        .output()
+        .unwrap();
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Run symtool on `input` with `args`, returning the output or the error message.
+fn symtool(name: &str, args: &[&str], input: &[u8]) -> Result<Vec<u8>, String> {
+    let dir = TempDir::new(name);
+    let (input_path, output_path) = (dir.path("input"), dir.path("output"));
+    std::fs::write(&input_path, input).unwrap();
+    let args: Vec<_> = args
+        .iter()
+        .copied()
+        .chain([&*input_path, &*output_path])
+        .collect();
+    run(&args)?;
+    Ok(std::fs::read(&output_path).unwrap())
+}
+
+/// The sorted symbol names of each object in `bytes`, by archive member.
+fn member_names(bytes: &[u8]) -> Vec<(Option<String>, Vec<String>)> {
+    let mut members = Vec::new();
+    transform(&mut Cursor::new(bytes), &mut Vec::new(), |context| {
+        let mut names = Vec::new();
+        match &context.object {
+            symtool_backend::object::Object::Elf(elf) => {
+                for item in
+                    symtool_backend::elf::SymtabIter::symtab_from_elf(context.bytes, elf)?.unwrap()
+                {
+                    if let (Some(name), _) = item? {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+            _ => panic!("unexpected object format"),
+        }
+        names.sort();
+        members.push((context.member.map(str::to_string), names));
+        Ok::<_, symtool_backend::error::Error>(())
+    })
+    .unwrap();
+    members
+}
+
+#[test]
+fn max_matches_counted_per_archive_member() {
+    let object = ObjectBuilder::new()
+        .symbol(Symbol::new("foo"))
+        .build(Format::Elf);
+    let archive = build_archive(
+        &[("a.o", object.clone()), ("b.o", object)],
+        ArchiveVariant::Gnu,
+    );
+    let output = symtool(
+        "max-matches-archive",
+        &["--rename", "foo", "bar", "--max-matches", "1"],
+        &archive,
+    )
+    .unwrap();
+    assert_eq!(
+        member_names(&output),
+        vec![
+            (Some("a.o".to_string()), vec!["bar".to_string()]),
+            (Some("b.o".to_string()), vec!["bar".to_string()]),
+        ]
+    );
+
+    let object = ObjectBuilder::new()
+        .symbol(Symbol::new("foo_a"))
+        .symbol(Symbol::new("foo_b"))
+        .build(Format::Elf);
+    let archive = build_archive(&[("a.o", object)], ArchiveVariant::Gnu);
+    let error = symtool(
+        "max-matches-member",
+        &["--hidden", "^foo_", "--max-matches", "1"],
+        &archive,
+    )
+    .unwrap_err();
+    assert!(error.contains("matched more than 1 symbol(s)"), "{}", error);
+}
+
+/// Turn the `.symtab` of an ELF object into a `.dynsym`, as if stripped from a shared library.
+fn without_symtab(mut bytes: Vec<u8>) -> Vec<u8> {
+    let elf = goblin::elf::Elf::parse(&bytes).unwrap();
+    let index = elf
+        .section_headers
+        .iter()
+        .position(|header| header.sh_type == goblin::elf::section_header::SHT_SYMTAB)
+        .unwrap();
+    let offset = elf.header.e_shoff as usize + index * elf.header.e_shentsize as usize + 4;
+    bytes[offset..offset + 4]
+        .copy_from_slice(&goblin::elf::section_header::SHT_DYNSYM.to_le_bytes());
+    bytes
+}
+
+#[test]
+fn dynsym_symbols_not_made_local() {
+    let object = without_symtab(
+        ObjectBuilder::new()
+            .symbol(Symbol::new("foo"))
+            .build(Format::Elf),
+    );
+    let error = symtool("dynsym-local", &["--localize-symbol", "foo"], &object).unwrap_err();
+    assert!(
+        error.contains("Can't make 'foo' local in .dynsym"),
+        "{}",
+        error
+    );
+
+    let output = symtool("dynsym-hidden", &["--hidden", "foo"], &object).unwrap();
+    let elf = goblin::elf::Elf::parse(&output).unwrap();
+    let (_, sym) = symtool_backend::elf::SymtabIter::dynsym_from_elf(&output, &elf)
+        .unwrap()
+        .unwrap()
+        .map(Result::unwrap)
+        .find(|(name, _)| name.as_deref() == Some(&"foo"))
+        .unwrap();
+    assert_eq!(sym.st_visibility(), goblin::elf::sym::STV_HIDDEN);
+    assert_eq!(sym.st_bind(), goblin::elf::sym::STB_GLOBAL);
+}
+
+fn foo_object() -> Vec<u8> {
+    ObjectBuilder::new()
+        .symbol(Symbol::new("foo"))
+        .build(Format::Elf)
+}
+
+fn names(bytes: &[u8]) -> Vec<String> {
+    member_names(bytes).remove(0).1
+}
+
+#[test]
+fn outputs_rewritten_through_links() {
+    let dir = TempDir::new("links");
+    let (input, output, link) = (dir.path("input"), dir.path("output"), dir.path("link"));
+    std::fs::write(&input, foo_object()).unwrap();
+    std::fs::write(&output, b"old").unwrap();
+    std::fs::hard_link(&output, &link).unwrap();
+    run(&["--rename", "foo", "bar", &input, &link]).unwrap();
+    assert_eq!(names(&std::fs::read(&output).unwrap()), ["bar"]);
+    assert_eq!(dir.files(), ["input", "link", "output"]);
+
+    #[cfg(unix)]
+    {
+        let symlink = dir.path("symlink");
+        std::os::unix::fs::symlink(&output, &symlink).unwrap();
+        run(&["--rename", "foo", "baz", &input, &symlink]).unwrap();
+        let metadata = std::fs::symlink_metadata(&symlink).unwrap();
+        assert!(metadata.file_type().is_symlink());
+        assert_eq!(names(&std::fs::read(&output).unwrap()), ["baz"]);
+        assert_eq!(dir.files(), ["input", "link", "output", "symlink"]);
+    }
+}
+
+#[test]
+fn outputs_modified_in_place() {
+    let dir = TempDir::new("in-place");
+    let path = dir.path("object.o");
+    std::fs::write(&path, foo_object()).unwrap();
+    run(&["--rename", "foo", "bar", &path, &path]).unwrap();
+    assert_eq!(names(&std::fs::read(&path).unwrap()), ["bar"]);
+    assert_eq!(dir.files(), ["object.o"]);
+}
+
+#[test]
+fn failed_runs_leave_outputs_untouched() {
+    let dir = TempDir::new("failed");
+    let (input, output) = (dir.path("input"), dir.path("output"));
+    std::fs::write(&input, foo_object()).unwrap();
+    let error = run(&["--rename", "missing", "x", &input, &output]).unwrap_err();
+    assert!(error.contains("'missing'"), "{}", error);
+    assert_eq!(dir.files(), ["input"]);
+
+    std::fs::write(&output, b"old").unwrap();
+    run(&["--rename", "missing", "x", &input, &output]).unwrap_err();
+    assert_eq!(std::fs::read(&output).unwrap(), b"old");
+    assert_eq!(dir.files(), ["input", "output"]);
+}
+
+#[test]
+fn configuration_scoped_to_its_rules_and_directory() {
+    let dir = TempDir::new("config");
+    let (config, input, output) = (
+        dir.path("config.toml"),
+        dir.path("input"),
+        dir.path("output"),
+    );
+    std::fs::write(&input, foo_object()).unwrap();
+    std::fs::write(&config, "[[rule]]\nhidden = 'foo'\n").unwrap();
+    // --ignore-missing comes before any rule of the command line, so it isn't given to the
+    // rule of the file
+    run(&[
+        "--config",
+        &config,
+        "--ignore-missing",
+        "--rename",
+        "nope",
+        "nop",
+        &input,
+        &output,
+    ])
+    .unwrap();
+
+    std::fs::write(&config, "exclude-symbols = 'keep.txt'\n").unwrap();
+    std::fs::write(dir.path("keep.txt"), "foo\n").unwrap();
+    let error = run(&[
+        "--config", &config, "--rename", "foo", "bar", &input, &output,
+    ])
+    .unwrap_err();
+    assert!(error.contains("excluded by --exclude-symbols"), "{}", error);
+}
+
+#[test]
+fn dynsym_symbols_renamed_with_rewrite() {
+    let library = ObjectBuilder::new()
+        .symbol(Symbol::new("foo"))
+        .symbol(Symbol::new("bar"))
+        .relocation("foo")
+        .build_shared_elf();
+    // Only the symbols of .dynsym are renamed without a .symtab
+    let library = symtool("dynsym-strip", &["--strip-all"], &library).unwrap();
+    // Even names of the same length would invalidate the hash tables
+    let args = ["--rename", "foo", "baz"];
+    let error = symtool("dynsym-rename", &args, &library).unwrap_err();
+    assert!(error.contains("requires --rewrite"), "{}", error);
+
+    let args = ["--rewrite", "--rename", "foo", "a_much_longer_name"];
+    let output = symtool("dynsym-rewrite", &args, &library).unwrap();
+    let elf = goblin::elf::Elf::parse(&output).unwrap();
+    let mut names = elf
+        .dynsyms
+        .iter()
+        .map(|sym| elf.dynstrtab.get(sym.st_name).unwrap().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["", "a_much_longer_name", "bar"]);
+    let reloc = elf.dynrelas.iter().next().unwrap();
+    let sym = elf.dynsyms.get(reloc.r_sym).unwrap();
+    assert_eq!(
+        elf.dynstrtab.get(sym.st_name).unwrap().unwrap(),
+        "a_much_longer_name"
+    );
+}
+
+#[test]
+fn versions_defined_with_rewrite() {
+    let library = ObjectBuilder::new()
+        .symbol(Symbol::new("foo"))
+        .symbol(Symbol::new("bar"))
+        .build_shared_elf();
+    let args = ["--set-symbol-version", "foo@@LIBFOO_1.0"];
+    let error = symtool("versions-in-place", &args, &library).unwrap_err();
+    assert!(
+        error.contains("Give --rewrite to add LIBFOO_1.0"),
+        "{}",
+        error
+    );
+
+    let args = [
+        "--rewrite",
+        "--set-symbol-version",
+        "foo@@LIBFOO_1.0",
+        "--set-symbol-version",
+        "bar@LIBFOO_0.9",
+    ];
+    let output = symtool("versions-rewrite", &args, &library).unwrap();
+    let elf = goblin::elf::Elf::parse(&output).unwrap();
+    let versions = symtool_backend::elf::SymbolVersions::from_elf(&output, &elf).unwrap();
+    assert_eq!(versions.definitions(), ["LIBFOO_0.9", "LIBFOO_1.0"]);
+    let symbols = symtool_backend::elf::SymtabIter::dynsym_from_elf(&output, &elf)
+        .unwrap()
+        .unwrap()
+        .enumerate()
+        .filter_map(|(index, item)| {
+            let name = item.unwrap().0?.to_string();
+            let version = versions.get(index).unwrap().map(|v| (v.name, v.hidden));
+            Some((name, version))
+        })
+        .collect::<Vec<_>>();
+    assert!(symbols.contains(&("foo".to_string(), Some(("LIBFOO_1.0", false)))));
+    assert!(symbols.contains(&("bar".to_string(), Some(("LIBFOO_0.9", true)))));
+}
+
+/// Turn the symbol `name` of an ELF object into a common symbol of 4 bytes aligned to 8.
+fn with_common(mut bytes: Vec<u8>, name: &str) -> Vec<u8> {
+    let elf = goblin::elf::Elf::parse(&bytes).unwrap();
+    let (_, sym) = symtool_backend::elf::SymtabIter::symtab_from_elf(&bytes, &elf)
+        .unwrap()
+        .unwrap()
+        .map(Result::unwrap)
+        .find(|(symbol, _)| symbol.as_deref() == Some(&name))
+        .unwrap();
+    let patch = sym
+        .patch_with(goblin::elf::sym::Sym {
+            st_shndx: goblin::elf::section_header::SHN_COMMON as usize,
+            st_value: 8,
+            st_size: 4,
+            ..sym.value
+        })
+        .unwrap();
+    patch.apply(&mut bytes);
+    bytes
+}
+
+#[test]
+fn common_symbols_defined_in_added_bss() {
+    let object = with_common(
+        ObjectBuilder::new()
+            .symbol(Symbol::new("foo"))
+            .symbol(Symbol::new("counter").kind(Kind::Object))
+            .build(Format::Elf),
+        "counter",
+    );
+    // .bss is added after the string table rebuilt by --rewrite
+    for (args, foo) in &[
+        (&["--define-common"][..], "foo"),
+        (
+            &[
+                "--define-common",
+                "--rewrite",
+                "--rename",
+                "foo",
+                "a_longer_foo",
+            ],
+            "a_longer_foo",
+        ),
+    ] {
+        let output = symtool("common", args, &object).unwrap();
+        let elf = goblin::elf::Elf::parse(&output).unwrap();
+        let (index, bss) = elf
+            .section_headers
+            .iter()
+            .enumerate()
+            .find(|(_, header)| elf.shdr_strtab.get(header.sh_name).unwrap().unwrap() == ".bss")
+            .unwrap();
+        assert_eq!(bss.sh_type, goblin::elf::section_header::SHT_NOBITS);
+        assert_eq!((bss.sh_size, bss.sh_addralign), (4, 8));
+        let sym = elf
+            .syms
+            .iter()
+            .find(|sym| elf.strtab.get(sym.st_name).unwrap().unwrap() == "counter")
+            .unwrap();
+        assert_eq!((sym.st_shndx, sym.st_value, sym.st_size), (index, 0, 4));
+        assert!(names(&output).iter().any(|name| name == foo));
+    }
+}
+
+#[test]
+fn symbols_renamed_longer_with_rewrite() {
+    let object = ObjectBuilder::new()
+        .symbol(Symbol::new("foo"))
+        .symbol(Symbol::new("bar").undefined())
+        .build(Format::Elf);
+    let args = ["--rename", "foo", "a_much_longer_name"];
+    let error = symtool("rename-long", &args, &object).unwrap_err();
+    assert!(error.contains("--rewrite"), "{}", error);
+
+    let args = ["--rewrite", "--rename", "foo", "a_much_longer_name"];
+    let output = symtool("rename-rewrite", &args, &object).unwrap();
+    assert_eq!(names(&output), ["a_much_longer_name", "bar"]);
+}
+
+#[test]
+fn obfuscated_names_mapped_without_collisions() {
+    let dir = TempDir::new("obfuscate");
+    let (input, output, map) = (dir.path("input"), dir.path("output"), dir.path("map"));
+    std::fs::write(
+        &input,
+        ObjectBuilder::new()
+            .symbol(Symbol::new("a").binding(Binding::Local))
+            .symbol(Symbol::new("b").binding(Binding::Local))
+            .symbol(Symbol::new("exported"))
+            .build(Format::Elf),
+    )
+    .unwrap();
+    // Both one-character names are truncated to "s"
+    let args = ["--obfuscate", "--obfuscate-map", &map, &input, &output];
+    let error = run(&args).unwrap_err();
+    assert!(error.contains("'a' and 'b'"), "{}", error);
+    assert_eq!(dir.files(), ["input"]);
+
+    let args = [
+        "--rewrite",
+        "--obfuscate",
+        "--obfuscate-map",
+        &map,
+        "--seed",
+        "1.0",
+        &input,
+        &output,
+    ];
+    run(&args).unwrap();
+    let obfuscated = names(&std::fs::read(&output).unwrap());
+    let mapped = std::fs::read_to_string(&map).unwrap();
+    let mut originals: Vec<_> = mapped
+        .lines()
+        .map(|line| {
+            let (hash, original) = line.split_once(' ').unwrap();
+            assert_eq!(hash.len(), 17);
+            assert!(obfuscated.iter().any(|name| name == hash));
+            original
+        })
+        .collect();
+    originals.sort_unstable();
+    assert_eq!(originals, ["a", "b"]);
+    assert!(obfuscated.iter().any(|name| name == "exported"));
+
+    // The same seed gives the same names
+    run(&args).unwrap();
+    assert_eq!(std::fs::read_to_string(&map).unwrap(), mapped);
+}
+
+#[test]
+fn indexed_symbols_queried() {
+    let dir = TempDir::new("index");
+    let (objects, database) = (dir.path("objects"), dir.path("symbols.db"));
+    std::fs::create_dir(&objects).unwrap();
+    std::fs::write(
+        dir.0.join("objects").join("foo.o"),
+        ObjectBuilder::new()
+            .symbol(Symbol::new("foo_open"))
+            .symbol(Symbol::new("foo_internal").hidden())
+            .symbol(Symbol::new("bar").undefined())
+            .build(Format::Elf),
+    )
+    .unwrap();
+    std::fs::write(dir.0.join("objects").join("notes.txt"), "not an object").unwrap();
+    run(&["index", &objects, "-o", &database]).unwrap();
+
+    let query = |query: &str| {
+        let output = run(&["query", "-d", &database, query]).unwrap();
+        output
+            .lines()
+            .map(|line| line.rsplit(": ").next().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(query("exported AND name ~ \"^foo_\""), ["foo_open"]);
+    assert_eq!(query("NOT defined"), ["bar"]);
+    assert!(run(&["query", "-d", &database, "name ~"]).is_err());
+}
+
+#[test]
+fn configuration_merged_with_command_line() {
+    let dir = TempDir::new("config-merge");
+    let (input, output) = (dir.path("input"), dir.path("output"));
+    std::fs::write(
+        &input,
+        ObjectBuilder::new()
+            .symbol(Symbol::new("foo_internal"))
+            .symbol(Symbol::new("bar"))
+            .build(Format::Elf),
+    )
+    .unwrap();
+    // The file next to the input is found without --config
+    std::fs::write(dir.path(".symtool.toml"), "[[rule]]\nhidden = '^foo_'\n").unwrap();
+    run(&["--rename", "bar", "baz", &input, &output]).unwrap();
+    let elf_hidden = |bytes: &[u8], name: &str| {
+        let elf = goblin::elf::Elf::parse(bytes).unwrap();
+        let sym = elf
+            .syms
+            .iter()
+            .find(|sym| elf.strtab.get(sym.st_name).unwrap().unwrap() == name)
+            .unwrap();
+        sym.st_other & 0x3 == goblin::elf::sym::STV_HIDDEN
+    };
+    let bytes = std::fs::read(&output).unwrap();
+    assert_eq!(names(&bytes), ["baz", "foo_internal"]);
+    assert!(elf_hidden(&bytes, "foo_internal"));
+
+    run(&["--no-config", "--rename", "bar", "baz", &input, &output]).unwrap();
+    assert!(!elf_hidden(
+        &std::fs::read(&output).unwrap(),
+        "foo_internal"
+    ));
+}